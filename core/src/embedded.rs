@@ -0,0 +1,91 @@
+//! Implementation of the `embedded-can` frame trait, so code written against the
+//! embedded HAL CAN traits can use frames directly. Enabled with the
+//! `embedded-can` feature.
+
+use embedded_can::{ExtendedId, Id, StandardId};
+
+use crate::Frame;
+
+impl embedded_can::Frame for Frame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Frame> {
+        match id.into() {
+            Id::Standard(id) => Frame::new(id.as_raw() as u32, data).ok(),
+            Id::Extended(id) => Frame::new_ext(id.as_raw(), data).ok(),
+        }
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Frame> {
+        match id.into() {
+            Id::Standard(id) => Frame::new_remote(id.as_raw() as u32, false, dlc).ok(),
+            Id::Extended(id) => Frame::new_remote(id.as_raw(), true, dlc).ok(),
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        self.id.is_extended()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.rtr
+    }
+
+    fn id(&self) -> Id {
+        self.id.into()
+    }
+
+    fn dlc(&self) -> usize {
+        self.can_dlc as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.rtr {
+            &[]
+        } else {
+            self.payload()
+        }
+    }
+}
+
+impl From<crate::Id> for Id {
+    fn from(id: crate::Id) -> Id {
+        // both types only hold valid IDs
+        match id {
+            crate::Id::Standard(id) => Id::Standard(StandardId::new(id.as_raw()).unwrap()),
+            crate::Id::Extended(id) => Id::Extended(ExtendedId::new(id.as_raw()).unwrap()),
+        }
+    }
+}
+
+impl From<Id> for crate::Id {
+    fn from(id: Id) -> crate::Id {
+        match id {
+            Id::Standard(id) => crate::StandardId::new(id.as_raw()).unwrap().into(),
+            Id::Extended(id) => crate::ExtendedId::new(id.as_raw()).unwrap().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::Frame as _;
+
+    #[test]
+    fn test_hal_frame() {
+        let f =
+            <Frame as embedded_can::Frame>::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        assert_eq!(
+            embedded_can::Frame::id(&f),
+            Id::Standard(StandardId::new(0x123).unwrap())
+        );
+        assert_eq!((f.dlc(), f.data()), (2, &[1u8, 2][..]));
+        assert!(!f.is_extended() && f.is_data_frame());
+
+        let f = <Frame as embedded_can::Frame>::new_remote(ExtendedId::new(0x1ABCDEF).unwrap(), 4)
+            .unwrap();
+        assert!(f.is_extended() && f.is_remote_frame());
+        assert_eq!((f.dlc(), f.data()), (4, &[][..]));
+
+        assert!(<Frame as embedded_can::Frame>::new(ExtendedId::ZERO, &[0; 9]).is_none());
+    }
+}
@@ -0,0 +1,148 @@
+//! Fluent construction of frames.
+
+use crate::{Frame, FrameError, Id};
+
+/// Builds a `Frame` one property at a time, returned by `Frame::builder`.
+///
+/// ```
+/// # use cantact_core::Frame;
+/// let f = Frame::builder()
+///     .id(0x123)
+///     .extended()
+///     .data(&[1, 2, 3])
+///     .fd()
+///     .brs()
+///     .build()
+///     .unwrap();
+/// assert!(f.is_extended() && f.fd && f.brs);
+/// ```
+///
+/// Nothing is checked until `FrameBuilder::build`, which returns `FrameError`
+/// if the frame is not valid, see `Frame::validate`.
+#[derive(Debug, Clone, Default)]
+pub struct FrameBuilder {
+    frame: Frame,
+    // the ID is checked against its type when building
+    id: u32,
+    ext: bool,
+    // length of the data, which can be over what a frame carries until built
+    len: usize,
+    // requested length of a remote frame
+    remote: Option<usize>,
+}
+
+impl FrameBuilder {
+    /// Set the ID. The ID is standard unless `FrameBuilder::extended` is called.
+    pub fn id(mut self, id: u32) -> FrameBuilder {
+        self.id = id;
+        self
+    }
+
+    /// Set the ID and its type from an `Id`.
+    pub fn typed_id(mut self, id: impl Into<Id>) -> FrameBuilder {
+        let id = id.into();
+        self.id = id.as_raw();
+        self.ext = id.is_extended();
+        self
+    }
+
+    /// Use an extended (29 bit) ID.
+    pub fn extended(mut self) -> FrameBuilder {
+        self.ext = true;
+        self
+    }
+
+    /// Set the channel to send the frame on.
+    pub fn channel(mut self, channel: u8) -> FrameBuilder {
+        self.frame.channel = channel;
+        self
+    }
+
+    /// Set the data. The DLC is set from its length.
+    pub fn data(mut self, data: &[u8]) -> FrameBuilder {
+        let n = data.len().min(self.frame.data.len());
+        self.frame.data = [0; 64];
+        self.frame.data[..n].copy_from_slice(&data[..n]);
+        self.len = data.len();
+        self.remote = None;
+        self
+    }
+
+    /// Make a remote frame requesting `len` bytes of data.
+    pub fn remote(mut self, len: usize) -> FrameBuilder {
+        self.frame.data = [0; 64];
+        self.len = 0;
+        self.remote = Some(len);
+        self
+    }
+
+    /// Make a CAN FD frame.
+    pub fn fd(mut self) -> FrameBuilder {
+        self.frame.fd = true;
+        self
+    }
+
+    /// Set the bit rate switch flag, so the data is sent at the data bitrate.
+    /// Only valid for FD frames.
+    pub fn brs(mut self) -> FrameBuilder {
+        self.frame.brs = true;
+        self
+    }
+
+    /// Set the error state indicator flag. Only valid for FD frames.
+    pub fn esi(mut self) -> FrameBuilder {
+        self.frame.esi = true;
+        self
+    }
+
+    /// Build the frame, checking it with `Frame::validate`. Returns `FrameError`
+    /// if the ID is out of range for its type, or the frame is not valid or
+    /// cannot carry the data.
+    pub fn build(self) -> Result<Frame, FrameError> {
+        let mut f = self.frame;
+        f.id = Id::new(self.id, self.ext).ok_or(FrameError)?;
+        match self.remote {
+            Some(len) => {
+                f.rtr = true;
+                f.set_len(len)?;
+            }
+            None => f.set_len(self.len)?,
+        }
+        f.validate()?;
+        Ok(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let f = Frame::builder()
+            .id(0x1ABCDEF)
+            .extended()
+            .channel(1)
+            .data(&[0xAA; 10])
+            .fd()
+            .brs()
+            .build()
+            .unwrap();
+        assert_eq!(
+            (f.raw_id(), f.is_extended(), f.channel),
+            (0x1ABCDEF, true, 1)
+        );
+        assert_eq!(f.len(), 12);
+        assert_eq!(f.payload()[..10], [0xAA; 10]);
+        assert_eq!(f.payload()[10..], [0, 0]);
+
+        let f = Frame::builder().id(0x7DF).remote(8).build().unwrap();
+        assert!(f.rtr && f.can_dlc == 8);
+
+        // an extended value without extended(), too much data, and remote FD
+        assert!(Frame::builder().id(0x800).build().is_err());
+        assert!(Frame::builder().data(&[0; 9]).build().is_err());
+        assert!(Frame::builder().fd().remote(1).build().is_err());
+        assert!(Frame::builder().brs().build().is_err());
+    }
+}
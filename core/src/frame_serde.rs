@@ -0,0 +1,220 @@
+//! Serialization of frames.
+//!
+//! Frames are serialized as a flat record with the data as a hex string, for
+//! example in JSON:
+//!
+//! ```text
+//! {"channel":0,"id":291,"ext":false,"rtr":false,"fd":false,"brs":false,"esi":false,
+//!  "dlc":4,"data":"DEADBEEF","echo_id":null,"timestamp_us":1500}
+//! ```
+//!
+//! When deserializing, all fields except `id` are optional. Without `dlc`, the DLC
+//! is taken from the length of `data`, padding FD data to the next valid length.
+
+use core::fmt;
+use core::time::Duration;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Direction, Frame, Id};
+
+#[derive(Serialize, Deserialize)]
+struct FrameRepr {
+    #[serde(default)]
+    channel: u8,
+    id: u32,
+    #[serde(default)]
+    ext: bool,
+    #[serde(default)]
+    rtr: bool,
+    #[serde(default)]
+    fd: bool,
+    #[serde(default)]
+    brs: bool,
+    #[serde(default)]
+    esi: bool,
+    #[serde(default)]
+    dlc: Option<u8>,
+    #[serde(default)]
+    data: Hex,
+    // set for frames echoed after transmission
+    #[serde(default)]
+    echo_id: Option<u32>,
+    #[serde(default)]
+    timestamp_us: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_flags: Option<u8>,
+}
+
+// data bytes, written as a hex string without needing an allocator
+struct Hex {
+    data: [u8; 64],
+    len: usize,
+}
+
+impl Default for Hex {
+    fn default() -> Hex {
+        Hex {
+            data: [0u8; 64],
+            len: 0,
+        }
+    }
+}
+
+impl Serialize for Hex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+        let mut text = [0u8; 128];
+        for (i, b) in self.data[..self.len].iter().enumerate() {
+            text[i * 2] = DIGITS[(b >> 4) as usize];
+            text[i * 2 + 1] = DIGITS[(b & 0xF) as usize];
+        }
+        // only ASCII digits were written
+        serializer.serialize_str(core::str::from_utf8(&text[..self.len * 2]).unwrap())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hex, D::Error> {
+        deserializer.deserialize_str(HexVisitor)
+    }
+}
+
+struct HexVisitor;
+
+impl<'de> Visitor<'de> for HexVisitor {
+    type Value = Hex;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a hex string")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Hex, E> {
+        if !s.is_ascii() || !s.len().is_multiple_of(2) {
+            return Err(E::custom(format_args!("invalid hex data '{}'", s)));
+        }
+        let mut hex = Hex {
+            len: s.len() / 2,
+            ..Default::default()
+        };
+        if hex.len > hex.data.len() {
+            return Err(E::custom(format_args!(
+                "too much data for a frame: {} bytes",
+                hex.len
+            )));
+        }
+        for (i, b) in hex.data[..hex.len].iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| E::custom(format_args!("invalid hex data '{}'", s)))?;
+        }
+        Ok(hex)
+    }
+}
+
+impl From<&Frame> for FrameRepr {
+    fn from(f: &Frame) -> FrameRepr {
+        let mut data = Hex::default();
+        if !f.rtr {
+            data.len = f.len();
+            data.data[..data.len].copy_from_slice(f.payload());
+        }
+        FrameRepr {
+            channel: f.channel,
+            id: f.raw_id(),
+            ext: f.is_extended(),
+            rtr: f.rtr,
+            fd: f.fd,
+            brs: f.brs,
+            esi: f.esi,
+            dlc: Some(f.can_dlc),
+            data,
+            echo_id: f.echo_id(),
+            timestamp_us: f.timestamp.map(|t| t.as_micros() as u64),
+            raw_flags: f.raw_flags,
+        }
+    }
+}
+
+impl FrameRepr {
+    fn to_frame<E: de::Error>(&self) -> Result<Frame, E> {
+        let data = &self.data.data[..self.data.len];
+        let mut f = Frame {
+            channel: self.channel,
+            id: Id::new(self.id, self.ext)
+                .ok_or_else(|| E::custom(format_args!("invalid ID {:X}", self.id)))?,
+            rtr: self.rtr,
+            fd: self.fd,
+            brs: self.brs,
+            esi: self.esi,
+            direction: match self.echo_id {
+                Some(id) => Direction::TxEcho(id),
+                None => Direction::Rx,
+            },
+            timestamp: self.timestamp_us.map(Duration::from_micros),
+            raw_flags: self.raw_flags,
+            ..Default::default()
+        };
+        match self.dlc {
+            Some(dlc) => {
+                f.can_dlc = dlc;
+                if !f.rtr && data.len() != f.len() {
+                    return Err(E::custom(format_args!(
+                        "DLC {} does not match {} bytes of data",
+                        dlc,
+                        data.len()
+                    )));
+                }
+            }
+            // FD data is padded to the next valid length
+            None => f.set_len(data.len()).map_err(|_| {
+                E::custom(format_args!(
+                    "too much data for a frame: {} bytes",
+                    data.len()
+                ))
+            })?,
+        }
+        f.data[..data.len()].copy_from_slice(data);
+        Ok(f)
+    }
+}
+
+impl Serialize for Frame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FrameRepr::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Frame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Frame, D::Error> {
+        FrameRepr::deserialize(deserializer)?.to_frame()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_json() {
+        let mut f = Frame::new(0x123, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        f.timestamp = Some(Duration::from_micros(1500));
+        let json = serde_json::to_string(&f).unwrap();
+        assert_eq!(
+            json,
+            "{\"channel\":0,\"id\":291,\"ext\":false,\"rtr\":false,\"fd\":false,\
+             \"brs\":false,\"esi\":false,\"dlc\":4,\"data\":\"DEADBEEF\",\
+             \"echo_id\":null,\"timestamp_us\":1500}"
+        );
+        let g: Frame = serde_json::from_str(&json).unwrap();
+        assert_eq!(g, f);
+        assert_eq!(g.timestamp, f.timestamp);
+
+        let g: Frame =
+            serde_json::from_str("{\"id\":1,\"fd\":true,\"data\":\"0102030405060708090A\"}")
+                .unwrap();
+        assert_eq!((g.can_dlc, g.len()), (9, 12));
+        assert!(serde_json::from_str::<Frame>("{\"id\":1,\"dlc\":2,\"data\":\"01\"}").is_err());
+        assert!(serde_json::from_str::<Frame>("{\"id\":1,\"data\":\"0G\"}").is_err());
+    }
+}
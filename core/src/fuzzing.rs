@@ -0,0 +1,102 @@
+//! Random generation of frames and bit timings for fuzzing. Enabled with the
+//! `arbitrary` feature.
+//!
+//! Generated frames always pass `Frame::validate`: standard IDs fit in 11 bits,
+//! extended IDs in 29 bits, the DLC is at most 8 for classic frames, data bytes
+//! beyond the DLC are zero, remote frames carry no data, and only FD frames
+//! have the BRS and ESI flags. The functions building them from raw parts are
+//! public, for other generators such as property testing strategies.
+
+use core::time::Duration;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{BitTiming, Direction, Frame, Id};
+
+/// Build a classic frame from its raw parts, clamping everything into range.
+pub fn frame_from_parts(
+    ext: bool,
+    id: u32,
+    rtr: bool,
+    dlc: u8,
+    bytes: [u8; 8],
+    channel: u8,
+    echo: Option<u32>,
+) -> Frame {
+    let can_dlc = dlc % 9;
+    let mut data = [0u8; 64];
+    if !rtr {
+        data[..can_dlc as usize].copy_from_slice(&bytes[..can_dlc as usize]);
+    }
+    let direction = match echo {
+        Some(id) => Direction::TxEcho(id),
+        None => Direction::Rx,
+    };
+    Frame {
+        id: Id::truncate(id, ext),
+        can_dlc,
+        channel,
+        data,
+        rtr,
+        direction,
+        ..Default::default()
+    }
+}
+
+/// Turn a frame into an FD data frame, taking up to 64 bytes of data from
+/// `bytes`, which must hold the length given by `dlc`.
+pub fn fd_from_parts(mut f: Frame, dlc: u8, bytes: &[u8], brs: bool, esi: bool) -> Frame {
+    f.fd = true;
+    f.rtr = false;
+    f.brs = brs;
+    f.esi = esi;
+    f.can_dlc = dlc % 16;
+    let len = f.len();
+    f.data = [0u8; 64];
+    f.data[..len].copy_from_slice(&bytes[..len]);
+    f
+}
+
+/// Build a bit timing from its raw parts, clamping everything into the ranges
+/// accepted by common controllers.
+pub fn bit_timing_from_parts(brp: u32, seg1: u32, seg2: u32, sjw: u32) -> BitTiming {
+    let phase_seg2 = 2 + seg2 % 7;
+    BitTiming {
+        brp: 1 + brp % 32,
+        prop_seg: 0,
+        phase_seg1: 3 + seg1 % 15,
+        phase_seg2,
+        sjw: 1 + sjw % phase_seg2.min(4),
+    }
+}
+
+impl<'a> Arbitrary<'a> for Frame {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Frame> {
+        let mut f = frame_from_parts(
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+        );
+        if u.arbitrary()? {
+            let bytes: [u8; 64] = u.arbitrary()?;
+            f = fd_from_parts(f, u.arbitrary()?, &bytes, u.arbitrary()?, u.arbitrary()?);
+        }
+        f.timestamp = Option::<u64>::arbitrary(u)?.map(Duration::from_micros);
+        Ok(f)
+    }
+}
+
+impl<'a> Arbitrary<'a> for BitTiming {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<BitTiming> {
+        Ok(bit_timing_from_parts(
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+        ))
+    }
+}
@@ -0,0 +1,594 @@
+//! Frame and bit timing types of the CANtact driver, which depend on neither
+//! the USB driver nor the standard library, so they can be shared by other
+//! transports and by embedded code. Everything here is re-exported at the root
+//! of the `cantact-driver` crate.
+//!
+//! With the `arbitrary` feature, frames and bit timings can be generated for
+//! fuzzing, see `fuzzing`. With the `embedded-can` feature, `Frame` implements
+//! the `embedded_can::Frame` trait.
+
+#![cfg_attr(not(test), no_std)]
+#![warn(missing_docs)]
+
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "embedded-can")]
+mod embedded;
+mod frame_builder;
+mod frame_serde;
+#[cfg(feature = "arbitrary")]
+pub mod fuzzing;
+mod text;
+pub use frame_builder::FrameBuilder;
+
+/// Largest standard (11 bit) CAN ID.
+pub const MAX_STANDARD_ID: u32 = 0x7FF;
+/// Largest extended (29 bit) CAN ID.
+pub const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+
+// data length for each DLC code of an FD frame, see ISO 11898-1
+const FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Returns the number of data bytes given by a DLC.
+///
+/// DLC codes 9 to 15 stand for 12, 16, 20, 24, 32, 48, and 64 bytes in FD frames,
+/// and for 8 bytes in classic frames.
+pub fn dlc_to_len(dlc: u8, fd: bool) -> usize {
+    if fd {
+        FD_LENGTHS[(dlc & 0xF) as usize]
+    } else {
+        dlc.min(8) as usize
+    }
+}
+
+/// Returns the smallest DLC for `len` bytes of data, saturating at 8 for classic
+/// frames and at 15 (64 bytes) for FD frames.
+pub fn len_to_dlc(len: usize, fd: bool) -> u8 {
+    if fd {
+        FD_LENGTHS.iter().position(|l| *l >= len).unwrap_or(15) as u8
+    } else {
+        len.min(8) as u8
+    }
+}
+
+/// A standard (11 bit) CAN ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StandardId(u16);
+
+impl StandardId {
+    /// The ID 0, which has the highest priority.
+    pub const ZERO: StandardId = StandardId(0);
+    /// The ID 0x7FF, which has the lowest priority.
+    pub const MAX: StandardId = StandardId(MAX_STANDARD_ID as u16);
+
+    /// Returns the ID, or `None` if `raw` is over 0x7FF.
+    pub fn new(raw: u16) -> Option<StandardId> {
+        if raw as u32 <= MAX_STANDARD_ID {
+            Some(StandardId(raw))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the ID as a number.
+    pub fn as_raw(&self) -> u16 {
+        self.0
+    }
+}
+
+/// An extended (29 bit) CAN ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtendedId(u32);
+
+impl ExtendedId {
+    /// The ID 0, which has the highest priority.
+    pub const ZERO: ExtendedId = ExtendedId(0);
+    /// The ID 0x1FFFFFFF, which has the lowest priority.
+    pub const MAX: ExtendedId = ExtendedId(MAX_EXTENDED_ID);
+
+    /// Returns the ID, or `None` if `raw` is over 0x1FFFFFFF.
+    pub fn new(raw: u32) -> Option<ExtendedId> {
+        if raw <= MAX_EXTENDED_ID {
+            Some(ExtendedId(raw))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the ID as a number.
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+/// A standard or extended CAN ID.
+///
+/// Unlike a number and a flag, an `Id` cannot hold an extended value marked as
+/// standard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Id {
+    /// A standard (11 bit) ID.
+    Standard(StandardId),
+    /// An extended (29 bit) ID.
+    Extended(ExtendedId),
+}
+
+impl Id {
+    /// Returns a standard ID if `ext` is false and an extended ID otherwise, or
+    /// `None` if `raw` is out of range for the ID type.
+    pub fn new(raw: u32, ext: bool) -> Option<Id> {
+        if ext {
+            ExtendedId::new(raw).map(Id::Extended)
+        } else {
+            (raw <= MAX_STANDARD_ID).then_some(Id::Standard(StandardId(raw as u16)))
+        }
+    }
+
+    /// Returns the ID given by the low bits of `raw`, ignoring the bits beyond
+    /// the range of the ID type, as devices do.
+    #[cfg(any(test, feature = "arbitrary"))]
+    pub(crate) fn truncate(raw: u32, ext: bool) -> Id {
+        if ext {
+            Id::Extended(ExtendedId(raw & MAX_EXTENDED_ID))
+        } else {
+            Id::Standard(StandardId((raw & MAX_STANDARD_ID) as u16))
+        }
+    }
+
+    /// Returns the ID as a number.
+    pub fn as_raw(&self) -> u32 {
+        match self {
+            Id::Standard(id) => id.as_raw() as u32,
+            Id::Extended(id) => id.as_raw(),
+        }
+    }
+
+    /// Returns true if this is an extended ID.
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Id::Extended(_))
+    }
+}
+
+impl From<StandardId> for Id {
+    fn from(id: StandardId) -> Id {
+        Id::Standard(id)
+    }
+}
+
+impl From<ExtendedId> for Id {
+    fn from(id: ExtendedId) -> Id {
+        Id::Extended(id)
+    }
+}
+
+/// Direction of a frame as seen by the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Frame was received from another node on the bus.
+    Rx,
+    /// Frame was transmitted by this device, and echoed back by the device once
+    /// it was sent on the bus. Contains the echo ID returned by `Interface::send`,
+    /// and the frame's `timestamp` is the time the transmission completed. The
+    /// same echo ID and timestamp are reported in the `TxEvent` of the frame.
+    TxEcho(u32),
+}
+
+/// Error returned when a frame is not valid, or cannot carry the data given for
+/// it. The driver reports it as `Error::InvalidFrame`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameError;
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid frame")
+    }
+}
+
+/// Controller Area Network Frame
+///
+/// Frames compare equal and hash alike when they would look the same on the bus:
+/// the channel, ID, flags, DLC, and the data bytes given by the DLC are compared.
+/// The timestamp and whether the frame was received or echoed (`direction`) are
+/// ignored, as are data bytes beyond the DLC and the data of remote frames.
+///
+/// With serde, frames are represented as a flat record with the data as a hex
+/// string, such as `{"channel":0,"id":291,"ext":false,...,"dlc":4,"data":"DEADBEEF",
+/// "echo_id":null,"timestamp_us":1500}` in JSON.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// CAN frame arbitration ID, standard (11 bit) or extended (29 bit).
+    pub id: Id,
+
+    /// CAN frame Data Length Code (DLC). For FD frames, codes 9 to 15 stand for
+    /// 12, 16, 20, 24, 32, 48, and 64 bytes of data.
+    pub can_dlc: u8,
+
+    /// Device channel used to send or receive the frame.
+    pub channel: u8,
+
+    /// Frame data contents. Classic frames use the first 8 bytes, FD frames up
+    /// to all 64. Bytes beyond the length given by the DLC are not sent.
+    pub data: [u8; 64],
+
+    /// CAN Flexible Data (CAN-FD) frame flag.
+    pub fd: bool,
+
+    /// Bit Rate Switch (BRS) flag of FD frames. When true, the data phase is
+    /// sent at the data bitrate of the channel.
+    pub brs: bool,
+
+    /// Error State Indicator (ESI) flag of FD frames. Set by a sender which is
+    /// error passive.
+    pub esi: bool,
+
+    /// Whether the frame was received from the bus or is an echo of a frame
+    /// sent by this device. Ignored when sending.
+    pub direction: Direction,
+
+    /// Remote Transmission Request (RTR) flag.
+    pub rtr: bool,
+
+    /// Timestamp when frame was received. For echoes of sent frames, this is
+    /// the time the transmission completed. The clock is chosen with
+    /// `Interface::set_timestamp_mode`.
+    pub timestamp: Option<Duration>,
+
+    /// Host frame flag bits of the gs_usb protocol which are not modelled by
+    /// other fields, for experimenting with device firmware. When sending, these
+    /// bits are set in addition to those for `fd`, `brs`, and `esi`. Received
+    /// frames carry the unmodelled bits set by the device, or `None` if there
+    /// are none. Ignored when comparing frames.
+    pub raw_flags: Option<u8>,
+}
+impl Default for Frame {
+    /// Returns a default CAN frame with all values set to zero/false.
+    fn default() -> Frame {
+        Frame {
+            id: Id::Standard(StandardId::ZERO),
+            can_dlc: 0,
+            data: [0u8; 64],
+            channel: 0,
+            fd: false,
+            brs: false,
+            esi: false,
+            direction: Direction::Rx,
+            rtr: false,
+            timestamp: None,
+            raw_flags: None,
+        }
+    }
+}
+
+impl Frame {
+    /// Create a data frame with a standard (11 bit) ID.
+    ///
+    /// Returns `FrameError` if the ID is over 0x7FF or there are more than 8
+    /// bytes of data.
+    pub fn new(id: u32, data: &[u8]) -> Result<Frame, FrameError> {
+        Frame::new_data(id, false, data)
+    }
+
+    /// Create a data frame with an extended (29 bit) ID.
+    ///
+    /// Returns `FrameError` if the ID is over 0x1FFFFFFF or there are more than 8
+    /// bytes of data.
+    pub fn new_ext(id: u32, data: &[u8]) -> Result<Frame, FrameError> {
+        Frame::new_data(id, true, data)
+    }
+
+    /// Create a remote frame requesting `len` bytes of data, with a standard or
+    /// extended ID.
+    ///
+    /// Returns `FrameError` if the ID is out of range for its type or `len` is
+    /// over 8.
+    pub fn new_remote(id: u32, ext: bool, len: usize) -> Result<Frame, FrameError> {
+        let mut f = Frame::with_id(id, ext)?;
+        f.rtr = true;
+        f.set_len(len)?;
+        Ok(f)
+    }
+
+    fn new_data(id: u32, ext: bool, data: &[u8]) -> Result<Frame, FrameError> {
+        let mut f = Frame::with_id(id, ext)?;
+        f.set_len(data.len())?;
+        f.data[..data.len()].copy_from_slice(data);
+        Ok(f)
+    }
+
+    fn with_id(id: u32, ext: bool) -> Result<Frame, FrameError> {
+        Ok(Frame {
+            id: Id::new(id, ext).ok_or(FrameError)?,
+            ..Default::default()
+        })
+    }
+
+    /// Returns a builder for a frame. Without other settings, it builds an empty
+    /// classic data frame with standard ID 0 on channel 0.
+    pub fn builder() -> FrameBuilder {
+        FrameBuilder::default()
+    }
+
+    /// Check that the frame can be sent on a CAN bus.
+    ///
+    /// Returns `FrameError` for classic frames with a DLC over 8, FD frames with
+    /// a DLC over 15, and remote FD frames, which do not exist. The BRS and ESI
+    /// flags are only allowed on FD frames.
+    pub fn validate(&self) -> Result<(), FrameError> {
+        let max_dlc = if self.fd { 15 } else { 8 };
+        if self.can_dlc > max_dlc || (self.fd && self.rtr) || (!self.fd && (self.brs || self.esi)) {
+            return Err(FrameError);
+        }
+        Ok(())
+    }
+
+    /// Set the DLC for `len` bytes of data.
+    ///
+    /// FD frames can only carry the lengths listed for `Frame::len`, other lengths
+    /// are rounded up to the next one and the padding bytes are set to zero.
+    /// Returns `FrameError` if the frame cannot carry `len` bytes, which is over
+    /// 8 bytes for classic frames and over 64 bytes for FD frames.
+    pub fn set_len(&mut self, len: usize) -> Result<(), FrameError> {
+        if len > if self.fd { 64 } else { 8 } {
+            return Err(FrameError);
+        }
+        self.can_dlc = len_to_dlc(len, self.fd);
+        let padded = self.len();
+        self.data[len..padded].fill(0);
+        Ok(())
+    }
+
+    /// Returns the number of data bytes given by the DLC.
+    ///
+    /// DLC codes 9 to 15 stand for 12, 16, 20, 24, 32, 48, and 64 bytes in FD
+    /// frames, and for 8 bytes in classic frames. For remote frames, this is the
+    /// length of the requested data.
+    pub fn len(&self) -> usize {
+        dlc_to_len(self.can_dlc, self.fd)
+    }
+
+    /// Returns true if the DLC is zero.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the data bytes given by the DLC.
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..self.len()]
+    }
+
+    /// Returns the ID as a number, like `Id::as_raw`.
+    pub fn raw_id(&self) -> u32 {
+        self.id.as_raw()
+    }
+
+    /// Returns true if the frame has an extended ID.
+    pub fn is_extended(&self) -> bool {
+        self.id.is_extended()
+    }
+
+    /// Returns true if this is an echo of a frame sent by this device, in
+    /// hardware loopback mode or not.
+    pub fn is_echo(&self) -> bool {
+        self.echo_id().is_some()
+    }
+
+    /// Returns the echo ID if this is an echo of a frame sent by this device.
+    pub fn echo_id(&self) -> Option<u32> {
+        match self.direction {
+            Direction::Rx => None,
+            Direction::TxEcho(id) => Some(id),
+        }
+    }
+
+    // the fields compared by PartialEq and Hash
+    fn key(&self) -> (u8, Id, [bool; 4], u8, &[u8]) {
+        let data = if self.rtr { &[][..] } else { self.payload() };
+        (
+            self.channel,
+            self.id,
+            [self.rtr, self.fd, self.brs, self.esi],
+            self.can_dlc,
+            data,
+        )
+    }
+}
+impl PartialEq for Frame {
+    fn eq(&self, other: &Frame) -> bool {
+        self.key() == other.key()
+    }
+}
+impl Eq for Frame {}
+impl Hash for Frame {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+/// Bit timing of a CAN channel, in time quanta of the CAN clock divided by the
+/// prescaler. A bit is one quantum of synchronization segment followed by the
+/// propagation and phase segments, and is sampled at the end of `phase_seg1`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitTiming {
+    /// Propagation segment, in time quanta.
+    pub prop_seg: u32,
+    /// Phase segment 1, in time quanta.
+    pub phase_seg1: u32,
+    /// Phase segment 2, in time quanta.
+    pub phase_seg2: u32,
+    /// Synchronization jump width, in time quanta.
+    pub sjw: u32,
+    /// Bitrate prescaler.
+    pub brp: u32,
+}
+
+impl BitTiming {
+    /// Returns the number of time quanta in a bit.
+    pub fn quanta(&self) -> u32 {
+        1 + self.prop_seg + self.phase_seg1 + self.phase_seg2
+    }
+
+    /// Returns the bitrate in bits/second with a CAN clock of `clock` Hz.
+    pub fn bitrate(&self, clock: u32) -> u32 {
+        clock / self.brp.max(1) / self.quanta()
+    }
+
+    /// Returns the sample point as a fraction of the bit time.
+    pub fn sample_point(&self) -> f32 {
+        (1 + self.prop_seg + self.phase_seg1) as f32 / self.quanta() as f32
+    }
+}
+
+/// Ranges of bit timing values accepted by the CAN controller of a channel, as
+/// reported by the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitTimingLimits {
+    /// Shortest propagation segment plus phase segment 1, in time quanta.
+    pub tseg1_min: u32,
+    /// Longest propagation segment plus phase segment 1, in time quanta.
+    pub tseg1_max: u32,
+    /// Shortest phase segment 2, in time quanta.
+    pub tseg2_min: u32,
+    /// Longest phase segment 2, in time quanta.
+    pub tseg2_max: u32,
+    /// Longest synchronization jump width, in time quanta.
+    pub sjw_max: u32,
+    /// Smallest bitrate prescaler.
+    pub brp_min: u32,
+    /// Largest bitrate prescaler.
+    pub brp_max: u32,
+    /// Step between prescaler values.
+    pub brp_inc: u32,
+}
+
+impl Default for BitTimingLimits {
+    /// Limits of the original CANtact controller.
+    fn default() -> BitTimingLimits {
+        BitTimingLimits {
+            tseg1_min: 3,
+            tseg1_max: 17,
+            tseg2_min: 2,
+            tseg2_max: 8,
+            sjw_max: 4,
+            brp_min: 1,
+            brp_max: 32,
+            brp_inc: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dlc() {
+        for len in 0..=64 {
+            let dlc = len_to_dlc(len, true);
+            assert!(dlc_to_len(dlc, true) >= len);
+            assert!(dlc == 0 || dlc_to_len(dlc - 1, true) < len);
+        }
+        assert_eq!(len_to_dlc(12, false), 8);
+        assert_eq!(dlc_to_len(15, false), 8);
+
+        let bt = BitTiming {
+            prop_seg: 0,
+            phase_seg1: 13,
+            phase_seg2: 2,
+            sjw: 1,
+            brp: 3,
+        };
+        assert_eq!(bt.bitrate(24_000_000), 500_000);
+        assert_eq!(bt.sample_point(), 0.875);
+    }
+
+    #[test]
+    fn test_id() {
+        assert!(StandardId::new(0x800).is_none());
+        assert!(ExtendedId::new(0x2000_0000).is_none());
+
+        assert_eq!(Id::new(0x7FF, false), Some(StandardId::MAX.into()));
+        assert_eq!(Id::new(0x800, false), None);
+        assert_eq!(Id::new(0x800, true), Some(Id::Extended(ExtendedId(0x800))));
+        assert_eq!(Id::truncate(0x3FFF_FFFF, true), ExtendedId::MAX.into());
+
+        let f = Frame {
+            id: ExtendedId::new(0x1ABC_DEF0).unwrap().into(),
+            ..Default::default()
+        };
+        assert_eq!((f.raw_id(), f.is_extended()), (0x1ABC_DEF0, true));
+        // the same number as a standard ID is a different frame
+        let mut g = f.clone();
+        g.id = Id::truncate(0x1ABC_DEF0, false);
+        assert_eq!((g.raw_id(), g.is_extended()), (0x6F0, false));
+        assert_ne!(f, g);
+    }
+
+    #[test]
+    fn test_frame_len() {
+        let mut f = Frame {
+            fd: true,
+            ..Default::default()
+        };
+        for (len, dlc, padded) in [
+            (8, 8, 8),
+            (12, 9, 12),
+            (13, 10, 16),
+            (33, 14, 48),
+            (64, 15, 64),
+        ] {
+            f.data = [0xFF; 64];
+            f.set_len(len).unwrap();
+            assert_eq!((f.can_dlc, f.len()), (dlc, padded));
+            assert!(f.data[len..padded].iter().all(|b| *b == 0));
+        }
+        assert!(f.set_len(65).is_err());
+
+        // classic frames treat DLC 9 to 15 as 8 bytes
+        f.fd = false;
+        f.can_dlc = 15;
+        assert_eq!(f.len(), 8);
+        assert!(f.set_len(9).is_err());
+    }
+
+    #[test]
+    fn test_frame_constructors() {
+        let f = Frame::new(0x123, &[1, 2, 3]).unwrap();
+        assert_eq!((f.raw_id(), f.is_extended(), f.can_dlc), (0x123, false, 3));
+        assert_eq!(f.payload(), [1, 2, 3]);
+        assert!(Frame::new(0x800, &[]).is_err());
+        assert!(Frame::new(0x123, &[0; 9]).is_err());
+
+        let f = Frame::new_ext(0x1ABC_DEF0, &[]).unwrap();
+        assert!(f.is_extended() && f.is_empty());
+        assert!(Frame::new_ext(0x2000_0000, &[]).is_err());
+
+        let f = Frame::new_remote(0x7FF, false, 8).unwrap();
+        assert!(f.rtr);
+        assert_eq!(f.can_dlc, 8);
+        assert!(Frame::new_remote(0x7FF, false, 9).is_err());
+    }
+
+    #[test]
+    fn test_frame_eq() {
+        use std::collections::HashSet;
+
+        let a = Frame::new(0x123, &[1, 2]).unwrap();
+        let mut b = a.clone();
+        b.timestamp = Some(Duration::from_secs(1));
+        b.direction = Direction::TxEcho(3);
+        b.data[5] = 0xFF;
+        assert_eq!(a, b);
+
+        let mut c = a.clone();
+        c.data[1] = 3;
+        assert_ne!(a, c);
+
+        let set: HashSet<Frame> = vec![a, b, c].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+}
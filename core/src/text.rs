@@ -0,0 +1,142 @@
+//! Frames as text, in the format of a line of `candump -l` from can-utils.
+
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+
+use crate::{Direction, Frame, FrameError, Id};
+
+impl fmt::Display for Frame {
+    /// Formats the frame like candump: `(1629730123.456789) can0 123#DEADBEEF`,
+    /// or `can0 123#DEADBEEF` if the frame has no timestamp.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(t) = self.timestamp {
+            write!(f, "({}.{:06}) ", t.as_secs(), t.subsec_micros())?;
+        }
+        write!(f, "can{} ", self.channel)?;
+        if self.is_extended() {
+            write!(f, "{:08X}", self.raw_id())?;
+        } else {
+            write!(f, "{:03X}", self.raw_id())?;
+        }
+        if self.fd {
+            // FD flags nibble, as in can-utils
+            let flags = (self.brs as u8) | (self.esi as u8) << 1;
+            write!(f, "##{:X}", flags)?;
+        } else {
+            write!(f, "#")?;
+        }
+        if self.rtr {
+            write!(f, "R")?;
+        } else {
+            for b in self.payload() {
+                write!(f, "{:02X}", b)?;
+            }
+        }
+        if let Direction::TxEcho(_) = self.direction {
+            write!(f, " T")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Frame {
+    type Err = FrameError;
+
+    /// Parses a line of a candump log file, as written by `Display`. The
+    /// timestamp is optional, and may have fewer than 6 decimal places.
+    ///
+    /// The interface name must end with the channel number, as in `can0` or
+    /// `vcan1`. Returns `FrameError` if the text is not a valid log entry.
+    fn from_str(line: &str) -> Result<Frame, FrameError> {
+        parse(line).ok_or(FrameError)
+    }
+}
+
+fn parse(line: &str) -> Option<Frame> {
+    let mut parts = line.split_whitespace();
+    let mut f = Frame::default();
+
+    let mut part = parts.next()?;
+    if let Some(t) = part.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        let (secs, frac) = t.split_at(t.find('.')?);
+        let frac = &frac[1..];
+        if frac.is_empty() || frac.len() > 9 || !frac.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let nanos = frac.parse::<u32>().ok()? * 10u32.pow(9 - frac.len() as u32);
+        f.timestamp = Some(Duration::new(secs.parse().ok()?, nanos));
+        part = parts.next()?;
+    }
+
+    let digits = part.len() - part.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    f.channel = part[part.len() - digits..].parse().ok()?;
+
+    let frame = parts.next()?;
+    let (id, rest) = frame.split_at(frame.find('#')?);
+    let ext = match id.len() {
+        3 => false,
+        8 => true,
+        _ => return None,
+    };
+    f.id = Id::new(u32::from_str_radix(id, 16).ok()?, ext)?;
+
+    let mut data = &rest[1..];
+    if let Some(fd) = data.strip_prefix('#') {
+        // FD flags nibble
+        let flags = u8::from_str_radix(fd.get(..1)?, 16).ok()?;
+        f.fd = true;
+        f.brs = flags & 1 != 0;
+        f.esi = flags & 2 != 0;
+        data = &fd[1..];
+    }
+    if let Some(dlc) = data.strip_prefix('R') {
+        f.rtr = true;
+        f.can_dlc = match dlc {
+            "" => 0,
+            d => d.parse().ok().filter(|d| *d <= 8)?,
+        };
+    } else {
+        if !data.is_ascii() || !data.len().is_multiple_of(2) || data.len() / 2 > f.data.len() {
+            return None;
+        }
+        for (i, b) in f.data.iter_mut().take(data.len() / 2).enumerate() {
+            *b = u8::from_str_radix(&data[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        f.set_len(data.len() / 2).ok()?;
+        if f.len() != data.len() / 2 {
+            // not a valid FD length
+            return None;
+        }
+    }
+
+    match parts.next() {
+        None | Some("R") => {}
+        Some("T") => {
+            // the echo ID is not logged
+            f.direction = Direction::TxEcho(0);
+        }
+        Some(_) => return None,
+    }
+    Some(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_text() {
+        let f: Frame = "can1 123#DEADBEEF".parse().unwrap();
+        let mut expected = Frame::new(0x123, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        expected.channel = 1;
+        assert_eq!(f, expected);
+        assert_eq!(f.to_string(), "can1 123#DEADBEEF");
+
+        let f: Frame = "(1629730123.456) can0 1ABCDEF0#R".parse().unwrap();
+        assert_eq!(f.timestamp, Some(Duration::from_millis(1_629_730_123_456)));
+        assert_eq!(f.to_string(), "(1629730123.456000) can0 1ABCDEF0#R");
+
+        assert!("can0 123#ABC".parse::<Frame>().is_err());
+    }
+}
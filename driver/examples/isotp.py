@@ -0,0 +1,24 @@
+#!/usr/bin/env python
+# coding: utf-8
+
+"""
+Python CANtact ISO-TP example.
+
+Sends a UDS TesterPresent request using the driver's ISO-TP implementation
+and prints the response.
+"""
+
+import cantact
+
+intf = cantact.Interface()
+intf.set_bitrate(0, 500000)
+intf.set_enabled(0, True)
+intf.start()
+
+# send on 0x7E0, receive on 0x7E8
+tp = intf.isotp(0x7E0, 0x7E8)
+try:
+    resp = tp.request(bytes([0x3E, 0x00]), 1000)
+    print(resp.hex())
+finally:
+    intf.stop()
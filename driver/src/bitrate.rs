@@ -0,0 +1,105 @@
+//! Named bitrate presets.
+
+use serde::{Deserialize, Serialize};
+
+/// Commonly used bitrate configurations, for use with `Interface::set_bitrate_preset`.
+///
+/// Classic CAN presets use the sample points recommended by CiA 301. CAN FD
+/// presets (`Fd*`) configure both the nominal (arbitration) and data phase, and
+/// require a device which supports CAN FD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bitrate {
+    /// 10 kbit/s
+    K10,
+    /// 20 kbit/s
+    K20,
+    /// 50 kbit/s
+    K50,
+    /// 100 kbit/s
+    K100,
+    /// 125 kbit/s
+    K125,
+    /// 250 kbit/s
+    K250,
+    /// 500 kbit/s
+    K500,
+    /// 800 kbit/s
+    K800,
+    /// 1 Mbit/s
+    M1,
+    /// CAN FD, 500 kbit/s arbitration and 1 Mbit/s data phase
+    Fd500k1M,
+    /// CAN FD, 500 kbit/s arbitration and 2 Mbit/s data phase
+    Fd500k2M,
+    /// CAN FD, 500 kbit/s arbitration and 4 Mbit/s data phase
+    Fd500k4M,
+    /// CAN FD, 500 kbit/s arbitration and 5 Mbit/s data phase
+    Fd500k5M,
+    /// CAN FD, 500 kbit/s arbitration and 8 Mbit/s data phase
+    Fd500k8M,
+    /// CAN FD, 1 Mbit/s arbitration and 4 Mbit/s data phase
+    Fd1M4M,
+    /// CAN FD, 1 Mbit/s arbitration and 8 Mbit/s data phase
+    Fd1M8M,
+}
+
+// sample points for CAN FD, following CiA 601-3
+const FD_NOMINAL_SAMPLE_POINT: f32 = 0.8;
+pub(crate) const FD_DATA_SAMPLE_POINT: f32 = 0.75;
+
+impl Bitrate {
+    /// Returns the nominal (arbitration phase) bitrate in bits/second.
+    pub fn nominal_bitrate(&self) -> u32 {
+        match self {
+            Bitrate::K10 => 10_000,
+            Bitrate::K20 => 20_000,
+            Bitrate::K50 => 50_000,
+            Bitrate::K100 => 100_000,
+            Bitrate::K125 => 125_000,
+            Bitrate::K250 => 250_000,
+            Bitrate::K500 => 500_000,
+            Bitrate::K800 => 800_000,
+            Bitrate::M1 => 1_000_000,
+            Bitrate::Fd500k1M
+            | Bitrate::Fd500k2M
+            | Bitrate::Fd500k4M
+            | Bitrate::Fd500k5M
+            | Bitrate::Fd500k8M => 500_000,
+            Bitrate::Fd1M4M | Bitrate::Fd1M8M => 1_000_000,
+        }
+    }
+
+    /// Returns the nominal sample point, as a fraction of the bit time.
+    pub fn sample_point(&self) -> f32 {
+        if self.is_fd() {
+            return FD_NOMINAL_SAMPLE_POINT;
+        }
+        match self.nominal_bitrate() {
+            1_000_000 => 0.75,
+            800_000 => 0.8,
+            _ => 0.875,
+        }
+    }
+
+    /// Returns the data phase bitrate in bits/second for CAN FD presets.
+    pub fn data_bitrate(&self) -> Option<u32> {
+        match self {
+            Bitrate::Fd500k1M => Some(1_000_000),
+            Bitrate::Fd500k2M => Some(2_000_000),
+            Bitrate::Fd500k4M | Bitrate::Fd1M4M => Some(4_000_000),
+            Bitrate::Fd500k5M => Some(5_000_000),
+            Bitrate::Fd500k8M | Bitrate::Fd1M8M => Some(8_000_000),
+            _ => None,
+        }
+    }
+
+    /// Returns the data phase sample point for CAN FD presets.
+    pub fn data_sample_point(&self) -> Option<f32> {
+        self.data_bitrate().map(|_| FD_DATA_SAMPLE_POINT)
+    }
+
+    /// Returns true if this is a CAN FD preset.
+    pub fn is_fd(&self) -> bool {
+        self.data_bitrate().is_some()
+    }
+}
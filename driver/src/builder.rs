@@ -0,0 +1,323 @@
+//! Opening and configuring an interface in one step.
+
+use crate::bitrate::FD_DATA_SAMPLE_POINT;
+use crate::device::Selector;
+use crate::{
+    calculate_bit_timing, calculate_bit_timing_with_sample_point, BitTimingLimits, Capabilities,
+    Channel, Error, Interface, OverflowPolicy, TimestampMode,
+};
+
+/// Configuration of one channel, passed to `InterfaceBuilder::channel`. The
+/// default is a classic CAN channel at 500 kbit/s.
+///
+/// ```
+/// # use cantact::ChannelConfig;
+/// let config = ChannelConfig {
+///     bitrate: 500_000,
+///     fd: true,
+///     data_bitrate: 2_000_000,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelConfig {
+    /// Bitrate in bits/second.
+    pub bitrate: u32,
+    /// When true, the channel is started in CAN FD mode.
+    pub fd: bool,
+    /// Bitrate of the CAN FD data phase in bits/second. If 0, the data phase
+    /// uses `bitrate`.
+    pub data_bitrate: u32,
+    /// When true, the channel will not transmit on the bus.
+    pub monitor: bool,
+    /// When true, the channel is in hardware loopback mode.
+    pub loopback: bool,
+    /// When true, frames are not retransmitted if arbitration is lost or no
+    /// acknowledgement is received.
+    pub one_shot: bool,
+    /// When true, the controller samples each bit three times.
+    pub triple_sample: bool,
+    /// Switch the termination resistor on or off, or leave it as it is if None.
+    pub termination: Option<bool>,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> ChannelConfig {
+        ChannelConfig {
+            bitrate: 500_000,
+            fd: false,
+            data_bitrate: 0,
+            monitor: false,
+            loopback: false,
+            one_shot: false,
+            triple_sample: false,
+            termination: None,
+        }
+    }
+}
+
+impl From<&Channel> for ChannelConfig {
+    fn from(ch: &Channel) -> ChannelConfig {
+        ChannelConfig {
+            bitrate: ch.bitrate,
+            fd: ch.fd,
+            data_bitrate: ch.data_bitrate,
+            monitor: ch.monitor,
+            loopback: ch.loopback,
+            one_shot: ch.one_shot,
+            triple_sample: ch.triple_sample,
+            termination: None,
+        }
+    }
+}
+
+impl ChannelConfig {
+    // check that the channel can be configured this way, before anything is
+    // written to the device
+    pub(crate) fn validate(
+        &self,
+        caps: Capabilities,
+        clock: u32,
+        limits: &BitTimingLimits,
+    ) -> Result<(), Error> {
+        if (self.fd && !caps.fd)
+            || (self.triple_sample && !caps.triple_sample)
+            || (self.termination.is_some() && !caps.termination)
+        {
+            return Err(Error::Unsupported);
+        }
+        calculate_bit_timing(clock, self.bitrate, 1, limits)?;
+        if self.fd {
+            calculate_bit_timing_with_sample_point(
+                clock,
+                self.data_bitrate(),
+                FD_DATA_SAMPLE_POINT,
+                1,
+                limits,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn data_bitrate(&self) -> u32 {
+        if self.data_bitrate == 0 {
+            self.bitrate
+        } else {
+            self.data_bitrate
+        }
+    }
+}
+
+// device to open, owning the serial number
+#[derive(Debug, Clone)]
+enum Select {
+    Available,
+    Index(usize),
+    Serial(String),
+    UserId(u32),
+}
+
+/// Opens and configures an `Interface` in one step, returned by
+/// `Interface::builder`.
+///
+/// ```no_run
+/// # use cantact::{ChannelConfig, Interface};
+/// let mut i = Interface::builder()
+///     .serial("0123456789")
+///     .channel(0, ChannelConfig { bitrate: 500_000, ..Default::default() })
+///     .rx_queue(4096)
+///     .build()?;
+/// # Ok::<(), cantact::Error>(())
+/// ```
+///
+/// The whole configuration is checked against the device before any of it is
+/// applied, so `InterfaceBuilder::build` either returns an interface ready to
+/// start or an error. Channels without a configuration are disabled.
+#[derive(Debug, Clone)]
+pub struct InterfaceBuilder {
+    select: Select,
+    usb_ids: Vec<(u16, u16)>,
+    channels: Vec<(usize, ChannelConfig)>,
+    rx_capacity: Option<usize>,
+    rx_policy: OverflowPolicy,
+    timestamp_mode: Option<TimestampMode>,
+    auto_reconnect: bool,
+}
+
+impl Interface {
+    /// Returns a builder which opens the first available device and configures
+    /// it, see `InterfaceBuilder`.
+    pub fn builder() -> InterfaceBuilder {
+        InterfaceBuilder {
+            select: Select::Available,
+            usb_ids: vec![],
+            channels: vec![],
+            rx_capacity: None,
+            rx_policy: OverflowPolicy::Block,
+            timestamp_mode: None,
+            auto_reconnect: false,
+        }
+    }
+
+    // apply the configuration of a channel which passed `ChannelConfig::validate`
+    pub(crate) fn configure_channel(
+        &mut self,
+        ch: usize,
+        config: &ChannelConfig,
+    ) -> Result<(), Error> {
+        self.set_bitrate(ch, config.bitrate)?;
+        if config.fd {
+            self.set_data_bitrate(ch, config.data_bitrate())?;
+        } else {
+            self.channels[ch].fd = false;
+            self.channels[ch].data_bitrate = 0;
+            self.timings.lock().unwrap()[ch].data = None;
+        }
+        self.set_monitor(ch, config.monitor)?;
+        self.set_loopback(ch, config.loopback)?;
+        self.set_one_shot(ch, config.one_shot)?;
+        self.set_triple_sample(ch, config.triple_sample)?;
+        if let Some(enabled) = config.termination {
+            self.set_termination(ch, enabled)?;
+        }
+        Ok(())
+    }
+}
+
+impl InterfaceBuilder {
+    /// Open the device with this USB serial number, see `Interface::open_serial`.
+    pub fn serial(mut self, serial: &str) -> InterfaceBuilder {
+        self.select = Select::Serial(serial.to_string());
+        self
+    }
+
+    /// Open the `index`th device found, see `Interface::open_index`.
+    pub fn index(mut self, index: usize) -> InterfaceBuilder {
+        self.select = Select::Index(index);
+        self
+    }
+
+    /// Open the device with this user ID, see `Interface::open_user_id`.
+    pub fn user_id(mut self, id: u32) -> InterfaceBuilder {
+        self.select = Select::UserId(id);
+        self
+    }
+
+    /// Also look for devices with this USB vendor and product ID, see
+    /// `Interface::open_usb_ids`.
+    pub fn usb_id(mut self, vendor: u16, product: u16) -> InterfaceBuilder {
+        self.usb_ids.push((vendor, product));
+        self
+    }
+
+    /// Enable a channel with the given configuration, replacing any earlier
+    /// configuration of the channel.
+    pub fn channel(mut self, channel: usize, config: ChannelConfig) -> InterfaceBuilder {
+        self.channels.retain(|(ch, _)| *ch != channel);
+        self.channels.push((channel, config));
+        self
+    }
+
+    /// Limit the queues of received frames to `capacity` frames, see
+    /// `Interface::set_rx_queue`.
+    pub fn rx_queue(mut self, capacity: usize) -> InterfaceBuilder {
+        self.rx_capacity = Some(capacity);
+        self
+    }
+
+    /// Set what happens to frames received while a queue is full, see
+    /// `Interface::set_rx_queue`.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> InterfaceBuilder {
+        self.rx_policy = policy;
+        self
+    }
+
+    /// Set the clock used to timestamp frames, see
+    /// `Interface::set_timestamp_mode`.
+    pub fn timestamp_mode(mut self, mode: TimestampMode) -> InterfaceBuilder {
+        self.timestamp_mode = Some(mode);
+        self
+    }
+
+    /// Reconnect when the device is unplugged while running, see
+    /// `Interface::set_auto_reconnect`.
+    pub fn auto_reconnect(mut self, enabled: bool) -> InterfaceBuilder {
+        self.auto_reconnect = enabled;
+        self
+    }
+
+    /// Open the device and apply the configuration.
+    ///
+    /// Returns `Error::DeviceNotFound` if there is no matching device,
+    /// `Error::InvalidChannel` for a channel the device doesn't have,
+    /// `Error::Unsupported` for a feature a channel doesn't support, and
+    /// `Error::InvalidBitrate` for a bitrate which can't be configured.
+    pub fn build(self) -> Result<Interface, Error> {
+        let sel = match &self.select {
+            Select::Available => Selector::Available,
+            Select::Index(i) => Selector::Index(*i),
+            Select::Serial(s) => Selector::Serial(s),
+            Select::UserId(id) => Selector::UserId(*id),
+        };
+        let mut i = Interface::open(sel, &self.usb_ids)?;
+
+        for (ch, config) in self.channels.iter() {
+            let caps = i.capabilities(*ch)?;
+            config.validate(caps, i.can_clock, &i.limits[*ch])?;
+        }
+        let hw_timestamp = (0..i.channels()).all(|ch| i.features(ch).hw_timestamp);
+        if self.timestamp_mode == Some(TimestampMode::Hardware) && !hw_timestamp {
+            return Err(Error::Unsupported);
+        }
+
+        for ch in 0..i.channels() {
+            i.set_enabled(ch, false)?;
+        }
+        for (ch, config) in self.channels.iter() {
+            i.set_enabled(*ch, true)?;
+            i.configure_channel(*ch, config)?;
+        }
+        i.set_rx_queue(self.rx_capacity, self.rx_policy)?;
+        if let Some(mode) = self.timestamp_mode {
+            i.set_timestamp_mode(mode)?;
+        }
+        i.set_auto_reconnect(self.auto_reconnect);
+        Ok(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let clock = 48_000_000;
+        let limits = BitTimingLimits::default();
+        let caps = Capabilities::default();
+        assert!(ChannelConfig::default()
+            .validate(caps, clock, &limits)
+            .is_ok());
+
+        let fd = ChannelConfig {
+            fd: true,
+            data_bitrate: 2_000_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            fd.validate(caps, clock, &limits),
+            Err(Error::Unsupported)
+        ));
+        let caps = Capabilities { fd: true, ..caps };
+        assert!(fd.validate(caps, clock, &limits).is_ok());
+
+        let slow = ChannelConfig {
+            bitrate: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            slow.validate(caps, clock, &limits),
+            Err(Error::InvalidBitrate(1))
+        ));
+    }
+}
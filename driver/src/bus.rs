@@ -0,0 +1,191 @@
+//! Message level access to a channel using a database.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::periodic;
+use crate::{Database, Error, Frame, Interface, SubscriptionHandle, TaskHandle};
+
+// all bits of an extended ID
+const ID_MASK: u32 = 0x1FFF_FFFF;
+
+type Hook = Arc<Mutex<periodic::Hook>>;
+
+/// A channel of an interface combined with a message database, for sending and
+/// receiving messages by name instead of as raw frames.
+pub struct Bus {
+    i: Interface,
+    channel: usize,
+    db: Arc<Database>,
+    periodic: HashMap<String, TaskHandle>,
+    // last frame sent for each message, holding the signal values to keep
+    frames: HashMap<String, Frame>,
+    hooks: HashMap<String, Hook>,
+}
+
+impl Bus {
+    /// Use `channel` of an interface with the messages defined in `db`.
+    pub fn new(i: Interface, channel: usize, db: Database) -> Result<Bus, Error> {
+        if channel >= i.channels() {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(Bus {
+            i,
+            channel,
+            db: Arc::new(db),
+            periodic: HashMap::new(),
+            frames: HashMap::new(),
+            hooks: HashMap::new(),
+        })
+    }
+
+    /// Start the interface. Received messages are delivered to the callbacks
+    /// registered with `Bus::on_message`.
+    pub fn start(&mut self) -> Result<(), Error> {
+        self.i.start(|_| {})
+    }
+
+    /// Stop the interface.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.i.stop()
+    }
+
+    /// Returns the underlying interface, for configuration and raw frame access.
+    pub fn interface(&mut self) -> &mut Interface {
+        &mut self.i
+    }
+
+    /// Returns the message database.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Send a message once with the given signal values. Signals which are not
+    /// given are sent with a raw value of zero.
+    ///
+    /// Returns the echo ID assigned to the frame, see `Interface::send`.
+    pub fn send_message(&mut self, name: &str, signals: &[(&str, f64)]) -> Result<u32, Error> {
+        let f = self.encode(name, signals)?;
+        self.send_frame(name, f)
+    }
+
+    /// Send a message every `interval` with the given signal values. If the message
+    /// is already being sent periodically, its signal values are replaced and its
+    /// schedule is kept.
+    pub fn send_message_periodic(
+        &mut self,
+        name: &str,
+        signals: &[(&str, f64)],
+        interval: Duration,
+    ) -> Result<(), Error> {
+        let f = self.encode(name, signals)?;
+        match self.periodic.get(name) {
+            Some(&handle) => self.i.update_periodic(handle, f),
+            None => {
+                let handle = self.i.send_periodic(f, interval)?;
+                self.periodic.insert(name.to_string(), handle);
+                self.set_periodic_hook(name);
+                Ok(())
+            }
+        }
+    }
+
+    /// Set the value of a single signal, keeping the last values sent for the other
+    /// signals of its message.
+    ///
+    /// If the message is sent periodically, the next transmissions carry the new
+    /// value. Otherwise the message is sent once. If several messages have a signal
+    /// with this name, the first one in the database is used.
+    pub fn set_signal(&mut self, signal: &str, value: f64) -> Result<(), Error> {
+        let db = Arc::clone(&self.db);
+        let m = db.signal_owner(signal)?;
+        let mut f = match self.frames.get(&m.name) {
+            Some(f) => f.clone(),
+            None => self.encode(&m.name, &[])?,
+        };
+        m.signal(signal)?.encode(&mut f.data, value);
+        self.frames.insert(m.name.clone(), f.clone());
+        match self.periodic.get(&m.name) {
+            Some(&handle) => self.i.update_periodic(handle, f),
+            None => self.send_frame(&m.name, f).map(|_| ()),
+        }
+    }
+
+    /// Call `hook` with the frame of a message before every time it is sent, for
+    /// example to update a rolling counter or checksum.
+    ///
+    /// For periodic messages the hook is called from the driver's timing thread, and
+    /// should return quickly.
+    pub fn on_transmit(
+        &mut self,
+        name: &str,
+        hook: impl FnMut(&mut Frame) + Send + 'static,
+    ) -> Result<(), Error> {
+        self.db.message(name)?;
+        self.hooks
+            .insert(name.to_string(), Arc::new(Mutex::new(Box::new(hook))));
+        self.set_periodic_hook(name);
+        Ok(())
+    }
+
+    /// Stop sending a message started with `Bus::send_message_periodic`.
+    pub fn stop_message(&mut self, name: &str) {
+        if let Some(handle) = self.periodic.remove(name) {
+            self.i.stop_periodic(handle);
+        }
+    }
+
+    /// Call `callback` with the decoded signal values every time the message is
+    /// received from another node.
+    ///
+    /// The callback is called from the receive thread. Remove it with
+    /// `Bus::unsubscribe`.
+    pub fn on_message(
+        &mut self,
+        name: &str,
+        mut callback: impl FnMut(HashMap<String, f64>) + Send + 'static,
+    ) -> Result<SubscriptionHandle, Error> {
+        let id = self.db.message(name)?.id;
+        let db = Arc::clone(&self.db);
+        let name = name.to_string();
+        Ok(self
+            .i
+            .subscribe_channel(self.channel, id, ID_MASK, move |f| {
+                // the subscription filter ignores the ID type
+                if let Ok(m) = db.message(&name) {
+                    if m.matches(&f) {
+                        callback(m.decode(&f));
+                    }
+                }
+            }))
+    }
+
+    /// Remove a callback added with `Bus::on_message`.
+    pub fn unsubscribe(&mut self, handle: SubscriptionHandle) {
+        self.i.unsubscribe(handle);
+    }
+
+    // build a frame for a message and remember it as the message's last values
+    fn encode(&mut self, name: &str, signals: &[(&str, f64)]) -> Result<Frame, Error> {
+        let mut f = self.db.message(name)?.encode(signals)?;
+        f.channel = self.channel as u8;
+        self.frames.insert(name.to_string(), f.clone());
+        Ok(f)
+    }
+
+    fn send_frame(&mut self, name: &str, mut f: Frame) -> Result<u32, Error> {
+        if let Some(hook) = self.hooks.get(name) {
+            (hook.lock().unwrap())(&mut f);
+        }
+        self.i.send(f)
+    }
+
+    fn set_periodic_hook(&mut self, name: &str) {
+        if let (Some(&handle), Some(hook)) = (self.periodic.get(name), self.hooks.get(name)) {
+            let hook = Arc::clone(hook);
+            self.i
+                .set_periodic_hook(handle, move |f| (hook.lock().unwrap())(f));
+        }
+    }
+}
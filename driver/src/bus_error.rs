@@ -0,0 +1,231 @@
+//! Decoding of error frames reported by the device.
+
+use crate::device::gsusb::*;
+use crate::device::HostFrame;
+
+/// State of a channel's CAN controller, as defined by ISO 11898-1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BusState {
+    /// Normal operation, both error counters are below 96.
+    ErrorActive,
+    /// One of the error counters has reached 96.
+    ErrorWarning,
+    /// One of the error counters has reached 128. The controller no longer
+    /// sends active error flags.
+    ErrorPassive,
+    /// The transmit error counter has exceeded 255 and the controller has
+    /// disconnected from the bus.
+    BusOff,
+}
+
+/// A single problem reported in an error frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BusErrorKind {
+    /// A transmission timed out.
+    TxTimeout,
+    /// Arbitration was lost.
+    LostArbitration,
+    /// A transmitted frame was not acknowledged by any node.
+    NoAck,
+    /// The controller went bus-off.
+    BusOff,
+    /// The controller was restarted after bus-off.
+    Restarted,
+    /// The controller's receive buffer overflowed.
+    RxOverflow,
+    /// The controller's transmit buffer overflowed.
+    TxOverflow,
+    /// Bit error, the bit read back differed from the bit sent.
+    Bit,
+    /// Unable to send a dominant bit.
+    Bit0,
+    /// Unable to send a recessive bit, the bus may be stuck dominant.
+    Bit1,
+    /// Form error, a fixed-form field contained an illegal value.
+    Form,
+    /// Stuff error, more than five consecutive bits of equal value.
+    Stuff,
+    /// CRC error.
+    Crc,
+    /// Overload condition.
+    Overload,
+    /// Transceiver problem, such as a wiring fault.
+    Transceiver,
+    /// A bus error without further details.
+    Other,
+}
+
+/// Transmit and receive error counters of a channel's controller, returned by
+/// `Interface::error_counters`.
+///
+/// The controller goes error passive when either counter reaches 128, and bus-off
+/// when the transmit error counter exceeds 255.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorCounters {
+    /// Transmit error counter (TEC).
+    pub tx: u32,
+    /// Receive error counter (REC).
+    pub rx: u32,
+}
+
+impl BusError {
+    /// Returns the error counters reported with the error.
+    pub fn error_counters(&self) -> ErrorCounters {
+        ErrorCounters {
+            tx: self.tx_error_count as u32,
+            rx: self.rx_error_count as u32,
+        }
+    }
+}
+
+/// An error reported by the device through an error frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusError {
+    /// Channel which reported the error.
+    pub channel: u8,
+    /// The problems contained in the error frame.
+    pub kinds: Vec<BusErrorKind>,
+    /// New controller state, if the error frame reports a state change.
+    pub state: Option<BusState>,
+    /// Transmit error counter (TEC) at the time of the error.
+    pub tx_error_count: u8,
+    /// Receive error counter (REC) at the time of the error.
+    pub rx_error_count: u8,
+}
+
+impl BusState {
+    /// Converts a state reported by the GetState request, returns None for the
+    /// stopped and sleeping states of a channel which is not on the bus.
+    pub(crate) fn from_device_state(state: u32) -> Option<BusState> {
+        match state {
+            s if s == CanState::ErrorActive as u32 => Some(BusState::ErrorActive),
+            s if s == CanState::ErrorWarning as u32 => Some(BusState::ErrorWarning),
+            s if s == CanState::ErrorPassive as u32 => Some(BusState::ErrorPassive),
+            s if s == CanState::BusOff as u32 => Some(BusState::BusOff),
+            _ => None,
+        }
+    }
+}
+
+impl BusError {
+    pub(crate) fn from_host_frame(hf: &HostFrame) -> BusError {
+        let class = hf.can_id & !GSUSB_ERR_FLAG;
+        let ctrl = hf.data[1];
+        let prot = hf.data[2];
+        let location = hf.data[3];
+
+        let mut kinds = vec![];
+        if class & CAN_ERR_TX_TIMEOUT > 0 {
+            kinds.push(BusErrorKind::TxTimeout);
+        }
+        if class & CAN_ERR_LOSTARB > 0 {
+            kinds.push(BusErrorKind::LostArbitration);
+        }
+        if class & CAN_ERR_CRTL > 0 {
+            if ctrl & CAN_ERR_CRTL_RX_OVERFLOW > 0 {
+                kinds.push(BusErrorKind::RxOverflow);
+            }
+            if ctrl & CAN_ERR_CRTL_TX_OVERFLOW > 0 {
+                kinds.push(BusErrorKind::TxOverflow);
+            }
+        }
+        if class & CAN_ERR_PROT > 0 {
+            let prot_kinds = [
+                (CAN_ERR_PROT_BIT, BusErrorKind::Bit),
+                (CAN_ERR_PROT_FORM, BusErrorKind::Form),
+                (CAN_ERR_PROT_STUFF, BusErrorKind::Stuff),
+                (CAN_ERR_PROT_BIT0, BusErrorKind::Bit0),
+                (CAN_ERR_PROT_BIT1, BusErrorKind::Bit1),
+                (CAN_ERR_PROT_OVERLOAD, BusErrorKind::Overload),
+            ];
+            for (flag, kind) in prot_kinds.iter() {
+                if prot & flag > 0 {
+                    kinds.push(*kind);
+                }
+            }
+            if location == CAN_ERR_PROT_LOC_CRC_SEQ || location == CAN_ERR_PROT_LOC_CRC_DEL {
+                kinds.push(BusErrorKind::Crc);
+            }
+        }
+        if class & CAN_ERR_TRX > 0 {
+            kinds.push(BusErrorKind::Transceiver);
+        }
+        if class & CAN_ERR_ACK > 0 {
+            kinds.push(BusErrorKind::NoAck);
+        }
+        if class & CAN_ERR_BUSOFF > 0 {
+            kinds.push(BusErrorKind::BusOff);
+        }
+        if class & CAN_ERR_RESTARTED > 0 {
+            kinds.push(BusErrorKind::Restarted);
+        }
+        if class & CAN_ERR_BUSERROR > 0 && kinds.is_empty() {
+            kinds.push(BusErrorKind::Other);
+        }
+
+        let state = if class & CAN_ERR_BUSOFF > 0 {
+            Some(BusState::BusOff)
+        } else if class & CAN_ERR_CRTL > 0
+            && ctrl & (CAN_ERR_CRTL_RX_PASSIVE | CAN_ERR_CRTL_TX_PASSIVE) > 0
+        {
+            Some(BusState::ErrorPassive)
+        } else if class & CAN_ERR_CRTL > 0
+            && ctrl & (CAN_ERR_CRTL_RX_WARNING | CAN_ERR_CRTL_TX_WARNING) > 0
+        {
+            Some(BusState::ErrorWarning)
+        } else if (class & CAN_ERR_CRTL > 0 && ctrl & CAN_ERR_CRTL_ACTIVE > 0)
+            || class & CAN_ERR_RESTARTED > 0
+        {
+            Some(BusState::ErrorActive)
+        } else {
+            None
+        };
+
+        BusError {
+            channel: hf.channel,
+            kinds,
+            state,
+            tx_error_count: hf.data[6],
+            rx_error_count: hf.data[7],
+        }
+    }
+
+    /// Returns true if the error frame reported the given problem.
+    pub fn has(&self, kind: BusErrorKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_host_frame() {
+        let mut data = [0u8; 64];
+        data[1] = CAN_ERR_CRTL_TX_PASSIVE;
+        data[2] = CAN_ERR_PROT_STUFF;
+        data[6] = 130;
+        data[7] = 4;
+        let hf = HostFrame {
+            echo_id: GSUSB_RX_ECHO_ID,
+            flags: 0,
+            reserved: 0,
+            can_id: GSUSB_ERR_FLAG | CAN_ERR_PROT | CAN_ERR_ACK | CAN_ERR_CRTL,
+            can_dlc: 8,
+            channel: 1,
+            data,
+            timestamp_us: None,
+        };
+        let e = BusError::from_host_frame(&hf);
+        assert_eq!(e.channel, 1);
+        assert!(e.has(BusErrorKind::Stuff) && e.has(BusErrorKind::NoAck));
+        assert!(!e.has(BusErrorKind::BusOff));
+        assert_eq!(e.state, Some(BusState::ErrorPassive));
+        assert_eq!((e.tx_error_count, e.rx_error_count), (130, 4));
+        assert_eq!(e.error_counters(), ErrorCounters { tx: 130, rx: 4 });
+
+        assert_eq!(BusState::from_device_state(3), Some(BusState::BusOff));
+        assert_eq!(BusState::from_device_state(CanState::Stopped as u32), None);
+    }
+}
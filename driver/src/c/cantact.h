@@ -9,11 +9,14 @@ struct CantactFrame {
 	uint8_t channel;
 	uint32_t id;
 	uint8_t dlc;
-	uint8_t data[8];
+	uint8_t data[64];
 	uint8_t ext;
 	uint8_t fd;
 	uint8_t loopback;
 	uint8_t rtr;
+	uint32_t echo_id;
+	uint8_t brs;
+	uint8_t esi;
 };
 
 extern "C" {
@@ -30,11 +33,18 @@ extern "C" {
 
 	__declspec(dllimport) int32_t cantact_transmit(cantacthnd hnd, const struct CantactFrame f);
 
+	__declspec(dllimport) int32_t cantact_start_periodic(cantacthnd hnd, const struct CantactFrame f, uint32_t interval_ms, uint64_t* handle);
+	__declspec(dllimport) int32_t cantact_update_periodic(cantacthnd hnd, uint64_t handle, const struct CantactFrame f);
+	__declspec(dllimport) int32_t cantact_stop_periodic(cantacthnd hnd, uint64_t handle);
+
 	__declspec(dllimport) int32_t cantact_set_bitrate(cantacthnd hnd, uint8_t channel, uint32_t bitrate);
 	__declspec(dllimport) int32_t cantact_set_enabled(cantacthnd hnd, uint8_t channel, uint8_t enabled);
 	__declspec(dllimport) int32_t cantact_set_monitor(cantacthnd hnd, uint8_t channel, uint8_t enabled);
 	__declspec(dllimport) int32_t cantact_set_hw_loopback(cantacthnd hnd, uint8_t channel, uint8_t enabled);
 
+	__declspec(dllimport) int32_t cantact_add_filter_expr(cantacthnd hnd, uint8_t channel, const char* expr, uint64_t* handle);
+	__declspec(dllimport) int32_t cantact_remove_filter(cantacthnd hnd, uint64_t handle);
+
 	__declspec(dllimport) int32_t cantact_get_channel_count(cantacthnd hnd);
 }
 
@@ -8,7 +8,11 @@
 
 #![allow(clippy::missing_safety_doc)]
 
-use crate::{Frame, Interface};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::time::Duration;
+
+use crate::{Direction, Filter, FilterExpr, FilterHandle, Frame, Id, Interface, TaskHandle};
 
 /// A CAN frame in a C representation
 #[repr(C)]
@@ -16,27 +20,54 @@ pub struct CFrame {
     channel: u8,
     id: u32,
     dlc: u8,
-    data: [u8; 8],
+    data: [u8; 64],
     // these types are boolean flags, but C FFI hates bools
     // use u8s instead: 1 = true, 0 = false
     ext: u8,
     fd: u8,
     loopback: u8,
     rtr: u8,
+    // echo ID for frames echoed back after transmission (loopback = 1)
+    echo_id: u32,
+    // FD bit rate switch and error state indicator flags
+    brs: u8,
+    esi: u8,
 }
 impl CFrame {
     fn from_frame(f: Frame) -> CFrame {
         CFrame {
             channel: f.channel,
-            id: f.can_id,
+            id: f.raw_id(),
             dlc: f.can_dlc,
             data: f.data,
-            ext: if f.ext { 1 } else { 0 },
+            ext: if f.is_extended() { 1 } else { 0 },
             fd: if f.fd { 1 } else { 0 },
-            loopback: if f.loopback { 1 } else { 0 },
+            loopback: if f.is_echo() { 1 } else { 0 },
             rtr: if f.rtr { 1 } else { 0 },
+            echo_id: match f.direction {
+                Direction::TxEcho(id) => id,
+                Direction::Rx => 0,
+            },
+            brs: if f.brs { 1 } else { 0 },
+            esi: if f.esi { 1 } else { 0 },
         }
     }
+    // `None` if the ID is out of range for its type of ID
+    fn to_frame(&self) -> Option<Frame> {
+        Some(Frame {
+            channel: self.channel,
+            id: Id::new(self.id, self.ext > 0)?,
+            can_dlc: self.dlc,
+            data: self.data,
+            fd: self.fd > 0,
+            brs: self.brs > 0,
+            esi: self.esi > 0,
+            direction: Direction::Rx,
+            rtr: self.rtr > 0,
+            timestamp: None,
+            raw_flags: None,
+        })
+    }
 }
 
 /// Interface state. A pointer to this struct is provided when initializing the
@@ -65,7 +96,7 @@ pub extern "C" fn cantact_init() -> *mut CInterface {
 /// After calling, the pointer is no longer valid.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_deinit(ptr: *mut CInterface) -> i32 {
-    Box::from_raw(ptr);
+    drop(Box::from_raw(ptr));
     0
 }
 
@@ -76,7 +107,7 @@ pub unsafe extern "C" fn cantact_set_rx_callback(
     ptr: *mut CInterface,
     cb: Option<extern "C" fn(*const CFrame)>,
 ) -> i32 {
-    let mut ci = &mut *ptr;
+    let ci = &mut *ptr;
     ci.c_rx_cb = cb;
     0
 }
@@ -98,7 +129,7 @@ pub unsafe extern "C" fn cantact_open(ptr: *mut CInterface) -> i32 {
 /// can be performed.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_close(ptr: *mut CInterface) -> i32 {
-    let mut ci = &mut *ptr;
+    let ci = &mut *ptr;
     ci.i = None;
     0
 }
@@ -107,61 +138,81 @@ pub unsafe extern "C" fn cantact_close(ptr: *mut CInterface) -> i32 {
 ///
 /// This function starts a thread which will call the registered callback
 /// when a frame is received.
+///
+/// Returns a negative error code if the device could not be started.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_start(ptr: *mut CInterface) -> i32 {
     let ci = &mut *ptr;
 
     let cb = ci.c_rx_cb;
     match &mut ci.i {
-        Some(i) => i
-            .start(move |f: Frame| {
-                match cb {
-                    None => {}
-                    Some(cb) => {
-                        cb(&CFrame::from_frame(f));
-                    }
-                };
-            })
-            .expect("failed to start device"),
-        None => return -1,
-    };
-    0
+        Some(i) => match i.start(move |f: Frame| {
+            match cb {
+                None => {}
+                Some(cb) => {
+                    cb(&CFrame::from_frame(f));
+                }
+            };
+        }) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
 }
 
 /// Stop CAN communication. This will stop all configured CAN channels.
+///
+/// Returns a negative error code if a channel could not be stopped.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_stop(ptr: *mut CInterface) -> i32 {
     let ci = &mut *ptr;
     match &mut ci.i {
-        Some(i) => i.stop().expect("failed to stop device"),
-        None => return -1,
+        Some(i) => match i.stop() {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
     }
-    0
 }
 
 /// Transmit a frame. Can only be called if the device is running.
+///
+/// Returns a negative error code if the ID is out of range for its type of ID,
+/// or the frame could not be sent.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_transmit(ptr: *mut CInterface, cf: CFrame) -> i32 {
     let ci = &mut *ptr;
+    let id = match Id::new(cf.id, cf.ext > 0) {
+        Some(id) => id,
+        None => return -1,
+    };
     let f = Frame {
         channel: 0, //cf.channel,
-        can_id: cf.id,
+        id,
         can_dlc: cf.dlc,
         data: cf.data,
-        ext: cf.ext > 0,
         fd: cf.fd > 0,
-        loopback: false,
+        brs: cf.brs > 0,
+        esi: cf.esi > 0,
+        direction: Direction::Rx,
         rtr: cf.rtr > 0,
         timestamp: None,
+        raw_flags: None,
     };
     match &mut ci.i {
-        Some(i) => i.send(f).expect("failed to transmit frame"),
-        None => return -1,
-    };
-    0
+        Some(i) => match i.send(f) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
 }
 
 /// Sets the bitrate for a chanel to the given value in bits per second.
+///
+/// Returns a negative error code if the channel does not exist, or the bitrate
+/// can't be set.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_set_bitrate(
     ptr: *mut CInterface,
@@ -170,15 +221,18 @@ pub unsafe extern "C" fn cantact_set_bitrate(
 ) -> i32 {
     let ci = &mut *ptr;
     match &mut ci.i {
-        Some(i) => i
-            .set_bitrate(channel as usize, bitrate)
-            .expect("failed to set bitrate"),
-        None => return -1,
+        Some(i) => match i.set_bitrate(channel as usize, bitrate) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
     }
-    0
 }
 
 /// Enable or disable a channel.
+///
+/// Returns a negative error code if the channel does not exist, or the
+/// setting can't be changed while the device is running.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_set_enabled(
     ptr: *mut CInterface,
@@ -187,16 +241,19 @@ pub unsafe extern "C" fn cantact_set_enabled(
 ) -> i32 {
     let ci = &mut *ptr;
     match &mut ci.i {
-        Some(i) => i
-            .set_enabled(channel as usize, enabled > 0)
-            .expect("failed to enable channel"),
-        None => return -1,
+        Some(i) => match i.set_enabled(channel as usize, enabled > 0) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
     }
-    0
 }
 
 /// Enable or disable bus monitoring mode for a channel. When enabled, channel
 /// will not transmit frames or acknoweldgements.
+///
+/// Returns a negative error code if the channel does not exist, or the
+/// setting can't be changed while the device is running.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_set_monitor(
     ptr: *mut CInterface,
@@ -205,16 +262,19 @@ pub unsafe extern "C" fn cantact_set_monitor(
 ) -> i32 {
     let ci = &mut *ptr;
     match &mut ci.i {
-        Some(i) => i
-            .set_monitor(channel as usize, enabled > 0)
-            .expect("failed to set monitoring mode"),
-        None => return -1,
+        Some(i) => match i.set_monitor(channel as usize, enabled > 0) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
     }
-    0
 }
 
 /// Enable or disable hardware loopback for a channel. This will cause sent
 /// frames to be received. This mode is mostly intended for device testing.
+///
+/// Returns a negative error code if the channel does not exist, or the
+/// setting can't be changed while the device is running.
 #[no_mangle]
 pub unsafe extern "C" fn cantact_set_hw_loopback(
     ptr: *mut CInterface,
@@ -223,12 +283,59 @@ pub unsafe extern "C" fn cantact_set_hw_loopback(
 ) -> i32 {
     let ci = &mut *ptr;
     match &mut ci.i {
-        Some(i) => i
-            .set_loopback(channel as usize, enabled > 0)
-            .expect("failed to enable channel"),
-        None => return -1,
+        Some(i) => match i.set_loopback(channel as usize, enabled > 0) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+/// Add a filter for frames received on a channel, given as a filter expression
+/// such as `"id == 0x7E8 || (id & 0x700) == 0x100"`. See `FilterExpr` for the
+/// syntax.
+///
+/// Stores a handle for use with `cantact_remove_filter` in `handle`. Returns a
+/// negative error code if the expression is not valid or the filter could not
+/// be added.
+#[no_mangle]
+pub unsafe extern "C" fn cantact_add_filter_expr(
+    ptr: *mut CInterface,
+    channel: u8,
+    expr: *const c_char,
+    handle: *mut u64,
+) -> i32 {
+    let ci = &mut *ptr;
+    if expr.is_null() || handle.is_null() {
+        return -1;
+    }
+    let expr = match CStr::from_ptr(expr).to_str().map(str::parse::<FilterExpr>) {
+        Ok(Ok(expr)) => expr,
+        _ => return -1,
+    };
+    match &mut ci.i {
+        Some(i) => match i.add_filter(channel as usize, Filter::Expr(expr)) {
+            Ok(h) => {
+                *handle = h.0;
+                0
+            }
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+/// Remove a filter added with `cantact_add_filter_expr`.
+#[no_mangle]
+pub unsafe extern "C" fn cantact_remove_filter(ptr: *mut CInterface, handle: u64) -> i32 {
+    let ci = &mut *ptr;
+    match &mut ci.i {
+        Some(i) => match i.remove_filter(FilterHandle(handle)) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
     }
-    0
 }
 
 /// Get the number of CAN channels the device has.
@@ -242,3 +349,68 @@ pub unsafe extern "C" fn cantact_get_channel_count(ptr: *mut CInterface) -> i32
         None => -1,
     }
 }
+
+/// Start transmitting a frame every `interval_ms` milliseconds. Frames are sent
+/// by the driver while the device is running.
+///
+/// Stores a handle for use with `cantact_update_periodic` and
+/// `cantact_stop_periodic` in `handle`. Returns a negative error code on
+/// failure.
+#[no_mangle]
+pub unsafe extern "C" fn cantact_start_periodic(
+    ptr: *mut CInterface,
+    cf: CFrame,
+    interval_ms: u32,
+    handle: *mut u64,
+) -> i32 {
+    let ci = &mut *ptr;
+    if handle.is_null() {
+        return -1;
+    }
+    let f = match cf.to_frame() {
+        Some(f) => f,
+        None => return -1,
+    };
+    match &mut ci.i {
+        Some(i) => match i.send_periodic(f, Duration::from_millis(interval_ms as u64)) {
+            Ok(h) => {
+                *handle = h.0;
+                0
+            }
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+/// Replace the frame sent by a periodic transmission, keeping its schedule.
+#[no_mangle]
+pub unsafe extern "C" fn cantact_update_periodic(
+    ptr: *mut CInterface,
+    handle: u64,
+    cf: CFrame,
+) -> i32 {
+    let ci = &mut *ptr;
+    let f = match cf.to_frame() {
+        Some(f) => f,
+        None => return -1,
+    };
+    match &mut ci.i {
+        Some(i) => match i.update_periodic(TaskHandle(handle), f) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        },
+        None => -1,
+    }
+}
+
+/// Stop a periodic transmission.
+#[no_mangle]
+pub unsafe extern "C" fn cantact_stop_periodic(ptr: *mut CInterface, handle: u64) -> i32 {
+    let ci = &mut *ptr;
+    match &mut ci.i {
+        Some(i) => i.stop_periodic(TaskHandle(handle)),
+        None => return -1,
+    }
+    0
+}
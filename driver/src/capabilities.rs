@@ -0,0 +1,57 @@
+//! Decoding of the optional features supported by a device.
+
+use crate::device::gsusb::*;
+
+/// Optional features supported by a channel of a device, as advertised by its
+/// firmware, returned by `Interface::capabilities`.
+///
+/// Channels of the same device may support different features. Features which
+/// are not supported should not be enabled, since the device will reject or
+/// ignore the request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// CAN FD frames and data phase bit timing.
+    pub fd: bool,
+    /// Listen only (monitor) mode.
+    pub listen_only: bool,
+    /// Hardware loopback mode.
+    pub loop_back: bool,
+    /// One-shot mode, where frames are not retransmitted on failure.
+    pub one_shot: bool,
+    /// Triple sampling of each bit.
+    pub triple_sample: bool,
+    /// Hardware timestamps on received frames.
+    pub hw_timestamp: bool,
+    /// Blinking the device LED to identify it.
+    pub identify: bool,
+    /// Storing a user ID in the device.
+    pub user_id: bool,
+    /// Switchable bus termination resistor.
+    pub termination: bool,
+    /// Reporting of bus errors as error frames.
+    pub berr_reporting: bool,
+    /// Querying the controller state of a channel.
+    pub get_state: bool,
+    /// Acceptance filters in the device, used by `Interface::add_filter`.
+    pub hw_filter: bool,
+}
+
+impl Capabilities {
+    pub(crate) fn from_features(features: u32) -> Capabilities {
+        let has = |flag: u32| features & flag > 0;
+        Capabilities {
+            fd: has(GSUSB_FEATURE_FD),
+            listen_only: has(GSUSB_FEATURE_LISTEN_ONLY),
+            loop_back: has(GSUSB_FEATURE_LOOP_BACK),
+            one_shot: has(GSUSB_FEATURE_ONE_SHOT),
+            triple_sample: has(GSUSB_FEATURE_TRIPLE_SAMPLE),
+            hw_timestamp: has(GSUSB_FEATURE_HW_TIMESTAMP),
+            identify: has(GSUSB_FEATURE_IDENTIFY),
+            user_id: has(GSUSB_FEATURE_USER_ID),
+            termination: has(GSUSB_FEATURE_TERMINATION),
+            berr_reporting: has(GSUSB_FEATURE_BERR_REPORTING),
+            get_state: has(GSUSB_FEATURE_GET_STATE),
+            hw_filter: has(GSUSB_FEATURE_HW_FILTER),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! The text log format of `candump -l` from can-utils.
+
+use std::io::{self, BufRead, ErrorKind, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use super::index::{self, Cursor, Index};
+use crate::Frame;
+
+// lines between index entries
+const DEFAULT_BLOCK_SIZE: u64 = 4096;
+
+/// Format a frame as a line of a candump log file, without the trailing newline:
+/// `(<seconds>.<microseconds>) can<channel> <id>#<data>`.
+///
+/// Frames without a timestamp are logged at time zero.
+pub fn candump_line(f: &Frame) -> String {
+    match f.timestamp {
+        Some(_) => f.to_string(),
+        None => format!("(0.000000) {}", f),
+    }
+}
+
+/// Parse a line of a candump log file, as written by `candump_line`. The
+/// timestamp is optional, and may have fewer than 6 decimal places.
+///
+/// The interface name must end with the channel number, as in `can0` or `vcan1`.
+/// Returns `None` if the line is not a valid log entry.
+pub fn parse_candump_line(line: &str) -> Option<Frame> {
+    line.parse().ok()
+}
+
+/// Writes frames to a candump log file, building an index as it goes.
+pub struct CandumpWriter<W: Write> {
+    inner: W,
+    offset: u64,
+    index: Index,
+    block_size: u64,
+}
+
+impl<W: Write> CandumpWriter<W> {
+    /// Start a log.
+    pub fn new(inner: W) -> CandumpWriter<W> {
+        CandumpWriter {
+            inner,
+            offset: 0,
+            index: Index::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    /// Set the number of lines between index entries. Defaults to 4096.
+    pub fn set_block_size(&mut self, lines: u64) {
+        self.block_size = lines.max(1);
+    }
+
+    /// Append a frame to the log.
+    pub fn write(&mut self, f: &Frame) -> io::Result<()> {
+        if self.index.frames().is_multiple_of(self.block_size) {
+            self.index.start_block(self.offset);
+        }
+        let line = candump_line(f) + "\n";
+        self.inner.write_all(line.as_bytes())?;
+        self.offset += line.len() as u64;
+        self.index.add(f);
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns the index of the frames written so far.
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads frames from a candump log file. Iterating over the reader returns each
+/// frame in the log. Empty lines are skipped.
+pub struct CandumpReader<R: BufRead> {
+    inner: R,
+    line: String,
+    // frame read ahead while seeking
+    pending: Option<Frame>,
+}
+
+impl<R: BufRead> CandumpReader<R> {
+    /// Open a log.
+    pub fn new(inner: R) -> CandumpReader<R> {
+        CandumpReader {
+            inner,
+            line: String::new(),
+            pending: None,
+        }
+    }
+
+    /// Read the next frame, or `None` at the end of the log.
+    pub fn read(&mut self) -> io::Result<Option<Frame>> {
+        if let Some(f) = self.pending.take() {
+            return Ok(Some(f));
+        }
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            let line = self.line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            return match parse_candump_line(line) {
+                Some(f) => Ok(Some(f)),
+                None => Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid candump line: {}", line),
+                )),
+            };
+        }
+    }
+}
+
+impl<R: BufRead + Seek> CandumpReader<R> {
+    /// Jump to the `n`th frame in the log, counting from zero.
+    pub fn seek_to_frame(&mut self, index: &Index, n: u64) -> io::Result<()> {
+        index::seek_to_frame(self, index, n)
+    }
+
+    /// Jump to the first frame with a timestamp at or after `t`.
+    pub fn seek_to_time(&mut self, index: &Index, t: Duration) -> io::Result<()> {
+        index::seek_to_time(self, index, t)
+    }
+
+    /// Jump to the `n`th occurrence of an ID, counting from zero.
+    pub fn seek_to_occurrence(
+        &mut self,
+        index: &Index,
+        id: u32,
+        ext: bool,
+        n: usize,
+    ) -> io::Result<()> {
+        index::seek_to_occurrence(self, index, id, ext, n)
+    }
+}
+
+impl<R: BufRead + Seek> Cursor for CandumpReader<R> {
+    fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        self.read()
+    }
+
+    fn seek_block(&mut self, offset: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.pending = None;
+        Ok(())
+    }
+
+    fn push_back(&mut self, f: Frame) {
+        self.pending = Some(f);
+    }
+}
+
+impl<R: BufRead> Iterator for CandumpReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<io::Result<Frame>> {
+        self.read().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+
+    #[test]
+    fn test_candump() {
+        let f = parse_candump_line("(1629730123.456789) can1 123#DEADBEEF").unwrap();
+        assert_eq!((f.raw_id(), f.can_dlc, f.channel), (0x123, 4, 1));
+        assert_eq!(f.data[..4], [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(f.timestamp, Some(Duration::new(1629730123, 456_789_000)));
+        assert_eq!(candump_line(&f), "(1629730123.456789) can1 123#DEADBEEF");
+
+        let f = parse_candump_line("vcan0 1ABCDEF0#R T").unwrap();
+        assert!(f.is_extended() && f.rtr && f.is_echo());
+        assert_eq!(candump_line(&f), "(0.000000) can0 1ABCDEF0#R T");
+
+        assert!(parse_candump_line("can0 800#00").is_none());
+        assert!(parse_candump_line("can0 123#0").is_none());
+
+        let line = "(0.000000) can0 123##0000102030405060708090A0B";
+        let f = parse_candump_line(line).unwrap();
+        assert!(f.fd);
+        assert_eq!((f.can_dlc, f.payload().len()), (9, 12));
+        assert_eq!(candump_line(&f), line);
+        let f = parse_candump_line("can0 123##311").unwrap();
+        assert!(f.brs && f.esi);
+        assert_eq!(candump_line(&f), "(0.000000) can0 123##311");
+        // 9 bytes is not a valid FD length
+        assert!(parse_candump_line("can0 123##0000102030405060708").is_none());
+
+        let mut w = CandumpWriter::new(vec![]);
+        w.set_block_size(4);
+        for n in 0..20 {
+            w.write(&Frame {
+                id: Id::new(0x100 + n % 2, false).unwrap(),
+                timestamp: Some(Duration::from_millis(n as u64)),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        let index = w.index().clone();
+        let mut r = CandumpReader::new(io::Cursor::new(w.into_inner()));
+        r.seek_to_time(&index, Duration::from_millis(13)).unwrap();
+        assert_eq!(r.read().unwrap().unwrap().raw_id(), 0x101);
+        r.seek_to_occurrence(&index, 0x100, false, 3).unwrap();
+        assert_eq!(
+            r.read().unwrap().unwrap().timestamp,
+            Some(Duration::from_millis(6))
+        );
+        assert_eq!(r.count(), 13);
+    }
+}
@@ -0,0 +1,508 @@
+//! Compact binary capture format for long-term logging.
+//!
+//! Most traffic on a bus is cyclic: the same IDs are sent at fixed intervals, and
+//! only a few bytes of their data change between frames. Each record therefore
+//! stores the time since the previous record, and only the data bytes which
+//! changed since the last frame with the same ID.
+//!
+//! Records are grouped into blocks. The delta state is reset at the start of every
+//! block, so each block can be decoded on its own.
+//!
+//! The file starts with the magic bytes `CTDL` and a version byte. Each record
+//! starts with a flags byte, followed by:
+//!
+//! * the time since the previous record in microseconds, as a zigzag varint, if
+//!   `FLAG_TIMESTAMP` is set
+//! * the ID as a varint
+//! * the channel, if `FLAG_CHANNEL` is set
+//! * the echo ID as a varint, if `FLAG_ECHO` is set
+//! * the DLC in the low 4 bits, with the BRS and ESI flags of FD frames in bits
+//!   4 and 5
+//! * the data: either all bytes, or if `FLAG_DELTA` is set, a bitmap of the bytes
+//!   which changed followed by those bytes.
+//!
+//! A flags byte with `FLAG_CONTROL` set starts a new block.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use super::index::{self, Cursor, Index};
+use crate::{Direction, Frame, Id};
+
+const MAGIC: &[u8; 4] = b"CTDL";
+const VERSION: u8 = 1;
+
+const FLAG_EXT: u8 = 1 << 0;
+const FLAG_RTR: u8 = 1 << 1;
+const FLAG_FD: u8 = 1 << 2;
+const FLAG_ECHO: u8 = 1 << 3;
+const FLAG_CHANNEL: u8 = 1 << 4;
+const FLAG_DELTA: u8 = 1 << 5;
+const FLAG_TIMESTAMP: u8 = 1 << 6;
+const FLAG_CONTROL: u8 = 1 << 7;
+
+const CONTROL_BLOCK: u8 = FLAG_CONTROL;
+
+// FD flags, stored above the DLC in its byte
+const DLC_MASK: u8 = 0x0F;
+const DLC_BRS: u8 = 1 << 4;
+const DLC_ESI: u8 = 1 << 5;
+
+const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+// delta state shared by the writer and reader, reset at the start of every block
+#[derive(Default)]
+struct State {
+    last_timestamp: u64,
+    // last data seen for each channel, ID, and ID type
+    last_data: HashMap<(u8, u32, bool), Vec<u8>>,
+}
+
+fn data_len(f: &Frame) -> usize {
+    if f.rtr {
+        0
+    } else {
+        f.len()
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn read_byte(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = read_byte(r)?;
+        v |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "varint too long"))
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+/// Writes frames in the delta-encoded capture format.
+pub struct DeltaWriter<W: Write> {
+    inner: W,
+    state: State,
+    block_size: usize,
+    in_block: usize,
+    buf: Vec<u8>,
+    offset: u64,
+    index: Index,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    /// Start a capture, writing the file header.
+    pub fn new(mut inner: W) -> io::Result<DeltaWriter<W>> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&[VERSION])?;
+        Ok(DeltaWriter {
+            inner,
+            state: State::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            in_block: 0,
+            buf: vec![],
+            offset: (MAGIC.len() + 1) as u64,
+            index: Index::default(),
+        })
+    }
+
+    /// Set the number of frames in each independently decodable block. Smaller
+    /// blocks compress less but limit how much is lost if the file is corrupted.
+    /// Defaults to 4096.
+    pub fn set_block_size(&mut self, frames: usize) {
+        self.block_size = frames.max(1);
+    }
+
+    /// Append a frame to the capture.
+    pub fn write(&mut self, f: &Frame) -> io::Result<()> {
+        self.buf.clear();
+        if self.in_block == 0 {
+            self.buf.push(CONTROL_BLOCK);
+            self.state = State::default();
+            self.index.start_block(self.offset);
+        }
+
+        let mut flags = 0;
+        if f.is_extended() {
+            flags |= FLAG_EXT;
+        }
+        if f.rtr {
+            flags |= FLAG_RTR;
+        }
+        if f.fd {
+            flags |= FLAG_FD;
+        }
+        if let Direction::TxEcho(_) = f.direction {
+            flags |= FLAG_ECHO;
+        }
+        if f.channel != 0 {
+            flags |= FLAG_CHANNEL;
+        }
+        if f.timestamp.is_some() {
+            flags |= FLAG_TIMESTAMP;
+        }
+        let data = &f.data[..data_len(f)];
+        let key = (f.channel, f.raw_id(), f.is_extended());
+        let last = self
+            .state
+            .last_data
+            .get(&key)
+            .filter(|last| last.len() == data.len() && !data.is_empty());
+        if last.is_some() {
+            flags |= FLAG_DELTA;
+        }
+
+        self.buf.push(flags);
+        if let Some(t) = f.timestamp {
+            let t = t.as_micros() as u64;
+            write_varint(
+                &mut self.buf,
+                zigzag(t as i64 - self.state.last_timestamp as i64),
+            );
+            self.state.last_timestamp = t;
+        }
+        write_varint(&mut self.buf, f.raw_id() as u64);
+        if f.channel != 0 {
+            self.buf.push(f.channel);
+        }
+        if let Direction::TxEcho(id) = f.direction {
+            write_varint(&mut self.buf, id as u64);
+        }
+        let mut dlc = f.can_dlc & DLC_MASK;
+        if f.brs {
+            dlc |= DLC_BRS;
+        }
+        if f.esi {
+            dlc |= DLC_ESI;
+        }
+        self.buf.push(dlc);
+        match last {
+            Some(last) => {
+                let mut bitmap = vec![0u8; data.len().div_ceil(8)];
+                let mut changed = vec![];
+                for (n, (new, old)) in data.iter().zip(last.iter()).enumerate() {
+                    if new != old {
+                        bitmap[n / 8] |= 1 << (n % 8);
+                        changed.push(*new);
+                    }
+                }
+                self.buf.extend_from_slice(&bitmap);
+                self.buf.extend_from_slice(&changed);
+            }
+            None => self.buf.extend_from_slice(data),
+        }
+        self.state.last_data.insert(key, data.to_vec());
+
+        self.inner.write_all(&self.buf)?;
+        self.offset += self.buf.len() as u64;
+        self.index.add(f);
+        self.in_block = (self.in_block + 1) % self.block_size;
+        Ok(())
+    }
+
+    /// Returns the index of the frames written so far.
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads frames from a delta-encoded capture. Iterating over the reader returns
+/// each frame in the order it was written.
+pub struct DeltaReader<R: Read> {
+    inner: R,
+    state: State,
+    // frame read ahead while seeking
+    pending: Option<Frame>,
+}
+
+impl<R: Read> DeltaReader<R> {
+    /// Open a capture, checking the file header.
+    pub fn new(mut inner: R) -> io::Result<DeltaReader<R>> {
+        let mut header = [0u8; 5];
+        inner.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a delta-encoded capture",
+            ));
+        }
+        Ok(DeltaReader {
+            inner,
+            state: State::default(),
+            pending: None,
+        })
+    }
+
+    /// Read the next frame, or `None` at the end of the capture.
+    pub fn read(&mut self) -> io::Result<Option<Frame>> {
+        if let Some(f) = self.pending.take() {
+            return Ok(Some(f));
+        }
+        let mut flags = match read_byte(&mut self.inner) {
+            Ok(b) => b,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        while flags & FLAG_CONTROL != 0 {
+            if flags != CONTROL_BLOCK {
+                return Err(io::Error::new(ErrorKind::InvalidData, "unknown record"));
+            }
+            self.state = State::default();
+            flags = read_byte(&mut self.inner)?;
+        }
+
+        let r = &mut self.inner;
+        let mut f = Frame {
+            rtr: flags & FLAG_RTR != 0,
+            fd: flags & FLAG_FD != 0,
+            ..Default::default()
+        };
+        if flags & FLAG_TIMESTAMP != 0 {
+            let t = (self.state.last_timestamp as i64 + unzigzag(read_varint(r)?)) as u64;
+            self.state.last_timestamp = t;
+            f.timestamp = Some(Duration::from_micros(t));
+        }
+        f.id = Id::new(read_varint(r)? as u32, flags & FLAG_EXT != 0)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "invalid ID"))?;
+        if flags & FLAG_CHANNEL != 0 {
+            f.channel = read_byte(r)?;
+        }
+        if flags & FLAG_ECHO != 0 {
+            f.direction = Direction::TxEcho(read_varint(r)? as u32);
+        }
+        let dlc = read_byte(r)?;
+        f.can_dlc = dlc & DLC_MASK;
+        f.brs = dlc & DLC_BRS != 0;
+        f.esi = dlc & DLC_ESI != 0;
+
+        let len = data_len(&f);
+        let key = (f.channel, f.raw_id(), f.is_extended());
+        if flags & FLAG_DELTA != 0 {
+            let last = match self.state.last_data.get(&key) {
+                Some(last) if last.len() == len => last,
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "delta record without a previous frame",
+                    ))
+                }
+            };
+            let mut bitmap = vec![0u8; len.div_ceil(8)];
+            r.read_exact(&mut bitmap)?;
+            for n in 0..len {
+                f.data[n] = if bitmap[n / 8] & (1 << (n % 8)) != 0 {
+                    read_byte(r)?
+                } else {
+                    last[n]
+                };
+            }
+        } else {
+            r.read_exact(&mut f.data[..len])?;
+        }
+        self.state.last_data.insert(key, f.data[..len].to_vec());
+        Ok(Some(f))
+    }
+}
+
+impl<'a> DeltaReader<&'a [u8]> {
+    // read the frames of a block, starting at its block record
+    #[cfg_attr(not(feature = "mmap"), allow(dead_code))]
+    pub(crate) fn block(data: &'a [u8]) -> DeltaReader<&'a [u8]> {
+        DeltaReader {
+            inner: data,
+            state: State::default(),
+            pending: None,
+        }
+    }
+}
+
+impl Index {
+    /// Build the index of a delta-encoded capture held in memory, for captures
+    /// written without saving their index.
+    pub fn scan_delta(data: &[u8]) -> io::Result<Index> {
+        let mut index = Index::default();
+        let mut r = DeltaReader::new(data)?;
+        loop {
+            if r.inner.first() == Some(&CONTROL_BLOCK) {
+                index.start_block((data.len() - r.inner.len()) as u64);
+            }
+            match r.read()? {
+                Some(f) => index.add(&f),
+                None => return Ok(index),
+            }
+        }
+    }
+}
+
+impl<R: Read + Seek> DeltaReader<R> {
+    /// Jump to the `n`th frame in the capture, counting from zero.
+    pub fn seek_to_frame(&mut self, index: &Index, n: u64) -> io::Result<()> {
+        index::seek_to_frame(self, index, n)
+    }
+
+    /// Jump to the first frame with a timestamp at or after `t`.
+    pub fn seek_to_time(&mut self, index: &Index, t: Duration) -> io::Result<()> {
+        index::seek_to_time(self, index, t)
+    }
+
+    /// Jump to the `n`th occurrence of an ID, counting from zero.
+    pub fn seek_to_occurrence(
+        &mut self,
+        index: &Index,
+        id: u32,
+        ext: bool,
+        n: usize,
+    ) -> io::Result<()> {
+        index::seek_to_occurrence(self, index, id, ext, n)
+    }
+}
+
+impl<R: Read + Seek> Cursor for DeltaReader<R> {
+    fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        self.read()
+    }
+
+    fn seek_block(&mut self, offset: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.state = State::default();
+        self.pending = None;
+        Ok(())
+    }
+
+    fn push_back(&mut self, f: Frame) {
+        self.pending = Some(f);
+    }
+}
+
+impl<R: Read> Iterator for DeltaReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<io::Result<Frame>> {
+        self.read().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::write_candump;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let mut frames = vec![];
+        for n in 0..100u64 {
+            let mut f = Frame {
+                id: Id::new(0x100 + (n % 3) as u32, false).unwrap(),
+                can_dlc: 8,
+                timestamp: Some(Duration::from_micros(10_000 * n)),
+                ..Default::default()
+            };
+            f.data[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, (n >> 8) as u8, n as u8]);
+            frames.push(f);
+        }
+        frames.push(Frame {
+            id: Id::new(0x1ABCDEF, true).unwrap(),
+            rtr: true,
+            channel: 1,
+            direction: Direction::TxEcho(7),
+            ..Default::default()
+        });
+        let mut fd = Frame {
+            id: Id::new(0x200, false).unwrap(),
+            fd: true,
+            brs: true,
+            can_dlc: 15,
+            ..Default::default()
+        };
+        for (i, b) in fd.data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        frames.push(fd);
+
+        let mut w = DeltaWriter::new(vec![]).unwrap();
+        w.set_block_size(16);
+        for f in frames.iter() {
+            w.write(f).unwrap();
+        }
+        let w_index = w.index().clone();
+        assert_eq!(w_index.frames(), frames.len() as u64);
+        let buf = w.into_inner();
+
+        let read: Vec<Frame> = DeltaReader::new(&buf[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read.len(), frames.len());
+        for (a, b) in read.iter().zip(frames.iter()) {
+            assert_eq!(a.raw_id(), b.raw_id());
+            assert_eq!(a.data, b.data);
+            assert_eq!(a.timestamp, b.timestamp);
+            assert_eq!(
+                (a.is_extended(), a.rtr, a.channel),
+                (b.is_extended(), b.rtr, b.channel)
+            );
+            assert_eq!(a.direction, b.direction);
+        }
+
+        // jump around using the index
+        let mut saved = vec![];
+        w_index.write_to(&mut saved).unwrap();
+        let index = Index::read_from(&saved[..]).unwrap();
+        assert_eq!(index, w_index);
+        assert_eq!(Index::scan_delta(&buf).unwrap(), w_index);
+        let mut r = DeltaReader::new(io::Cursor::new(&buf)).unwrap();
+        r.seek_to_time(&index, Duration::from_micros(505_000))
+            .unwrap();
+        assert_eq!(r.read().unwrap().unwrap().timestamp, frames[51].timestamp);
+        r.seek_to_occurrence(&index, 0x102, false, 10).unwrap();
+        assert_eq!(r.read().unwrap().unwrap().data, frames[32].data);
+        r.seek_to_frame(&index, 100).unwrap();
+        assert_eq!(r.read().unwrap().unwrap().raw_id(), 0x1ABCDEF);
+        let fd = r.read().unwrap().unwrap();
+        assert_eq!(fd.payload(), frames[101].payload());
+        assert!(fd.fd && fd.brs && !fd.esi);
+        assert!(r.seek_to_frame(&index, 102).is_err());
+
+        let mut log = vec![];
+        let n = write_candump(DeltaReader::new(&buf[..]).unwrap(), &mut log).unwrap();
+        assert_eq!(n, frames.len() as u64);
+        // several times smaller than the same frames as text
+        assert!(buf.len() * 3 < log.len());
+        let log = String::from_utf8(log).unwrap();
+        assert!(log.starts_with("(0.000000) can0 100#0102030405060000\n"));
+        assert!(log.contains("(0.000000) can1 01ABCDEF#R T\n"));
+        assert!(log.ends_with("3D3E3F\n"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Indexes for seeking in large captures without reading them from the start.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Duration;
+
+use crate::Frame;
+
+const MAGIC: &[u8; 4] = b"CTIX";
+const VERSION: u8 = 1;
+
+// a point in the capture where reading can start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Block {
+    // number of frames before the block
+    pub(crate) frame: u64,
+    // byte offset of the block in the capture
+    pub(crate) offset: u64,
+    // timestamp of the first frame in the block, in microseconds
+    pub(crate) timestamp: Option<u64>,
+}
+
+/// Index of a capture, mapping timestamps to file offsets and listing where each
+/// ID occurs.
+///
+/// An index is built while writing a capture with `DeltaWriter` or
+/// `CandumpWriter`, and can be saved next to it. Readers use the index to jump to
+/// a timestamp or an occurrence of an ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    blocks: Vec<Block>,
+    // frame numbers of each occurrence of an ID and ID type
+    occurrences: HashMap<(u32, bool), Vec<u64>>,
+    frames: u64,
+}
+
+impl Index {
+    pub(crate) fn start_block(&mut self, offset: u64) {
+        self.blocks.push(Block {
+            frame: self.frames,
+            offset,
+            timestamp: None,
+        });
+    }
+
+    pub(crate) fn add(&mut self, f: &Frame) {
+        if let Some(b) = self.blocks.last_mut() {
+            if b.timestamp.is_none() {
+                b.timestamp = f.timestamp.map(|t| t.as_micros() as u64);
+            }
+        }
+        self.occurrences
+            .entry((f.raw_id(), f.is_extended()))
+            .or_default()
+            .push(self.frames);
+        self.frames += 1;
+    }
+
+    /// Returns the number of frames in the capture.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns the frame numbers at which a frame with this ID occurs.
+    pub fn occurrences(&self, id: u32, ext: bool) -> &[u64] {
+        self.occurrences
+            .get(&(id, ext))
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns every ID in the capture, with whether it is extended.
+    pub fn ids(&self) -> Vec<(u32, bool)> {
+        let mut ids: Vec<_> = self.occurrences.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    // byte ranges of every block, given the length of the capture
+    #[cfg_attr(not(feature = "mmap"), allow(dead_code))]
+    pub(crate) fn block_ranges(&self, len: u64) -> Vec<(u64, u64)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let end = self.blocks.get(i + 1).map(|n| n.offset).unwrap_or(len);
+                (b.offset, end)
+            })
+            .collect()
+    }
+
+    // the last block starting at or before a frame
+    pub(crate) fn block_for_frame(&self, n: u64) -> Option<Block> {
+        let i = self.blocks.partition_point(|b| b.frame <= n);
+        self.blocks[..i].last().copied()
+    }
+
+    // the last block starting at or before a time, or the first block
+    pub(crate) fn block_for_time(&self, t: Duration) -> Option<Block> {
+        let t = t.as_micros() as u64;
+        self.blocks
+            .iter()
+            .rev()
+            .find(|b| matches!(b.timestamp, Some(bt) if bt <= t))
+            .or_else(|| self.blocks.first())
+            .copied()
+    }
+
+    /// Save the index.
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.frames.to_le_bytes());
+        buf.extend_from_slice(&(self.blocks.len() as u64).to_le_bytes());
+        for b in self.blocks.iter() {
+            buf.extend_from_slice(&b.frame.to_le_bytes());
+            buf.extend_from_slice(&b.offset.to_le_bytes());
+            buf.extend_from_slice(&b.timestamp.unwrap_or(u64::MAX).to_le_bytes());
+        }
+        buf.extend_from_slice(&(self.occurrences.len() as u64).to_le_bytes());
+        for ((id, ext), frames) in self.occurrences.iter() {
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.push(*ext as u8);
+            buf.extend_from_slice(&(frames.len() as u64).to_le_bytes());
+            for n in frames.iter() {
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        w.write_all(&buf)
+    }
+
+    /// Load an index saved with `Index::write_to`.
+    pub fn read_from(mut r: impl Read) -> io::Result<Index> {
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a capture index",
+            ));
+        }
+        let mut index = Index {
+            frames: read_u64(&mut r)?,
+            ..Default::default()
+        };
+        for _ in 0..read_u64(&mut r)? {
+            let frame = read_u64(&mut r)?;
+            let offset = read_u64(&mut r)?;
+            let timestamp = Some(read_u64(&mut r)?).filter(|t| *t != u64::MAX);
+            index.blocks.push(Block {
+                frame,
+                offset,
+                timestamp,
+            });
+        }
+        for _ in 0..read_u64(&mut r)? {
+            let mut id = [0u8; 5];
+            r.read_exact(&mut id)?;
+            let key = (u32::from_le_bytes([id[0], id[1], id[2], id[3]]), id[4] != 0);
+            let count = read_u64(&mut r)?;
+            let mut frames = vec![];
+            for _ in 0..count {
+                frames.push(read_u64(&mut r)?);
+            }
+            index.occurrences.insert(key, frames);
+        }
+        Ok(index)
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+// a reader which can be positioned at the start of a block
+pub(crate) trait Cursor {
+    fn read_frame(&mut self) -> io::Result<Option<Frame>>;
+    fn seek_block(&mut self, offset: u64) -> io::Result<()>;
+    fn push_back(&mut self, f: Frame);
+}
+
+fn not_indexed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, "position not in index")
+}
+
+pub(crate) fn seek_to_frame(c: &mut impl Cursor, index: &Index, n: u64) -> io::Result<()> {
+    if n >= index.frames {
+        return Err(not_indexed());
+    }
+    let b = index.block_for_frame(n).ok_or_else(not_indexed)?;
+    c.seek_block(b.offset)?;
+    for _ in b.frame..n {
+        c.read_frame()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn seek_to_time(c: &mut impl Cursor, index: &Index, t: Duration) -> io::Result<()> {
+    let b = index.block_for_time(t).ok_or_else(not_indexed)?;
+    c.seek_block(b.offset)?;
+    while let Some(f) = c.read_frame()? {
+        if f.timestamp.map(|ft| ft >= t).unwrap_or(false) {
+            c.push_back(f);
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn seek_to_occurrence(
+    c: &mut impl Cursor,
+    index: &Index,
+    id: u32,
+    ext: bool,
+    n: usize,
+) -> io::Result<()> {
+    let frame = *index.occurrences(id, ext).get(n).ok_or_else(not_indexed)?;
+    seek_to_frame(c, index, frame)
+}
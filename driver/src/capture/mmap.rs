@@ -0,0 +1,108 @@
+//! Memory-mapped access to delta-encoded captures. Enabled with the `mmap`
+//! feature.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use super::{DeltaReader, Index};
+
+/// A delta-encoded capture mapped into memory.
+///
+/// Frames are decoded directly from the mapping as they are read, so captures
+/// much larger than memory can be processed. Since every block of the capture
+/// can be decoded on its own, blocks can also be processed in parallel.
+pub struct MappedCapture {
+    map: Mmap,
+}
+
+impl MappedCapture {
+    /// Map a capture written with `DeltaWriter`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<MappedCapture> {
+        let file = File::open(path)?;
+        // the file must not be truncated while mapped, as with any mapping
+        let map = unsafe { Mmap::map(&file)? };
+        // check the header
+        DeltaReader::new(&map[..])?;
+        Ok(MappedCapture { map })
+    }
+
+    /// Returns the raw contents of the capture.
+    pub fn data(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Returns a lazy iterator over every frame in the capture.
+    pub fn frames(&self) -> DeltaReader<&[u8]> {
+        DeltaReader::new(&self.map[..]).unwrap()
+    }
+
+    /// Build the index of the capture, if it was not saved when it was written.
+    pub fn scan_index(&self) -> io::Result<Index> {
+        Index::scan_delta(&self.map)
+    }
+
+    /// Returns a parallel iterator over the blocks of the capture, each of which
+    /// is an iterator over the frames in the block.
+    ///
+    /// ```no_run
+    /// use cantact::capture::MappedCapture;
+    /// use rayon::prelude::*;
+    ///
+    /// let capture = MappedCapture::open("drive.ctdl").unwrap();
+    /// let index = capture.scan_index().unwrap();
+    /// let count = capture
+    ///     .par_blocks(&index)
+    ///     .map(|block| block.filter_map(|f| f.ok()).filter(|f| f.raw_id() == 0x123).count())
+    ///     .sum::<usize>();
+    /// ```
+    pub fn par_blocks<'a>(
+        &'a self,
+        index: &Index,
+    ) -> impl ParallelIterator<Item = DeltaReader<&'a [u8]>> + 'a {
+        index
+            .block_ranges(self.map.len() as u64)
+            .into_par_iter()
+            .map(move |(start, end)| DeltaReader::block(&self.map[start as usize..end as usize]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::DeltaWriter;
+    use crate::{Frame, Id};
+    use std::fs;
+
+    #[test]
+    fn test_mapped_capture() {
+        let path = std::env::temp_dir().join(format!("cantact-mmap-{}.ctdl", std::process::id()));
+        let mut w = DeltaWriter::new(File::create(&path).unwrap()).unwrap();
+        w.set_block_size(100);
+        for n in 0..1000u32 {
+            let mut f = Frame {
+                id: Id::new(n % 10, false).unwrap(),
+                can_dlc: 4,
+                ..Default::default()
+            };
+            f.data[0] = n as u8;
+            w.write(&f).unwrap();
+        }
+        let written = w.index().clone();
+        drop(w);
+
+        let capture = MappedCapture::open(&path).unwrap();
+        assert_eq!(capture.frames().count(), 1000);
+        let index = capture.scan_index().unwrap();
+        assert_eq!(index, written);
+        let count: usize = capture
+            .par_blocks(&index)
+            .map(|b| b.filter_map(|f| f.ok()).filter(|f| f.raw_id() == 3).count())
+            .sum();
+        assert_eq!(count, 100);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,34 @@
+//! Reading and writing captures of CAN traffic.
+//!
+//! Frames can be stored in the text format of `candump -l` from can-utils, or in
+//! a compact binary format which delta-encodes cyclic traffic, see `DeltaWriter`.
+//! Both writers build an `Index` of the capture, which the readers use to jump to
+//! a timestamp or to an occurrence of an ID.
+
+use std::io::{self, Write};
+
+use crate::Frame;
+
+mod candump;
+mod delta;
+mod index;
+#[cfg(feature = "mmap")]
+mod mmap;
+pub use candump::{candump_line, parse_candump_line, CandumpReader, CandumpWriter};
+pub use delta::{DeltaReader, DeltaWriter};
+pub use index::Index;
+#[cfg(feature = "mmap")]
+pub use mmap::MappedCapture;
+
+/// Convert a capture to a candump log, returning the number of frames written.
+pub fn write_candump(
+    frames: impl Iterator<Item = io::Result<Frame>>,
+    mut w: impl Write,
+) -> io::Result<u64> {
+    let mut count = 0;
+    for f in frames {
+        writeln!(w, "{}", candump_line(&f?))?;
+        count += 1;
+    }
+    Ok(count)
+}
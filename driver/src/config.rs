@@ -0,0 +1,191 @@
+//! Interface configuration files, so setups can be versioned and reproduced.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Channel, ChannelConfig, Error, Interface, OverflowPolicy, TimestampMode};
+
+/// Configuration of an interface, stored in a TOML or JSON file.
+///
+/// In TOML, each channel is a `[[channel]]` table:
+///
+/// ```toml
+/// rx_queue = 4096
+///
+/// [[channel]]
+/// bitrate = 500000
+/// enabled = true
+/// loopback = false
+/// monitor = false
+/// ```
+///
+/// Load a configuration with `InterfaceConfig::from_toml` or
+/// `InterfaceConfig::from_json`, apply it with `Interface::apply_config`, and
+/// get the configuration of an interface with `Interface::current_config`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InterfaceConfig {
+    /// Configuration of each channel, in order.
+    #[serde(rename = "channel", default)]
+    pub channels: Vec<Channel>,
+    /// Clock used for frame timestamps, or None to keep the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_mode: Option<TimestampMode>,
+    /// Capacity of the queues of received frames, or None for no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_queue: Option<usize>,
+    /// What happens to frames received while a queue is full.
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+}
+
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl InterfaceConfig {
+    /// Load a configuration from a TOML file.
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<InterfaceConfig, Error> {
+        InterfaceConfig::parse_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Load a configuration from a JSON file.
+    pub fn from_json(path: impl AsRef<Path>) -> Result<InterfaceConfig, Error> {
+        InterfaceConfig::parse_json(&fs::read_to_string(path)?)
+    }
+
+    /// Save the configuration to a TOML file.
+    pub fn save_toml(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(fs::write(path, self.to_toml()?)?)
+    }
+
+    /// Save the configuration to a JSON file.
+    pub fn save_json(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let text = serde_json::to_string_pretty(self).map_err(invalid_data)?;
+        Ok(fs::write(path, text)?)
+    }
+
+    fn parse_toml(text: &str) -> Result<InterfaceConfig, Error> {
+        toml::from_str(text).map_err(invalid_data)
+    }
+
+    fn parse_json(text: &str) -> Result<InterfaceConfig, Error> {
+        serde_json::from_str(text).map_err(invalid_data)
+    }
+
+    fn to_toml(&self) -> Result<String, Error> {
+        // going through a Value puts plain values before tables, as TOML requires
+        toml::Value::try_from(self)
+            .map(|v| v.to_string())
+            .map_err(invalid_data)
+    }
+}
+
+impl Interface {
+    /// Apply a configuration loaded from a file. Every channel of the device
+    /// must have a configuration, and the whole configuration is checked before
+    /// any of it is applied. Only the enabled flag of disabled channels is
+    /// applied.
+    ///
+    /// Returns `Error::Running` while the interface is running, and
+    /// `Error::InvalidChannel` if the number of channels does not match the
+    /// device.
+    pub fn apply_config(&mut self, config: &InterfaceConfig) -> Result<(), Error> {
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+        if config.channels.len() != self.channels() {
+            return Err(Error::InvalidChannel);
+        }
+        let hw_timestamp = (0..self.channels()).all(|ch| self.features(ch).hw_timestamp);
+        if config.timestamp_mode == Some(TimestampMode::Hardware) && !hw_timestamp {
+            return Err(Error::Unsupported);
+        }
+        for (n, ch) in config.channels.iter().enumerate() {
+            if ch.enabled {
+                ChannelConfig::from(ch).validate(
+                    self.features(n),
+                    self.can_clock,
+                    &self.limits[n],
+                )?;
+            }
+        }
+
+        for (n, ch) in config.channels.iter().enumerate() {
+            self.set_enabled(n, ch.enabled)?;
+            if ch.enabled {
+                self.configure_channel(n, &ChannelConfig::from(ch))?;
+            }
+        }
+        if let Some(mode) = config.timestamp_mode {
+            self.set_timestamp_mode(mode)?;
+        }
+        self.set_rx_queue(config.rx_queue, config.overflow_policy)
+    }
+
+    /// Returns the current configuration of the interface, which can be saved
+    /// to a file and applied again with `Interface::apply_config`.
+    pub fn current_config(&self) -> InterfaceConfig {
+        InterfaceConfig {
+            channels: self.channels.clone(),
+            timestamp_mode: Some(self.timestamp_mode),
+            rx_queue: self.rx_capacity,
+            overflow_policy: self.rx_policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let channel = Channel {
+            bitrate: 500_000,
+            enabled: true,
+            loopback: false,
+            monitor: true,
+            one_shot: false,
+            fd: true,
+            data_bitrate: 2_000_000,
+            triple_sample: false,
+        };
+        let config = InterfaceConfig {
+            channels: vec![
+                channel.clone(),
+                Channel {
+                    enabled: false,
+                    ..channel
+                },
+            ],
+            timestamp_mode: Some(TimestampMode::WallClock),
+            rx_queue: Some(4096),
+            overflow_policy: OverflowPolicy::DropOldest,
+        };
+
+        let toml = config.to_toml().unwrap();
+        assert!(toml.contains("[[channel]]"));
+        let loaded = InterfaceConfig::parse_toml(&toml).unwrap();
+        assert_eq!(loaded.channels.len(), 2);
+        assert!(loaded.channels[0].monitor && !loaded.channels[1].enabled);
+        assert_eq!(loaded.rx_queue, Some(4096));
+        assert_eq!(loaded.overflow_policy, OverflowPolicy::DropOldest);
+
+        let json = serde_json::to_string(&config).unwrap();
+        let loaded = InterfaceConfig::parse_json(&json).unwrap();
+        assert_eq!(loaded.channels[0].data_bitrate, 2_000_000);
+        assert_eq!(loaded.timestamp_mode, Some(TimestampMode::WallClock));
+
+        // older files only have the channels
+        let loaded = InterfaceConfig::parse_toml(
+            "[[channel]]\nbitrate = 250000\nenabled = true\nloopback = false\nmonitor = false\n",
+        )
+        .unwrap();
+        assert_eq!(loaded.channels[0].bitrate, 250_000);
+        assert_eq!(loaded.overflow_policy, OverflowPolicy::Block);
+        assert!(InterfaceConfig::parse_toml("[[channel]]\nbitrate = 1\n").is_err());
+    }
+}
@@ -0,0 +1,385 @@
+//! Message and signal definitions, and packing of signals into frames.
+
+use std::collections::HashMap;
+
+use crate::{len_to_dlc, Frame, Id};
+
+// bit 31 of a DBC message ID marks an extended ID
+const DBC_EXTENDED: u32 = 0x8000_0000;
+
+/// Errors when loading a database or looking up messages and signals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseError {
+    /// No message with this name is defined.
+    UnknownMessage(String),
+    /// The message has no signal with this name.
+    UnknownSignal(String),
+    /// A DBC file could not be parsed. Contains the line number of the error.
+    Parse(usize),
+    /// The message's ID is out of range for its type of ID. Contains the name
+    /// of the message.
+    InvalidId(String),
+}
+
+/// Order of the bytes of a signal within the frame data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Least significant byte first (Intel).
+    LittleEndian,
+    /// Most significant byte first (Motorola). The start bit is the most
+    /// significant bit of the signal, numbered as in DBC files.
+    BigEndian,
+}
+
+/// A value packed into the data of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    /// Name of the signal.
+    pub name: String,
+    /// Position of the signal's first bit in the frame data.
+    pub start_bit: u32,
+    /// Length of the signal in bits, from 1 to 64.
+    pub length: u32,
+    /// Byte order of the signal.
+    pub byte_order: ByteOrder,
+    /// True if the raw value is two's complement signed.
+    pub signed: bool,
+    /// Scale from the raw value to the physical value.
+    pub factor: f64,
+    /// Offset added to the scaled raw value to get the physical value.
+    pub offset: f64,
+    /// Unit of the physical value, may be empty.
+    pub unit: String,
+}
+
+impl Signal {
+    fn mask(&self) -> u64 {
+        if self.length >= 64 {
+            u64::MAX
+        } else {
+            (1 << self.length) - 1
+        }
+    }
+
+    // position in the data of each bit of the signal, least significant first
+    fn bits(&self) -> Vec<usize> {
+        let mut bits = Vec::with_capacity(self.length as usize);
+        let mut pos = self.start_bit as usize;
+        match self.byte_order {
+            ByteOrder::LittleEndian => bits.extend(pos..pos + self.length as usize),
+            ByteOrder::BigEndian => {
+                // the start bit is the most significant bit, the following bits
+                // run down each byte and continue in the next byte
+                for _ in 0..self.length {
+                    bits.push(pos);
+                    pos = if pos.is_multiple_of(8) {
+                        pos + 15
+                    } else {
+                        pos - 1
+                    };
+                }
+                bits.reverse();
+            }
+        }
+        bits
+    }
+
+    /// Read the physical value of the signal from frame data. Bits beyond the end
+    /// of the data read as zero.
+    pub fn decode(&self, data: &[u8]) -> f64 {
+        let mut raw = 0u64;
+        for (i, pos) in self.bits().into_iter().enumerate() {
+            if data
+                .get(pos / 8)
+                .map(|b| b >> (pos % 8) & 1 != 0)
+                .unwrap_or(false)
+            {
+                raw |= 1 << i;
+            }
+        }
+        let raw = if self.signed && self.length < 64 && raw >> (self.length - 1) != 0 {
+            (raw | !self.mask()) as i64 as f64
+        } else if self.signed {
+            raw as i64 as f64
+        } else {
+            raw as f64
+        };
+        raw * self.factor + self.offset
+    }
+
+    /// Write the physical value of the signal into frame data, leaving other bits
+    /// unchanged. Values out of range for the signal are truncated, and bits beyond
+    /// the end of the data are dropped.
+    pub fn encode(&self, data: &mut [u8], value: f64) {
+        let raw = ((value - self.offset) / self.factor).round() as i64 as u64 & self.mask();
+        for (i, pos) in self.bits().into_iter().enumerate() {
+            if let Some(b) = data.get_mut(pos / 8) {
+                if raw >> i & 1 != 0 {
+                    *b |= 1 << (pos % 8);
+                } else {
+                    *b &= !(1 << (pos % 8));
+                }
+            }
+        }
+    }
+}
+
+/// A message definition: a CAN ID and the signals carried in its data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// Name of the message.
+    pub name: String,
+    /// CAN arbitration ID of the message.
+    pub id: u32,
+    /// True if the message uses an extended (29 bit) ID.
+    pub ext: bool,
+    /// Length of the message data in bytes, up to 64 for FD messages.
+    pub length: u8,
+    /// Signals packed into the message.
+    pub signals: Vec<Signal>,
+}
+
+impl Message {
+    /// Returns the signal with the given name.
+    pub fn signal(&self, name: &str) -> Result<&Signal, DatabaseError> {
+        self.signals
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| DatabaseError::UnknownSignal(name.to_string()))
+    }
+
+    /// Build a frame for this message. Signals which are not given are sent with a
+    /// raw value of zero.
+    pub fn encode(&self, values: &[(&str, f64)]) -> Result<Frame, DatabaseError> {
+        let id = Id::new(self.id, self.ext)
+            .ok_or_else(|| DatabaseError::InvalidId(self.name.clone()))?;
+        let mut f = Frame {
+            id,
+            // messages longer than 8 bytes are sent as FD frames
+            fd: self.length > 8,
+            ..Default::default()
+        };
+        f.can_dlc = len_to_dlc(self.length as usize, f.fd);
+        for (name, value) in values.iter() {
+            self.signal(name)?
+                .encode(&mut f.data[..(self.length as usize).min(64)], *value);
+        }
+        Ok(f)
+    }
+
+    /// Returns the physical value of every signal in a frame of this message.
+    pub fn decode(&self, f: &Frame) -> HashMap<String, f64> {
+        self.signals
+            .iter()
+            .map(|s| (s.name.clone(), s.decode(f.payload())))
+            .collect()
+    }
+
+    /// Returns true if the frame is an instance of this message.
+    pub fn matches(&self, f: &Frame) -> bool {
+        f.raw_id() == self.id && f.is_extended() == self.ext && !f.rtr
+    }
+}
+
+/// A set of message definitions, such as those loaded from a DBC file.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    messages: Vec<Message>,
+}
+
+impl Database {
+    /// Create an empty database.
+    pub fn new() -> Database {
+        Database::default()
+    }
+
+    /// Parse the message and signal definitions (`BO_` and `SG_` lines) of a DBC
+    /// file. Other sections are ignored. Multiplexed signals are treated as
+    /// ordinary signals.
+    pub fn from_dbc(dbc: &str) -> Result<Database, DatabaseError> {
+        let mut db = Database::new();
+        for (n, line) in dbc.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with("BO_ ") {
+                db.messages
+                    .push(parse_message(line).ok_or(DatabaseError::Parse(n + 1))?);
+            } else if line.starts_with("SG_ ") {
+                let signal = parse_signal(line).ok_or(DatabaseError::Parse(n + 1))?;
+                db.messages
+                    .last_mut()
+                    .ok_or(DatabaseError::Parse(n + 1))?
+                    .signals
+                    .push(signal);
+            }
+        }
+        Ok(db)
+    }
+
+    /// Add a message definition, replacing any message with the same name.
+    pub fn add_message(&mut self, message: Message) {
+        self.messages.retain(|m| m.name != message.name);
+        self.messages.push(message);
+    }
+
+    /// Returns the message with the given name.
+    pub fn message(&self, name: &str) -> Result<&Message, DatabaseError> {
+        self.messages
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| DatabaseError::UnknownMessage(name.to_string()))
+    }
+
+    /// Returns the first message with a signal of the given name.
+    pub fn signal_owner(&self, signal: &str) -> Result<&Message, DatabaseError> {
+        self.messages
+            .iter()
+            .find(|m| m.signals.iter().any(|s| s.name == signal))
+            .ok_or_else(|| DatabaseError::UnknownSignal(signal.to_string()))
+    }
+
+    /// Returns the message which the frame is an instance of, if any.
+    pub fn message_for(&self, f: &Frame) -> Option<&Message> {
+        self.messages.iter().find(|m| m.matches(f))
+    }
+
+    /// Returns all messages in the database.
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+}
+
+// BO_ <id> <name>: <length> <sender>
+fn parse_message(line: &str) -> Option<Message> {
+    let mut parts = line.split_whitespace().skip(1);
+    let id: u32 = parts.next()?.parse().ok()?;
+    let name = parts.next()?.strip_suffix(':')?.to_string();
+    let length: u8 = parts.next()?.parse().ok()?;
+    Some(Message {
+        name,
+        id: id & !DBC_EXTENDED,
+        ext: id & DBC_EXTENDED != 0,
+        length: length.min(64),
+        signals: vec![],
+    })
+}
+
+// SG_ <name> [M|m<n>] : <start>|<length>@<order><sign> (<factor>,<offset>) [<min>|<max>] "<unit>" <receivers>
+fn parse_signal(line: &str) -> Option<Signal> {
+    let (head, tail) = line.split_at(line.find(':')?);
+    let name = head.split_whitespace().nth(1)?.to_string();
+    let mut parts = tail[1..].split_whitespace();
+
+    let layout = parts.next()?;
+    let (start_bit, rest) = layout.split_at(layout.find('|')?);
+    let (length, format) = rest[1..].split_at(rest.find('@')? - 1);
+    let mut format = format[1..].chars();
+    let byte_order = match format.next()? {
+        '0' => ByteOrder::BigEndian,
+        '1' => ByteOrder::LittleEndian,
+        _ => return None,
+    };
+    let signed = match format.next()? {
+        '+' => false,
+        '-' => true,
+        _ => return None,
+    };
+
+    let scale = parts.next()?.strip_prefix('(')?.strip_suffix(')')?;
+    let mut scale = scale.split(',');
+    let factor: f64 = scale.next()?.parse().ok()?;
+    let offset: f64 = scale.next()?.parse().ok()?;
+
+    let unit = match (tail.find('"'), tail.rfind('"')) {
+        (Some(a), Some(b)) if b > a => tail[a + 1..b].to_string(),
+        _ => String::new(),
+    };
+
+    let length: u32 = length.parse().ok()?;
+    if length == 0 || length > 64 {
+        return None;
+    }
+    Some(Signal {
+        name,
+        start_bit: start_bit.parse().ok()?,
+        length,
+        byte_order,
+        signed,
+        factor,
+        offset,
+        unit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"
+VERSION ""
+
+BU_: ECU Tester
+
+BO_ 256 EngineStatus: 8 ECU
+ SG_ Rpm : 0|16@1+ (0.25,0) [0|16383.75] "rpm" Tester
+ SG_ Temp : 16|8@1- (1,-40) [-40|215] "degC" Tester
+ SG_ Pressure : 31|12@0+ (0.5,0) [0|2047.5] "kPa" Tester
+
+BO_ 2147484417 EngineCmd: 4 Tester
+ SG_ Enable : 0|1@1+ (1,0) [0|1] "" ECU
+
+BO_ 1024 BatteryCells: 64 ECU
+ SG_ Cell1 : 0|16@1+ (0.001,0) [0|65.535] "V" Tester
+ SG_ Cell32 : 496|16@1+ (0.001,0) [0|65.535] "V" Tester
+"#;
+
+    #[test]
+    fn test_database() {
+        let db = Database::from_dbc(DBC).unwrap();
+        assert_eq!(db.messages().len(), 3);
+        let cmd = db.message("EngineCmd").unwrap();
+        assert_eq!((cmd.id, cmd.ext, cmd.length), (0x301, true, 4));
+
+        let status = db.message("EngineStatus").unwrap();
+        assert_eq!(status.signal("Rpm").unwrap().unit, "rpm");
+        let f = status
+            .encode(&[("Rpm", 1000.0), ("Temp", -10.0), ("Pressure", 300.0)])
+            .unwrap();
+        assert_eq!(f.data[..5], [0xA0, 0x0F, 0x1E, 0x25, 0x80]);
+        assert!(db.message_for(&f).is_some());
+        assert_eq!(db.signal_owner("Enable").unwrap().name, "EngineCmd");
+
+        let values = status.decode(&f);
+        assert_eq!(values["Rpm"], 1000.0);
+        assert_eq!(values["Temp"], -10.0);
+        assert_eq!(values["Pressure"], 300.0);
+
+        assert_eq!(
+            status.encode(&[("Speed", 1.0)]).unwrap_err(),
+            DatabaseError::UnknownSignal(String::from("Speed"))
+        );
+        // messages over 8 bytes are FD frames
+        let cells = db.message("BatteryCells").unwrap();
+        assert_eq!(cells.length, 64);
+        let f = cells.encode(&[("Cell1", 3.3), ("Cell32", 4.2)]).unwrap();
+        assert!(f.fd);
+        assert_eq!((f.can_dlc, f.len()), (15, 64));
+        assert_eq!(f.data[62..], [0x68, 0x10]);
+        let values = cells.decode(&f);
+        assert!((values["Cell1"] - 3.3).abs() < 1e-9);
+        assert!((values["Cell32"] - 4.2).abs() < 1e-9);
+        let short = parse_message("BO_ 1025 Short: 10 ECU").unwrap();
+        assert_eq!(short.encode(&[]).unwrap().len(), 12);
+        assert_eq!(parse_message("BO_ 1026 Long: 200 ECU").unwrap().length, 64);
+        // IDs are not truncated to fit their type
+        let wide = Message { id: 0x800, ..short };
+        assert_eq!(
+            wide.encode(&[]).unwrap_err(),
+            DatabaseError::InvalidId(String::from("Short"))
+        );
+
+        assert_eq!(
+            Database::from_dbc("BO_ 1 Broken 8 ECU").unwrap_err(),
+            DatabaseError::Parse(1)
+        );
+    }
+}
@@ -5,15 +5,90 @@
 pub(crate) const GSUSB_EXT_FLAG: u32 = 0x8000_0000;
 // can id is OR'd with flag when frame is RTR
 pub(crate) const GSUSB_RTR_FLAG: u32 = 0x4000_0000;
+// can id is OR'd with flag when frame is an error frame
+pub(crate) const GSUSB_ERR_FLAG: u32 = 0x2000_0000;
 // echo id for non-loopback frames
 pub(crate) const GSUSB_RX_ECHO_ID: u32 = 0xFFFF_FFFF;
 
+pub(crate) use crate::BitTiming;
+use crate::{BitTimingLimits, Direction, Frame, Id, MAX_EXTENDED_ID, MAX_STANDARD_ID};
+
+// host frame flags
+pub(crate) const GSUSB_FLAG_OVERFLOW: u8 = 1;
+pub(crate) const GSUSB_FLAG_FD: u8 = 1 << 1;
+pub(crate) const GSUSB_FLAG_BRS: u8 = 1 << 2;
+pub(crate) const GSUSB_FLAG_ESI: u8 = 1 << 3;
+
+// size of the host frame header, before the data
+pub(crate) const HOST_FRAME_HEADER_SIZE: usize = 12;
+// data bytes carried by classic and FD host frames
+pub(crate) const HOST_FRAME_CLASSIC_DATA: usize = 8;
+pub(crate) const HOST_FRAME_FD_DATA: usize = 64;
+
 // device features bit map
 pub(crate) const GSUSB_FEATURE_LISTEN_ONLY: u32 = 1;
 pub(crate) const GSUSB_FEATURE_LOOP_BACK: u32 = 1 << 1;
+pub(crate) const GSUSB_FEATURE_TRIPLE_SAMPLE: u32 = 1 << 2;
+pub(crate) const GSUSB_FEATURE_ONE_SHOT: u32 = 1 << 3;
+pub(crate) const GSUSB_FEATURE_HW_TIMESTAMP: u32 = 1 << 4;
+pub(crate) const GSUSB_FEATURE_IDENTIFY: u32 = 1 << 5;
+pub(crate) const GSUSB_FEATURE_USER_ID: u32 = 1 << 6;
+pub(crate) const GSUSB_FEATURE_PAD_PKTS_TO_MAX_PKT_SIZE: u32 = 1 << 7;
+pub(crate) const GSUSB_FEATURE_FD: u32 = 1 << 8;
+pub(crate) const GSUSB_FEATURE_REQ_USB_QUIRK_LPC546XX: u32 = 1 << 9;
+pub(crate) const GSUSB_FEATURE_BT_CONST_EXT: u32 = 1 << 10;
+pub(crate) const GSUSB_FEATURE_TERMINATION: u32 = 1 << 11;
+pub(crate) const GSUSB_FEATURE_BERR_REPORTING: u32 = 1 << 12;
+pub(crate) const GSUSB_FEATURE_GET_STATE: u32 = 1 << 13;
+// acceptance filters set with the SetFilter request, an extension of the
+// gs_usb protocol which is only implemented by some firmware
+pub(crate) const GSUSB_FEATURE_HW_FILTER: u32 = 1 << 14;
+
+// number of ID/mask pairs in a SetFilter request
+pub(crate) const GSUSB_FILTER_SLOTS: usize = 6;
+
+// identify modes
+pub(crate) const GSUSB_IDENTIFY_OFF: u32 = 0;
+pub(crate) const GSUSB_IDENTIFY_ON: u32 = 1;
+
+// states of the termination resistor
+pub(crate) const GSUSB_TERMINATION_DISABLED: u32 = 0;
+pub(crate) const GSUSB_TERMINATION_ENABLED: u32 = 1;
+
+// error classes, OR'd into the can id of error frames (see linux/can/error.h)
+pub(crate) const CAN_ERR_TX_TIMEOUT: u32 = 0x0000_0001;
+pub(crate) const CAN_ERR_LOSTARB: u32 = 0x0000_0002;
+pub(crate) const CAN_ERR_CRTL: u32 = 0x0000_0004;
+pub(crate) const CAN_ERR_PROT: u32 = 0x0000_0008;
+pub(crate) const CAN_ERR_TRX: u32 = 0x0000_0010;
+pub(crate) const CAN_ERR_ACK: u32 = 0x0000_0020;
+pub(crate) const CAN_ERR_BUSOFF: u32 = 0x0000_0040;
+pub(crate) const CAN_ERR_BUSERROR: u32 = 0x0000_0080;
+pub(crate) const CAN_ERR_RESTARTED: u32 = 0x0000_0100;
+
+// controller problems, reported in data[1] of error frames
+pub(crate) const CAN_ERR_CRTL_RX_OVERFLOW: u8 = 0x01;
+pub(crate) const CAN_ERR_CRTL_TX_OVERFLOW: u8 = 0x02;
+pub(crate) const CAN_ERR_CRTL_RX_WARNING: u8 = 0x04;
+pub(crate) const CAN_ERR_CRTL_TX_WARNING: u8 = 0x08;
+pub(crate) const CAN_ERR_CRTL_RX_PASSIVE: u8 = 0x10;
+pub(crate) const CAN_ERR_CRTL_TX_PASSIVE: u8 = 0x20;
+pub(crate) const CAN_ERR_CRTL_ACTIVE: u8 = 0x40;
+
+// protocol violations, reported in data[2] of error frames
+pub(crate) const CAN_ERR_PROT_BIT: u8 = 0x01;
+pub(crate) const CAN_ERR_PROT_FORM: u8 = 0x02;
+pub(crate) const CAN_ERR_PROT_STUFF: u8 = 0x04;
+pub(crate) const CAN_ERR_PROT_BIT0: u8 = 0x08;
+pub(crate) const CAN_ERR_PROT_BIT1: u8 = 0x10;
+pub(crate) const CAN_ERR_PROT_OVERLOAD: u8 = 0x20;
+
+// location of protocol violations, reported in data[3] of error frames
+pub(crate) const CAN_ERR_PROT_LOC_CRC_SEQ: u8 = 0x08;
+pub(crate) const CAN_ERR_PROT_LOC_CRC_DEL: u8 = 0x18;
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum UsbBreq {
     HostFormat = 0,
     BitTiming,
@@ -23,6 +98,38 @@ pub(crate) enum UsbBreq {
     DeviceConfig,
     Timestamp,
     Identify,
+    GetUserId,
+    SetUserId,
+    DataBitTiming,
+    BitTimingConstsExt,
+    SetTermination,
+    GetTermination,
+    GetState,
+    SetFilter,
+}
+
+impl UsbBreq {
+    /// Name of the request, for error messages.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            UsbBreq::HostFormat => "host format",
+            UsbBreq::BitTiming => "bit timing",
+            UsbBreq::Mode => "mode",
+            UsbBreq::Berr => "bus error reporting",
+            UsbBreq::BitTimingConsts => "bit timing constants",
+            UsbBreq::DeviceConfig => "device config",
+            UsbBreq::Timestamp => "timestamp",
+            UsbBreq::Identify => "identify",
+            UsbBreq::GetUserId => "get user id",
+            UsbBreq::SetUserId => "set user id",
+            UsbBreq::DataBitTiming => "data bit timing",
+            UsbBreq::BitTimingConstsExt => "extended bit timing constants",
+            UsbBreq::SetTermination => "set termination",
+            UsbBreq::GetTermination => "get termination",
+            UsbBreq::GetState => "get state",
+            UsbBreq::SetFilter => "set filter",
+        }
+    }
 }
 #[repr(u8)]
 pub(crate) enum CanMode {
@@ -59,31 +166,20 @@ impl Mode {
     }
 }
 
-#[repr(C)]
-#[derive(Debug)]
-pub(crate) struct BitTiming {
-    pub prop_seg: u32,
-    pub phase_seg1: u32,
-    pub phase_seg2: u32,
-    pub sjw: u32,
-    pub brp: u32,
-}
-impl BitTiming {
-    pub(crate) fn to_le_bytes(&self) -> Vec<u8> {
-        let mut data: Vec<u8> = vec![];
-        data.extend_from_slice(&self.prop_seg.to_le_bytes());
-        data.extend_from_slice(&self.phase_seg1.to_le_bytes());
-        data.extend_from_slice(&self.phase_seg2.to_le_bytes());
-        data.extend_from_slice(&self.sjw.to_le_bytes());
-        data.extend_from_slice(&self.brp.to_le_bytes());
-        data
-    }
+pub(crate) fn bit_timing_to_le_bytes(bt: BitTiming) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![];
+    data.extend_from_slice(&bt.prop_seg.to_le_bytes());
+    data.extend_from_slice(&bt.phase_seg1.to_le_bytes());
+    data.extend_from_slice(&bt.phase_seg2.to_le_bytes());
+    data.extend_from_slice(&bt.sjw.to_le_bytes());
+    data.extend_from_slice(&bt.brp.to_le_bytes());
+    data
 }
 
 #[derive(Debug)]
 #[repr(C)]
 pub(crate) struct BitTimingConsts {
-    feature: u32,
+    pub(crate) feature: u32,
     pub(crate) fclk_can: u32,
     tseg1_min: u32,
     tseg1_max: u32,
@@ -109,6 +205,19 @@ impl BitTimingConsts {
             brp_inc: u32_from_le_bytes(&bs[36..40]),
         }
     }
+
+    pub(crate) fn limits(&self) -> BitTimingLimits {
+        BitTimingLimits {
+            tseg1_min: self.tseg1_min,
+            tseg1_max: self.tseg1_max,
+            tseg2_min: self.tseg2_min,
+            tseg2_max: self.tseg2_max,
+            sjw_max: self.sjw_max,
+            brp_min: self.brp_min,
+            brp_max: self.brp_max,
+            brp_inc: self.brp_inc,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -134,6 +243,24 @@ impl DeviceConfig {
     }
 }
 
+// controller state of a channel, returned by the GetState request
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct DeviceState {
+    pub(crate) state: u32,
+    pub(crate) rxerr: u32,
+    pub(crate) txerr: u32,
+}
+impl DeviceState {
+    pub(crate) fn from_le_bytes(bs: &[u8]) -> DeviceState {
+        DeviceState {
+            state: u32_from_le_bytes(&bs[0..4]),
+            rxerr: u32_from_le_bytes(&bs[4..8]),
+            txerr: u32_from_le_bytes(&bs[8..12]),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub(crate) struct HostFrame {
@@ -145,20 +272,35 @@ pub(crate) struct HostFrame {
     pub flags: u8,
     pub reserved: u8,
 
-    pub data: [u8; 8],
+    // 8 bytes are transferred for classic frames, 64 for FD frames
+    pub data: [u8; 64],
+
+    // device time in microseconds, sent after the data when hardware
+    // timestamps are enabled
+    pub timestamp_us: Option<u32>,
 }
 impl HostFrame {
     pub(crate) fn from_le_bytes(bs: &[u8]) -> HostFrame {
+        let flags = bs[10];
+        let len = if flags & GSUSB_FLAG_FD > 0 {
+            HOST_FRAME_FD_DATA
+        } else {
+            HOST_FRAME_CLASSIC_DATA
+        };
+        let bs_data = &bs[HOST_FRAME_HEADER_SIZE..];
+        let timestamp_us = bs_data.get(len..len + 4).map(u32_from_le_bytes);
+        let len = len.min(bs_data.len());
+        let mut data = [0u8; 64];
+        data[..len].copy_from_slice(&bs_data[..len]);
         HostFrame {
             echo_id: u32_from_le_bytes(&bs[0..4]),
             can_id: u32_from_le_bytes(&bs[4..8]),
             can_dlc: bs[8],
             channel: bs[9],
-            flags: bs[10],
+            flags,
             reserved: bs[11],
-            data: [
-                bs[12], bs[13], bs[14], bs[15], bs[16], bs[17], bs[18], bs[19],
-            ],
+            data,
+            timestamp_us,
         }
     }
     pub(crate) fn to_le_bytes(&self) -> Vec<u8> {
@@ -169,7 +311,88 @@ impl HostFrame {
         data.push(self.channel);
         data.push(self.flags);
         data.push(self.reserved);
-        data.extend_from_slice(&self.data);
+        if self.flags & GSUSB_FLAG_FD > 0 {
+            data.extend_from_slice(&self.data);
+        } else {
+            data.extend_from_slice(&self.data[..HOST_FRAME_CLASSIC_DATA]);
+        }
         data
     }
+
+    // convert a frame to the format expected by the device
+    pub(crate) fn from_frame(f: &Frame, echo_id: u32) -> HostFrame {
+        // if frame is extended, set the extended bit in host frame CAN ID
+        let mut can_id = if f.id.is_extended() {
+            f.id.as_raw() | GSUSB_EXT_FLAG
+        } else {
+            f.id.as_raw()
+        };
+        // if frame is RTR, set the RTR bit in host frame CAN ID
+        can_id = if f.rtr {
+            can_id | GSUSB_RTR_FLAG
+        } else {
+            can_id
+        };
+        // BRS and ESI only exist in FD frames
+        let mut flags = f.raw_flags.unwrap_or(0);
+        if f.fd {
+            flags |= GSUSB_FLAG_FD;
+            if f.brs {
+                flags |= GSUSB_FLAG_BRS;
+            }
+            if f.esi {
+                flags |= GSUSB_FLAG_ESI;
+            }
+        }
+        HostFrame {
+            echo_id,
+            flags,
+            reserved: 0,
+            can_id,
+            can_dlc: f.can_dlc,
+            channel: f.channel,
+            data: f.data,
+            timestamp_us: None,
+        }
+    }
+
+    pub(crate) fn to_frame(&self) -> Frame {
+        // check the extended bit of host frame
+        // if set, frame is extended
+        let ext = (self.can_id & GSUSB_EXT_FLAG) > 0;
+        // check the RTR bit of host frame
+        // if set, frame is RTR
+        let rtr = (self.can_id & GSUSB_RTR_FLAG) > 0;
+        // remove flags from CAN ID, ignoring the bits beyond the range of the ID
+        // type as devices do
+        let mask = if ext {
+            MAX_EXTENDED_ID
+        } else {
+            MAX_STANDARD_ID
+        };
+        let id = Id::new(self.can_id & mask, ext).unwrap();
+        // echo of a sent frame if echo_id is not -1
+        let direction = if self.echo_id != GSUSB_RX_ECHO_ID {
+            Direction::TxEcho(self.echo_id)
+        } else {
+            Direction::Rx
+        };
+
+        Frame {
+            id,
+            can_dlc: self.can_dlc,
+            data: self.data,
+            channel: self.channel,
+            direction,
+            rtr,
+            fd: self.flags & GSUSB_FLAG_FD > 0,
+            brs: self.flags & GSUSB_FLAG_BRS > 0,
+            esi: self.flags & GSUSB_FLAG_ESI > 0,
+            timestamp: None,
+            raw_flags: match self.flags & !(GSUSB_FLAG_FD | GSUSB_FLAG_BRS | GSUSB_FLAG_ESI) {
+                0 => None,
+                bits => Some(bits),
+            },
+        }
+    }
 }
@@ -0,0 +1,119 @@
+//! USB hotplug notifications from libusb.
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use libc::{c_int, c_void, timeval};
+use libusb1_sys::constants::*;
+use libusb1_sys::*;
+
+use super::{serial_number, Error, UsbContext, EVENT_TIMEOUT_US, USB_PID, USB_VID};
+
+/// A CANtact device attached to or removed from the bus.
+pub(crate) struct UsbEvent {
+    pub(crate) arrived: bool,
+    pub(crate) bus: u8,
+    pub(crate) address: u8,
+    // only read for arriving devices, removed ones can't be opened
+    pub(crate) serial: Option<String>,
+}
+
+// reference to a device passed from the hotplug callback to `Hotplug::poll`
+struct DeviceRef(*mut libusb_device);
+
+unsafe impl Send for DeviceRef {}
+
+impl Drop for DeviceRef {
+    fn drop(&mut self) {
+        unsafe { libusb_unref_device(self.0) }
+    }
+}
+
+extern "system" fn hotplug_cb(
+    _ctx: *mut libusb_context,
+    device: *mut libusb_device,
+    event: c_int,
+    user_data: *mut c_void,
+) -> c_int {
+    let send = unsafe { &*(user_data as *const Sender<(bool, DeviceRef)>) };
+    // no requests to the device are allowed from the callback, so it is handed
+    // to `Hotplug::poll` to read the serial number
+    let dev = DeviceRef(unsafe { libusb_ref_device(device) });
+    send.send((event == LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED, dev))
+        .ok();
+    // keep the callback registered
+    0
+}
+
+/// Returns true if libusb supports hotplug notifications on this system.
+pub(crate) fn supported() -> bool {
+    unsafe { libusb_has_capability(LIBUSB_CAP_HAS_HOTPLUG) != 0 }
+}
+
+/// A registered hotplug callback. Events are collected with `Hotplug::poll`.
+pub(crate) struct Hotplug {
+    ctx: UsbContext,
+    handle: libusb_hotplug_callback_handle,
+    events: Receiver<(bool, DeviceRef)>,
+    // the sender is referenced by the callback until it is deregistered
+    _send: Box<Sender<(bool, DeviceRef)>>,
+}
+
+unsafe impl Send for Hotplug {}
+
+impl Hotplug {
+    /// Register for notifications. Devices which are already attached are
+    /// reported as arrived by the first `Hotplug::poll`.
+    pub(crate) fn new(ctx: UsbContext) -> Result<Hotplug, Error> {
+        let (send, events) = unbounded();
+        let send = Box::new(send);
+        let mut handle = 0;
+        match unsafe {
+            libusb_hotplug_register_callback(
+                ctx.as_ptr(),
+                LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED | LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT,
+                LIBUSB_HOTPLUG_ENUMERATE,
+                USB_VID as c_int,
+                USB_PID as c_int,
+                LIBUSB_HOTPLUG_MATCH_ANY,
+                hotplug_cb,
+                &*send as *const _ as *mut c_void,
+                &mut handle,
+            )
+        } {
+            LIBUSB_SUCCESS => {}
+            e => return Err(Error::Libusb("libusb_hotplug_register_callback", e)),
+        }
+        Ok(Hotplug {
+            ctx,
+            handle,
+            events,
+            _send: send,
+        })
+    }
+
+    /// Wait a short time for devices to be attached or removed, and return the
+    /// events which happened.
+    pub(crate) fn poll(&mut self) -> Vec<UsbEvent> {
+        let tv = timeval {
+            tv_sec: 0,
+            tv_usec: EVENT_TIMEOUT_US,
+        };
+        unsafe {
+            libusb_handle_events_timeout(self.ctx.as_ptr(), &tv);
+        }
+        self.events
+            .try_iter()
+            .map(|(arrived, dev)| UsbEvent {
+                arrived,
+                bus: unsafe { libusb_get_bus_number(dev.0) },
+                address: unsafe { libusb_get_device_address(dev.0) },
+                serial: if arrived { serial_number(dev.0) } else { None },
+            })
+            .collect()
+    }
+}
+
+impl Drop for Hotplug {
+    fn drop(&mut self) {
+        unsafe { libusb_hotplug_deregister_callback(self.ctx.as_ptr(), self.handle) }
+    }
+}
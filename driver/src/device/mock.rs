@@ -0,0 +1,227 @@
+//! A device which answers requests without any hardware, and fails the ones
+//! chosen by a test, to check how USB failures are handled.
+
+use std::sync::{Arc, Mutex};
+
+use super::*;
+use crate::{Frame, Interface};
+
+// clock of the CAN controller of the mock device
+const MOCK_CAN_CLOCK: u32 = 48_000_000;
+
+/// Transfers which fail on the mock device.
+#[derive(Debug, Default)]
+pub(crate) struct Faults {
+    /// Control requests which stall.
+    pub(crate) requests: Vec<UsbBreq>,
+    /// Bulk out transfers fail.
+    pub(crate) send: bool,
+    /// Sent frames are not echoed, as if no other node acknowledged them.
+    pub(crate) no_echo: bool,
+    /// Submitting the bulk in transfers fails.
+    pub(crate) start: bool,
+    /// The device is unplugged, every transfer fails.
+    pub(crate) unplugged: bool,
+}
+
+// control request with its channel and data
+pub(crate) type Request = (UsbBreq, u16, Vec<u8>);
+
+/// Handle to the faults of a mock device, which can be changed while the device
+/// is in use.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Mock {
+    pub(crate) faults: Arc<Mutex<Faults>>,
+    /// Feature flags advertised by every channel, besides listen only and
+    /// loopback.
+    pub(crate) features: u32,
+    /// Control requests written to the device, with their channel and data.
+    pub(crate) written: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Mock {
+    pub(super) fn control_out(
+        &self,
+        req: UsbBreq,
+        channel: u16,
+        data: &[u8],
+        disconnected: &AtomicBool,
+    ) -> Result<(), Error> {
+        self.check(req, disconnected)?;
+        self.written
+            .lock()
+            .unwrap()
+            .push((req, channel, data.to_vec()));
+        Ok(())
+    }
+
+    fn check(&self, req: UsbBreq, disconnected: &AtomicBool) -> Result<(), Error> {
+        let faults = self.faults.lock().unwrap();
+        if faults.unplugged {
+            disconnected.store(true, Ordering::SeqCst);
+            return Err(Error::ControlTransfer(
+                req.name(),
+                LIBUSB_TRANSFER_NO_DEVICE,
+            ));
+        }
+        if faults.requests.contains(&req) {
+            return Err(Error::ControlTransfer(req.name(), LIBUSB_TRANSFER_STALL));
+        }
+        Ok(())
+    }
+
+    pub(super) fn control_in(
+        &self,
+        req: UsbBreq,
+        _channel: u16,
+        len: usize,
+        disconnected: &AtomicBool,
+    ) -> Result<Vec<u8>, Error> {
+        self.check(req, disconnected)?;
+        let mut data = vec![0u8; len];
+        match req {
+            UsbBreq::DeviceConfig => {
+                // two channels, icount is zero indexed
+                data[3] = 1;
+            }
+            UsbBreq::BitTimingConsts => {
+                let feature = self.features | GSUSB_FEATURE_LISTEN_ONLY | GSUSB_FEATURE_LOOP_BACK;
+                // tseg1, tseg2, sjw and brp limits
+                let consts = [feature, MOCK_CAN_CLOCK, 1, 16, 1, 8, 4, 1, 1024, 1];
+                for (i, c) in consts.iter().enumerate() {
+                    data[i * 4..i * 4 + 4].copy_from_slice(&c.to_le_bytes());
+                }
+            }
+            _ => {}
+        }
+        Ok(data)
+    }
+
+    // sent frames are echoed back, like a real device does once they are on the
+    // bus
+    pub(super) fn send(
+        &self,
+        frame: HostFrame,
+        rx: &Sender<HostFrame>,
+        disconnected: &AtomicBool,
+    ) -> Result<(), Error> {
+        let faults = self.faults.lock().unwrap();
+        if faults.unplugged {
+            disconnected.store(true, Ordering::SeqCst);
+            return Err(Error::Transfer("send", LIBUSB_TRANSFER_NO_DEVICE));
+        }
+        if faults.send {
+            return Err(Error::Transfer("send", LIBUSB_TRANSFER_ERROR));
+        }
+        if !faults.no_echo {
+            rx.send(frame).ok();
+        }
+        Ok(())
+    }
+
+    pub(super) fn start_transfers(&self, disconnected: &AtomicBool) -> Result<(), Error> {
+        let faults = self.faults.lock().unwrap();
+        if faults.unplugged {
+            disconnected.store(true, Ordering::SeqCst);
+            return Err(Error::Libusb(
+                "start_transfers: libusb_submit_transfer",
+                LIBUSB_ERROR_NO_DEVICE,
+            ));
+        }
+        if faults.start {
+            return Err(Error::Libusb(
+                "start_transfers: libusb_submit_transfer",
+                LIBUSB_ERROR_IO,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Device {
+    /// Create a device which is backed by `mock` instead of a USB device.
+    pub(crate) fn mock(mock: Mock) -> Device {
+        let (send, recv) = queue::channel(None);
+        Device {
+            ctx: Arc::new(UsbContext {
+                ctx: ptr::null_mut(),
+            }),
+            // never dereferenced, every transfer goes to the mock
+            hnd: ptr::NonNull::dangling(),
+            running: Arc::new(AtomicBool::new(true)),
+
+            ctrl_transfer: ptr::NonNull::dangling(),
+            ctrl_buf: [0u8; CTRL_BUF_SIZE],
+            ctrl_transfer_pending: RwLock::from(false),
+
+            out_transfer: ptr::NonNull::dangling(),
+            out_buf: vec![],
+            out_transfer_pending: RwLock::from(false),
+
+            in_transfers: [ptr::null_mut(); BULK_IN_TRANSFER_COUNT],
+            in_bufs: [[0u8; BULK_IN_BUF_SIZE]; BULK_IN_TRANSFER_COUNT],
+            in_active: AtomicUsize::new(0),
+
+            can_rx_send: send,
+            can_rx_recv: recv,
+            rx_policy: OverflowPolicy::Block,
+
+            usb_counters: UsbCounters::default(),
+            disconnected: AtomicBool::new(false),
+            serial: None,
+
+            mock: Some(mock),
+        }
+    }
+
+    /// Pass a frame to the receive thread, as if the device received it.
+    pub(crate) fn inject(&self, f: HostFrame) {
+        self.can_rx_send.send(f).unwrap();
+    }
+}
+
+/// Create an interface on a mock device.
+pub(crate) fn interface(mock: Mock) -> Interface {
+    Interface::from_device(Device::mock(mock), &[]).unwrap()
+}
+
+/// Pass a host frame to the receive thread of an interface on a mock device,
+/// such as an echo or an error frame.
+pub(crate) fn inject(i: &Interface, f: HostFrame) {
+    i.dev.lock().unwrap().inject(f);
+}
+
+/// Returns a function which does what `inject` does, for threads which pass
+/// frames while the test waits on the interface.
+pub(crate) fn injector(i: &Interface) -> impl Fn(HostFrame) + Send + 'static {
+    let rx = device_queue(i);
+    move |f| rx.send(f).unwrap()
+}
+
+/// The queue between the device and the receive thread of an interface on a
+/// mock device, for tests which fill it up.
+pub(crate) fn device_queue(i: &Interface) -> Sender<HostFrame> {
+    i.dev.lock().unwrap().can_rx_send.clone()
+}
+
+/// Pass a frame to the receive thread of an interface on a mock device, as if
+/// another node sent it.
+pub(crate) fn receive(i: &Interface, f: &Frame) {
+    inject(i, HostFrame::from_frame(f, GSUSB_RX_ECHO_ID));
+}
+
+/// Returns a function which does what `receive` does, for hooks which answer
+/// the frames sent by a test as another node would.
+pub(crate) fn receiver(i: &Interface) -> impl Fn(&Frame) + Send + 'static {
+    let inject = injector(i);
+    move |f: &Frame| inject(HostFrame::from_frame(f, GSUSB_RX_ECHO_ID))
+}
+
+/// Poll until `done` returns true, failing after a second.
+pub(crate) fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -1,19 +1,28 @@
 #![allow(dead_code)]
-use crossbeam_channel::{unbounded, Receiver, Sender, TryRecvError};
-use libc::c_void;
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use libc::{c_void, timeval};
 use libusb1_sys::constants::*;
 use libusb1_sys::*;
+use std::ffi::CStr;
+use std::fmt;
 use std::mem;
 use std::mem::size_of;
 use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::thread;
+use std::time::{Duration, Instant};
 
 pub mod gsusb;
+pub(crate) mod hotplug;
+#[cfg(test)]
+pub(crate) mod mock;
 pub(crate) use gsusb::*;
 
+use crate::queue::{self, OverflowPolicy};
+use crate::UsbStats;
+
 // CANtact USB VID / PID
 const USB_VID: u16 = 0x1d50;
 const USB_PID: u16 = 0x606f;
@@ -22,17 +31,88 @@ const USB_PID: u16 = 0x606f;
 const CTRL_BUF_SIZE: usize = 64;
 // number of bulk in transfers
 const BULK_IN_TRANSFER_COUNT: usize = 32;
-// buffer size for bulk in transfer
-const BULK_IN_BUF_SIZE: usize = 32;
+// buffer size for bulk in transfer, large enough for an FD host frame with a
+// hardware timestamp
+const BULK_IN_BUF_SIZE: usize = 80;
 // timeout for bulk in transfers
 const BULK_IN_TIMEOUT_MS: u32 = 5000;
+// timeout for control transfers made while looking for a device
+const ENUM_CTRL_TIMEOUT_MS: u32 = 1000;
+// longest time to wait for cancelled transfers to complete
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(1);
+// longest time the event thread waits for libusb events before checking whether
+// the device is still open
+pub(crate) const EVENT_TIMEOUT_US: libc::suseconds_t = 100_000;
 
 #[derive(Debug)]
 pub enum Error {
-    LibusbError(&'static str, i32),
+    /// A libusb function failed with an error code.
+    Libusb(&'static str, i32),
     DeviceNotFound,
     TransferAllocFailed,
     InvalidControlResponse,
+    /// A control request, named by `UsbBreq::name`, completed with a transfer
+    /// status other than completed.
+    ControlTransfer(&'static str, i32),
+    /// A bulk transfer completed with a transfer status other than completed.
+    Transfer(&'static str, i32),
+}
+
+// name of a libusb transfer status
+fn transfer_status_name(status: i32) -> &'static str {
+    match status {
+        LIBUSB_TRANSFER_COMPLETED => "completed",
+        LIBUSB_TRANSFER_ERROR => "error",
+        LIBUSB_TRANSFER_TIMED_OUT => "timed out",
+        LIBUSB_TRANSFER_CANCELLED => "cancelled",
+        LIBUSB_TRANSFER_STALL => "stall",
+        LIBUSB_TRANSFER_NO_DEVICE => "no device",
+        LIBUSB_TRANSFER_OVERFLOW => "overflow",
+        _ => "unknown status",
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Libusb(func, code) => {
+                let name = unsafe { CStr::from_ptr(libusb_error_name(*code)) };
+                write!(f, "{} failed: {}", func, name.to_string_lossy())
+            }
+            Error::DeviceNotFound => write!(f, "device not found"),
+            Error::TransferAllocFailed => write!(f, "failed to allocate a USB transfer"),
+            Error::InvalidControlResponse => write!(f, "short response to a control request"),
+            Error::ControlTransfer(req, status) => write!(
+                f,
+                "{} request failed: {}",
+                req,
+                transfer_status_name(*status)
+            ),
+            Error::Transfer(name, status) => {
+                write!(f, "{} failed: {}", name, transfer_status_name(*status))
+            }
+        }
+    }
+}
+
+// USB transfer counters, updated from the libusb callbacks
+#[derive(Debug, Default)]
+struct UsbCounters {
+    in_transfers: AtomicU64,
+    in_bytes: AtomicU64,
+    in_timeouts: AtomicU64,
+    in_errors: AtomicU64,
+    out_transfers: AtomicU64,
+    out_errors: AtomicU64,
+    stalls: AtomicU64,
+    resubmissions: AtomicU64,
+    resubmit_failures: AtomicU64,
+    // received frames dropped because the rx queue was full
+    rx_drops: AtomicU64,
+}
+
+fn inc(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
 }
 
 #[derive(Debug)]
@@ -44,13 +124,13 @@ unsafe impl Send for UsbContext {}
 unsafe impl Sync for UsbContext {}
 
 impl UsbContext {
-    pub(crate) fn new() -> UsbContext {
+    pub(crate) fn new() -> Result<UsbContext, Error> {
         let mut context = mem::MaybeUninit::<*mut libusb_context>::uninit();
         match unsafe { libusb_init(context.as_mut_ptr()) } {
-            LIBUSB_SUCCESS => UsbContext {
+            LIBUSB_SUCCESS => Ok(UsbContext {
                 ctx: unsafe { context.assume_init() },
-            },
-            _ => panic!("could not initialize libusb context"),
+            }),
+            e => Err(Error::Libusb("libusb_init", e)),
         }
     }
     fn as_ptr(&self) -> *mut libusb_context {
@@ -59,7 +139,10 @@ impl UsbContext {
 }
 impl Drop for UsbContext {
     fn drop(&mut self) {
-        unsafe { libusb_exit(self.ctx) }
+        // a mock device has no context
+        if !self.ctx.is_null() {
+            unsafe { libusb_exit(self.ctx) }
+        }
     }
 }
 
@@ -78,23 +161,56 @@ pub(crate) struct Device {
 
     in_transfers: [*mut libusb_transfer; BULK_IN_TRANSFER_COUNT],
     in_bufs: [[u8; BULK_IN_BUF_SIZE]; BULK_IN_TRANSFER_COUNT],
+    // bulk in transfers which are submitted, the callback may still be called
+    // for these so they must not be freed
+    in_active: AtomicUsize,
 
     can_rx_send: Sender<HostFrame>,
     pub can_rx_recv: Receiver<HostFrame>,
+    rx_policy: OverflowPolicy,
+
+    usb_counters: UsbCounters,
+    // set when libusb reports that the device is gone
+    disconnected: AtomicBool,
+    serial: Option<String>,
+
+    #[cfg(test)]
+    mock: Option<mock::Mock>,
 }
 
+// the device is only accessed through the Interface, transfers are completed on
+// the libusb event thread
+unsafe impl Send for Device {}
+
 extern "system" fn ctrl_cb(xfer: *mut libusb_transfer) {
     let dev_ptr = unsafe { (*xfer).user_data as *mut Device };
     let dev = unsafe { &mut *dev_ptr };
 
-    let _status = unsafe { (*xfer).status };
+    let status = unsafe { (*xfer).status };
+    if status == LIBUSB_TRANSFER_NO_DEVICE {
+        dev.disconnected.store(true, Ordering::SeqCst);
+    }
 
     *dev.ctrl_transfer_pending.write().unwrap() = false;
 }
 extern "system" fn bulk_out_cb(xfer: *mut libusb_transfer) {
     let dev_ptr = unsafe { (*xfer).user_data as *mut Device };
     let dev = unsafe { &mut *dev_ptr };
-    let _status = unsafe { (*xfer).status };
+    let status = unsafe { (*xfer).status };
+
+    let c = &dev.usb_counters;
+    match status {
+        LIBUSB_TRANSFER_COMPLETED => inc(&c.out_transfers, 1),
+        LIBUSB_TRANSFER_STALL => {
+            inc(&c.stalls, 1);
+            inc(&c.out_errors, 1);
+        }
+        LIBUSB_TRANSFER_NO_DEVICE => {
+            dev.disconnected.store(true, Ordering::SeqCst);
+            inc(&c.out_errors, 1);
+        }
+        _ => inc(&c.out_errors, 1),
+    }
 
     *dev.out_transfer_pending.write().unwrap() = false;
 }
@@ -104,37 +220,231 @@ extern "system" fn bulk_in_cb(xfer: *mut libusb_transfer) {
     let dev = unsafe { &mut *dev_ptr };
     let status = unsafe { (*xfer).status };
 
-    if status == LIBUSB_TRANSFER_COMPLETED {
-        let frame_data = unsafe { std::slice::from_raw_parts((*xfer).buffer, BULK_IN_BUF_SIZE) };
-        let f = HostFrame::from_le_bytes(frame_data);
-        dev.can_rx_send.send(f).unwrap();
+    let c = &dev.usb_counters;
+    match status {
+        LIBUSB_TRANSFER_COMPLETED => {
+            inc(&c.in_transfers, 1);
+            inc(&c.in_bytes, unsafe { (*xfer).actual_length } as u64);
+            let len = unsafe { (*xfer).actual_length } as usize;
+            let frame_data = unsafe {
+                std::slice::from_raw_parts(
+                    (*xfer).buffer,
+                    len.clamp(HOST_FRAME_HEADER_SIZE, BULK_IN_BUF_SIZE),
+                )
+            };
+            let f = HostFrame::from_le_bytes(frame_data);
+            let dropped = queue::push(&dev.can_rx_send, Some(&dev.can_rx_recv), dev.rx_policy, f);
+            inc(&c.rx_drops, dropped);
+        }
+        LIBUSB_TRANSFER_CANCELLED => {}
+        LIBUSB_TRANSFER_TIMED_OUT => inc(&c.in_timeouts, 1),
+        LIBUSB_TRANSFER_STALL => {
+            inc(&c.stalls, 1);
+            inc(&c.in_errors, 1);
+        }
+        LIBUSB_TRANSFER_NO_DEVICE => {
+            dev.disconnected.store(true, Ordering::SeqCst);
+            inc(&c.in_errors, 1);
+        }
+        _ => inc(&c.in_errors, 1),
     }
-    if status != LIBUSB_TRANSFER_CANCELLED {
-        // resubmit the transfer unless it was cancelled
-        unsafe {
-            libusb_submit_transfer(xfer);
+    if status != LIBUSB_TRANSFER_CANCELLED && status != LIBUSB_TRANSFER_NO_DEVICE {
+        // resubmit the transfer unless it was cancelled or the device is gone
+        match unsafe { libusb_submit_transfer(xfer) } {
+            LIBUSB_SUCCESS => {
+                inc(&c.resubmissions, 1);
+                return;
+            }
+            _ => inc(&c.resubmit_failures, 1),
         }
     }
+    dev.in_active.fetch_sub(1, Ordering::SeqCst);
 }
 
-impl Device {
-    pub(crate) fn new(ctx: UsbContext) -> Result<Device, Error> {
-        let hnd = unsafe { libusb_open_device_with_vid_pid(ctx.as_ptr(), USB_VID, USB_PID) };
-        if hnd.is_null() {
-            return Err(Error::DeviceNotFound);
-        }
+/// Selects which of the connected devices to open.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Selector<'a> {
+    /// The first device which is not in use, by this or another process.
+    Available,
+    /// The `n`th device found, counting from zero.
+    Index(usize),
+    /// The device with this USB serial number.
+    Serial(&'a str),
+    /// The device with this user ID.
+    UserId(u32),
+}
 
-        match unsafe { libusb_detach_kernel_driver(hnd, 0) } {
-            LIBUSB_SUCCESS => {}
-            LIBUSB_ERROR_NOT_FOUND => { /* device already disconnected */ }
-            LIBUSB_ERROR_NOT_SUPPORTED => { /* can't detach on this system (not linux) */ }
-            e => return Err(Error::LibusbError("libusb_detach_kernel_driver", e)),
+// read an ASCII string descriptor, returns None if the device has none
+fn string_descriptor(hnd: *mut libusb_device_handle, index: u8) -> Option<String> {
+    if index == 0 {
+        return None;
+    }
+    let mut buf = [0u8; 256];
+    let n = unsafe {
+        libusb_get_string_descriptor_ascii(hnd, index, buf.as_mut_ptr(), buf.len() as i32)
+    };
+    if n < 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..n as usize]).into_owned())
+}
+
+fn claim(hnd: *mut libusb_device_handle) -> Result<(), Error> {
+    match unsafe { libusb_detach_kernel_driver(hnd, 0) } {
+        LIBUSB_SUCCESS => {}
+        LIBUSB_ERROR_NOT_FOUND => { /* device already disconnected */ }
+        LIBUSB_ERROR_NOT_SUPPORTED => { /* can't detach on this system (not linux) */ }
+        e => return Err(Error::Libusb("libusb_detach_kernel_driver", e)),
+    }
+
+    match unsafe { libusb_claim_interface(hnd, 0) } {
+        LIBUSB_SUCCESS => Ok(()),
+        e => Err(Error::Libusb("libusb_claim_interface", e)),
+    }
+}
+
+/// Returns the serial number of a device, or None if it can't be read, for
+/// example because the device is in use by another process.
+pub(crate) fn serial_number(d: *mut libusb_device) -> Option<String> {
+    let mut hnd = ptr::null_mut();
+    if unsafe { libusb_open(d, &mut hnd) } != LIBUSB_SUCCESS {
+        return None;
+    }
+    let serial = handle_serial_number(hnd);
+    unsafe { libusb_close(hnd) };
+    serial
+}
+
+// serial number of an open device
+fn handle_serial_number(hnd: *mut libusb_device_handle) -> Option<String> {
+    let mut desc = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
+    let d = unsafe { libusb_get_device(hnd) };
+    if unsafe { libusb_get_device_descriptor(d, desc.as_mut_ptr()) } != LIBUSB_SUCCESS {
+        return None;
+    }
+    let desc = unsafe { desc.assume_init() };
+    string_descriptor(hnd, desc.iSerialNumber)
+}
+
+// user ID of an open device, None if the device has none
+fn handle_user_id(hnd: *mut libusb_device_handle) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    // bmRequestType: direction = in, type = vendor, recipient = interface
+    let n = unsafe {
+        libusb_control_transfer(
+            hnd,
+            0b1100_0001,
+            UsbBreq::GetUserId as u8,
+            0,
+            0,
+            buf.as_mut_ptr(),
+            buf.len() as u16,
+            ENUM_CTRL_TIMEOUT_MS,
+        )
+    };
+    if n != buf.len() as i32 {
+        return None;
+    }
+    Some(u32::from_le_bytes(buf))
+}
+
+// returns true if an open device is the one selected by a serial number or
+// user ID, and for any other selector
+fn selected(hnd: *mut libusb_device_handle, sel: Selector) -> bool {
+    match sel {
+        Selector::Serial(serial) => handle_serial_number(hnd).as_deref() == Some(serial),
+        Selector::UserId(id) => handle_user_id(hnd) == Some(id),
+        _ => true,
+    }
+}
+
+// open a device and claim its interface, so no other handle can use it
+fn open_and_claim(d: *mut libusb_device) -> Result<*mut libusb_device_handle, Error> {
+    let mut hnd = ptr::null_mut();
+    match unsafe { libusb_open(d, &mut hnd) } {
+        LIBUSB_SUCCESS => {}
+        e => return Err(Error::Libusb("libusb_open", e)),
+    }
+    if let Err(e) = claim(hnd) {
+        unsafe { libusb_close(hnd) };
+        return Err(e);
+    }
+    Ok(hnd)
+}
+
+// open and claim the device matching `sel`, with the CANtact VID and PID or one
+// of the pairs in `ids`. Each device is claimed by at most one handle, so
+// several devices can be open at once, each by its own `Device`.
+fn open_device(
+    ctx: &UsbContext,
+    sel: Selector,
+    ids: &[(u16, u16)],
+) -> Result<*mut libusb_device_handle, Error> {
+    let mut list = ptr::null();
+    let n = unsafe { libusb_get_device_list(ctx.as_ptr(), &mut list) };
+    if n < 0 {
+        return Err(Error::Libusb("libusb_get_device_list", n as i32));
+    }
+    let devices = unsafe { std::slice::from_raw_parts(list, n as usize) };
+
+    let mut result = Err(Error::DeviceNotFound);
+    let mut index = 0;
+    for &d in devices {
+        let mut desc = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
+        if unsafe { libusb_get_device_descriptor(d, desc.as_mut_ptr()) } != LIBUSB_SUCCESS {
+            continue;
+        }
+        let desc = unsafe { desc.assume_init() };
+        let id = (desc.idVendor, desc.idProduct);
+        if id != (USB_VID, USB_PID) && !ids.contains(&id) {
+            continue;
         }
 
-        match unsafe { libusb_claim_interface(hnd, 0) } {
-            LIBUSB_SUCCESS => {}
-            e => return Err(Error::LibusbError("libusb_claim_interface", e)),
+        match sel {
+            Selector::Available => {
+                // devices which are in use are skipped, but a device which
+                // can't be accessed is reported if no other one is found
+                match open_and_claim(d) {
+                    Ok(hnd) => {
+                        result = Ok(hnd);
+                        break;
+                    }
+                    Err(e @ Error::Libusb(_, LIBUSB_ERROR_ACCESS)) => result = Err(e),
+                    Err(_) => {}
+                }
+            }
+            Selector::Index(i) => {
+                if index == i {
+                    result = open_and_claim(d);
+                    break;
+                }
+                index += 1;
+            }
+            Selector::Serial(_) | Selector::UserId(_) => {
+                // devices which are in use are skipped
+                let hnd = match open_and_claim(d) {
+                    Ok(hnd) => hnd,
+                    Err(_) => continue,
+                };
+                if selected(hnd, sel) {
+                    result = Ok(hnd);
+                    break;
+                }
+                unsafe {
+                    libusb_release_interface(hnd, 0);
+                    libusb_close(hnd);
+                }
+            }
         }
+    }
+
+    unsafe { libusb_free_device_list(list, 1) };
+    result
+}
+
+impl Device {
+    pub(crate) fn new(ctx: UsbContext, sel: Selector, ids: &[(u16, u16)]) -> Result<Device, Error> {
+        let hnd = open_device(&ctx, sel, ids)?;
 
         let ctrl_transfer = unsafe { libusb_alloc_transfer(0) };
         if ctrl_transfer.is_null() {
@@ -144,7 +454,7 @@ impl Device {
         let in_bufs: [[u8; BULK_IN_BUF_SIZE]; BULK_IN_TRANSFER_COUNT] =
             [[0u8; BULK_IN_BUF_SIZE]; BULK_IN_TRANSFER_COUNT];
 
-        let (send, recv) = unbounded();
+        let (send, recv) = queue::channel(None);
 
         let d = Device {
             ctx: Arc::new(ctx),
@@ -161,18 +471,33 @@ impl Device {
 
             in_transfers: [ptr::null_mut(); BULK_IN_TRANSFER_COUNT],
             in_bufs,
+            in_active: AtomicUsize::new(0),
 
             can_rx_send: send,
             can_rx_recv: recv,
+            rx_policy: OverflowPolicy::Block,
+
+            usb_counters: UsbCounters::default(),
+            disconnected: AtomicBool::new(false),
+            serial: handle_serial_number(hnd),
+
+            #[cfg(test)]
+            mock: None,
         };
 
-        // start the libusb event thread
+        // start the libusb event thread. Every device has its own context and
+        // thread, which wakes up regularly so it exits soon after the device is
+        // dropped.
         let ctx = d.ctx.clone();
         let running = d.running.clone();
         thread::spawn(move || {
+            let tv = timeval {
+                tv_sec: 0,
+                tv_usec: EVENT_TIMEOUT_US,
+            };
             while running.load(Ordering::SeqCst) {
                 unsafe {
-                    libusb_handle_events(ctx.as_ptr());
+                    libusb_handle_events_timeout(ctx.as_ptr(), &tv);
                 }
             }
         });
@@ -181,6 +506,11 @@ impl Device {
     }
 
     pub(crate) fn start_transfers(&mut self) -> Result<(), Error> {
+        #[cfg(test)]
+        if let Some(mock) = &self.mock {
+            return mock.start_transfers(&self.disconnected);
+        }
+
         // create the in transfers, fill the transfers, and submit them
         for i in 0..BULK_IN_TRANSFER_COUNT {
             let xfer = unsafe { libusb_alloc_transfer(0) };
@@ -190,21 +520,22 @@ impl Device {
             self.in_transfers[i] = xfer;
             self.fill_bulk_in_transfer(i);
 
+            // counted before submitting, the callback may run straight away
+            self.in_active.fetch_add(1, Ordering::SeqCst);
             match unsafe { libusb_submit_transfer(self.in_transfers[i]) } {
                 LIBUSB_SUCCESS => {}
                 e => {
-                    return Err(Error::LibusbError(
-                        "start_transfers: libusb_submit_transfer",
-                        e,
-                    ))
+                    self.in_active.fetch_sub(1, Ordering::SeqCst);
+                    return Err(Error::Libusb("start_transfers: libusb_submit_transfer", e));
                 }
             };
         }
         Ok(())
     }
 
-    pub(crate) fn stop_transfers(&self) -> Result<(), Error> {
+    pub(crate) fn stop_transfers(&mut self) -> Result<(), Error> {
         // cancel all bulk in transfers
+        let mut result = Ok(());
         for xfer in self.in_transfers.iter() {
             if xfer.is_null() {
                 // ignore null transfers
@@ -213,10 +544,29 @@ impl Device {
             match unsafe { libusb_cancel_transfer(*xfer) } {
                 LIBUSB_SUCCESS => {}
                 LIBUSB_ERROR_NOT_FOUND => { /* already destroyed */ }
-                e => return Err(Error::LibusbError("libusb_cancel_transfer", e)),
+                LIBUSB_ERROR_NO_DEVICE => { /* device unplugged */ }
+                e => result = Err(Error::Libusb("libusb_cancel_transfer", e)),
             }
         }
-        Ok(())
+
+        // wait for the event thread to complete the cancelled transfers, so they
+        // can be freed
+        let deadline = Instant::now() + CANCEL_TIMEOUT;
+        while self.in_active.load(Ordering::SeqCst) > 0 {
+            if Instant::now() > deadline {
+                // still in use by libusb, leak the transfers rather than free them
+                self.in_transfers = [ptr::null_mut(); BULK_IN_TRANSFER_COUNT];
+                return Err(Error::Libusb("stop_transfers", LIBUSB_ERROR_TIMEOUT));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        for xfer in self.in_transfers.iter_mut() {
+            if !xfer.is_null() {
+                unsafe { libusb_free_transfer(*xfer) };
+                *xfer = ptr::null_mut();
+            }
+        }
+        result
     }
 
     fn fill_control_transfer(
@@ -227,7 +577,7 @@ impl Device {
         index: u16,
         data: &[u8],
     ) {
-        let mut transfer = unsafe { &mut *self.ctrl_transfer.as_ptr() };
+        let transfer = unsafe { &mut *self.ctrl_transfer.as_ptr() };
 
         // clear buffer
         self.ctrl_buf = [0u8; CTRL_BUF_SIZE];
@@ -242,7 +592,7 @@ impl Device {
         self.ctrl_buf[7] = (data.len() >> 8) as u8;
 
         // copy control out data
-        self.ctrl_buf[8..(data.len() + 8)].clone_from_slice(&data[..]);
+        self.ctrl_buf[8..(data.len() + 8)].clone_from_slice(data);
 
         transfer.dev_handle = self.hnd.as_ptr();
         transfer.endpoint = 0;
@@ -255,7 +605,7 @@ impl Device {
     }
 
     fn fill_bulk_out_transfer(&mut self, transfer: *mut libusb_transfer) {
-        let mut transfer = unsafe { &mut *transfer };
+        let transfer = unsafe { &mut *transfer };
         let buf = &mut self.out_buf;
 
         transfer.dev_handle = self.hnd.as_ptr();
@@ -269,7 +619,7 @@ impl Device {
     }
 
     fn fill_bulk_in_transfer(&mut self, idx: usize) {
-        let mut transfer = unsafe { &mut *self.in_transfers[idx] };
+        let transfer = unsafe { &mut *self.in_transfers[idx] };
         let buf = &mut self.in_bufs[idx];
 
         transfer.dev_handle = self.hnd.as_ptr();
@@ -283,33 +633,51 @@ impl Device {
     }
 
     fn control_out(&mut self, req: UsbBreq, channel: u16, data: &[u8]) -> Result<(), Error> {
+        #[cfg(test)]
+        if let Some(mock) = &self.mock {
+            return mock.control_out(req, channel, data, &self.disconnected);
+        }
+
         // bmRequestType: direction = out, type = vendor, recipient = interface
         let rt = 0b0100_0001;
         self.fill_control_transfer(rt, req as u8, channel, 0, data);
         *self.ctrl_transfer_pending.write().unwrap() = true;
         match unsafe { libusb_submit_transfer(self.ctrl_transfer.as_ptr()) } {
             LIBUSB_SUCCESS => {}
-            e => return Err(Error::LibusbError("control_out: libusb_submit_transfer", e)),
+            e => return Err(Error::Libusb("control_out: libusb_submit_transfer", e)),
         }
 
         // wait for transfer to complete
         while *self.ctrl_transfer_pending.read().unwrap() {}
+        self.ctrl_status(req)
+    }
 
-        Ok(())
+    // result of the last control transfer
+    fn ctrl_status(&self, req: UsbBreq) -> Result<(), Error> {
+        match unsafe { (*self.ctrl_transfer.as_ptr()).status } {
+            LIBUSB_TRANSFER_COMPLETED => Ok(()),
+            status => Err(Error::ControlTransfer(req.name(), status)),
+        }
     }
 
     fn control_in(&mut self, req: UsbBreq, channel: u16, len: usize) -> Result<Vec<u8>, Error> {
+        #[cfg(test)]
+        if let Some(mock) = &self.mock {
+            return mock.control_in(req, channel, len, &self.disconnected);
+        }
+
         // bmRequestType: direction = in, type = vendor, recipient = interface
         let rt = 0b1100_0001;
-        self.fill_control_transfer(rt, req as u8, channel, 0, &vec![0u8; len].as_slice());
+        self.fill_control_transfer(rt, req as u8, channel, 0, vec![0u8; len].as_slice());
         *self.ctrl_transfer_pending.write().unwrap() = true;
         match unsafe { libusb_submit_transfer(self.ctrl_transfer.as_ptr()) } {
             LIBUSB_SUCCESS => {}
-            e => return Err(Error::LibusbError("control_in: libusb_submit_transfer", e)),
+            e => return Err(Error::Libusb("control_in: libusb_submit_transfer", e)),
         }
 
         // wait for transfer to complete
         while *self.ctrl_transfer_pending.read().unwrap() {}
+        self.ctrl_status(req)?;
         let xfer_len = unsafe { (*self.ctrl_transfer.as_ptr()).actual_length } as usize;
         if xfer_len < len {
             // we didn't get the full struct we asked for
@@ -325,16 +693,58 @@ impl Device {
     }
 
     pub(crate) fn set_bit_timing(&mut self, channel: u16, timing: BitTiming) -> Result<(), Error> {
-        self.control_out(UsbBreq::BitTiming, channel, &timing.to_le_bytes())
+        self.control_out(UsbBreq::BitTiming, channel, &bit_timing_to_le_bytes(timing))
+    }
+
+    pub(crate) fn set_data_bit_timing(
+        &mut self,
+        channel: u16,
+        timing: BitTiming,
+    ) -> Result<(), Error> {
+        self.control_out(
+            UsbBreq::DataBitTiming,
+            channel,
+            &bit_timing_to_le_bytes(timing),
+        )
     }
 
     pub(crate) fn set_mode(&mut self, channel: u16, device_mode: Mode) -> Result<(), Error> {
         self.control_out(UsbBreq::Mode, channel, &device_mode.to_le_bytes())
     }
 
-    pub(crate) fn set_identify(&mut self, val: u32) -> Result<(), Error> {
+    pub(crate) fn set_identify(&mut self, channel: u16, on: bool) -> Result<(), Error> {
+        let mode = if on {
+            GSUSB_IDENTIFY_ON
+        } else {
+            GSUSB_IDENTIFY_OFF
+        };
+        self.control_out(UsbBreq::Identify, channel, &mode.to_le_bytes())
+    }
+
+    pub(crate) fn get_user_id(&mut self) -> Result<u32, Error> {
         let channel = 0;
-        self.control_out(UsbBreq::Identify, channel, &val.to_le_bytes())
+        let data = self.control_in(UsbBreq::GetUserId, channel, size_of::<u32>())?;
+        Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    pub(crate) fn set_user_id(&mut self, id: u32) -> Result<(), Error> {
+        let channel = 0;
+        self.control_out(UsbBreq::SetUserId, channel, &id.to_le_bytes())
+    }
+
+    pub(crate) fn set_termination(&mut self, channel: u16, enabled: bool) -> Result<(), Error> {
+        let state = if enabled {
+            GSUSB_TERMINATION_ENABLED
+        } else {
+            GSUSB_TERMINATION_DISABLED
+        };
+        self.control_out(UsbBreq::SetTermination, channel, &state.to_le_bytes())
+    }
+
+    pub(crate) fn get_termination(&mut self, channel: u16) -> Result<bool, Error> {
+        let data = self.control_in(UsbBreq::GetTermination, channel, size_of::<u32>())?;
+        let state = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        Ok(state == GSUSB_TERMINATION_ENABLED)
     }
 
     pub(crate) fn set_berr(&mut self, val: u32) -> Result<(), Error> {
@@ -349,8 +759,7 @@ impl Device {
         Ok(DeviceConfig::from_le_bytes(&data))
     }
 
-    pub(crate) fn get_bit_timing_consts(&mut self) -> Result<BitTimingConsts, Error> {
-        let channel = 0;
+    pub(crate) fn get_bit_timing_consts(&mut self, channel: u16) -> Result<BitTimingConsts, Error> {
         let data = self.control_in(
             UsbBreq::BitTimingConsts,
             channel,
@@ -359,6 +768,27 @@ impl Device {
         Ok(BitTimingConsts::from_le_bytes(&data))
     }
 
+    /// Program the acceptance filters of a channel. A frame is received if its
+    /// ID, without the flags, matches any of the (id, mask) pairs. An empty
+    /// list receives every frame.
+    pub(crate) fn set_filters(
+        &mut self,
+        channel: u16,
+        filters: &[(u32, u32)],
+    ) -> Result<(), Error> {
+        let mut data = (filters.len() as u32).to_le_bytes().to_vec();
+        for (id, mask) in filters.iter().take(GSUSB_FILTER_SLOTS) {
+            data.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(&mask.to_le_bytes());
+        }
+        self.control_out(UsbBreq::SetFilter, channel, &data)
+    }
+
+    pub(crate) fn get_state(&mut self, channel: u16) -> Result<DeviceState, Error> {
+        let data = self.control_in(UsbBreq::GetState, channel, size_of::<DeviceState>())?;
+        Ok(DeviceState::from_le_bytes(&data))
+    }
+
     pub(crate) fn get_timestamp(&mut self) -> Result<u32, Error> {
         let channel = 0;
         let data = self.control_in(UsbBreq::Timestamp, channel, size_of::<u32>())?;
@@ -367,6 +797,11 @@ impl Device {
     }
 
     pub(crate) fn send(&mut self, frame: HostFrame) -> Result<(), Error> {
+        #[cfg(test)]
+        if let Some(mock) = &self.mock {
+            return mock.send(frame, &self.can_rx_send, &self.disconnected);
+        }
+
         self.out_buf.clear();
         self.out_buf.append(&mut frame.to_le_bytes());
 
@@ -375,13 +810,63 @@ impl Device {
 
         match unsafe { libusb_submit_transfer(self.out_transfer.as_ptr()) } {
             LIBUSB_SUCCESS => {}
-            e => return Err(Error::LibusbError("send: libusb_submit_transfer", e)),
+            e => return Err(Error::Libusb("send: libusb_submit_transfer", e)),
         }
 
         // wait for transfer to complete
         while *self.out_transfer_pending.read().unwrap() {}
+        match unsafe { (*self.out_transfer.as_ptr()).status } {
+            LIBUSB_TRANSFER_COMPLETED => Ok(()),
+            status => Err(Error::Transfer("send", status)),
+        }
+    }
 
-        Ok(())
+    pub(crate) fn usb_stats(&self) -> UsbStats {
+        let c = &self.usb_counters;
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        UsbStats {
+            in_transfers: get(&c.in_transfers),
+            in_bytes: get(&c.in_bytes),
+            in_timeouts: get(&c.in_timeouts),
+            in_errors: get(&c.in_errors),
+            out_transfers: get(&c.out_transfers),
+            out_errors: get(&c.out_errors),
+            stalls: get(&c.stalls),
+            resubmissions: get(&c.resubmissions),
+            resubmit_failures: get(&c.resubmit_failures),
+            in_buffer_size: BULK_IN_BUF_SIZE as u64,
+        }
+    }
+
+    /// Returns the number of received frames dropped because the queue of
+    /// received frames was full.
+    pub(crate) fn rx_drops(&self) -> u64 {
+        self.usb_counters.rx_drops.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset_rx_drops(&self) {
+        self.usb_counters.rx_drops.store(0, Ordering::Relaxed);
+    }
+
+    /// Replace the queue of received frames with one holding up to `capacity`
+    /// frames, handling overflows with `policy`. Only allowed while the
+    /// transfers are stopped, frames in the old queue are discarded.
+    pub(crate) fn set_rx_queue(&mut self, capacity: Option<usize>, policy: OverflowPolicy) {
+        let (send, recv) = queue::channel(capacity);
+        self.can_rx_send = send;
+        self.can_rx_recv = recv;
+        self.rx_policy = policy;
+    }
+
+    /// Returns true once libusb has reported that the device was unplugged. A
+    /// disconnected device can't be used again, it has to be reopened.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    /// Returns the USB serial number of the device, if it has one.
+    pub(crate) fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
     }
 
     pub(crate) fn try_recv(&self) -> Option<HostFrame> {
@@ -394,18 +879,27 @@ impl Device {
     pub(crate) fn recv(&self) -> HostFrame {
         match self.can_rx_recv.recv() {
             Ok(f) => f,
-            Err(e) => panic!(e),
+            Err(e) => panic!("{}", e),
         }
     }
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
-        // stop the threau
+        // fails if the device was unplugged, it is closed either way. The event
+        // thread has to keep running until the transfers are cancelled.
+        self.stop_transfers().ok();
+
+        // stop the thread
         self.running.store(false, Ordering::SeqCst);
 
-        self.stop_transfers().unwrap();
+        #[cfg(test)]
+        if self.mock.is_some() {
+            return;
+        }
         unsafe {
+            // control transfers are waited for, so this one is not in use
+            libusb_free_transfer(self.ctrl_transfer.as_ptr());
             libusb_release_interface(self.hnd.as_ptr(), 0);
             libusb_close(self.hnd.as_ptr());
         }
@@ -0,0 +1,250 @@
+//! Bus health diagnostics.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::{BusError, BusErrorKind, BusState, ChannelCounters};
+
+// error frames per second above which errors are considered persistent
+const PERSISTENT_ERROR_RATE: f32 = 1.0;
+
+/// A problem found by `Interface::diagnose`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// No frames or errors were seen at all.
+    Silent,
+    /// Only frames sent by this device were seen, no other node transmitted.
+    NoOtherNodes,
+    /// Frames sent by this device were not acknowledged. Contains the number of
+    /// acknowledgement errors reported.
+    NoAck(u64),
+    /// The device was unable to send recessive bits, the bus appears to be stuck
+    /// dominant. Contains the number of errors reported.
+    StuckDominant(u64),
+    /// The device was unable to send dominant bits. Contains the number of errors
+    /// reported.
+    NoDominant(u64),
+    /// Protocol errors were seen but no valid frames were received, which usually
+    /// means the bitrate does not match the other nodes.
+    BitrateMismatch,
+    /// Error frames were reported continuously. Contains the number of error frames.
+    PersistentErrors(u64),
+    /// The controller entered the error passive state.
+    ErrorPassive,
+    /// The controller went bus-off.
+    BusOff,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Silent => write!(
+                f,
+                "bus is silent: no frames or errors seen. Check that other nodes are powered \
+                 and that the device is connected to the bus"
+            ),
+            Finding::NoOtherNodes => write!(
+                f,
+                "only frames sent by this device were seen, no other node is transmitting"
+            ),
+            Finding::NoAck(n) => write!(
+                f,
+                "transmitted frames are not acknowledged ({} acknowledgement errors). Check \
+                 wiring, termination, and that another node is on the bus at the same bitrate",
+                n
+            ),
+            Finding::StuckDominant(n) => write!(
+                f,
+                "bus appears stuck dominant ({} errors sending recessive bits). Check for \
+                 shorted CANH/CANL or a faulty node",
+                n
+            ),
+            Finding::NoDominant(n) => write!(
+                f,
+                "unable to drive the bus dominant ({} errors sending dominant bits). Check the \
+                 wiring between the device and the bus",
+                n
+            ),
+            Finding::BitrateMismatch => write!(
+                f,
+                "protocol errors without any valid frames, the bitrate probably does not match \
+                 the bus"
+            ),
+            Finding::PersistentErrors(n) => write!(f, "persistent error frames ({} seen)", n),
+            Finding::ErrorPassive => write!(f, "controller entered the error passive state"),
+            Finding::BusOff => write!(f, "controller went bus-off"),
+        }
+    }
+}
+
+/// Result of `Interface::diagnose`.
+#[derive(Debug, Clone)]
+pub struct DiagnosticReport {
+    /// Channel which was diagnosed.
+    pub channel: u8,
+    /// How long the bus was observed.
+    pub duration: Duration,
+    /// Frames received from other nodes.
+    pub frames_received: u64,
+    /// Frames sent by this device.
+    pub frames_sent: u64,
+    /// Frames sent by this device and echoed back once transmitted.
+    pub frames_echoed: u64,
+    /// Errors reported by the device.
+    pub errors: Vec<BusError>,
+    /// Problems found, empty if the bus looks healthy.
+    pub findings: Vec<Finding>,
+}
+
+impl DiagnosticReport {
+    pub(crate) fn analyze(
+        channel: u8,
+        duration: Duration,
+        before: &ChannelCounters,
+        after: &ChannelCounters,
+        errors: Vec<BusError>,
+    ) -> DiagnosticReport {
+        let frames_received = after.rx_frames.saturating_sub(before.rx_frames);
+        let frames_sent = after.tx_frames.saturating_sub(before.tx_frames);
+        let frames_echoed = after.echo_frames.saturating_sub(before.echo_frames);
+        let count = |kind: BusErrorKind| errors.iter().filter(|e| e.has(kind)).count() as u64;
+
+        let mut findings = vec![];
+        if frames_received == 0 && frames_sent == 0 && frames_echoed == 0 && errors.is_empty() {
+            findings.push(Finding::Silent);
+        }
+        if frames_echoed > 0 && frames_received == 0 {
+            findings.push(Finding::NoOtherNodes);
+        }
+        let no_ack = count(BusErrorKind::NoAck);
+        if no_ack > 0 || (frames_sent > 0 && frames_echoed == 0) {
+            findings.push(Finding::NoAck(no_ack));
+        }
+        let bit1 = count(BusErrorKind::Bit1);
+        if bit1 > 0 {
+            findings.push(Finding::StuckDominant(bit1));
+        }
+        let bit0 = count(BusErrorKind::Bit0);
+        if bit0 > 0 {
+            findings.push(Finding::NoDominant(bit0));
+        }
+        let protocol = errors
+            .iter()
+            .filter(|e| {
+                e.has(BusErrorKind::Stuff)
+                    || e.has(BusErrorKind::Form)
+                    || e.has(BusErrorKind::Crc)
+                    || e.has(BusErrorKind::Bit)
+            })
+            .count();
+        if protocol > 0 && frames_received == 0 {
+            findings.push(Finding::BitrateMismatch);
+        }
+        let rate = errors.len() as f32 / duration.as_secs_f32().max(0.001);
+        if errors.len() > 1 && rate >= PERSISTENT_ERROR_RATE {
+            findings.push(Finding::PersistentErrors(errors.len() as u64));
+        }
+        if errors.iter().any(|e| e.state == Some(BusState::BusOff)) {
+            findings.push(Finding::BusOff);
+        } else if errors
+            .iter()
+            .any(|e| e.state == Some(BusState::ErrorPassive))
+        {
+            findings.push(Finding::ErrorPassive);
+        }
+
+        DiagnosticReport {
+            channel,
+            duration,
+            frames_received,
+            frames_sent,
+            frames_echoed,
+            errors,
+            findings,
+        }
+    }
+
+    /// Returns true if no problems were found.
+    pub fn healthy(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+impl fmt::Display for DiagnosticReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Channel {} observed for {:.1}s:",
+            self.channel,
+            self.duration.as_secs_f32()
+        )?;
+        writeln!(f, "\tframes received: {}", self.frames_received)?;
+        writeln!(f, "\tframes sent: {}", self.frames_sent)?;
+        writeln!(f, "\tframes echoed: {}", self.frames_echoed)?;
+        writeln!(f, "\terror frames: {}", self.errors.len())?;
+        if self.findings.is_empty() {
+            return writeln!(f, "No problems found.");
+        }
+        writeln!(f, "Problems found:")?;
+        for finding in self.findings.iter() {
+            writeln!(f, "\t- {}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(kinds: Vec<BusErrorKind>, state: Option<BusState>) -> BusError {
+        BusError {
+            channel: 0,
+            kinds,
+            state,
+            tx_error_count: 0,
+            rx_error_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let d = Duration::from_secs(1);
+        let zero = ChannelCounters::default();
+
+        let r = DiagnosticReport::analyze(0, d, &zero, &zero, vec![]);
+        assert_eq!(r.findings, vec![Finding::Silent]);
+
+        let healthy = ChannelCounters {
+            rx_frames: 100,
+            tx_frames: 10,
+            echo_frames: 10,
+            ..Default::default()
+        };
+        assert!(DiagnosticReport::analyze(0, d, &zero, &healthy, vec![]).healthy());
+
+        // our frames are never acknowledged
+        let unacked = ChannelCounters {
+            tx_frames: 10,
+            ..Default::default()
+        };
+        let errors = vec![
+            error(vec![BusErrorKind::NoAck], None),
+            error(vec![BusErrorKind::NoAck], Some(BusState::ErrorPassive)),
+        ];
+        let r = DiagnosticReport::analyze(0, d, &zero, &unacked, errors);
+        assert_eq!(
+            r.findings,
+            vec![
+                Finding::NoAck(2),
+                Finding::PersistentErrors(2),
+                Finding::ErrorPassive
+            ]
+        );
+
+        // wrong bitrate
+        let errors = vec![error(vec![BusErrorKind::Stuff], None)];
+        let r = DiagnosticReport::analyze(0, d, &zero, &zero, errors);
+        assert_eq!(r.findings, vec![Finding::BitrateMismatch]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Delivery of received frames to parts of the driver waiting on them.
+
+use std::sync::Mutex;
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+
+use crate::queue;
+use crate::{BusError, Frame};
+
+struct Waiter {
+    token: u64,
+    matches: Box<dyn Fn(&Frame) -> bool + Send>,
+    send: Sender<Frame>,
+    // waiters for a single frame are removed once it was delivered
+    once: bool,
+}
+
+struct ErrorListener {
+    token: u64,
+    send: Sender<BusError>,
+    // errors which didn't fit in the listener's queue
+    dropped: u64,
+}
+
+/// Hands received frames to anyone waiting for a matching frame. Shared between
+/// the `Interface` and its rx thread.
+pub(crate) struct Dispatcher {
+    next_token: Mutex<u64>,
+    waiters: Mutex<Vec<Waiter>>,
+    error_listeners: Mutex<Vec<ErrorListener>>,
+}
+
+impl Dispatcher {
+    pub(crate) fn new() -> Dispatcher {
+        Dispatcher {
+            next_token: Mutex::new(0),
+            waiters: Mutex::new(vec![]),
+            error_listeners: Mutex::new(vec![]),
+        }
+    }
+
+    fn token(&self) -> u64 {
+        let mut t = self.next_token.lock().unwrap();
+        *t += 1;
+        *t
+    }
+
+    /// Wait for the next frame accepted by `matches`. The returned receiver gets
+    /// at most one frame. The token can be used to cancel the wait.
+    pub(crate) fn wait_for(
+        &self,
+        matches: impl Fn(&Frame) -> bool + Send + 'static,
+    ) -> (u64, Receiver<Frame>) {
+        let token = self.token();
+        let (send, recv) = bounded(1);
+        self.waiters.lock().unwrap().push(Waiter {
+            token,
+            matches: Box::new(matches),
+            send,
+            once: true,
+        });
+        (token, recv)
+    }
+
+    /// Receive all frames accepted by `matches` until cancelled with the returned
+    /// token.
+    pub(crate) fn subscribe(
+        &self,
+        matches: impl Fn(&Frame) -> bool + Send + 'static,
+    ) -> (u64, Receiver<Frame>) {
+        let token = self.token();
+        let (send, recv) = unbounded();
+        self.waiters.lock().unwrap().push(Waiter {
+            token,
+            matches: Box::new(matches),
+            send,
+            once: false,
+        });
+        (token, recv)
+    }
+
+    /// Stop waiting for the frame or frames registered with `token`.
+    pub(crate) fn cancel(&self, token: u64) {
+        self.waiters.lock().unwrap().retain(|w| w.token != token);
+    }
+
+    /// Receive all errors reported by the device until the listener is removed
+    /// with `remove_error_listener`. Up to `capacity` errors are queued, or any
+    /// number if None, errors which don't fit are dropped and counted.
+    pub(crate) fn listen_errors(&self, capacity: Option<usize>) -> (u64, Receiver<BusError>) {
+        let token = self.token();
+        let (send, recv) = queue::channel(capacity);
+        self.error_listeners.lock().unwrap().push(ErrorListener {
+            token,
+            send,
+            dropped: 0,
+        });
+        (token, recv)
+    }
+
+    pub(crate) fn remove_error_listener(&self, token: u64) {
+        self.error_listeners
+            .lock()
+            .unwrap()
+            .retain(|l| l.token != token);
+    }
+
+    /// Returns the number of errors dropped because the queue of a listener was
+    /// full, zero if the listener was removed.
+    #[cfg_attr(not(feature = "python"), allow(dead_code))]
+    pub(crate) fn dropped_errors(&self, token: u64) -> u64 {
+        let listeners = self.error_listeners.lock().unwrap();
+        listeners
+            .iter()
+            .find(|l| l.token == token)
+            .map_or(0, |l| l.dropped)
+    }
+
+    /// Deliver an error reported by the device to all error listeners.
+    pub(crate) fn dispatch_error(&self, err: &BusError) {
+        // listeners whose receiver was dropped are removed
+        self.error_listeners
+            .lock()
+            .unwrap()
+            .retain_mut(|l| match l.send.try_send(err.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    l.dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    /// Deliver a received frame to all matching waiters.
+    pub(crate) fn dispatch(&self, f: &Frame) {
+        self.waiters.lock().unwrap().retain(|w| {
+            if !(w.matches)(f) {
+                return true;
+            }
+            // the waiter may have given up already, in which case it is removed
+            let delivered = w.send.try_send(f.clone()).is_ok();
+            delivered && !w.once
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_listeners() {
+        let d = Dispatcher::new();
+        let err = |channel| BusError {
+            channel,
+            kinds: vec![],
+            state: None,
+            tx_error_count: 0,
+            rx_error_count: 0,
+        };
+        let (bounded, small) = d.listen_errors(Some(2));
+        let (_, all) = d.listen_errors(None);
+        let (_, gone) = d.listen_errors(None);
+        drop(gone);
+        for channel in 0..5 {
+            d.dispatch_error(&err(channel));
+        }
+        let channels: Vec<u8> = small.try_iter().map(|e| e.channel).collect();
+        assert_eq!(channels, [0, 1]);
+        assert_eq!(d.dropped_errors(bounded), 3);
+        assert_eq!(all.len(), 5);
+        assert_eq!(d.error_listeners.lock().unwrap().len(), 2);
+
+        d.remove_error_listener(bounded);
+        assert_eq!(d.dropped_errors(bounded), 0);
+    }
+}
@@ -0,0 +1,79 @@
+//! Implementation of the `embedded-can` traits, so code written against the
+//! embedded HAL CAN traits can use an `Interface` directly. The frame trait is implemented in
+//! `cantact-core`.
+//!
+//! `Interface` implements both the non-blocking (`embedded_can::nb::Can`) and
+//! blocking (`embedded_can::blocking::Can`) traits. Receiving starts queueing
+//! frames received from other nodes on all channels the first time it is
+//! called; frames received before then are only passed to the rx callback.
+
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+use embedded_can::ErrorKind;
+
+use crate::{Error, Frame, Interface, RX_POLL_INTERVAL};
+
+impl embedded_can::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        // errors on the bus are reported through `BusError`, not as an `Error`
+        ErrorKind::Other
+    }
+}
+
+impl Interface {
+    // queue of received frames for the HAL traits, created on first use
+    fn hal_rx(&mut self) -> Result<&Receiver<Frame>, Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        if self.hal_rx.is_none() {
+            let (send, recv) = unbounded();
+            self.subscribe(0, 0, move |f| {
+                send.send(f).ok();
+            });
+            self.hal_rx = Some(recv);
+        }
+        Ok(self.hal_rx.as_ref().unwrap())
+    }
+}
+
+impl embedded_can::nb::Can for Interface {
+    type Frame = Frame;
+    type Error = Error;
+
+    /// Queue a frame for transmission. Returns `WouldBlock` while the device has
+    /// no room for the frame, see `Interface::try_send`. No frame is ever
+    /// replaced.
+    fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, Error> {
+        match self.try_send(frame.clone()) {
+            Ok(_) => Ok(None),
+            Err(Error::WouldBlock) => Err(nb::Error::WouldBlock),
+            Err(e) => Err(nb::Error::Other(e)),
+        }
+    }
+
+    fn receive(&mut self) -> nb::Result<Frame, Error> {
+        self.hal_rx()?.try_recv().map_err(|_| nb::Error::WouldBlock)
+    }
+}
+
+impl embedded_can::blocking::Can for Interface {
+    type Frame = Frame;
+    type Error = Error;
+
+    fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.send(frame.clone()).map(|_| ())
+    }
+
+    /// Wait for a frame. Returns `Error::NotRunning` if the interface is stopped
+    /// while waiting.
+    fn receive(&mut self) -> Result<Frame, Error> {
+        let rx = self.hal_rx()?.clone();
+        loop {
+            match rx.recv_timeout(RX_POLL_INTERVAL) {
+                Ok(f) => return Ok(f),
+                Err(RecvTimeoutError::Timeout) if *self.running.read().unwrap() => {}
+                Err(_) => return Err(Error::NotRunning),
+            }
+        }
+    }
+}
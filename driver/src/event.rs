@@ -0,0 +1,129 @@
+//! A single stream of everything the receive thread sees, delivered by
+//! `Interface::start_events`.
+
+use crossbeam_channel::Sender;
+
+use crate::{BusError, BusErrorKind, BusState, Frame};
+
+/// Something which happened on an interface, received from the channel returned
+/// by `Interface::start_events`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A frame was received, see `Interface::start`.
+    Frame(Frame),
+    /// The device reported an error frame.
+    BusError(BusError),
+    /// The controller of a channel changed state. Reported after the `BusError`
+    /// announcing the change.
+    StateChange {
+        /// Channel whose state changed.
+        channel: u8,
+        /// The new state.
+        state: BusState,
+    },
+    /// The device was unplugged. Unless auto-reconnect is enabled, no more events
+    /// follow, see `Interface::set_auto_reconnect`.
+    DeviceGone,
+    /// The device ran out of buffer space for received frames on a channel, and
+    /// frames were lost.
+    Overflow {
+        /// Channel which lost frames.
+        channel: u8,
+    },
+}
+
+/// Turns what the receive thread sees into events.
+pub(crate) struct Events {
+    send: Sender<Event>,
+    // last known state of each channel
+    states: Vec<BusState>,
+}
+
+impl Events {
+    pub(crate) fn new(send: Sender<Event>, channels: usize) -> Events {
+        Events {
+            send,
+            states: vec![BusState::ErrorActive; channels],
+        }
+    }
+
+    // the receiver may have been dropped, events are discarded then
+    fn emit(&self, ev: Event) {
+        self.send.send(ev).ok();
+    }
+
+    /// A frame was received. `overflow` is set if the device lost frames before
+    /// this one.
+    pub(crate) fn frame(&mut self, f: &Frame, overflow: bool) {
+        if overflow {
+            self.emit(Event::Overflow { channel: f.channel });
+        }
+        self.emit(Event::Frame(f.clone()));
+    }
+
+    /// An error frame was received.
+    pub(crate) fn error(&mut self, err: &BusError) {
+        let channel = err.channel;
+        if err.has(BusErrorKind::RxOverflow) {
+            self.emit(Event::Overflow { channel });
+        }
+        self.emit(Event::BusError(err.clone()));
+        if let (Some(state), Some(last)) = (err.state, self.states.get_mut(channel as usize)) {
+            if *last != state {
+                *last = state;
+                self.emit(Event::StateChange { channel, state });
+            }
+        }
+    }
+
+    /// The device was unplugged.
+    pub(crate) fn device_gone(&mut self) {
+        self.emit(Event::DeviceGone);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    #[test]
+    fn test_events() {
+        let (send, recv) = unbounded();
+        let mut ev = Events::new(send, 2);
+        let err = |kinds, state| BusError {
+            channel: 1,
+            kinds,
+            state,
+            tx_error_count: 100,
+            rx_error_count: 0,
+        };
+
+        let f = Frame::default();
+        ev.frame(&f, true);
+        let warning = err(vec![], Some(BusState::ErrorWarning));
+        ev.error(&warning);
+        // no change of state the second time
+        ev.error(&warning);
+        let overflow = err(vec![BusErrorKind::RxOverflow], None);
+        ev.error(&overflow);
+        ev.device_gone();
+
+        assert_eq!(
+            recv.try_iter().collect::<Vec<_>>(),
+            vec![
+                Event::Overflow { channel: 0 },
+                Event::Frame(f),
+                Event::BusError(warning.clone()),
+                Event::StateChange {
+                    channel: 1,
+                    state: BusState::ErrorWarning
+                },
+                Event::BusError(warning),
+                Event::Overflow { channel: 1 },
+                Event::BusError(overflow),
+                Event::DeviceGone,
+            ]
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Filtering of received frames by ID, applied on the rx thread before frames
+//! are delivered.
+
+use crate::device::gsusb::GSUSB_FILTER_SLOTS;
+use crate::{FilterExpr, Frame};
+
+/// Filter for received frames, added to a channel with `Interface::add_filter`.
+/// Mask and range filters look at the number of the ID only, standard and
+/// extended IDs with the same value are not told apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Frames with an ID matching `id` in the bits set in `mask`.
+    Mask {
+        /// ID to match.
+        id: u32,
+        /// Bits of the ID which are compared.
+        mask: u32,
+    },
+    /// Frames with an ID from `first` to `last`, inclusive.
+    Range {
+        /// Lowest ID which matches.
+        first: u32,
+        /// Highest ID which matches.
+        last: u32,
+    },
+    /// Frames which don't match the inner filter. Added to a channel, it
+    /// rejects the frames the inner filter matches.
+    Not(Box<Filter>),
+    /// Frames for which a filter expression is true, see `FilterExpr`.
+    Expr(FilterExpr),
+}
+
+impl Filter {
+    /// Returns a filter matching the frames this one doesn't match.
+    pub fn invert(self) -> Filter {
+        match self {
+            Filter::Not(f) => *f,
+            f => Filter::Not(Box::new(f)),
+        }
+    }
+
+    /// Returns true if the frame passes the filter.
+    pub fn matches(&self, f: &Frame) -> bool {
+        match self {
+            Filter::Mask { id, mask } => f.raw_id() & mask == id & mask,
+            Filter::Range { first, last } => (*first..=*last).contains(&f.raw_id()),
+            Filter::Not(inner) => !inner.matches(f),
+            Filter::Expr(expr) => expr.matches(f),
+        }
+    }
+}
+
+/// Handle to a filter, used to remove it with `Interface::remove_filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FilterHandle(pub(crate) u64);
+
+/// Filters of all channels of an `Interface`. Shared with the rx thread.
+pub(crate) struct Filters {
+    next_handle: u64,
+    // (handle, channel, filter)
+    filters: Vec<(FilterHandle, u8, Filter)>,
+}
+
+impl Filters {
+    pub(crate) fn new() -> Filters {
+        Filters {
+            next_handle: 0,
+            filters: vec![],
+        }
+    }
+
+    pub(crate) fn add(&mut self, channel: u8, filter: Filter) -> FilterHandle {
+        self.next_handle += 1;
+        let handle = FilterHandle(self.next_handle);
+        self.filters.push((handle, channel, filter));
+        handle
+    }
+
+    /// Remove a filter, returning the channel it was on.
+    pub(crate) fn remove(&mut self, handle: FilterHandle) -> Option<u8> {
+        let pos = self.filters.iter().position(|(h, _, _)| *h == handle)?;
+        Some(self.filters.remove(pos).1)
+    }
+
+    pub(crate) fn clear(&mut self, channel: u8) {
+        self.filters.retain(|(_, ch, _)| *ch != channel);
+    }
+
+    /// Returns the (id, mask) pairs to program into a device with acceptance
+    /// filters. Only mask filters can be done in hardware, so if a channel has
+    /// any other filter, or more filters than the device has slots, the device
+    /// receives every frame and filtering is left to `Filters::accepts`.
+    pub(crate) fn hw_table(&self, channel: u8) -> Vec<(u32, u32)> {
+        let mut table = vec![];
+        for (_, ch, filter) in self.filters.iter() {
+            match filter {
+                _ if *ch != channel => {}
+                Filter::Mask { id, mask } => table.push((*id, *mask)),
+                _ => return vec![],
+            }
+        }
+        if table.len() > GSUSB_FILTER_SLOTS {
+            return vec![];
+        }
+        table
+    }
+
+    /// Returns true if the frame should be delivered: it matches one of the
+    /// filters of its channel, or the channel only has inverted filters or none
+    /// at all, and it is not rejected by an inverted filter.
+    pub(crate) fn accepts(&self, f: &Frame) -> bool {
+        let mut filtered = false;
+        let mut matched = false;
+        for (_, ch, filter) in self.filters.iter() {
+            if *ch != f.channel {
+                continue;
+            }
+            match filter {
+                Filter::Not(_) if !filter.matches(f) => return false,
+                Filter::Not(_) => {}
+                _ => {
+                    filtered = true;
+                    matched |= filter.matches(f);
+                }
+            }
+        }
+        matched || !filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::gsusb::GSUSB_FEATURE_HW_FILTER;
+    use crate::device::mock::{self, Mock};
+    use crate::device::UsbBreq;
+
+    #[test]
+    fn test_filters() {
+        let frame = |channel, id| Frame {
+            channel,
+            ..Frame::new(id, &[]).unwrap()
+        };
+        let mut filters = Filters::new();
+        assert!(filters.accepts(&frame(0, 0x123)));
+
+        filters.add(
+            0,
+            Filter::Mask {
+                id: 0x100,
+                mask: 0x700,
+            },
+        );
+        let range = filters.add(
+            0,
+            Filter::Range {
+                first: 0x7E0,
+                last: 0x7EF,
+            },
+        );
+        assert!(filters.accepts(&frame(0, 0x123)));
+        assert!(filters.accepts(&frame(0, 0x7E8)));
+        assert!(!filters.accepts(&frame(0, 0x200)));
+        // other channels are not filtered
+        assert!(filters.accepts(&frame(1, 0x200)));
+
+        filters.add(
+            0,
+            Filter::Range {
+                first: 0x120,
+                last: 0x12F,
+            }
+            .invert(),
+        );
+        assert!(!filters.accepts(&frame(0, 0x123)));
+        assert!(filters.accepts(&frame(0, 0x130)));
+
+        // only mask filters are programmed into the device
+        assert_eq!(filters.hw_table(0), vec![]);
+        assert_eq!(filters.remove(range), Some(0));
+        assert!(!filters.accepts(&frame(0, 0x7E8)));
+        filters.clear(0);
+        assert!(filters.accepts(&frame(0, 0x7E8)));
+        let masks = (0..6).map(|id| Filter::Mask { id, mask: 0x7FF });
+        for f in masks {
+            filters.add(0, f);
+        }
+        assert_eq!(filters.hw_table(0).len(), 6);
+        filters.add(0, Filter::Mask { id: 6, mask: 0x7FF });
+        assert_eq!(filters.hw_table(0), vec![]);
+        filters.clear(0);
+
+        // expressions see the whole frame, and are filtered on the host
+        filters.add(0, Filter::Expr("id > 0x100 && dlc == 0".parse().unwrap()));
+        assert!(filters.accepts(&frame(0, 0x123)));
+        assert!(!filters.accepts(&frame(0, 0x100)));
+        assert_eq!(filters.hw_table(0), vec![]);
+        filters.clear(0);
+
+        // inverted filters alone only reject frames
+        filters.add(1, Filter::Mask { id: 0, mask: 0 }.invert());
+        assert!(!filters.accepts(&frame(1, 0x200)));
+        assert!(filters.accepts(&frame(0, 0x200)));
+    }
+
+    #[test]
+    fn test_hw_filters() {
+        let mock = Mock {
+            features: GSUSB_FEATURE_HW_FILTER,
+            ..Default::default()
+        };
+        let mut i = mock::interface(mock.clone());
+        let last_filter = || {
+            let written = mock.written.lock().unwrap();
+            let (req, channel, data) = written.last().unwrap().clone();
+            assert_eq!(req, UsbBreq::SetFilter);
+            (channel, data)
+        };
+
+        i.add_filter(
+            1,
+            Filter::Mask {
+                id: 0x100,
+                mask: 0x700,
+            },
+        )
+        .unwrap();
+        let (channel, data) = last_filter();
+        assert_eq!(channel, 1);
+        assert_eq!(data, [1, 0, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0]);
+
+        // ranges are filtered on the host, so the device receives everything
+        let range = i
+            .add_filter(1, Filter::Range { first: 0, last: 7 })
+            .unwrap();
+        assert_eq!(last_filter().1, [0, 0, 0, 0]);
+        i.remove_filter(range).unwrap();
+        assert_eq!(last_filter().1.len(), 12);
+
+        // a failed request leaves the filters as they were
+        mock.faults.lock().unwrap().requests = vec![UsbBreq::SetFilter];
+        assert!(i.add_filter(1, Filter::Mask { id: 0, mask: 0 }).is_err());
+        mock.faults.lock().unwrap().requests.clear();
+        i.clear_filters(1).unwrap();
+        assert_eq!(last_filter().1, [0, 0, 0, 0]);
+    }
+}
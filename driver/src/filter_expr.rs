@@ -0,0 +1,462 @@
+//! Filter expressions such as `id==0x7E8 || (id&0x700)==0x100 && data[0]==0x10`,
+//! parsed once into a tree which is evaluated for every received frame.
+//!
+//! Operators and their precedence, from lowest to highest, follow Rust:
+//! `||`, `&&`, comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`), `|`, `^`, `&`,
+//! `<<` and `>>`, then the unary `!` and `~`. Values are unsigned 64 bit
+//! integers, comparisons and logical operators give 0 or 1, and an expression
+//! matches a frame if its value is not 0.
+//!
+//! Numbers are decimal, hexadecimal (`0x`) or binary (`0b`). The fields of a
+//! frame are:
+//!
+//! - `id`: the CAN ID
+//! - `dlc`: the data length code
+//! - `len`: the number of data bytes
+//! - `data[N]`: data byte `N`, or 0 if the frame is shorter
+//! - `channel`: the channel the frame was received on
+//! - `ext`, `rtr`, `fd`, `brs`, `esi`: the flags of the frame, 0 or 1
+//!
+//! Parentheses, unary operators and chains of binary operators can be nested
+//! up to 64 deep.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Error, Frame};
+
+// deepest nesting of nodes in an expression, so parsing, evaluating and
+// dropping it can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// A compiled filter expression, created by parsing its text with
+/// `str::parse`. See `Filter::Expr`.
+///
+/// ```
+/// # use cantact::{FilterExpr, Frame};
+/// let expr: FilterExpr = "(id & 0x700) == 0x100 && dlc > 2 && data[0] == 0x10".parse()?;
+/// assert!(expr.matches(&Frame::new(0x123, &[0x10, 0, 0])?));
+/// assert!(!expr.matches(&Frame::new(0x223, &[0x10, 0, 0])?));
+/// # Ok::<(), cantact::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterExpr {
+    text: String,
+    root: Node,
+}
+
+impl FilterExpr {
+    /// Returns true if the expression is not 0 for the frame.
+    pub fn matches(&self, f: &Frame) -> bool {
+        self.root.eval(f) != 0
+    }
+
+    /// Returns the value of the expression for the frame.
+    pub fn eval(&self, f: &Frame) -> u64 {
+        self.root.eval(f)
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = Error;
+
+    /// Parses a filter expression. Returns `Error::InvalidFilter` describing
+    /// the first problem found if it is not valid.
+    fn from_str(s: &str) -> Result<FilterExpr, Error> {
+        let mut p = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            depth: 0,
+        };
+        let root = p.expr(0)?;
+        match p.peek() {
+            None => Ok(FilterExpr {
+                text: s.trim().to_string(),
+                root,
+            }),
+            Some(t) => Err(invalid(format!("unexpected {}", t))),
+        }
+    }
+}
+
+impl fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::InvalidFilter(msg)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Dlc,
+    Len,
+    Channel,
+    Ext,
+    Rtr,
+    Fd,
+    Brs,
+    Esi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+}
+
+impl Op {
+    // binding strength of binary operators, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+            Op::BitOr => 4,
+            Op::BitXor => 5,
+            Op::BitAnd => 6,
+            Op::Shl | Op::Shr => 7,
+        }
+    }
+
+    fn apply(self, a: u64, b: u64) -> u64 {
+        match self {
+            Op::Or => (a != 0 || b != 0) as u64,
+            Op::And => (a != 0 && b != 0) as u64,
+            Op::Eq => (a == b) as u64,
+            Op::Ne => (a != b) as u64,
+            Op::Lt => (a < b) as u64,
+            Op::Le => (a <= b) as u64,
+            Op::Gt => (a > b) as u64,
+            Op::Ge => (a >= b) as u64,
+            Op::BitOr => a | b,
+            Op::BitXor => a ^ b,
+            Op::BitAnd => a & b,
+            Op::Shl => a.checked_shl(b as u32).unwrap_or(0),
+            Op::Shr => a.checked_shr(b as u32).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Num(u64),
+    Field(Field),
+    Data(usize),
+    Not(Box<Node>),
+    Complement(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, f: &Frame) -> u64 {
+        match self {
+            Node::Num(n) => *n,
+            Node::Field(field) => match field {
+                Field::Id => f.raw_id() as u64,
+                Field::Dlc => f.can_dlc as u64,
+                Field::Len => f.len() as u64,
+                Field::Channel => f.channel as u64,
+                Field::Ext => f.is_extended() as u64,
+                Field::Rtr => f.rtr as u64,
+                Field::Fd => f.fd as u64,
+                Field::Brs => f.brs as u64,
+                Field::Esi => f.esi as u64,
+            },
+            Node::Data(n) => f.payload().get(*n).copied().unwrap_or(0) as u64,
+            Node::Not(a) => (a.eval(f) == 0) as u64,
+            Node::Complement(a) => !a.eval(f),
+            // logical operators skip the right side like in Rust
+            Node::Binary(Op::Or, a, b) => (a.eval(f) != 0 || b.eval(f) != 0) as u64,
+            Node::Binary(Op::And, a, b) => (a.eval(f) != 0 && b.eval(f) != 0) as u64,
+            Node::Binary(op, a, b) => op.apply(a.eval(f), b.eval(f)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(u64),
+    Ident(String),
+    Op(Op),
+    Not,
+    Complement,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "number {}", n),
+            Token::Ident(s) => write!(f, "'{}'", s),
+            Token::Op(op) => write!(f, "operator {:?}", op),
+            Token::Not => write!(f, "'!'"),
+            Token::Complement => write!(f, "'~'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::OpenBracket => write!(f, "'['"),
+            Token::CloseBracket => write!(f, "']'"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(match c.is_ascii_digit() {
+                true => Token::Num(parse_number(&word)?),
+                false => Token::Ident(word),
+            });
+            continue;
+        }
+        let (token, len) = match (c, next) {
+            ('|', Some('|')) => (Token::Op(Op::Or), 2),
+            ('&', Some('&')) => (Token::Op(Op::And), 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', Some('<')) => (Token::Op(Op::Shl), 2),
+            ('>', Some('>')) => (Token::Op(Op::Shr), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('|', _) => (Token::Op(Op::BitOr), 1),
+            ('^', _) => (Token::Op(Op::BitXor), 1),
+            ('&', _) => (Token::Op(Op::BitAnd), 1),
+            ('!', _) => (Token::Not, 1),
+            ('~', _) => (Token::Complement, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('[', _) => (Token::OpenBracket, 1),
+            (']', _) => (Token::CloseBracket, 1),
+            _ => return Err(invalid(format!("unexpected character '{}'", c))),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+fn parse_number(s: &str) -> Result<u64, Error> {
+    let lower = s.to_lowercase();
+    let n = if let Some(hex) = lower.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+    n.map_err(|_| invalid(format!("invalid number '{}'", s)))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // nodes enclosing the one being parsed
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, Error> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t.ok_or_else(|| invalid(String::from("unexpected end of expression")))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), Error> {
+        match self.next()? {
+            t if t == expected => Ok(()),
+            t => Err(invalid(format!("expected {}, found {}", expected, t))),
+        }
+    }
+
+    // parses operators binding tighter than `min_precedence` by precedence
+    // climbing, folding operations on constants
+    fn expr(&mut self, min_precedence: u8) -> Result<Node, Error> {
+        let depth = self.depth;
+        let left = self.chain(min_precedence);
+        self.depth = depth;
+        left
+    }
+
+    // each operator of a chain like `a || b || c` encloses the ones before it,
+    // so it counts as one more level of nesting
+    fn chain(&mut self, min_precedence: u8) -> Result<Node, Error> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.pos += 1;
+            self.enter()?;
+            let right = self.expr(op.precedence())?;
+            left = match (&left, &right) {
+                (Node::Num(a), Node::Num(b)) => Node::Num(op.apply(*a, *b)),
+                _ => Node::Binary(op, Box::new(left), Box::new(right)),
+            };
+        }
+        Ok(left)
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(invalid(String::from("expression nested too deeply")));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<Node, Error> {
+        self.enter()?;
+        let node = self.operand();
+        self.depth -= 1;
+        node
+    }
+
+    fn operand(&mut self) -> Result<Node, Error> {
+        match self.next()? {
+            Token::Not => Ok(match self.unary()? {
+                Node::Num(n) => Node::Num((n == 0) as u64),
+                a => Node::Not(Box::new(a)),
+            }),
+            Token::Complement => Ok(match self.unary()? {
+                Node::Num(n) => Node::Num(!n),
+                a => Node::Complement(Box::new(a)),
+            }),
+            Token::Num(n) => Ok(Node::Num(n)),
+            Token::Open => {
+                let e = self.expr(0)?;
+                self.expect(Token::Close)?;
+                Ok(e)
+            }
+            Token::Ident(name) => self.field(&name),
+            t => Err(invalid(format!("unexpected {}", t))),
+        }
+    }
+
+    fn field(&mut self, name: &str) -> Result<Node, Error> {
+        let field = match name {
+            "id" => Field::Id,
+            "dlc" => Field::Dlc,
+            "len" => Field::Len,
+            "channel" => Field::Channel,
+            "ext" => Field::Ext,
+            "rtr" => Field::Rtr,
+            "fd" => Field::Fd,
+            "brs" => Field::Brs,
+            "esi" => Field::Esi,
+            "data" => {
+                self.expect(Token::OpenBracket)?;
+                let index = match self.next()? {
+                    Token::Num(n) if n < 64 => n as usize,
+                    t => return Err(invalid(format!("invalid data index {}", t))),
+                };
+                self.expect(Token::CloseBracket)?;
+                return Ok(Node::Data(index));
+            }
+            _ => return Err(invalid(format!("unknown field '{}'", name))),
+        };
+        Ok(Node::Field(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_expr() {
+        let parse = |s: &str| s.parse::<FilterExpr>().unwrap();
+        let expr = parse("id==0x7E8 || (id&0x700)==0x100 && dlc>2 && data[0]==0x10");
+        assert!(expr.matches(&Frame::new(0x7E8, &[]).unwrap()));
+        assert!(expr.matches(&Frame::new(0x123, &[0x10, 0, 0]).unwrap()));
+        assert!(!expr.matches(&Frame::new(0x123, &[0x10, 0]).unwrap()));
+        assert!(!expr.matches(&Frame::new(0x223, &[0x10, 0, 0]).unwrap()));
+        assert_eq!(
+            expr.to_string(),
+            "id==0x7E8 || (id&0x700)==0x100 && dlc>2 && data[0]==0x10"
+        );
+
+        // bit operators bind tighter than comparisons, and constants are folded
+        assert_eq!(
+            parse("id & 0x700 == 0x100").root,
+            parse("(id&0x700)==(0x100)").root
+        );
+        assert_eq!(parse("1 << 4 | 0b1").root, Node::Num(0x11));
+        let f = Frame::new_ext(0x18DAF110, &[1, 2]).unwrap();
+        assert_eq!(parse("id >> 8 & 0xFF").eval(&f), 0xF1);
+        assert!(parse("ext && !rtr && len == 2 && data[5] == 0").matches(&f));
+        assert!(parse("~id & 1").matches(&f));
+
+        for bad in [
+            "", "id ==", "id = 1", "foo == 1", "data[64]", "(id", "0xZZ", "id 1",
+        ] {
+            assert!(
+                matches!(bad.parse::<FilterExpr>(), Err(Error::InvalidFilter(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |open: &str, n: usize, close: &str| {
+            format!("{}id{}", open.repeat(n), close.repeat(n)).parse::<FilterExpr>()
+        };
+        assert!(nested("(", 63, ")").is_ok());
+        assert!(nested("!", 63, "").is_ok());
+        assert!(nested("~(", 31, ")").is_ok());
+        assert!(matches!(nested("(", 64, ")"), Err(Error::InvalidFilter(_))));
+        assert!(matches!(nested("!", 64, ""), Err(Error::InvalidFilter(_))));
+        assert!(matches!(
+            nested("(", 100_000, ")"),
+            Err(Error::InvalidFilter(_))
+        ));
+
+        let chain = |n: usize| vec!["id"; n].join(" || ").parse::<FilterExpr>();
+        assert!(chain(64).is_ok());
+        assert!(matches!(chain(65), Err(Error::InvalidFilter(_))));
+        assert!(matches!(chain(100_000), Err(Error::InvalidFilter(_))));
+        // operators of other precedences don't add to the nesting
+        assert!("id == 1 && data[0] & 0xF0 == 0x10 || !(dlc < 8)"
+            .parse::<FilterExpr>()
+            .is_ok());
+    }
+}
@@ -0,0 +1,112 @@
+//! Random generation of frames, bit timings, and channel configurations for
+//! fuzzing and property testing. Enabled with the `testing` feature, or its
+//! older name `fuzzing`.
+//!
+//! Generated frames always pass `Frame::validate`: standard IDs fit in 11 bits,
+//! extended IDs in 29 bits, the DLC is at most 8 for classic frames, data bytes
+//! beyond the DLC are zero, remote frames carry no data, and only FD frames
+//! have the BRS and ESI flags. Frames and bit timings are built by
+//! `cantact_core::fuzzing`.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use cantact_core::fuzzing::{bit_timing_from_parts, fd_from_parts, frame_from_parts};
+use proptest::prelude::*;
+
+use crate::device::HostFrame;
+use crate::{BitTiming, Channel, Frame, MAX_EXTENDED_ID, MAX_STANDARD_ID};
+
+impl<'a> Arbitrary<'a> for HostFrame {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<HostFrame> {
+        let f = Frame::arbitrary(u)?;
+        let mut hf = HostFrame::from_frame(&f, u.arbitrary()?);
+        hf.timestamp_us = u.arbitrary()?;
+        Ok(hf)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Channel {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Channel> {
+        Ok(Channel {
+            bitrate: u.int_in_range(10_000..=1_000_000)?,
+            enabled: u.arbitrary()?,
+            loopback: u.arbitrary()?,
+            monitor: u.arbitrary()?,
+            one_shot: u.arbitrary()?,
+            fd: u.arbitrary()?,
+            data_bitrate: u.arbitrary()?,
+            triple_sample: u.arbitrary()?,
+        })
+    }
+}
+
+/// Strategy generating any valid classic frame, received from the bus on
+/// channel 0.
+pub fn frame() -> impl Strategy<Value = Frame> {
+    (
+        any::<bool>(),
+        any::<u32>(),
+        any::<bool>(),
+        0u8..=8,
+        any::<[u8; 8]>(),
+    )
+        .prop_map(|(ext, id, rtr, dlc, data)| frame_from_parts(ext, id, rtr, dlc, data, 0, None))
+}
+
+/// Strategy generating valid data frames with standard IDs.
+pub fn standard_frame() -> impl Strategy<Value = Frame> {
+    (0..=MAX_STANDARD_ID, 0u8..=8, any::<[u8; 8]>())
+        .prop_map(|(id, dlc, data)| frame_from_parts(false, id, false, dlc, data, 0, None))
+}
+
+/// Strategy generating valid data frames with extended IDs.
+pub fn extended_frame() -> impl Strategy<Value = Frame> {
+    (0..=MAX_EXTENDED_ID, 0u8..=8, any::<[u8; 8]>())
+        .prop_map(|(id, dlc, data)| frame_from_parts(true, id, false, dlc, data, 0, None))
+}
+
+/// Strategy generating valid CAN FD frames with any ID, length, and flags.
+pub fn fd_frame() -> impl Strategy<Value = Frame> {
+    (
+        frame(),
+        0u8..=15,
+        proptest::collection::vec(any::<u8>(), 64),
+        any::<bool>(),
+        any::<bool>(),
+    )
+        .prop_map(|(f, dlc, data, brs, esi)| fd_from_parts(f, dlc, &data, brs, esi))
+}
+
+/// Strategy generating bit timings within the ranges accepted by common
+/// controllers.
+pub fn bit_timing() -> impl Strategy<Value = BitTiming> {
+    any::<(u32, u32, u32, u32)>()
+        .prop_map(|(brp, seg1, seg2, sjw)| bit_timing_from_parts(brp, seg1, seg2, sjw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slcan::{self, Command};
+
+    proptest! {
+        #[test]
+        fn test_slcan_round_trip(f in frame()) {
+            let line = slcan::encode_frame(&f, None);
+            let parsed = match slcan::parse_command(line.trim_end_matches('\r')) {
+                Some(Command::Transmit(p)) => p,
+                c => panic!("unexpected command {:?}", c),
+            };
+            prop_assert_eq!(parsed.raw_id(), f.raw_id());
+            prop_assert_eq!(parsed.can_dlc, f.can_dlc);
+            prop_assert_eq!(parsed.data, f.data);
+            prop_assert_eq!((parsed.is_extended(), parsed.rtr), (f.is_extended(), f.rtr));
+        }
+
+        #[test]
+        fn test_host_frame_round_trip(f in prop_oneof![frame(), fd_frame()], echo_id: u32) {
+            prop_assert!(f.validate().is_ok());
+            let hf = HostFrame::from_le_bytes(&HostFrame::from_frame(&f, echo_id).to_le_bytes());
+            prop_assert_eq!(hf.to_frame(), f);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Forwarding of received frames to another channel or interface, done on the
+//! rx thread as frames arrive.
+
+use crate::hook::{Hook, HookHandle, Pipeline};
+use crate::tx::Transmitter;
+use crate::{Filter, Frame};
+
+/// Replacement of the ID of forwarded frames, see `Route::map_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdMap {
+    /// Keep the ID.
+    Keep,
+    /// Replace the ID.
+    Set(u32),
+    /// Add an offset to the ID.
+    Offset(i64),
+    /// Replace the bits of the ID set in `mask` with those of `value`.
+    Mask {
+        /// Bits of the ID which are replaced.
+        mask: u32,
+        /// New value of the replaced bits.
+        value: u32,
+    },
+}
+
+impl IdMap {
+    pub(crate) fn apply(&self, id: u32) -> u32 {
+        match *self {
+            IdMap::Keep => id,
+            IdMap::Set(id) => id,
+            // frames with IDs out of range are dropped by the hook
+            IdMap::Offset(offset) => (id as i64 + offset).clamp(0, u32::MAX as i64) as u32,
+            IdMap::Mask { mask, value } => (id & !mask) | (value & mask),
+        }
+    }
+}
+
+/// Change to a data byte of forwarded frames, see `Route::mangle`. Bytes past
+/// the end of the frame are left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mangle {
+    /// Replace the byte at `index` with `value`.
+    Set {
+        /// Index of the byte.
+        index: usize,
+        /// New value of the byte.
+        value: u8,
+    },
+    /// Clear the bits of the byte at `index` which are not set in `mask`.
+    And {
+        /// Index of the byte.
+        index: usize,
+        /// Bits which are kept.
+        mask: u8,
+    },
+    /// Set the bits of the byte at `index` which are set in `mask`.
+    Or {
+        /// Index of the byte.
+        index: usize,
+        /// Bits which are set.
+        mask: u8,
+    },
+    /// Flip the bits of the byte at `index` which are set in `mask`.
+    Xor {
+        /// Index of the byte.
+        index: usize,
+        /// Bits which are flipped.
+        mask: u8,
+    },
+}
+
+impl Mangle {
+    pub(crate) fn apply(&self, f: &mut Frame) {
+        let len = f.len();
+        let (index, value) = match *self {
+            Mangle::Set { index, value } if index < len => (index, value),
+            Mangle::And { index, mask } if index < len => (index, f.data[index] & mask),
+            Mangle::Or { index, mask } if index < len => (index, f.data[index] | mask),
+            Mangle::Xor { index, mask } if index < len => (index, f.data[index] ^ mask),
+            _ => return,
+        };
+        f.data[index] = value;
+    }
+}
+
+/// A forwarding rule of the gateway, added with `Interface::add_route` or
+/// `Interface::add_route_to`.
+///
+/// The filter, ID map and changes of a route make up its pipeline of hooks,
+/// which more hooks can be added to with `Interface::add_route_hook`.
+/// Forwarded frames then go through the tx hooks of the target interface.
+///
+/// ```no_run
+/// # use cantact::{Filter, IdMap, Interface, Mangle, Route};
+/// # let mut i = Interface::new()?;
+/// // forward 0x100 to 0x1FF from channel 0 to channel 1, moved up to 0x500
+/// let route = Route::new(0, 1)
+///     .filter(Filter::Range { first: 0x100, last: 0x1FF })
+///     .map_id(IdMap::Offset(0x400))
+///     .mangle(Mangle::Set { index: 0, value: 0xFF });
+/// i.add_route(route)?;
+/// // and everything from channel 1 back to channel 0 unchanged
+/// i.add_route(Route::new(1, 0))?;
+/// # Ok::<(), cantact::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    from: u8,
+    to: u8,
+    filter: Option<Filter>,
+    id_map: IdMap,
+    mangle: Vec<Mangle>,
+}
+
+impl Route {
+    /// Returns a route forwarding every frame received on channel `from` to
+    /// channel `to`, unchanged.
+    pub fn new(from: u8, to: u8) -> Route {
+        Route {
+            from,
+            to,
+            filter: None,
+            id_map: IdMap::Keep,
+            mangle: vec![],
+        }
+    }
+
+    /// Only forward frames matching `filter`.
+    pub fn filter(mut self, filter: Filter) -> Route {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Change the ID of forwarded frames.
+    pub fn map_id(mut self, id_map: IdMap) -> Route {
+        self.id_map = id_map;
+        self
+    }
+
+    /// Change the data of forwarded frames. Changes are applied in the order
+    /// they were added.
+    pub fn mangle(mut self, mangle: Mangle) -> Route {
+        self.mangle.push(mangle);
+        self
+    }
+
+    /// Returns the channel frames are received on.
+    pub fn from_channel(&self) -> u8 {
+        self.from
+    }
+
+    /// Returns the channel frames are forwarded to.
+    pub fn to_channel(&self) -> u8 {
+        self.to
+    }
+
+    // the hooks forwarded frames go through
+    fn pipeline(&self) -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        if let Some(filter) = &self.filter {
+            pipeline.add(Box::new(filter.clone()));
+        }
+        pipeline.add(Box::new(self.id_map));
+        for m in self.mangle.iter() {
+            pipeline.add(Box::new(*m));
+        }
+        pipeline
+    }
+}
+
+// returns the frame to send for a received frame, if it is forwarded
+fn forward(route: &Route, pipeline: &mut Pipeline, f: &Frame) -> Option<Frame> {
+    if f.channel != route.from {
+        return None;
+    }
+    let mut out = f.clone();
+    out.channel = route.to;
+    out.timestamp = None;
+    pipeline.run(&mut out).then_some(out)
+}
+
+/// Handle to a route, used to remove it with `Interface::remove_route`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RouteHandle(u64);
+
+/// Frames handled by a route, returned by `Interface::route_counters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouteCounters {
+    /// Frames sent on the target channel.
+    pub forwarded: u64,
+    /// Frames which matched the route but could not be sent, because the
+    /// target is not running, the changed frame is not valid, or sending
+    /// failed.
+    pub dropped: u64,
+}
+
+struct Entry {
+    handle: RouteHandle,
+    route: Route,
+    pipeline: Pipeline,
+    // sends on the target interface
+    target: Transmitter,
+    counters: RouteCounters,
+}
+
+/// Routes of an `Interface`. Shared with the rx thread.
+pub(crate) struct Gateway {
+    next_handle: u64,
+    routes: Vec<Entry>,
+}
+
+impl Gateway {
+    pub(crate) fn new() -> Gateway {
+        Gateway {
+            next_handle: 0,
+            routes: vec![],
+        }
+    }
+
+    pub(crate) fn add(&mut self, route: Route, target: Transmitter) -> RouteHandle {
+        self.next_handle += 1;
+        let handle = RouteHandle(self.next_handle);
+        self.routes.push(Entry {
+            handle,
+            pipeline: route.pipeline(),
+            route,
+            target,
+            counters: RouteCounters::default(),
+        });
+        handle
+    }
+
+    pub(crate) fn remove(&mut self, handle: RouteHandle) {
+        self.routes.retain(|e| e.handle != handle);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    /// Add a hook to the end of the pipeline of a route, returning `None` if
+    /// the route was removed.
+    pub(crate) fn add_hook(
+        &mut self,
+        route: RouteHandle,
+        hook: Box<dyn Hook>,
+    ) -> Option<HookHandle> {
+        let e = self.routes.iter_mut().find(|e| e.handle == route)?;
+        Some(e.pipeline.add(hook))
+    }
+
+    /// Remove a hook from whichever route it was added to.
+    pub(crate) fn remove_hook(&mut self, handle: HookHandle) -> bool {
+        self.routes.iter_mut().any(|e| e.pipeline.remove(handle))
+    }
+
+    pub(crate) fn counters(&self, handle: RouteHandle) -> Option<RouteCounters> {
+        self.routes
+            .iter()
+            .find(|e| e.handle == handle)
+            .map(|e| e.counters)
+    }
+
+    /// Forward a frame received from the bus along every route it matches.
+    pub(crate) fn frame(&mut self, f: &Frame) {
+        for e in self.routes.iter_mut() {
+            if let Some(out) = forward(&e.route, &mut e.pipeline, f) {
+                match e.target.send_priority(&out) {
+                    Ok(_) => e.counters.forwarded += 1,
+                    Err(_) => e.counters.dropped += 1,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::{self, Mock};
+
+    #[test]
+    fn test_route() {
+        let route = Route::new(0, 1)
+            .filter(Filter::Range {
+                first: 0x100,
+                last: 0x1FF,
+            })
+            .map_id(IdMap::Mask {
+                mask: 0xF00,
+                value: 0x500,
+            })
+            .mangle(Mangle::Set {
+                index: 0,
+                value: 0xFF,
+            })
+            .mangle(Mangle::Xor {
+                index: 1,
+                mask: 0x0F,
+            })
+            .mangle(Mangle::Set {
+                index: 2,
+                value: 0xFF,
+            });
+        let mut pipeline = route.pipeline();
+        let mut f = Frame::new(0x123, &[1, 0x22]).unwrap();
+        let out = forward(&route, &mut pipeline, &f).unwrap();
+        assert_eq!(out.channel, 1);
+        assert_eq!(out.raw_id(), 0x523);
+        // the third byte is past the end of the frame
+        assert_eq!(out.payload(), &[0xFF, 0x2D]);
+
+        let other = Frame::new(0x223, &[]).unwrap();
+        assert!(forward(&route, &mut pipeline, &other).is_none());
+        f.channel = 1;
+        assert!(forward(&route, &mut pipeline, &f).is_none());
+
+        assert_eq!(IdMap::Offset(-0x100).apply(0x123), 0x23);
+        assert_eq!(IdMap::Set(0x7E8).apply(0x123), 0x7E8);
+
+        // a standard ID can't be moved past 0x7FF
+        let route = Route::new(0, 1).map_id(IdMap::Offset(0x700));
+        let mut pipeline = route.pipeline();
+        let f = Frame::new(0x123, &[]).unwrap();
+        assert!(forward(&route, &mut pipeline, &f).is_none());
+        let f = Frame::new_ext(0x123, &[]).unwrap();
+        assert_eq!(forward(&route, &mut pipeline, &f).unwrap().raw_id(), 0x823);
+    }
+
+    #[test]
+    fn test_gateway() {
+        let mut i = mock::interface(Mock::default());
+        let route = i
+            .add_route(
+                Route::new(0, 1)
+                    .map_id(IdMap::Offset(0x100))
+                    .mangle(Mangle::Set {
+                        index: 0,
+                        value: 0xAA,
+                    }),
+            )
+            .unwrap();
+        assert!(matches!(
+            i.add_route(Route::new(0, 2)),
+            Err(crate::Error::InvalidChannel)
+        ));
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        i.start(move |f| send.send(f).unwrap()).unwrap();
+        let mut f = Frame::new(0x123, &[1, 2]).unwrap();
+        mock::receive(&i, &f);
+        // received frames on channel 1 are not forwarded
+        f.channel = 1;
+        mock::receive(&i, &f);
+
+        let timeout = std::time::Duration::from_secs(1);
+        let received = recv.recv_timeout(timeout).unwrap();
+        assert_eq!((received.channel, received.raw_id()), (0, 0x123));
+        assert_eq!(recv.recv_timeout(timeout).unwrap().channel, 1);
+        // the forwarded frame is echoed back after being sent on channel 1
+        let echo = recv.recv_timeout(timeout).unwrap();
+        assert!(echo.is_echo());
+        assert_eq!((echo.channel, echo.raw_id()), (1, 0x223));
+        assert_eq!(echo.payload(), &[0xAA, 2]);
+        assert!(recv.recv_timeout(timeout / 10).is_err());
+        assert_eq!(i.route_counters(route).unwrap().forwarded, 1);
+
+        i.stop().unwrap();
+        i.remove_route(route);
+        assert!(i.route_counters(route).is_none());
+    }
+}
@@ -0,0 +1,286 @@
+//! Access to a single channel of an interface.
+
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+
+use crate::{
+    BitTiming, BusState, Capabilities, ChannelCounters, DiagnosticReport, Error, ErrorCounters,
+    Filter, FilterHandle, Frame, Id, Interface, IsoTpSocket, RateLimit, Response,
+    SubscriptionHandle, Transaction, WatchEvent, WatchHandle,
+};
+
+/// A handle to one channel of an `Interface`, returned by `Interface::channel`.
+///
+/// All operations apply to the channel the handle was created for, so the channel
+/// index only has to be validated once.
+#[derive(Debug)]
+pub struct ChannelHandle<'a> {
+    i: &'a mut Interface,
+    channel: usize,
+}
+
+impl<'a> ChannelHandle<'a> {
+    pub(crate) fn new(i: &'a mut Interface, channel: usize) -> ChannelHandle<'a> {
+        ChannelHandle { i, channel }
+    }
+
+    /// Returns the index of this channel.
+    pub fn index(&self) -> usize {
+        self.channel
+    }
+
+    /// Send a frame on this channel. The frame's `channel` field is ignored.
+    ///
+    /// Returns the echo ID assigned to the frame, see `Interface::send`.
+    pub fn send(&mut self, mut f: Frame) -> Result<u32, Error> {
+        f.channel = self.channel as u8;
+        self.i.send(f)
+    }
+
+    /// Wait up to `timeout` for the next frame received from the bus on this
+    /// channel. The frame is also passed to the rx callback as usual.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Frame, Error> {
+        if !*self.i.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        let channel = self.channel as u8;
+        let (token, recv) = self
+            .i
+            .dispatcher
+            .wait_for(move |f: &Frame| f.channel == channel && !f.is_echo());
+        recv.recv_timeout(timeout).map_err(|_| {
+            self.i.dispatcher.cancel(token);
+            Error::Timeout
+        })
+    }
+
+    /// Request data from another node using a remote frame, see
+    /// `Interface::request_remote`.
+    pub fn request_remote(&mut self, id: u32, timeout: Duration) -> Result<Frame, Error> {
+        self.i.request_remote(self.channel, id, timeout)
+    }
+
+    /// Send a frame on this channel and wait for the first matching response, see
+    /// `Interface::query`. The frame's `channel` field is ignored.
+    pub fn query(
+        &mut self,
+        mut request: Frame,
+        response_filter: impl Fn(&Frame) -> bool + Send + 'static,
+        timeout: Duration,
+    ) -> Result<Frame, Error> {
+        request.channel = self.channel as u8;
+        self.i.query(request, response_filter, timeout)
+    }
+
+    /// Send a frame on this channel without waiting for the response, see
+    /// `Interface::begin`. The frame's `channel` field is ignored.
+    pub fn begin(
+        &mut self,
+        mut request: Frame,
+        response: Response,
+        timeout: Duration,
+    ) -> Result<Transaction, Error> {
+        request.channel = self.channel as u8;
+        self.i.begin(request, response, timeout)
+    }
+
+    /// Set the bitrate of this channel, see `Interface::set_bitrate`.
+    pub fn set_bitrate(&mut self, bitrate: u32) -> Result<BitTiming, Error> {
+        self.i.set_bitrate(self.channel, bitrate)
+    }
+
+    /// Set the bitrate of this channel with the sample point closest to
+    /// `sample_point`, see `Interface::set_bitrate_with_sample_point`.
+    pub fn set_bitrate_with_sample_point(
+        &mut self,
+        bitrate: u32,
+        sample_point: f32,
+    ) -> Result<BitTiming, Error> {
+        self.i
+            .set_bitrate_with_sample_point(self.channel, bitrate, sample_point)
+    }
+
+    /// Set the synchronization jump width of this channel, see
+    /// `Interface::set_sjw`.
+    pub fn set_sjw(&mut self, sjw: u32) -> Result<(), Error> {
+        self.i.set_sjw(self.channel, sjw)
+    }
+
+    /// Returns the bit timing of this channel, see `Interface::bit_timing`.
+    pub fn bit_timing(&self) -> Option<BitTiming> {
+        self.i.bit_timing(self.channel)
+    }
+
+    /// Set a custom bit timing for this channel, see `Interface::set_bit_timing`.
+    pub fn set_bit_timing(
+        &mut self,
+        brp: u32,
+        phase_seg1: u32,
+        phase_seg2: u32,
+        sjw: u32,
+    ) -> Result<(), Error> {
+        self.i
+            .set_bit_timing(self.channel, brp, phase_seg1, phase_seg2, sjw)
+    }
+
+    /// Set the data phase bitrate of this channel, see
+    /// `Interface::set_data_bitrate`.
+    pub fn set_data_bitrate(&mut self, bitrate: u32) -> Result<(), Error> {
+        self.i.set_data_bitrate(self.channel, bitrate)
+    }
+
+    /// Set a custom data phase bit timing for this channel, see
+    /// `Interface::set_data_bit_timing`.
+    pub fn set_data_bit_timing(
+        &mut self,
+        brp: u32,
+        phase_seg1: u32,
+        phase_seg2: u32,
+        sjw: u32,
+    ) -> Result<(), Error> {
+        self.i
+            .set_data_bit_timing(self.channel, brp, phase_seg1, phase_seg2, sjw)
+    }
+
+    /// Enable or disable this channel, see `Interface::set_enabled`.
+    pub fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.i.set_enabled(self.channel, enabled)
+    }
+
+    /// Enable or disable listen only mode, see `Interface::set_monitor`.
+    pub fn set_monitor(&mut self, enabled: bool) -> Result<(), Error> {
+        self.i.set_monitor(self.channel, enabled)
+    }
+
+    /// Enable or disable hardware loopback mode, see `Interface::set_loopback`.
+    pub fn set_loopback(&mut self, enabled: bool) -> Result<(), Error> {
+        self.i.set_loopback(self.channel, enabled)
+    }
+
+    /// Enable or disable one-shot mode, see `Interface::set_one_shot`.
+    pub fn set_one_shot(&mut self, enabled: bool) -> Result<(), Error> {
+        self.i.set_one_shot(self.channel, enabled)
+    }
+
+    /// Enable or disable triple sampling, see `Interface::set_triple_sample`.
+    pub fn set_triple_sample(&mut self, enabled: bool) -> Result<(), Error> {
+        self.i.set_triple_sample(self.channel, enabled)
+    }
+
+    /// Open an ISO-TP connection on this channel, see `Interface::isotp`.
+    pub fn isotp(&self, tx_id: u32, rx_id: u32) -> Result<IsoTpSocket, Error> {
+        self.i.isotp(self.channel, tx_id, rx_id)
+    }
+
+    /// Limit the rate of frames sent with ID `id` on this channel, see
+    /// `Interface::set_id_rate_limit`.
+    pub fn set_id_rate_limit(&mut self, id: Id, limit: Option<RateLimit>) -> Result<(), Error> {
+        self.i.set_id_rate_limit(self.channel, id, limit)
+    }
+
+    /// Limit the share of the bus time used by frames sent on this channel, see
+    /// `Interface::set_max_bus_load`.
+    pub fn set_max_bus_load(&mut self, load: Option<f32>) -> Result<(), Error> {
+        self.i.set_max_bus_load(self.channel, load)
+    }
+
+    /// Add a filter for frames received on this channel, see
+    /// `Interface::add_filter`.
+    pub fn add_filter(&mut self, filter: Filter) -> Result<FilterHandle, Error> {
+        self.i.add_filter(self.channel, filter)
+    }
+
+    /// Remove all filters of this channel, see `Interface::clear_filters`.
+    pub fn clear_filters(&mut self) -> Result<(), Error> {
+        self.i.clear_filters(self.channel)
+    }
+
+    /// Call `callback` for every frame received on this channel with an ID
+    /// matching `id` in the bits set in `mask`, see `Interface::subscribe`.
+    pub fn subscribe(
+        &mut self,
+        id: u32,
+        mask: u32,
+        callback: impl FnMut(Frame) + Send + 'static,
+    ) -> SubscriptionHandle {
+        self.i.subscribe_channel(self.channel, id, mask, callback)
+    }
+
+    /// Returns a queue receiving every frame received on this channel, see
+    /// `Interface::channel_receiver`.
+    pub fn receiver(&mut self) -> Receiver<Frame> {
+        // the channel index was validated when the handle was created
+        self.i.channel_receiver(self.channel).unwrap()
+    }
+
+    /// Watch a cyclic message for changes, see `Interface::watch`.
+    pub fn watch(
+        &mut self,
+        id: u32,
+        mask: [u8; 8],
+        timeout: Option<Duration>,
+        callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> Result<WatchHandle, Error> {
+        self.i.watch(self.channel, id, mask, timeout, callback)
+    }
+
+    /// Supervise a cyclic message, see `Interface::supervise`.
+    pub fn supervise(
+        &mut self,
+        id: u32,
+        deadline: Duration,
+        callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> Result<WatchHandle, Error> {
+        self.i.supervise(self.channel, id, deadline, callback)
+    }
+
+    /// Returns the optional features supported by this channel.
+    pub fn capabilities(&self) -> Capabilities {
+        // the channel index was validated when the handle was created
+        self.i.capabilities(self.channel).unwrap()
+    }
+
+    /// Start or stop blinking the LED of this channel, see `Interface::identify`.
+    pub fn identify(&mut self, on: bool) -> Result<(), Error> {
+        self.i.identify(self.channel, on)
+    }
+
+    /// Switch the termination resistor of this channel on or off, see
+    /// `Interface::set_termination`.
+    pub fn set_termination(&mut self, enabled: bool) -> Result<(), Error> {
+        self.i.set_termination(self.channel, enabled)
+    }
+
+    /// Returns true if the termination resistor of this channel is switched on.
+    pub fn get_termination(&self) -> Result<bool, Error> {
+        self.i.get_termination(self.channel)
+    }
+
+    /// Returns the controller state of this channel, see `Interface::bus_state`.
+    pub fn bus_state(&self) -> Result<BusState, Error> {
+        self.i.bus_state(self.channel)
+    }
+
+    /// Returns the error counters of this channel, see
+    /// `Interface::error_counters`.
+    pub fn error_counters(&self) -> Result<ErrorCounters, Error> {
+        self.i.error_counters(self.channel)
+    }
+
+    /// Returns the traffic counters of this channel.
+    pub fn counters(&self) -> ChannelCounters {
+        // the channel index was validated when the handle was created
+        self.i.counters(self.channel).unwrap()
+    }
+
+    /// Reset the traffic counters of this channel.
+    pub fn reset_counters(&mut self) {
+        self.i.reset_counters(self.channel).unwrap()
+    }
+
+    /// Check the health of the bus on this channel, see `Interface::diagnose`.
+    pub fn diagnose(&mut self, duration: Duration) -> Result<DiagnosticReport, Error> {
+        self.i.diagnose(self.channel, duration)
+    }
+}
@@ -0,0 +1,198 @@
+//! Hooks which inspect, change or drop frames on their way in or out of an
+//! interface, and on their way through the gateway.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Filter, Frame, Id, IdMap, Mangle};
+
+// handles are unique across all pipelines, so `Interface::remove_hook` finds
+// the pipeline a hook is in
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// A step of a frame pipeline, added with `Interface::add_rx_hook`,
+/// `Interface::add_tx_hook` or `Interface::add_route_hook`.
+///
+/// Closures taking `&mut Frame` and returning a `bool` are hooks, as are
+/// `IdMap` and `Mangle`, which change every frame, and `Filter`, which drops
+/// the frames it doesn't match.
+///
+/// ```no_run
+/// # use cantact::{Filter, Interface, Mangle};
+/// # let mut i = Interface::new()?;
+/// // fix the checksum in the last byte of 0x123 before it is sent
+/// i.add_tx_hook(|f: &mut cantact::Frame| {
+///     if f.raw_id() == 0x123 && f.len() == 8 {
+///         f.data[7] = f.data[..7].iter().fold(0, |a, b| a ^ b);
+///     }
+///     true
+/// });
+/// // hide the VIN in received frames, and ignore diagnostics
+/// i.add_rx_hook(|f: &mut cantact::Frame| {
+///     if f.raw_id() == 0x3E0 {
+///         f.data.fill(0);
+///     }
+///     true
+/// });
+/// i.add_rx_hook(Filter::Range { first: 0, last: 0x6FF });
+/// # Ok::<(), cantact::Error>(())
+/// ```
+pub trait Hook: Send {
+    /// Inspect or change a frame. Returns false to drop it, so the hooks after
+    /// this one don't see it.
+    fn frame(&mut self, f: &mut Frame) -> bool;
+}
+
+impl<F: FnMut(&mut Frame) -> bool + Send> Hook for F {
+    fn frame(&mut self, f: &mut Frame) -> bool {
+        self(f)
+    }
+}
+
+impl Hook for IdMap {
+    // frames are dropped if the new ID is out of range for their type of ID
+    fn frame(&mut self, f: &mut Frame) -> bool {
+        match Id::new(self.apply(f.raw_id()), f.is_extended()) {
+            Some(id) => {
+                f.id = id;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Hook for Mangle {
+    fn frame(&mut self, f: &mut Frame) -> bool {
+        self.apply(f);
+        true
+    }
+}
+
+impl Hook for Filter {
+    fn frame(&mut self, f: &mut Frame) -> bool {
+        self.matches(f)
+    }
+}
+
+/// Handle to a hook, used to remove it with `Interface::remove_hook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookHandle(u64);
+
+/// Hooks run in the order they were added.
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    hooks: Vec<(HookHandle, Box<dyn Hook>)>,
+}
+
+impl Pipeline {
+    pub(crate) fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub(crate) fn add(&mut self, hook: Box<dyn Hook>) -> HookHandle {
+        let handle = HookHandle(NEXT_HANDLE.fetch_add(1, Ordering::Relaxed));
+        self.hooks.push((handle, hook));
+        handle
+    }
+
+    /// Remove a hook, returning false if it is not in this pipeline.
+    pub(crate) fn remove(&mut self, handle: HookHandle) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|(h, _)| *h != handle);
+        self.hooks.len() != len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    /// Pass a frame through the hooks. Returns false if one of them dropped it.
+    pub(crate) fn run(&mut self, f: &mut Frame) -> bool {
+        self.hooks.iter_mut().all(|(_, hook)| hook.frame(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::{self, Mock};
+    use crate::Route;
+
+    #[test]
+    fn test_pipeline() {
+        let mut p = Pipeline::new();
+        let mut f = Frame::new(0x123, &[1, 2]).unwrap();
+        assert!(p.run(&mut f));
+
+        p.add(Box::new(Filter::Range {
+            first: 0x100,
+            last: 0x1FF,
+        }));
+        let offset = p.add(Box::new(IdMap::Offset(0x100)));
+        p.add(Box::new(Mangle::Set {
+            index: 1,
+            value: 0xFF,
+        }));
+        p.add(Box::new(|f: &mut Frame| f.data[0] != 0));
+        assert!(p.run(&mut f));
+        assert_eq!(f.raw_id(), 0x223);
+        assert_eq!(f.payload(), &[1, 0xFF]);
+
+        // the filter sees the frame before the ID is changed
+        let mut f = Frame::new(0x223, &[1]).unwrap();
+        assert!(!p.run(&mut f));
+        assert_eq!(f.raw_id(), 0x223);
+        let mut f = Frame::new(0x123, &[0]).unwrap();
+        assert!(!p.run(&mut f));
+
+        assert!(p.remove(offset));
+        assert!(!p.remove(offset));
+        let mut f = Frame::new(0x123, &[1]).unwrap();
+        assert!(p.run(&mut f));
+        assert_eq!(f.raw_id(), 0x123);
+        p.clear();
+        assert!(p.run(&mut Frame::new(0x223, &[0]).unwrap()));
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut i = mock::interface(Mock::default());
+        let timeout = std::time::Duration::from_secs(1);
+        // sent frames get a counter in their first byte, 0x7FF is never sent
+        let mut counter = 0;
+        i.add_tx_hook(move |f: &mut Frame| {
+            counter += 1;
+            f.data[0] = counter;
+            f.raw_id() != 0x7FF
+        });
+        let drop_rx = i.add_rx_hook(Filter::Range {
+            first: 0x200,
+            last: 0x7FF,
+        });
+        let route = i.add_route(Route::new(0, 1)).unwrap();
+        i.add_route_hook(route, IdMap::Set(0x300)).unwrap();
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        i.start(move |f| send.send(f).unwrap()).unwrap();
+        assert!(matches!(
+            i.send(Frame::new(0x7FF, &[0]).unwrap()),
+            Err(crate::Error::FrameDropped)
+        ));
+        i.send(Frame::new(0x200, &[0]).unwrap()).unwrap();
+        let echo = recv.recv_timeout(timeout).unwrap();
+        assert_eq!((echo.raw_id(), echo.payload()), (0x200, &[2][..]));
+
+        // dropped by the rx hook, so not forwarded either
+        let f = Frame::new(0x100, &[0]).unwrap();
+        mock::receive(&i, &f);
+        assert!(recv.recv_timeout(timeout / 10).is_err());
+        i.remove_hook(drop_rx);
+        mock::receive(&i, &f);
+        assert_eq!(recv.recv_timeout(timeout).unwrap().raw_id(), 0x100);
+        // forwarded through the route hook and the tx hook
+        let echo = recv.recv_timeout(timeout).unwrap();
+        assert_eq!((echo.channel, echo.raw_id()), (1, 0x300));
+        assert_eq!(echo.payload(), &[3]);
+        i.stop().unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+//! Notifications of devices being attached and removed.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::device::hotplug::{self, Hotplug, UsbEvent};
+use crate::device::UsbContext;
+use crate::Error;
+
+/// A CANtact device on the USB bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Number of the bus the device is connected to.
+    pub bus: u8,
+    /// Address of the device on its bus.
+    pub address: u8,
+    /// USB serial number of the device, which can be passed to
+    /// `Interface::open_serial`. None if it could not be read, for example
+    /// because the device is in use by another process.
+    pub serial: Option<String>,
+}
+
+/// Change reported to the callback registered with `watch_devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotplugEvent {
+    /// A device was attached.
+    Attached(DeviceInfo),
+    /// A device was removed.
+    Removed(DeviceInfo),
+}
+
+/// Watches for devices being attached and removed, returned by `watch_devices`.
+/// Watching stops when it is dropped.
+pub struct DeviceWatcher {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+// devices which are attached, so removed devices can be reported with the
+// serial number read when they arrived
+struct Attached(HashMap<(u8, u8), Option<String>>);
+
+impl Attached {
+    fn event(&mut self, e: UsbEvent) -> HotplugEvent {
+        let key = (e.bus, e.address);
+        let mut info = DeviceInfo {
+            bus: e.bus,
+            address: e.address,
+            serial: e.serial,
+        };
+        if e.arrived {
+            self.0.insert(key, info.serial.clone());
+            HotplugEvent::Attached(info)
+        } else {
+            info.serial = self.0.remove(&key).flatten();
+            HotplugEvent::Removed(info)
+        }
+    }
+}
+
+/// Calls `callback` whenever a device is attached or removed, until the returned
+/// `DeviceWatcher` is dropped. Devices which are already attached are reported
+/// first. The callback is called from a separate thread.
+///
+/// Returns `Error::Unsupported` if libusb does not support hotplug
+/// notifications on this system.
+pub fn watch_devices(
+    mut callback: impl FnMut(HotplugEvent) + Send + 'static,
+) -> Result<DeviceWatcher, Error> {
+    if !hotplug::supported() {
+        return Err(Error::Unsupported);
+    }
+    let mut hotplug = Hotplug::new(UsbContext::new()?)?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = Arc::clone(&running);
+    let thread = thread::spawn(move || {
+        let mut attached = Attached(HashMap::new());
+        while r.load(Ordering::SeqCst) {
+            for e in hotplug.poll() {
+                callback(attached.event(e));
+            }
+        }
+    });
+
+    Ok(DeviceWatcher {
+        running,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attached() {
+        let mut a = Attached(HashMap::new());
+        let e = |arrived, address, serial: Option<&str>| UsbEvent {
+            arrived,
+            bus: 1,
+            address,
+            serial: serial.map(String::from),
+        };
+        let info = |address, serial: Option<&str>| DeviceInfo {
+            bus: 1,
+            address,
+            serial: serial.map(String::from),
+        };
+
+        assert_eq!(
+            a.event(e(true, 4, Some("A1"))),
+            HotplugEvent::Attached(info(4, Some("A1")))
+        );
+        assert_eq!(
+            a.event(e(false, 4, None)),
+            HotplugEvent::Removed(info(4, Some("A1")))
+        );
+        // a device which was never seen arriving
+        assert_eq!(
+            a.event(e(false, 5, None)),
+            HotplugEvent::Removed(info(5, None))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Deliberately faulty traffic, to test how other nodes handle errors on the
+//! bus.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::device::gsusb::{CanMode, Mode, GSUSB_FEATURE_LISTEN_ONLY};
+use crate::tx::TX_SLOTS;
+use crate::{Error, Frame, Interface};
+
+// how often a flood checks for a free transmit slot while the device is full
+const FLOOD_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+impl Interface {
+    /// Send a frame without checking it with `Frame::validate`, such as a
+    /// classic frame with a DLC over 8, an FD frame marked as remote, or a
+    /// classic frame with the BRS or ESI flags set through `Frame::raw_flags`.
+    /// What reaches the bus depends on the firmware, which may correct or
+    /// drop the frame.
+    ///
+    /// The frame goes through the tx hooks, but is not held back by rate
+    /// limits. Returns `Error::InvalidChannel` if the channel does not exist.
+    pub fn send_unchecked(&mut self, f: Frame) -> Result<u32, Error> {
+        if f.channel as usize > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        self.transmitter().send_unchecked(&f)
+    }
+
+    /// Stop acknowledging frames on a channel for `duration`, by running it in
+    /// listen only mode. If this device is the only other node on the bus,
+    /// frames sent by the node under test are not acknowledged, so it sees ACK
+    /// errors and eventually goes error passive.
+    ///
+    /// Blocks for `duration`, then starts the channel again in its configured
+    /// mode. Frames waiting to be sent on the channel are discarded.
+    ///
+    /// Returns `Error::NotRunning` if the interface is not running, and
+    /// `Error::InvalidChannel` if the channel does not exist or is disabled.
+    pub fn suppress_ack(&mut self, channel: usize, duration: Duration) -> Result<(), Error> {
+        if channel > self.channel_count || !self.channels[channel].enabled {
+            return Err(Error::InvalidChannel);
+        }
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        let flags = self.mode_flags(&self.channels[channel]);
+        self.restart_channel(channel, flags | GSUSB_FEATURE_LISTEN_ONLY)?;
+        thread::sleep(duration);
+        self.restart_channel(channel, flags)
+    }
+
+    // put a running channel into reset and start it again with `flags`
+    fn restart_channel(&mut self, channel: usize, flags: u32) -> Result<(), Error> {
+        // the tracker is taken before the device, like when sending
+        let mut tx = self.tx.lock().unwrap();
+        let mut dev = self.dev.lock().unwrap();
+        let reset = Mode {
+            mode: CanMode::Reset as u32,
+            flags: 0,
+        };
+        dev.set_mode(channel as u16, reset)?;
+        tx.discard(channel as u8);
+        let start = Mode {
+            mode: CanMode::Start as u32,
+            flags,
+        };
+        Ok(dev.set_mode(channel as u16, start)?)
+    }
+
+    /// Send `f` back to back for `duration` to overload the bus, ignoring rate
+    /// limits. With ID 0 the frames win arbitration against every other frame,
+    /// so other nodes can't send until the flood ends. Returns the number of
+    /// frames sent.
+    ///
+    /// Blocks for `duration`. Frames are only handed to the device while it has
+    /// a free transmit slot, so none are dropped by the device.
+    pub fn flood(&mut self, f: Frame, duration: Duration) -> Result<u64, Error> {
+        if f.channel as usize > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        let tx = self.transmitter();
+        let end = Instant::now() + duration;
+        let mut sent = 0;
+        while Instant::now() < end {
+            if self.tx.lock().unwrap().pending(f.channel) >= TX_SLOTS {
+                thread::sleep(FLOOD_POLL_INTERVAL);
+                continue;
+            }
+            tx.send_priority(&f)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::{self, Mock};
+    use crate::device::UsbBreq;
+
+    #[test]
+    fn test_injection() {
+        let mock = Mock::default();
+        let mut i = mock::interface(mock.clone());
+        let ms = Duration::from_millis;
+        assert!(matches!(i.suppress_ack(0, ms(1)), Err(Error::NotRunning)));
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        i.start(move |f| send.send(f).unwrap()).unwrap();
+        // a classic frame with DLC 12 is only sent unchecked
+        let f = Frame {
+            can_dlc: 12,
+            ..Frame::new(0x123, &[0; 8]).unwrap()
+        };
+        assert!(matches!(i.send(f.clone()), Err(Error::InvalidFrame)));
+        i.send_unchecked(f).unwrap();
+        assert_eq!(recv.recv_timeout(ms(1000)).unwrap().can_dlc, 12);
+
+        // the channel runs in listen only mode, then in its own mode again
+        i.suppress_ack(0, ms(1)).unwrap();
+        let modes: Vec<u32> = mock
+            .written
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(req, channel, _)| *req == UsbBreq::Mode && *channel == 0)
+            .map(|(_, _, data)| u32::from_le_bytes([data[4], data[5], data[6], data[7]]))
+            .collect();
+        let n = modes.len();
+        assert!(modes[n - 3] & GSUSB_FEATURE_LISTEN_ONLY != 0);
+        assert!(modes[n - 1] & GSUSB_FEATURE_LISTEN_ONLY == 0);
+
+        let sent = i.flood(Frame::new(0, &[0; 8]).unwrap(), ms(20)).unwrap();
+        assert!(sent > 0);
+        assert_eq!(recv.recv_timeout(ms(1000)).unwrap().raw_id(), 0);
+        i.stop().unwrap();
+    }
+}
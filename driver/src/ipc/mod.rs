@@ -0,0 +1,262 @@
+//! Sharing a single device between processes.
+//!
+//! An `IpcServer` owns an `Interface` and forwards frames to and from any number
+//! of `IpcClient`s over a local socket: a Unix domain socket on Linux and macOS,
+//! or a named pipe on Windows. Every message on the socket is a frame, prefixed
+//! with its length as a little-endian `u16`.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+
+use crate::tx::Transmitter;
+use crate::{Direction, Error, Frame, Id, Interface};
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::{IpcClient, IpcServer};
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use windows::{IpcClient, IpcServer};
+
+const FLAG_EXT: u8 = 1 << 0;
+const FLAG_RTR: u8 = 1 << 1;
+const FLAG_FD: u8 = 1 << 2;
+const FLAG_ECHO: u8 = 1 << 3;
+const FLAG_TIMESTAMP: u8 = 1 << 4;
+const FLAG_BRS: u8 = 1 << 5;
+const FLAG_ESI: u8 = 1 << 6;
+
+// channel, flags, id, dlc, echo id, timestamp, data length
+const HEADER_LEN: usize = 1 + 1 + 4 + 1 + 4 + 8 + 1;
+
+fn encode_frame(f: &Frame) -> Vec<u8> {
+    let mut flags = 0;
+    if f.is_extended() {
+        flags |= FLAG_EXT;
+    }
+    if f.rtr {
+        flags |= FLAG_RTR;
+    }
+    if f.fd {
+        flags |= FLAG_FD;
+    }
+    if f.brs {
+        flags |= FLAG_BRS;
+    }
+    if f.esi {
+        flags |= FLAG_ESI;
+    }
+    let echo_id = match f.direction {
+        Direction::Rx => 0,
+        Direction::TxEcho(id) => {
+            flags |= FLAG_ECHO;
+            id
+        }
+    };
+    if f.timestamp.is_some() {
+        flags |= FLAG_TIMESTAMP;
+    }
+    let timestamp = f.timestamp.map(|t| t.as_micros() as u64).unwrap_or(0);
+    let data = f.payload();
+
+    let len = HEADER_LEN + data.len();
+    let mut buf = Vec::with_capacity(2 + len);
+    buf.extend_from_slice(&(len as u16).to_le_bytes());
+    buf.push(f.channel);
+    buf.push(flags);
+    buf.extend_from_slice(&f.raw_id().to_le_bytes());
+    buf.push(f.can_dlc);
+    buf.extend_from_slice(&echo_id.to_le_bytes());
+    buf.extend_from_slice(&timestamp.to_le_bytes());
+    buf.push(data.len() as u8);
+    buf.extend_from_slice(data);
+    buf
+}
+
+fn read_frame(r: &mut impl Read) -> io::Result<Frame> {
+    let mut len = [0u8; 2];
+    r.read_exact(&mut len)?;
+    let mut buf = vec![0u8; u16::from_le_bytes(len) as usize];
+    r.read_exact(&mut buf)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid frame");
+    if buf.len() < HEADER_LEN {
+        return Err(invalid());
+    }
+    let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    let flags = buf[1];
+    let mut timestamp = [0u8; 8];
+    timestamp.copy_from_slice(&buf[11..19]);
+    let data_len = buf[19] as usize;
+    let mut data = [0u8; 64];
+    if data_len > data.len() || buf.len() != HEADER_LEN + data_len {
+        return Err(invalid());
+    }
+    data[..data_len].copy_from_slice(&buf[HEADER_LEN..]);
+
+    let echo = flags & FLAG_ECHO > 0;
+    Ok(Frame {
+        channel: buf[0],
+        id: Id::new(u32_at(2), flags & FLAG_EXT > 0).ok_or_else(invalid)?,
+        can_dlc: buf[6],
+        data,
+        rtr: flags & FLAG_RTR > 0,
+        fd: flags & FLAG_FD > 0,
+        brs: flags & FLAG_BRS > 0,
+        esi: flags & FLAG_ESI > 0,
+        direction: if echo {
+            Direction::TxEcho(u32_at(7))
+        } else {
+            Direction::Rx
+        },
+        timestamp: if flags & FLAG_TIMESTAMP > 0 {
+            Some(Duration::from_micros(u64::from_le_bytes(timestamp)))
+        } else {
+            None
+        },
+        // raw flags are not carried by the protocol
+        raw_flags: None,
+    })
+}
+
+/// Connected clients, shared with the thread accepting new connections.
+#[derive(Clone)]
+struct Clients {
+    writers: Arc<Mutex<Vec<Box<dyn Write + Send>>>>,
+    transmitter: Transmitter,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Clients {
+    // add a client connection, reading frames to transmit from `reader`
+    fn add(&self, mut reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) {
+        self.writers.lock().unwrap().push(Box::new(writer));
+        let transmitter = self.transmitter.clone();
+        let shutdown = Arc::clone(&self.shutdown);
+        thread::spawn(move || {
+            while let Ok(f) = read_frame(&mut reader) {
+                if shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                // there is no way to report errors to the client, the frame is
+                // dropped as it would be on a bus-off channel
+                transmitter.send(&f).ok();
+            }
+        });
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/// Server state shared by the implementations for each platform.
+struct ServerCore {
+    i: Interface,
+    clients: Clients,
+}
+
+impl ServerCore {
+    // start the interface, forwarding all frames to the connected clients
+    fn start(mut i: Interface) -> Result<ServerCore, Error> {
+        let clients = Clients {
+            writers: Arc::new(Mutex::new(vec![])),
+            transmitter: i.transmitter(),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        };
+        let writers = Arc::clone(&clients.writers);
+        i.start(move |f: Frame| {
+            let buf = encode_frame(&f);
+            // clients which have gone away are dropped
+            writers
+                .lock()
+                .unwrap()
+                .retain_mut(|w| w.write_all(&buf).is_ok());
+        })?;
+        Ok(ServerCore { i, clients })
+    }
+
+    fn stop(mut self) -> Result<Interface, Error> {
+        self.clients.shutdown.store(true, Ordering::SeqCst);
+        self.clients.writers.lock().unwrap().clear();
+        self.i.stop()?;
+        Ok(self.i)
+    }
+}
+
+/// Client side of a connection to an `IpcServer`, independent of the transport.
+struct ClientCore {
+    writer: Box<dyn Write + Send>,
+    rx: Receiver<Frame>,
+}
+
+impl ClientCore {
+    fn new(
+        mut reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> ClientCore {
+        let (send, rx) = unbounded();
+        thread::spawn(move || {
+            while let Ok(f) = read_frame(&mut reader) {
+                if send.send(f).is_err() {
+                    break;
+                }
+            }
+        });
+        ClientCore {
+            writer: Box::new(writer),
+            rx,
+        }
+    }
+
+    fn send(&mut self, f: &Frame) -> Result<(), Error> {
+        self.writer.write_all(&encode_frame(f))?;
+        Ok(())
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Frame, Error> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(f) => Ok(f),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::NotRunning),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipc_codec() {
+        let mut f = Frame {
+            channel: 1,
+            id: Id::new(0x1234_5678, true).unwrap(),
+            can_dlc: 3,
+            direction: Direction::TxEcho(42),
+            timestamp: Some(Duration::from_micros(1500)),
+            ..Default::default()
+        };
+        f.data[..3].copy_from_slice(&[1, 2, 3]);
+        let buf = encode_frame(&f);
+        assert_eq!(buf.len(), 2 + HEADER_LEN + 3);
+
+        let g = read_frame(&mut &buf[..]).unwrap();
+        assert_eq!(g.channel, 1);
+        assert_eq!(g.raw_id(), 0x1234_5678);
+        assert_eq!(g.data, f.data);
+        assert!(g.is_extended() && !g.rtr);
+        assert_eq!(g.direction, Direction::TxEcho(42));
+        assert_eq!(g.timestamp, f.timestamp);
+
+        // truncated message
+        assert!(read_frame(&mut &buf[..buf.len() - 1]).is_err());
+    }
+}
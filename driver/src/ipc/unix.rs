@@ -0,0 +1,86 @@
+//! Unix domain socket transport.
+
+use std::fs;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use super::{ClientCore, ServerCore};
+use crate::{Error, Frame, Interface};
+
+/// Shares an `Interface` with other processes through a Unix domain socket.
+pub struct IpcServer {
+    core: ServerCore,
+    path: PathBuf,
+}
+
+impl IpcServer {
+    /// Start the interface and accept clients on a socket at `path`. An existing
+    /// socket file at `path` is replaced.
+    ///
+    /// Frames received by the interface are sent to all clients, and frames sent
+    /// by any client are transmitted by the interface.
+    pub fn bind(i: Interface, path: impl AsRef<Path>) -> Result<IpcServer, Error> {
+        let path = path.as_ref().to_path_buf();
+        // a socket left behind by a previous server would make bind fail
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        let core = ServerCore::start(i)?;
+
+        let clients = core.clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if clients.is_shutdown() {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                if let Ok(writer) = stream.try_clone() {
+                    clients.add(stream, writer);
+                }
+            }
+        });
+
+        Ok(IpcServer { core, path })
+    }
+
+    /// Stop serving clients, remove the socket, and stop the interface.
+    pub fn stop(self) -> Result<Interface, Error> {
+        let i = self.core.stop()?;
+        // wake up the accepting thread so it sees the shutdown
+        UnixStream::connect(&self.path).ok();
+        fs::remove_file(&self.path).ok();
+        Ok(i)
+    }
+}
+
+/// Connection to an `IpcServer`.
+pub struct IpcClient {
+    core: ClientCore,
+}
+
+impl IpcClient {
+    /// Connect to a server listening on `path`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<IpcClient, Error> {
+        let stream = UnixStream::connect(path)?;
+        let writer = stream.try_clone()?;
+        Ok(IpcClient {
+            core: ClientCore::new(stream, writer),
+        })
+    }
+
+    /// Send a frame through the server's interface.
+    pub fn send(&mut self, f: &Frame) -> Result<(), Error> {
+        self.core.send(f)
+    }
+
+    /// Wait up to `timeout` for the next frame received by the server's interface.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Frame, Error> {
+        self.core.recv_timeout(timeout)
+    }
+}
@@ -0,0 +1,235 @@
+//! Windows named pipe transport.
+//!
+//! Pipes are opened for overlapped I/O so that a client's frames can be read and
+//! written at the same time from different threads.
+
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use winapi::shared::minwindef::{BOOL, DWORD, TRUE};
+use winapi::shared::winerror::{ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED};
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::fileapi::{CreateFileW, ReadFile, WriteFile, OPEN_EXISTING};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::ioapiset::GetOverlappedResult;
+use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+use winapi::um::synchapi::CreateEventW;
+use winapi::um::winbase::{
+    FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+    PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use winapi::um::winnt::{GENERIC_READ, GENERIC_WRITE, HANDLE};
+
+use super::{ClientCore, ServerCore};
+use crate::{Error, Frame, Interface};
+
+const PIPE_BUF_SIZE: DWORD = 4096;
+
+struct Handle(HANDLE);
+
+// the handle is only used for overlapped I/O, which may be issued from any thread
+unsafe impl Send for Handle {}
+unsafe impl Sync for Handle {}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// One end of a named pipe. Clones refer to the same pipe.
+#[derive(Clone)]
+struct Pipe(Arc<Handle>);
+
+impl Pipe {
+    // run an overlapped operation and wait for it to complete
+    fn overlapped(&self, op: impl FnOnce(HANDLE, *mut OVERLAPPED) -> BOOL) -> io::Result<usize> {
+        unsafe {
+            let event = CreateEventW(ptr::null_mut(), TRUE, 0, ptr::null());
+            if event.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let event = Handle(event);
+            let mut ov: OVERLAPPED = std::mem::zeroed();
+            ov.hEvent = event.0;
+
+            if op(self.0 .0, &mut ov) == 0 {
+                let err = GetLastError();
+                if err != ERROR_IO_PENDING {
+                    return Err(io::Error::from_raw_os_error(err as i32));
+                }
+            }
+            let mut n: DWORD = 0;
+            if GetOverlappedResult(self.0 .0, &mut ov, &mut n, TRUE) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.overlapped(|h, ov| unsafe {
+            ReadFile(
+                h,
+                buf.as_mut_ptr() as *mut _,
+                buf.len() as DWORD,
+                ptr::null_mut(),
+                ov,
+            )
+        });
+        match result {
+            // the other end closed the pipe
+            Err(e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+            r => r,
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.overlapped(|h, ov| unsafe {
+            WriteFile(
+                h,
+                buf.as_ptr() as *const _,
+                buf.len() as DWORD,
+                ptr::null_mut(),
+                ov,
+            )
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn wide(name: &str) -> Vec<u16> {
+    OsStr::new(name).encode_wide().chain(Some(0)).collect()
+}
+
+// create a new instance of the pipe for the next client
+fn create(name: &[u16]) -> io::Result<Pipe> {
+    let h = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUF_SIZE,
+            PIPE_BUF_SIZE,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if h == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Pipe(Arc::new(Handle(h))))
+}
+
+// wait for a client to connect to a pipe instance
+fn wait_for_client(pipe: &Pipe) -> io::Result<()> {
+    match pipe.overlapped(|h, ov| unsafe { ConnectNamedPipe(h, ov) }) {
+        Ok(_) => Ok(()),
+        // the client connected before we started waiting
+        Err(e) if e.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn connect(name: &str) -> io::Result<Pipe> {
+    let name = wide(name);
+    let h = unsafe {
+        CreateFileW(
+            name.as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            0,
+            ptr::null_mut(),
+            OPEN_EXISTING,
+            FILE_FLAG_OVERLAPPED,
+            ptr::null_mut(),
+        )
+    };
+    if h == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Pipe(Arc::new(Handle(h))))
+}
+
+/// Shares an `Interface` with other processes through a named pipe.
+pub struct IpcServer {
+    core: ServerCore,
+    name: String,
+}
+
+impl IpcServer {
+    /// Start the interface and accept clients on the named pipe `name`, for
+    /// example `\\.\pipe\cantact`.
+    ///
+    /// Frames received by the interface are sent to all clients, and frames sent
+    /// by any client are transmitted by the interface.
+    pub fn bind(i: Interface, name: &str) -> Result<IpcServer, Error> {
+        let wide_name = wide(name);
+        // create the first instance now, so that errors are reported to the caller
+        let mut pipe = create(&wide_name)?;
+        let core = ServerCore::start(i)?;
+
+        let clients = core.clients.clone();
+        thread::spawn(move || loop {
+            if wait_for_client(&pipe).is_err() || clients.is_shutdown() {
+                break;
+            }
+            clients.add(pipe.clone(), pipe);
+            pipe = match create(&wide_name) {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+        });
+
+        Ok(IpcServer {
+            core,
+            name: name.to_string(),
+        })
+    }
+
+    /// Stop serving clients and stop the interface.
+    pub fn stop(self) -> Result<Interface, Error> {
+        let i = self.core.stop()?;
+        // wake up the accepting thread so it sees the shutdown
+        connect(&self.name).ok();
+        Ok(i)
+    }
+}
+
+/// Connection to an `IpcServer`.
+pub struct IpcClient {
+    core: ClientCore,
+}
+
+impl IpcClient {
+    /// Connect to a server listening on the named pipe `name`.
+    pub fn connect(name: &str) -> Result<IpcClient, Error> {
+        let pipe = connect(name)?;
+        Ok(IpcClient {
+            core: ClientCore::new(pipe.clone(), pipe),
+        })
+    }
+
+    /// Send a frame through the server's interface.
+    pub fn send(&mut self, f: &Frame) -> Result<(), Error> {
+        self.core.send(f)
+    }
+
+    /// Wait up to `timeout` for the next frame received by the server's interface.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Frame, Error> {
+        self.core.recv_timeout(timeout)
+    }
+}
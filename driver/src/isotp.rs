@@ -0,0 +1,432 @@
+//! ISO-TP (ISO 15765-2) transport protocol for classic CAN.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crate::dispatch::Dispatcher;
+use crate::tx::Transmitter;
+use crate::{Direction, Error, Frame, Id};
+
+// largest payload which can be sent without escape sequences
+const MAX_PAYLOAD: usize = 4095;
+
+const PCI_SINGLE: u8 = 0x0;
+const PCI_FIRST: u8 = 0x1;
+const PCI_CONSECUTIVE: u8 = 0x2;
+const PCI_FLOW_CONTROL: u8 = 0x3;
+
+const FC_CONTINUE: u8 = 0x0;
+const FC_WAIT: u8 = 0x1;
+const FC_OVERFLOW: u8 = 0x2;
+
+// wait frames accepted in a row before giving up (N_WFTmax)
+const DEFAULT_MAX_WAIT: u32 = 10;
+
+/// Errors in an ISO-TP exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoTpError {
+    /// The payload is empty or longer than 4095 bytes.
+    InvalidLength,
+    /// The receiver reported that the payload does not fit its buffer.
+    Overflow,
+    /// A consecutive frame was received out of order.
+    WrongSequence,
+    /// A frame which is not valid ISO-TP was received.
+    InvalidFrame,
+    /// The receiver sent more wait flow control frames in a row than allowed.
+    WaitLimit,
+}
+
+/// An ISO-TP connection between a pair of CAN IDs, returned by `Interface::isotp`.
+///
+/// Segmentation, reassembly, and flow control are handled by the driver. Frames
+/// received on the connection's rx ID are still passed to the rx callback.
+pub struct IsoTpSocket {
+    transmitter: Transmitter,
+    dispatcher: Arc<Dispatcher>,
+    token: u64,
+    rx: Receiver<Frame>,
+    channel: u8,
+    tx_id: Id,
+    block_size: u8,
+    st_min: u8,
+    padding: Option<u8>,
+    timeout: Duration,
+    max_wait: u32,
+}
+
+impl IsoTpSocket {
+    pub(crate) fn new(
+        transmitter: Transmitter,
+        dispatcher: Arc<Dispatcher>,
+        channel: u8,
+        tx_id: Id,
+        rx_id: Id,
+    ) -> IsoTpSocket {
+        let (token, rx) = dispatcher.subscribe(move |f: &Frame| {
+            f.channel == channel && f.id == rx_id && f.direction == Direction::Rx && !f.rtr
+        });
+        IsoTpSocket {
+            transmitter,
+            dispatcher,
+            token,
+            rx,
+            channel,
+            tx_id,
+            block_size: 0,
+            st_min: 0,
+            padding: Some(0xCC),
+            timeout: Duration::from_secs(1),
+            max_wait: DEFAULT_MAX_WAIT,
+        }
+    }
+
+    /// Set the block size and separation time (STmin, encoded as in ISO 15765-2)
+    /// requested from the sender when receiving. Defaults to 0 for both, meaning
+    /// the sender may send all frames without waiting.
+    pub fn set_flow_control(&mut self, block_size: u8, st_min: u8) {
+        self.block_size = block_size;
+        self.st_min = st_min;
+    }
+
+    /// Set the byte used to pad frames to 8 bytes, or `None` to send frames with
+    /// the shortest DLC. Defaults to 0xCC.
+    pub fn set_padding(&mut self, padding: Option<u8>) {
+        self.padding = padding;
+    }
+
+    /// Set how long to wait for flow control and consecutive frames from the
+    /// other node (N_Bs and N_Cr). Defaults to one second.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set how many wait flow control frames in a row are accepted from the
+    /// receiver before sending fails with `IsoTpError::WaitLimit` (N_WFTmax).
+    /// Defaults to 10.
+    pub fn set_max_wait(&mut self, max_wait: u32) {
+        self.max_wait = max_wait;
+    }
+
+    /// Send a payload of up to 4095 bytes.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() || data.len() > MAX_PAYLOAD {
+            return Err(Error::IsoTp(IsoTpError::InvalidLength));
+        }
+        if data.len() <= 7 {
+            return self.send_frame(&single_frame(data));
+        }
+
+        self.send_frame(&first_frame(data))?;
+        let mut sn = 1u8;
+        let mut remaining = &data[6..];
+        while !remaining.is_empty() {
+            let (block_size, st_min) = self.wait_flow_control()?;
+            let mut sent = 0usize;
+            while !remaining.is_empty() && (block_size == 0 || sent < block_size as usize) {
+                let n = remaining.len().min(7);
+                self.send_frame(&consecutive_frame(sn, &remaining[..n]))?;
+                remaining = &remaining[n..];
+                sn = (sn + 1) & 0x0F;
+                sent += 1;
+                if !remaining.is_empty() {
+                    thread::sleep(st_min);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for the next payload from the other node.
+    pub fn recv(&mut self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let f = self.recv_frame(deadline)?;
+            match f.data[0] >> 4 {
+                PCI_SINGLE => {
+                    let len = (f.data[0] & 0x0F) as usize;
+                    if len == 0 || len + 1 > f.can_dlc as usize {
+                        return Err(Error::IsoTp(IsoTpError::InvalidFrame));
+                    }
+                    return Ok(f.data[1..1 + len].to_vec());
+                }
+                PCI_FIRST => return self.recv_segmented(&f),
+                // stray consecutive or flow control frames are ignored
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a payload and wait up to `timeout` for the response.
+    pub fn request(&mut self, data: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+        // drop anything received before the request was sent
+        while self.rx.try_recv().is_ok() {}
+        self.send(data)?;
+        self.recv(timeout)
+    }
+
+    fn recv_segmented(&mut self, first: &Frame) -> Result<Vec<u8>, Error> {
+        let len = ((first.data[0] as usize & 0x0F) << 8) | first.data[1] as usize;
+        if len <= 7 || first.can_dlc != 8 {
+            return Err(Error::IsoTp(IsoTpError::InvalidFrame));
+        }
+        let mut data = first.data[2..8].to_vec();
+        let mut sn = 1u8;
+        while data.len() < len {
+            self.send_frame(&flow_control(FC_CONTINUE, self.block_size, self.st_min))?;
+            let mut received = 0usize;
+            while data.len() < len && (self.block_size == 0 || received < self.block_size as usize)
+            {
+                let f = self.recv_frame(Instant::now() + self.timeout)?;
+                if f.data[0] >> 4 != PCI_CONSECUTIVE {
+                    return Err(Error::IsoTp(IsoTpError::InvalidFrame));
+                }
+                if f.data[0] & 0x0F != sn {
+                    return Err(Error::IsoTp(IsoTpError::WrongSequence));
+                }
+                let n = (len - data.len()).min(7).min(f.can_dlc as usize - 1);
+                data.extend_from_slice(&f.data[1..1 + n]);
+                sn = (sn + 1) & 0x0F;
+                received += 1;
+            }
+        }
+        Ok(data)
+    }
+
+    // returns the block size and separation time requested by the receiver
+    fn wait_flow_control(&mut self) -> Result<(u8, Duration), Error> {
+        let mut waits = 0;
+        loop {
+            let f = self.recv_frame(Instant::now() + self.timeout)?;
+            if f.data[0] >> 4 != PCI_FLOW_CONTROL {
+                continue;
+            }
+            match f.data[0] & 0x0F {
+                FC_CONTINUE => return Ok((f.data[1], separation_time(f.data[2]))),
+                FC_WAIT if waits < self.max_wait => waits += 1,
+                FC_WAIT => return Err(Error::IsoTp(IsoTpError::WaitLimit)),
+                FC_OVERFLOW => return Err(Error::IsoTp(IsoTpError::Overflow)),
+                _ => return Err(Error::IsoTp(IsoTpError::InvalidFrame)),
+            }
+        }
+    }
+
+    fn recv_frame(&self, deadline: Instant) -> Result<Frame, Error> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.rx.recv_timeout(timeout) {
+            Ok(f) if f.can_dlc == 0 => Err(Error::IsoTp(IsoTpError::InvalidFrame)),
+            Ok(f) => Ok(f),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::NotRunning),
+        }
+    }
+
+    fn send_frame(&self, payload: &[u8]) -> Result<(), Error> {
+        let mut data = [0u8; 64];
+        data[..8].fill(self.padding.unwrap_or(0));
+        data[..payload.len()].copy_from_slice(payload);
+        let f = Frame {
+            id: self.tx_id,
+            can_dlc: if self.padding.is_some() {
+                8
+            } else {
+                payload.len() as u8
+            },
+            channel: self.channel,
+            data,
+            ..Default::default()
+        };
+        self.transmitter.send(&f)?;
+        Ok(())
+    }
+}
+
+impl Drop for IsoTpSocket {
+    fn drop(&mut self) {
+        self.dispatcher.cancel(self.token);
+    }
+}
+
+fn single_frame(data: &[u8]) -> Vec<u8> {
+    let mut v = vec![(PCI_SINGLE << 4) | data.len() as u8];
+    v.extend_from_slice(data);
+    v
+}
+
+fn first_frame(data: &[u8]) -> Vec<u8> {
+    let len = data.len();
+    let mut v = vec![(PCI_FIRST << 4) | (len >> 8) as u8, (len & 0xFF) as u8];
+    v.extend_from_slice(&data[..6]);
+    v
+}
+
+fn consecutive_frame(sn: u8, data: &[u8]) -> Vec<u8> {
+    let mut v = vec![(PCI_CONSECUTIVE << 4) | sn];
+    v.extend_from_slice(data);
+    v
+}
+
+fn flow_control(status: u8, block_size: u8, st_min: u8) -> Vec<u8> {
+    vec![(PCI_FLOW_CONTROL << 4) | status, block_size, st_min]
+}
+
+// decode an STmin value, reserved values are treated as the maximum of 127ms
+fn separation_time(st_min: u8) -> Duration {
+    match st_min {
+        0x00..=0x7F => Duration::from_millis(st_min as u64),
+        0xF1..=0xF9 => Duration::from_micros((st_min - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::device::mock::{self, Mock};
+    use crate::Interface;
+
+    // a node on 0x7E8 which receives the payloads sent on 0x7E0, asking for
+    // blocks of `block_size` frames
+    fn receiving_node(i: &mut Interface, block_size: u8) -> Arc<Mutex<Vec<Vec<u8>>>> {
+        let payloads = Arc::new(Mutex::new(vec![]));
+        let received = Arc::clone(&payloads);
+        let receive = mock::receiver(i);
+        let fc = Frame::new(0x7E8, &flow_control(FC_CONTINUE, block_size, 0)).unwrap();
+        let (mut len, mut frames, mut payload) = (0, 0, vec![]);
+        i.add_tx_hook(move |f: &mut Frame| {
+            if f.raw_id() != 0x7E0 {
+                return true;
+            }
+            match f.data[0] >> 4 {
+                PCI_FIRST => {
+                    len = ((f.data[0] as usize & 0x0F) << 8) | f.data[1] as usize;
+                    payload = f.data[2..8].to_vec();
+                    frames = 0;
+                    receive(&fc);
+                }
+                PCI_CONSECUTIVE => {
+                    let n = (len - payload.len()).min(7);
+                    payload.extend_from_slice(&f.data[1..1 + n]);
+                    frames += 1;
+                    if payload.len() == len {
+                        received.lock().unwrap().push(std::mem::take(&mut payload));
+                    } else if frames == block_size as usize {
+                        frames = 0;
+                        receive(&fc);
+                    }
+                }
+                _ => {}
+            }
+            true
+        });
+        payloads
+    }
+
+    // a node on 0x7E8 which sends `payload` in the blocks asked for by the
+    // flow control frames sent on 0x7E0, once its first frame is received
+    fn sending_node(i: &mut Interface, payload: Vec<u8>) {
+        let receive = mock::receiver(i);
+        let (mut pos, mut sn) = (6, 1);
+        i.add_tx_hook(move |f: &mut Frame| {
+            if f.raw_id() != 0x7E0 || f.data[0] != (PCI_FLOW_CONTROL << 4) | FC_CONTINUE {
+                return true;
+            }
+            let mut frames = 0;
+            while pos < payload.len() && (f.data[1] == 0 || frames < f.data[1] as usize) {
+                let n = (payload.len() - pos).min(7);
+                let cf = consecutive_frame(sn, &payload[pos..pos + n]);
+                receive(&Frame::new(0x7E8, &cf).unwrap());
+                pos += n;
+                sn = (sn + 1) & 0x0F;
+                frames += 1;
+            }
+            true
+        });
+    }
+
+    #[test]
+    fn test_socket() {
+        let mut i = mock::interface(Mock::default());
+        let ms = Duration::from_millis;
+        assert!(matches!(
+            i.isotp(0, 0x7E0, 0x2000_0000),
+            Err(Error::InvalidFrame)
+        ));
+        let mut s = i.isotp(0, 0x7E0, 0x7E8).unwrap();
+        i.start(|_| {}).unwrap();
+
+        // an extended frame with the same number is another ID
+        mock::receive(&i, &Frame::new_ext(0x7E8, &[0x01, 0xAA]).unwrap());
+        mock::receive(&i, &Frame::new(0x7E8, &[0x01, 0xBB]).unwrap());
+        assert_eq!(s.recv(ms(1000)).unwrap(), [0xBB]);
+
+        // wait frames are accepted up to the limit
+        s.set_max_wait(2);
+        let payload = [0u8; 10];
+        for fc in [[0x31, 0, 0], [0x31, 0, 0], [0x30, 0, 0]] {
+            mock::receive(&i, &Frame::new(0x7E8, &fc).unwrap());
+        }
+        s.send(&payload).unwrap();
+        for _ in 0..3 {
+            mock::receive(&i, &Frame::new(0x7E8, &[0x31, 0, 0]).unwrap());
+        }
+        assert!(matches!(
+            s.send(&payload),
+            Err(Error::IsoTp(IsoTpError::WaitLimit))
+        ));
+        i.stop().unwrap();
+    }
+
+    #[test]
+    fn test_send_segmented() {
+        let payload: Vec<u8> = (0..MAX_PAYLOAD).map(|n| n as u8).collect();
+        // in one block, and in blocks of 8 frames
+        for block_size in [0, 8] {
+            let mut i = mock::interface(Mock::default());
+            let payloads = receiving_node(&mut i, block_size);
+            let mut s = i.isotp(0, 0x7E0, 0x7E8).unwrap();
+            i.start(|_| {}).unwrap();
+            s.send(&payload).unwrap();
+            s.send(&payload[..8]).unwrap();
+            assert_eq!(*payloads.lock().unwrap(), [&payload[..], &payload[..8]]);
+            i.stop().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recv_segmented() {
+        let payload: Vec<u8> = (0..MAX_PAYLOAD).map(|n| (n * 7) as u8).collect();
+        for block_size in [0, 8] {
+            let mut i = mock::interface(Mock::default());
+            sending_node(&mut i, payload.clone());
+            let mut s = i.isotp(0, 0x7E0, 0x7E8).unwrap();
+            s.set_flow_control(block_size, 0);
+            i.start(|_| {}).unwrap();
+            mock::receive(&i, &Frame::new(0x7E8, &first_frame(&payload)).unwrap());
+            assert_eq!(s.recv(Duration::from_secs(1)).unwrap(), payload);
+            i.stop().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_isotp_frames() {
+        assert_eq!(single_frame(&[0x10, 0x03]), vec![0x02, 0x10, 0x03]);
+
+        let data: Vec<u8> = (0..20).collect();
+        assert_eq!(first_frame(&data), vec![0x10, 20, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(consecutive_frame(0xF, &data[6..13])[0], 0x2F);
+        assert_eq!(flow_control(FC_CONTINUE, 8, 0x14), vec![0x30, 8, 0x14]);
+
+        let long: Vec<u8> = vec![0; 0x123];
+        assert_eq!(first_frame(&long)[..2], [0x11, 0x23]);
+
+        assert_eq!(separation_time(20), Duration::from_millis(20));
+        assert_eq!(separation_time(0xF3), Duration::from_micros(300));
+        assert_eq!(separation_time(0x80), Duration::from_millis(127));
+    }
+}
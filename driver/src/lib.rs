@@ -7,31 +7,149 @@
 
 #![warn(missing_docs)]
 
+use std::any::Any;
 use std::fmt;
-use std::sync::{Arc, RwLock};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time;
 
-use crossbeam_channel::RecvError;
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+use libusb1_sys::constants::{
+    LIBUSB_ERROR_ACCESS, LIBUSB_ERROR_NO_DEVICE, LIBUSB_ERROR_PIPE, LIBUSB_ERROR_TIMEOUT,
+    LIBUSB_TRANSFER_NO_DEVICE, LIBUSB_TRANSFER_STALL, LIBUSB_TRANSFER_TIMED_OUT,
+};
 
 use serde::{Deserialize, Serialize};
 
+/// Re-exported for the channels returned by `Interface::start_channel`.
+pub use crossbeam_channel;
+
 mod device;
 use device::gsusb::*;
 use device::*;
+use dispatch::Dispatcher;
+use event::Events;
+use filter::Filters;
+use gateway::Gateway;
+use hook::Pipeline;
+use live::LiveMonitor;
+use periodic::Scheduler;
+use ratelimit::RateLimiter;
+use reconnect::{ChannelTiming, ConnectionCallback, Restore};
+use subscribe::Subscriptions;
+use timestamp::HwClock;
+use trigger::Capture;
+use tx::{Transmitter, TxTracker};
+use watch::Watches;
+
+mod bitrate;
+mod builder;
+mod bus;
+mod bus_error;
+mod capabilities;
+mod config;
+mod database;
+mod diagnose;
+mod dispatch;
+#[cfg(feature = "embedded")]
+mod embedded;
+mod event;
+mod filter;
+mod filter_expr;
+mod gateway;
+mod handle;
+mod hook;
+mod hotplug;
+mod inject;
+mod isotp;
+mod live;
+mod periodic;
+mod queue;
+mod ratelimit;
+mod reconnect;
+mod sequence;
+mod session;
+mod sniffer;
+mod stats;
+mod subscribe;
+mod timestamp;
+mod transaction;
+mod trigger;
+mod tx;
+mod uds;
+mod watch;
+pub use bitrate::Bitrate;
+pub use builder::{ChannelConfig, InterfaceBuilder};
+pub use bus::Bus;
+pub use bus_error::{BusError, BusErrorKind, BusState, ErrorCounters};
+pub use cantact_core::{
+    dlc_to_len, len_to_dlc, BitTiming, BitTimingLimits, Direction, ExtendedId, Frame, FrameBuilder,
+    FrameError, Id, StandardId, MAX_EXTENDED_ID, MAX_STANDARD_ID,
+};
+pub use capabilities::Capabilities;
+pub use config::InterfaceConfig;
+pub use database::{ByteOrder, Database, DatabaseError, Message, Signal};
+pub use diagnose::{DiagnosticReport, Finding};
+pub use event::Event;
+pub use filter::{Filter, FilterHandle};
+pub use filter_expr::FilterExpr;
+pub use gateway::{IdMap, Mangle, Route, RouteCounters, RouteHandle};
+pub use handle::ChannelHandle;
+pub use hook::{Hook, HookHandle};
+pub use hotplug::{watch_devices, DeviceInfo, DeviceWatcher, HotplugEvent};
+pub use isotp::{IsoTpError, IsoTpSocket};
+pub use live::{ChannelLiveStats, LiveStats, Talker};
+pub use periodic::{PeriodicStats, TaskHandle};
+pub use queue::OverflowPolicy;
+pub use ratelimit::RateLimit;
+pub use reconnect::ConnectionEvent;
+pub use sequence::{Sequence, SequenceHandle};
+pub use session::{Manifest, Marker, Metadata, Session, Sink, MANIFEST};
+pub use sniffer::{Sniffer, SnifferEntry};
+pub use stats::{ChannelCounters, ChannelStats, IdStats, Stats, UsbStats};
+pub use subscribe::SubscriptionHandle;
+pub use timestamp::TimestampMode;
+pub use transaction::{Response, Transaction};
+pub use trigger::{Trigger, TriggerCondition};
+pub use tx::{TxConfirmation, TxEvent, TxResult};
+pub use uds::{Dtc, DtcRecord, DtcSeverity, DtcStatus, UdsClient, UdsError, ALL_DTCS};
+pub use watch::{WatchEvent, WatchHandle};
 
 pub mod c;
+pub mod capture;
+#[cfg(feature = "testing")]
+pub mod fuzzing;
+pub mod ipc;
 /// Implementation of Python bindings
 #[cfg(feature = "python")]
 pub mod python;
+pub mod slcan;
+
+// how often the rx thread wakes up to check timeouts when no frames are received
+const RX_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
 
 /// Errors generated by this library
 #[derive(Debug)]
 pub enum Error {
     /// Errors from device interaction.
     DeviceError(device::Error),
-    /// The device could not be found, or the user does not have permissions to access it.
+    /// The device could not be found.
     DeviceNotFound,
+    /// The user does not have permission to access the device.
+    PermissionDenied,
+    /// The device was unplugged.
+    Disconnected,
+    /// A USB endpoint of the device stalled.
+    UsbStall,
+    /// The device rejected a USB request.
+    Pipe,
+    /// The device failed a control request, usually because it does not support
+    /// the request. Contains the name of the request.
+    ControlTransferFailed {
+        /// Name of the request.
+        request: &'static str,
+    },
     /// Timeout while communicating with the device.
     Timeout,
     /// Attempted to perform an action on a device that is running when this is not allowed.
@@ -40,111 +158,118 @@ pub enum Error {
     NotRunning,
     /// Requested channel index does not exist on device.
     InvalidChannel,
+    /// The interval of a periodic transmission must be greater than zero.
+    InvalidInterval,
     /// The requested bitrate cannot be set within an acceptable tolerance
     InvalidBitrate(u32),
+    /// A sample point must be a fraction of the bit time between 0 and 1.
+    InvalidSamplePoint,
+    /// The synchronization jump width is zero, longer than phase segment 2, or
+    /// longer than the channel allows.
+    InvalidSjw,
+    /// A rate limit is not a positive number of frames per second, or a bus
+    /// load limit is not between 0 and 1.
+    InvalidRateLimit,
+    /// A filter expression could not be parsed. Contains the reason.
+    InvalidFilter(String),
+    /// The device does not support the requested feature.
+    Unsupported,
+    /// An ISO-TP exchange failed.
+    IsoTp(IsoTpError),
+    /// A UDS request failed.
+    Uds(UdsError),
+    /// A message or signal was not found in the database, or a database could
+    /// not be loaded.
+    Database(DatabaseError),
+    /// Error from a socket or file, such as the IPC server's socket.
+    Io(std::io::Error),
+    /// A user callback panicked on the receive thread. Contains the panic message.
+    CallbackPanicked(String),
+    /// A frame has an ID, DLC, flags, or data length which CAN does not allow, see
+    /// `Frame::validate`.
+    InvalidFrame,
+    /// A frame sent with `Interface::send_blocking` was not transmitted.
+    TxFailed(TxResult),
+    /// A frame could not be sent without waiting, see `Interface::try_send`.
+    WouldBlock,
+    /// A tx hook dropped the frame, see `Interface::add_tx_hook`.
+    FrameDropped,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DeviceError(e) => write!(f, "device error: {}", e),
+            Error::DeviceNotFound => write!(f, "no CANtact device found"),
+            Error::PermissionDenied => write!(f, "permission denied to access the device"),
+            Error::Disconnected => write!(f, "the device was disconnected"),
+            Error::UsbStall => write!(f, "a USB endpoint of the device stalled"),
+            Error::Pipe => write!(f, "the device rejected a USB request"),
+            Error::ControlTransferFailed { request } => {
+                write!(f, "the device failed the {} request", request)
+            }
+            Error::Timeout => write!(f, "timed out"),
+            Error::Running => write!(f, "not allowed while the interface is running"),
+            Error::NotRunning => write!(f, "the interface is not running"),
+            Error::InvalidChannel => write!(f, "the channel does not exist"),
+            Error::InvalidInterval => write!(f, "the interval must be greater than zero"),
+            Error::InvalidBitrate(bitrate) => write!(f, "bitrate {} can't be set", bitrate),
+            Error::InvalidSamplePoint => write!(f, "the sample point must be between 0 and 1"),
+            Error::InvalidRateLimit => write!(f, "the rate limit is out of range"),
+            Error::InvalidSjw => write!(f, "the synchronization jump width is out of range"),
+            Error::InvalidFilter(msg) => write!(f, "invalid filter expression: {}", msg),
+            Error::Unsupported => write!(f, "not supported by the device"),
+            Error::IsoTp(e) => write!(f, "ISO-TP error: {:?}", e),
+            Error::Uds(e) => write!(f, "UDS error: {:?}", e),
+            Error::Database(e) => write!(f, "database error: {:?}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::CallbackPanicked(msg) => write!(f, "callback panicked: {}", msg),
+            Error::InvalidFrame => write!(f, "invalid frame"),
+            Error::TxFailed(result) => write!(f, "frame not transmitted: {:?}", result),
+            Error::WouldBlock => write!(f, "the device has no room for the frame"),
+            Error::FrameDropped => write!(f, "the frame was dropped by a hook"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+impl From<DatabaseError> for Error {
+    fn from(e: DatabaseError) -> Error {
+        Error::Database(e)
+    }
 }
 impl From<device::Error> for Error {
     fn from(e: device::Error) -> Error {
-        // TODO
-        // this could do a much better job of converting
-        Error::DeviceError(e)
+        match e {
+            device::Error::DeviceNotFound => Error::DeviceNotFound,
+            device::Error::Libusb(_, LIBUSB_ERROR_ACCESS) => Error::PermissionDenied,
+            device::Error::Libusb(_, LIBUSB_ERROR_NO_DEVICE)
+            | device::Error::ControlTransfer(_, LIBUSB_TRANSFER_NO_DEVICE)
+            | device::Error::Transfer(_, LIBUSB_TRANSFER_NO_DEVICE) => Error::Disconnected,
+            device::Error::Libusb(_, LIBUSB_ERROR_PIPE) => Error::Pipe,
+            device::Error::Libusb(_, LIBUSB_ERROR_TIMEOUT)
+            | device::Error::ControlTransfer(_, LIBUSB_TRANSFER_TIMED_OUT)
+            | device::Error::Transfer(_, LIBUSB_TRANSFER_TIMED_OUT) => Error::Timeout,
+            device::Error::Transfer(_, LIBUSB_TRANSFER_STALL) => Error::UsbStall,
+            device::Error::ControlTransfer(request, _) => Error::ControlTransferFailed { request },
+            e => Error::DeviceError(e),
+        }
     }
 }
 
-/// Controller Area Network Frame
-#[derive(Debug, Clone)]
-pub struct Frame {
-    /// CAN frame arbitration ID.
-    pub can_id: u32,
-
-    /// CAN frame Data Length Code (DLC).
-    pub can_dlc: u8,
-
-    /// Device channel used to send or receive the frame.
-    pub channel: u8,
-
-    /// Frame data contents.
-    pub data: [u8; 8],
-
-    /// Extended (29 bit) arbitration identifier if true,
-    /// standard (11 bit) arbitration identifer if false.
-    pub ext: bool,
-
-    /// CAN Flexible Data (CAN-FD) frame flag.
-    pub fd: bool,
-
-    /// Loopback flag. When true, frame was sent by this device/channel.
-    /// False for received frames.
-    pub loopback: bool,
-
-    /// Remote Transmission Request (RTR) flag.
-    pub rtr: bool,
-
-    /// Timestamp when frame was received
-    pub timestamp: Option<time::Duration>,
-}
-impl Frame {
-    // convert to a frame format expected by the device
-    fn to_host_frame(&self) -> HostFrame {
-        // if frame is extended, set the extended bit in host frame CAN ID
-        let mut can_id = if self.ext {
-            self.can_id | GSUSB_EXT_FLAG
-        } else {
-            self.can_id
-        };
-        // if frame is RTR, set the RTR bit in host frame CAN ID
-        can_id = if self.rtr {
-            can_id | GSUSB_RTR_FLAG
-        } else {
-            can_id
-        };
-        HostFrame {
-            echo_id: 1,
-            flags: 0,
-            reserved: 0,
-            can_id,
-            can_dlc: self.can_dlc,
-            channel: self.channel,
-            data: self.data,
-        }
-    }
-    /// Returns a default CAN frame with all values set to zero/false.
-    pub fn default() -> Frame {
-        Frame {
-            can_id: 0,
-            can_dlc: 0,
-            data: [0u8; 8],
-            channel: 0,
-            ext: false,
-            fd: false,
-            loopback: false,
-            rtr: false,
-            timestamp: None,
-        }
-    }
-    fn from_host_frame(hf: HostFrame) -> Frame {
-        // check the extended bit of host frame
-        // if set, frame is extended
-        let ext = (hf.can_id & GSUSB_EXT_FLAG) > 0;
-        // check the RTR bit of host frame
-        // if set, frame is RTR
-        let rtr = (hf.can_id & GSUSB_RTR_FLAG) > 0;
-        // remove flags from CAN ID
-        let can_id = hf.can_id & 0x3FFF_FFFF;
-        // loopback frame if echo_id is not -1
-        let loopback = hf.echo_id != GSUSB_RX_ECHO_ID;
-
-        Frame {
-            can_id,
-            can_dlc: hf.can_dlc,
-            data: hf.data,
-            channel: hf.channel,
-            ext,
-            loopback,
-            rtr,
-            fd: false, // TODO
-            timestamp: None,
-        }
+impl From<FrameError> for Error {
+    fn from(_: FrameError) -> Error {
+        Error::InvalidFrame
     }
 }
 
@@ -159,27 +284,96 @@ pub struct Channel {
     pub loopback: bool,
     /// When true, device will not transmit on the bus.
     pub monitor: bool,
+    /// When true, frames are not retransmitted if arbitration is lost or no
+    /// acknowledgement is received.
+    #[serde(default)]
+    pub one_shot: bool,
+    /// When true, the channel is started in CAN FD mode.
+    #[serde(default)]
+    pub fd: bool,
+    /// Bitrate of the CAN FD data phase in bits/second, or 0 if not set.
+    #[serde(default)]
+    pub data_bitrate: u32,
+    /// When true, the controller samples each bit three times and takes the
+    /// majority value.
+    #[serde(default)]
+    pub triple_sample: bool,
 }
 
 /// Interface for interacting with CANtact devices
 pub struct Interface {
-    dev: Device,
+    dev: Arc<Mutex<Device>>,
     running: Arc<RwLock<bool>>,
+    rx_thread: Option<thread::JoinHandle<()>>,
+    // frames for `Interface::recv`, when started with `Interface::start_queued`
+    rx_queue: Option<Receiver<Frame>>,
+    rx_capacity: Option<usize>,
+    rx_policy: OverflowPolicy,
+    // events for `Interface::start_events`, handed to the rx thread on start
+    events: Option<crossbeam_channel::Sender<Event>>,
 
     can_clock: u32,
+    // feature flags of each channel
+    features: Vec<u32>,
+    // bit timings accepted by each channel
+    limits: Vec<BitTimingLimits>,
+    // synchronization jump width used when solving the bitrate of each channel
+    sjw: Vec<u32>,
+    // USB IDs the device was looked for with, besides the CANtact ones
+    usb_ids: Vec<(u16, u16)>,
     // zero indexed (0 = 1 channel, 1 = 2 channels, etc...)
     channel_count: usize,
     sw_version: u32,
     hw_version: u32,
 
     channels: Vec<Channel>,
+    // bit timings written to each channel, shared with the rx thread so they
+    // can be restored after reconnecting
+    timings: Arc<Mutex<Vec<ChannelTiming>>>,
+
+    tx: Arc<Mutex<TxTracker>>,
+    // rate limits of frames sent by all senders
+    limiter: Arc<Mutex<RateLimiter>>,
+    tx_callback: TxCallback,
+    error_callback: ErrorCallback,
+    counters: Arc<Mutex<Vec<ChannelCounters>>>,
+    // error counters reported by the last error frame of each channel
+    error_counters: Arc<Mutex<Vec<ErrorCounters>>>,
+    // frames lost on the device or in the queues of start_channel, frames
+    // dropped by the device's queue are counted by the device. The channels
+    // are filled in by `Interface::stats`.
+    stats: Arc<Mutex<Stats>>,
+    live: Arc<Mutex<LiveMonitor>>,
+    dispatcher: Arc<Dispatcher>,
+    watches: Arc<Mutex<Watches>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    filters: Arc<Mutex<Filters>>,
+    gateway: Arc<Mutex<Gateway>>,
+    capture: Arc<Mutex<Capture>>,
+    rx_hooks: Arc<Mutex<Pipeline>>,
+    tx_hooks: Arc<Mutex<Pipeline>>,
+    rx_panic: Arc<Mutex<Option<String>>>,
+    restart_on_panic: Arc<RwLock<bool>>,
+    auto_reconnect: Arc<RwLock<bool>>,
+    connection_callback: Arc<ConnectionCallback>,
+    scheduler: Scheduler,
+    timestamp_mode: TimestampMode,
+    // when the interface was last started, on the host's monotonic clock and
+    // as time since the Unix epoch
+    clock_start: Option<(time::Instant, time::Duration)>,
+    #[cfg(feature = "embedded")]
+    hal_rx: Option<crossbeam_channel::Receiver<Frame>>,
 }
 
+type TxCallback = Arc<Mutex<Option<Box<dyn FnMut(TxEvent) + Send>>>>;
+type ErrorCallback = Arc<Mutex<Option<Box<dyn FnMut(BusError) + Send>>>>;
+
 impl fmt::Debug for Interface {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Interface")
             .field("running", &(*self.running.read().unwrap()))
             .field("can_clock", &self.can_clock)
+            .field("features", &self.features)
             .field("channel_count", &self.channel_count)
             .field("sw_version", &self.sw_version)
             .field("hw_version", &self.hw_version)
@@ -188,19 +382,86 @@ impl fmt::Debug for Interface {
     }
 }
 
+/// Iterator over received frames, returned by `Interface::frames`.
+pub struct Frames<'a> {
+    i: &'a Interface,
+}
+
+impl Iterator for Frames<'_> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        self.i.recv().ok()
+    }
+}
+
+impl Drop for Interface {
+    /// Stops the interface if it is running, taking its channels off the bus.
+    fn drop(&mut self) {
+        if *self.running.read().unwrap() {
+            self.stop().ok();
+        }
+    }
+}
+
 impl Interface {
-    /// Creates a new interface. This always selects the first device found by
-    /// libusb. If no device is found, Error::DeviceNotFound is returned.
+    /// Creates a new interface for the first device found by libusb which is not
+    /// already in use, so calling this repeatedly opens every connected device
+    /// in turn. If no device is found, Error::DeviceNotFound is returned.
+    ///
+    /// Any number of interfaces can be open at once, each with its own USB
+    /// context, receive thread and queues.
     pub fn new() -> Result<Interface, Error> {
-        let mut dev = match Device::new(UsbContext::new()) {
-            Ok(d) => d,
-            Err(_) => return Err(Error::DeviceNotFound),
-        };
+        Interface::open(Selector::Available, &[])
+    }
 
-        let dev_config = dev.get_device_config()?;
-        let bt_consts = dev.get_bit_timing_consts()?;
+    /// Creates a new interface for the first available device with the CANtact
+    /// USB vendor and product ID, or one of the `(vendor, product)` pairs in
+    /// `ids`. This finds rebranded gs_usb compatible adapters, such as
+    /// candleLight clones or CANables with custom IDs.
+    pub fn open_usb_ids(ids: &[(u16, u16)]) -> Result<Interface, Error> {
+        Interface::open(Selector::Available, ids)
+    }
+
+    /// Creates a new interface for the `index`th device found by libusb, counting
+    /// from zero. The order is stable as long as devices are not plugged in or
+    /// removed. If there is no such device, Error::DeviceNotFound is returned.
+    pub fn open_index(index: usize) -> Result<Interface, Error> {
+        Interface::open(Selector::Index(index), &[])
+    }
+
+    /// Creates a new interface for the device with the USB serial number
+    /// `serial`. Devices which are already in use are skipped. If no device has
+    /// the serial number, Error::DeviceNotFound is returned.
+    pub fn open_serial(serial: &str) -> Result<Interface, Error> {
+        Interface::open(Selector::Serial(serial), &[])
+    }
+
+    /// Creates a new interface for the device with the user ID `id`, see
+    /// `Interface::set_user_id`. Devices which are already in use are skipped.
+    /// If no device has the user ID, Error::DeviceNotFound is returned.
+    pub fn open_user_id(id: u32) -> Result<Interface, Error> {
+        Interface::open(Selector::UserId(id), &[])
+    }
+
+    fn open(sel: Selector, usb_ids: &[(u16, u16)]) -> Result<Interface, Error> {
+        let dev = Device::new(UsbContext::new()?, sel, usb_ids)?;
+        Interface::from_device(dev, usb_ids)
+    }
 
+    // query the configuration of an opened device
+    fn from_device(mut dev: Device, usb_ids: &[(u16, u16)]) -> Result<Interface, Error> {
+        let dev_config = dev.get_device_config()?;
         let channel_count = dev_config.icount as usize;
+        let bt_consts = dev.get_bit_timing_consts(0)?;
+        // each channel advertises its own features and bit timing limits
+        let mut features = vec![bt_consts.feature];
+        let mut limits = vec![bt_consts.limits()];
+        for ch in 1..(channel_count + 1) {
+            let consts = dev.get_bit_timing_consts(ch as u16)?;
+            features.push(consts.feature);
+            limits.push(consts.limits());
+        }
 
         let mut channels = Vec::new();
         // note: channel_count is zero indexed
@@ -210,19 +471,102 @@ impl Interface {
                 enabled: true,
                 loopback: false,
                 monitor: false,
+                one_shot: false,
+                fd: false,
+                data_bitrate: 0,
+                triple_sample: false,
             });
         }
 
+        let dev = Arc::new(Mutex::new(dev));
+        let running = Arc::new(RwLock::from(false));
+        let tx = Arc::new(Mutex::new(TxTracker::new()));
+        let counters = Arc::new(Mutex::new(vec![
+            ChannelCounters::default();
+            channel_count + 1
+        ]));
+        let limiter = Arc::new(Mutex::new(RateLimiter::new(channel_count + 1)));
+        let tx_hooks = Arc::new(Mutex::new(Pipeline::new()));
+
+        let scheduler = {
+            let transmitter = Transmitter {
+                dev: Arc::clone(&dev),
+                running: Arc::clone(&running),
+                tx: Arc::clone(&tx),
+                counters: Arc::clone(&counters),
+                limiter: Arc::clone(&limiter),
+                hooks: Arc::clone(&tx_hooks),
+            };
+            Scheduler::new(move |f: &Frame| {
+                // frames are dropped while the interface is stopped, and a
+                // failed transmission is retried in the next period. They are
+                // not held back by rate limits.
+                transmitter.send_priority(f).ok();
+            })
+        };
+
+        // timestamps come from the device when it supports them
+        let hw_timestamp = features
+            .iter()
+            .all(|f| Capabilities::from_features(*f).hw_timestamp);
+        let timestamp_mode = if hw_timestamp {
+            TimestampMode::Hardware
+        } else {
+            TimestampMode::Monotonic
+        };
+
         let i = Interface {
             dev,
-            running: Arc::new(RwLock::from(false)),
+            running,
+            rx_thread: None,
+            rx_queue: None,
+            rx_capacity: None,
+            rx_policy: OverflowPolicy::Block,
+            events: None,
 
             channel_count,
             can_clock: bt_consts.fclk_can,
+            features,
+            limits,
+            sjw: vec![1; channel_count + 1],
+            usb_ids: usb_ids.to_vec(),
             sw_version: dev_config.sw_version,
             hw_version: dev_config.hw_version,
 
             channels,
+            timings: Arc::new(Mutex::new(vec![
+                ChannelTiming::default();
+                channel_count + 1
+            ])),
+
+            tx,
+            limiter,
+            tx_callback: Arc::new(Mutex::new(None)),
+            error_callback: Arc::new(Mutex::new(None)),
+            counters,
+            error_counters: Arc::new(Mutex::new(vec![
+                ErrorCounters::default();
+                channel_count + 1
+            ])),
+            stats: Arc::new(Mutex::new(Stats::default())),
+            live: Arc::new(Mutex::new(LiveMonitor::new(channel_count + 1))),
+            dispatcher: Arc::new(Dispatcher::new()),
+            watches: Arc::new(Mutex::new(Watches::new())),
+            subscriptions: Arc::new(Mutex::new(Subscriptions::new())),
+            filters: Arc::new(Mutex::new(Filters::new())),
+            gateway: Arc::new(Mutex::new(Gateway::new())),
+            capture: Arc::new(Mutex::new(Capture::new())),
+            rx_hooks: Arc::new(Mutex::new(Pipeline::new())),
+            tx_hooks,
+            rx_panic: Arc::new(Mutex::new(None)),
+            restart_on_panic: Arc::new(RwLock::from(false)),
+            auto_reconnect: Arc::new(RwLock::from(false)),
+            connection_callback: Arc::new(Mutex::new(None)),
+            scheduler,
+            timestamp_mode,
+            clock_start: None,
+            #[cfg(feature = "embedded")]
+            hal_rx: None,
         };
 
         Ok(i)
@@ -232,91 +576,615 @@ impl Interface {
     ///
     /// After starting the device, `Interface.send` can be used to send frames.
     /// For every received frame, the `rx_callback` closure will be called.
+    /// Error frames are passed to the callback set with `Interface::on_error`
+    /// instead.
+    ///
+    /// Frames are timestamped with the clock set by `Interface::set_timestamp_mode`.
+    ///
+    /// If `rx_callback` panics, the panic is caught and reported by
+    /// `Interface::check_rx`. Delivery of frames to the callback then stops, unless
+    /// enabled with `Interface::set_restart_on_panic`. The device keeps running
+    /// either way, and can be stopped with `Interface::stop`.
     pub fn start(
         &mut self,
         mut rx_callback: impl FnMut(Frame) + Sync + Send + 'static,
     ) -> Result<(), Error> {
-        // tell the device to go on bus
-        for (i, ch) in self.channels.iter().enumerate() {
-            let mut flags = 0;
-            if ch.monitor {
-                flags |= GSUSB_FEATURE_LISTEN_ONLY;
-            }
-            if ch.loopback {
-                flags |= GSUSB_FEATURE_LOOP_BACK;
-            }
+        self.rx_queue = None;
+        let timestamp_mode = self.timestamp_mode;
 
-            let mode = Mode {
-                mode: CanMode::Start as u32,
-                flags,
-            };
-            if ch.enabled {
-                self.dev.set_mode(i as u16, mode).unwrap();
-            }
+        // tell the device to go on bus
+        let mode_flags = self
+            .channels
+            .iter()
+            .map(|ch| ch.enabled.then(|| self.mode_flags(ch)))
+            .collect();
+        let restore = Restore {
+            serial: self.dev.lock().unwrap().serial().map(String::from),
+            timings: Arc::clone(&self.timings),
+            flags: mode_flags,
+            usb_ids: self.usb_ids.clone(),
+            rx_queue: (self.rx_capacity, self.rx_policy),
+            filters: Arc::clone(&self.filters),
+            hw_filter: (0..self.channels())
+                .map(|ch| self.features(ch).hw_filter)
+                .collect(),
+        };
+        let started = restore.start(&mut self.dev.lock().unwrap());
+        if let Err(e) = started {
+            // put the channels which were started back into reset
+            self.stop().ok();
+            return Err(e);
         }
 
         {
             *self.running.write().unwrap() = true;
         }
+        let start_time = time::Instant::now();
+        let wall_start = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.clock_start = Some((start_time, wall_start));
+        let one_shot: Vec<bool> = self.channels.iter().map(|ch| ch.one_shot).collect();
+        self.tx.lock().unwrap().reset(one_shot.clone());
 
         // rx callback thread
-        let can_rx = self.dev.can_rx_recv.clone();
+        let mut can_rx = self.dev.lock().unwrap().can_rx_recv.clone();
+        let dev = Arc::clone(&self.dev);
         let running = Arc::clone(&self.running);
-        let start_time = time::Instant::now();
-        thread::spawn(move || {
+        let tx = Arc::clone(&self.tx);
+        let tx_callback = Arc::clone(&self.tx_callback);
+        let error_callback = Arc::clone(&self.error_callback);
+        let counters = Arc::clone(&self.counters);
+        let error_counters = Arc::clone(&self.error_counters);
+        let stats = Arc::clone(&self.stats);
+        let live = Arc::clone(&self.live);
+        let dispatcher = Arc::clone(&self.dispatcher);
+        let watches = Arc::clone(&self.watches);
+        let subscriptions = Arc::clone(&self.subscriptions);
+        let filters = Arc::clone(&self.filters);
+        let gateway = Arc::clone(&self.gateway);
+        let capture = Arc::clone(&self.capture);
+        let rx_hooks = Arc::clone(&self.rx_hooks);
+        let rx_panic = Arc::clone(&self.rx_panic);
+        let restart_on_panic = Arc::clone(&self.restart_on_panic);
+        let auto_reconnect = Arc::clone(&self.auto_reconnect);
+        let connection_callback = Arc::clone(&self.connection_callback);
+        *rx_panic.lock().unwrap() = None;
+        watches.lock().unwrap().restart(start_time);
+        let mut hw_clock = HwClock::new();
+        let mut events = self
+            .events
+            .take()
+            .map(|send| Events::new(send, self.channel_count + 1));
+        self.rx_thread = Some(thread::spawn(move || {
+            // cleared when the rx callback panics and delivery is not restarted
+            let mut deliver = true;
             while *running.read().unwrap() {
-                match can_rx.recv() {
+                // frames to pass to the application, with their overflow flags
+                let mut ready = vec![];
+                match can_rx.recv_timeout(RX_POLL_INTERVAL) {
                     Ok(hf) => {
-                        let mut f = Frame::from_host_frame(hf);
-                        f.timestamp = Some(time::Instant::now().duration_since(start_time));
-                        rx_callback(f)
+                        let is_error = hf.can_id & GSUSB_ERR_FLAG > 0;
+                        let is_echo = hf.echo_id != GSUSB_RX_ECHO_ID;
+                        let overflow = hf.flags & GSUSB_FLAG_OVERFLOW > 0;
+                        if overflow {
+                            stats.lock().unwrap().rx_overflows += 1;
+                        }
+                        if let Some(c) = counters.lock().unwrap().get_mut(hf.channel as usize) {
+                            if is_error {
+                                c.error_frames += 1;
+                            } else if is_echo {
+                                c.echo_frames += 1;
+                            } else {
+                                c.rx_frames += 1;
+                                c.rx_bytes +=
+                                    dlc_to_len(hf.can_dlc, hf.flags & GSUSB_FLAG_FD > 0) as u64;
+                            }
+                        }
+
+                        let now = time::Instant::now();
+                        let host_time = now.duration_since(start_time);
+                        let timestamp = match (timestamp_mode, hf.timestamp_us) {
+                            (TimestampMode::Hardware, Some(us)) => {
+                                hw_clock.timestamp(us, host_time)
+                            }
+                            (TimestampMode::WallClock, _) => wall_start + host_time,
+                            _ => host_time,
+                        };
+                        let err = if is_error {
+                            Some(BusError::from_host_frame(&hf))
+                        } else {
+                            None
+                        };
+                        let tx_events = match &err {
+                            Some(err) => {
+                                dispatcher.dispatch_error(err);
+                                tx.lock().unwrap().error(err, now, timestamp)
+                            }
+                            None if is_echo => {
+                                let ev = tx.lock().unwrap().echo(&hf, now, timestamp);
+                                ev.into_iter().collect()
+                            }
+                            None => vec![],
+                        };
+                        let mut tx_callback = tx_callback.lock().unwrap();
+                        if let Some(cb) = tx_callback.as_mut() {
+                            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                                for ev in tx_events {
+                                    cb(ev);
+                                }
+                            }));
+                            if let Err(e) = result {
+                                *rx_panic.lock().unwrap() = Some(panic_message(e));
+                                if !*restart_on_panic.read().unwrap() {
+                                    *tx_callback = None;
+                                }
+                            }
+                        }
+                        drop(tx_callback);
+
+                        if let Some(err) = err {
+                            // error frames are not CAN frames, they only go to the
+                            // error callback
+                            live.lock().unwrap().error(err.channel, now);
+                            let released = capture.lock().unwrap().error(now);
+                            ready.extend(released.into_iter().map(|f| (f, false)));
+                            if err.has(BusErrorKind::RxOverflow) {
+                                stats.lock().unwrap().rx_overflows += 1;
+                            }
+                            if let Some(c) =
+                                error_counters.lock().unwrap().get_mut(err.channel as usize)
+                            {
+                                *c = err.error_counters();
+                            }
+                            if let Some(events) = events.as_mut() {
+                                events.error(&err);
+                            }
+                            let mut error_callback = error_callback.lock().unwrap();
+                            if let Some(cb) = error_callback.as_mut() {
+                                let result = panic::catch_unwind(AssertUnwindSafe(|| cb(err)));
+                                if let Err(e) = result {
+                                    *rx_panic.lock().unwrap() = Some(panic_message(e));
+                                    if !*restart_on_panic.read().unwrap() {
+                                        *error_callback = None;
+                                    }
+                                }
+                            }
+                        } else if let Some(f) = rx_frame(hf, timestamp, &rx_hooks, &rx_panic) {
+                            // forwarded first, to keep the latency of the gateway low
+                            if !is_echo {
+                                gateway.lock().unwrap().frame(&f);
+                            }
+                            let wanted = filters.lock().unwrap().accepts(&f);
+                            let released = capture.lock().unwrap().frame(&f, wanted, now);
+                            live.lock().unwrap().frame(&f, now);
+                            dispatcher.dispatch(&f);
+                            if !is_echo {
+                                watches.lock().unwrap().frame(&f, now);
+
+                                let mut subscriptions = subscriptions.lock().unwrap();
+                                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                                    subscriptions.frame(&f)
+                                }));
+                                if let Err(e) = result {
+                                    *rx_panic.lock().unwrap() = Some(panic_message(e));
+                                }
+                            }
+                            match released {
+                                Some(frames) => {
+                                    ready.extend(frames.into_iter().map(|f| (f, false)))
+                                }
+                                None if wanted => ready.push((f, overflow)),
+                                None => {}
+                            }
+                        }
                     }
-                    Err(RecvError) => {
+                    Err(RecvTimeoutError::Timeout) => {
+                        if dev.lock().unwrap().is_disconnected() {
+                            // frames waiting to be echoed were lost with the device
+                            tx.lock().unwrap().reset(one_shot.clone());
+                            connection_event(&connection_callback, ConnectionEvent::Disconnected);
+                            if let Some(events) = events.as_mut() {
+                                events.device_gone();
+                            }
+                            // counted by the old device, which is replaced
+                            let dropped = dev.lock().unwrap().rx_drops();
+                            match restore.reconnect(&dev, &running, &auto_reconnect) {
+                                Some(rx) => {
+                                    can_rx = rx;
+                                    stats.lock().unwrap().queue_drops += dropped;
+                                    // the device clock restarted with the device
+                                    hw_clock = HwClock::new();
+                                    connection_event(
+                                        &connection_callback,
+                                        ConnectionEvent::Reconnected,
+                                    );
+                                }
+                                None => {
+                                    *running.write().unwrap() = false;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
                         // channel disconnected
                         break;
                     }
                 }
+                let released = capture.lock().unwrap().poll(time::Instant::now());
+                ready.extend(released.into_iter().map(|f| (f, false)));
+                for (f, overflow) in ready {
+                    if let Some(events) = events.as_mut() {
+                        events.frame(&f, overflow);
+                    }
+                    if deliver {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| rx_callback(f)));
+                        if let Err(e) = result {
+                            *rx_panic.lock().unwrap() = Some(panic_message(e));
+                            deliver = *restart_on_panic.read().unwrap();
+                        }
+                    }
+                }
+                watches.lock().unwrap().check_timeouts(time::Instant::now());
             }
-        });
+        }));
+
+        let started = self.dev.lock().unwrap().start_transfers();
+        if let Err(e) = started {
+            // nothing can be received, so stop the channels and the rx thread
+            self.stop().ok();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Start CAN communication on all configured channels, like `Interface::start`,
+    /// but deliver received frames to the returned channel instead of a callback.
+    /// It can be used in a `crossbeam_channel::select!` loop alongside other
+    /// channels.
+    ///
+    /// The channel is disconnected once the interface stops and all frames have
+    /// been received from it. Frames which are not received are queued up to the
+    /// capacity set with `Interface::set_rx_queue`, without limit by default.
+    pub fn start_channel(&mut self) -> Result<Receiver<Frame>, Error> {
+        let (send, recv) = queue::channel(self.rx_capacity);
+        let policy = self.rx_policy;
+        // only kept to drop the oldest frame, a waiting sender must notice when
+        // the application drops the receiver
+        let oldest = (policy == OverflowPolicy::DropOldest).then(|| recv.clone());
+        let running = Arc::clone(&self.running);
+        let stats = Arc::clone(&self.stats);
+        self.start(move |f| {
+            // the receiver may have been dropped, the frame is discarded then
+            let dropped = match policy {
+                OverflowPolicy::Block => queue::push_wait(&send, f, &running),
+                _ => queue::push(&send, oldest.as_ref(), policy, f),
+            };
+            if dropped > 0 {
+                stats.lock().unwrap().queue_drops += dropped;
+            }
+        })?;
+        Ok(recv)
+    }
+
+    /// Start CAN communication on all configured channels, like `Interface::start`,
+    /// but deliver received frames, error frames, state changes and other events
+    /// to the returned channel as an `Event`. Error frames are passed to the
+    /// callback set with `Interface::on_error` as well.
+    ///
+    /// The channel is disconnected once the interface stops and all events have
+    /// been received from it.
+    pub fn start_events(&mut self) -> Result<Receiver<Event>, Error> {
+        let (send, recv) = unbounded();
+        self.events = Some(send);
+        // frames are delivered as events by the receive thread
+        if let Err(e) = self.start(|_| {}) {
+            self.events = None;
+            return Err(e);
+        }
+        Ok(recv)
+    }
 
-        self.dev.start_transfers().unwrap();
+    /// Start CAN communication on all configured channels, like `Interface::start`,
+    /// but queue received frames inside the interface. They are read with
+    /// `Interface::recv` and `Interface::recv_timeout`.
+    pub fn start_queued(&mut self) -> Result<(), Error> {
+        self.rx_queue = Some(self.start_channel()?);
         Ok(())
     }
 
+    /// Wait for the next received frame. Only available after starting with
+    /// `Interface::start_queued`, otherwise `Error::NotRunning` is returned. Once
+    /// the interface stops, the remaining frames are returned and then
+    /// `Error::NotRunning`.
+    pub fn recv(&self) -> Result<Frame, Error> {
+        let rx = self.rx_queue.as_ref().ok_or(Error::NotRunning)?;
+        rx.recv().map_err(|_| Error::NotRunning)
+    }
+
+    /// Wait up to `timeout` for the next received frame, like `Interface::recv`.
+    /// Returns `Error::Timeout` if no frame is received in time.
+    pub fn recv_timeout(&self, timeout: time::Duration) -> Result<Frame, Error> {
+        let rx = self.rx_queue.as_ref().ok_or(Error::NotRunning)?;
+        rx.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => Error::Timeout,
+            RecvTimeoutError::Disconnected => Error::NotRunning,
+        })
+    }
+
+    /// Returns an iterator over received frames, which waits for each frame with
+    /// `Interface::recv`. Only yields frames after starting with
+    /// `Interface::start_queued`, and ends once the interface stops.
+    pub fn frames(&self) -> Frames<'_> {
+        Frames { i: self }
+    }
+
     /// Stop CAN communication on all channels.
+    ///
+    /// Waits for the receive thread to finish, so no callbacks are called once
+    /// this returns, unless it is called from a callback. Channels are reset
+    /// even if an error is returned.
     pub fn stop(&mut self) -> Result<(), Error> {
-        // TODO multi-channel
-        for (i, ch) in self.channels.iter().enumerate() {
-            let mode = Mode {
+        let mut result = Ok(());
+        {
+            let mut dev = self.dev.lock().unwrap();
+            // nothing to reset on an unplugged device
+            if !dev.is_disconnected() {
+                for (i, ch) in self.channels.iter().enumerate() {
+                    let mode = Mode {
+                        mode: CanMode::Reset as u32,
+                        flags: 0,
+                    };
+                    if ch.enabled {
+                        if let Err(e) = dev.set_mode(i as u16, mode) {
+                            result = Err(e.into());
+                        }
+                    }
+                }
+                if let Err(e) = dev.stop_transfers() {
+                    result = Err(e.into());
+                }
+            }
+        }
+        *self.running.write().unwrap() = false;
+
+        // the thread exits within one poll interval of running being cleared
+        if let Some(rx_thread) = self.rx_thread.take() {
+            if rx_thread.thread().id() != thread::current().id() {
+                rx_thread.join().ok();
+            }
+        }
+        // frames which were not echoed yet never will be
+        self.tx.lock().unwrap().reset(vec![]);
+        result
+    }
+
+    // mode flags a channel is started with
+    fn mode_flags(&self, ch: &Channel) -> u32 {
+        let mut flags = 0;
+        if ch.monitor {
+            flags |= GSUSB_FEATURE_LISTEN_ONLY;
+        }
+        if ch.loopback {
+            flags |= GSUSB_FEATURE_LOOP_BACK;
+        }
+        if ch.one_shot {
+            flags |= GSUSB_FEATURE_ONE_SHOT;
+        }
+        if ch.triple_sample {
+            flags |= GSUSB_FEATURE_TRIPLE_SAMPLE;
+        }
+        if ch.fd {
+            flags |= GSUSB_FEATURE_FD;
+        }
+        if self.timestamp_mode == TimestampMode::Hardware {
+            flags |= GSUSB_FEATURE_HW_TIMESTAMP;
+        }
+        flags
+    }
+
+    // write bit timings to a channel and record them. While running, an enabled
+    // channel is put into reset for the change and started again in the same
+    // mode, and frames waiting to be sent on it are discarded by the device.
+    fn write_timing(
+        &mut self,
+        channel: usize,
+        nominal: Option<BitTiming>,
+        data: Option<BitTiming>,
+    ) -> Result<(), Error> {
+        let live = *self.running.read().unwrap() && self.channels[channel].enabled;
+        // the tracker is taken before the device, like when sending
+        let mut tx = self.tx.lock().unwrap();
+        let mut dev = self.dev.lock().unwrap();
+        if live {
+            let reset = Mode {
                 mode: CanMode::Reset as u32,
                 flags: 0,
             };
-            if ch.enabled {
-                self.dev.set_mode(i as u16, mode).unwrap();
-            }
+            dev.set_mode(channel as u16, reset)?;
+            tx.discard(channel as u8);
         }
 
-        self.dev.stop_transfers().unwrap();
-        *self.running.write().unwrap() = false;
+        let mut written = Ok(());
+        if let Some(bt) = nominal {
+            written = dev.set_bit_timing(channel as u16, bt);
+        }
+        if let (Ok(()), Some(bt)) = (&written, data) {
+            written = dev.set_data_bit_timing(channel as u16, bt);
+        }
+
+        // started again even if writing failed, to keep the old timing
+        let restarted = if live {
+            let start = Mode {
+                mode: CanMode::Start as u32,
+                flags: self.mode_flags(&self.channels[channel]),
+            };
+            dev.set_mode(channel as u16, start)
+        } else {
+            Ok(())
+        };
+        drop(dev);
+        drop(tx);
+        written?;
+        restarted?;
+
+        let mut timings = self.timings.lock().unwrap();
+        if nominal.is_some() {
+            timings[channel].nominal = nominal;
+        }
+        if data.is_some() {
+            timings[channel].data = data;
+        }
+        if let Some(bt) = nominal {
+            self.limiter.lock().unwrap().set_bitrate(
+                channel,
+                bt.bitrate(self.can_clock),
+                time::Instant::now(),
+            );
+        }
         Ok(())
     }
 
     /// Set bitrate for specified channel to requested bitrate value in bits per second.
-    pub fn set_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<(), Error> {
+    /// Returns the bit timing which was chosen, see `Interface::bit_timing`.
+    ///
+    /// The bitrate can be changed while the interface is running, for example to
+    /// scan for the bitrate of a bus. The channel is then briefly taken off the
+    /// bus, and frames waiting to be sent on it are discarded.
+    pub fn set_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<BitTiming, Error> {
         if channel > self.channel_count {
             return Err(Error::InvalidChannel);
         }
 
-        let bt = calculate_bit_timing(self.can_clock, bitrate)?;
-        self.dev
-            .set_bit_timing(channel as u16, bt)
-            .expect("failed to set bit timing");
+        let sjw = self.sjw[channel];
+        let bt = calculate_bit_timing(self.can_clock, bitrate, sjw, &self.limits[channel])?;
+        self.write_timing(channel, Some(bt), None)?;
+        self.channels[channel].bitrate = bitrate;
+        Ok(bt)
+    }
+
+    /// Set the synchronization jump width of the specified channel, in time
+    /// quanta. It is used for bitrates set later, and replaces the jump width of
+    /// the current bit timing. Defaults to 1.
+    ///
+    /// A longer jump width tolerates more difference between the clocks of the
+    /// nodes on the bus. Returns `Error::InvalidSjw` if the jump width is zero,
+    /// longer than the channel allows, or longer than phase segment 2 of the
+    /// current bit timing.
+    pub fn set_sjw(&mut self, channel: usize, sjw: u32) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if sjw == 0 || sjw > self.limits[channel].sjw_max {
+            return Err(Error::InvalidSjw);
+        }
+        if let Some(bt) = self.bit_timing(channel) {
+            if sjw > bt.phase_seg2 {
+                return Err(Error::InvalidSjw);
+            }
+            self.write_timing(channel, Some(BitTiming { sjw, ..bt }), None)?;
+        }
+        self.sjw[channel] = sjw;
+        Ok(())
+    }
 
+    /// Returns the bit timing last written to the specified channel, or None if
+    /// its bitrate has not been set. `BitTiming::bitrate` gives the exact
+    /// bitrate with the clock returned by `Interface::can_clock`, and
+    /// `BitTiming::sample_point` the sample point.
+    pub fn bit_timing(&self, channel: usize) -> Option<BitTiming> {
+        self.timings.lock().unwrap().get(channel)?.nominal
+    }
+
+    /// Returns the bit timing last written to the data phase of the specified
+    /// channel, or None if its data bitrate has not been set.
+    pub fn data_bit_timing(&self, channel: usize) -> Option<BitTiming> {
+        self.timings.lock().unwrap().get(channel)?.data
+    }
+
+    /// Set the bitrate of the specified channel in bits per second, choosing the
+    /// bit timing with the sample point closest to `sample_point`, a fraction of
+    /// the bit time. CiA recommends a sample point of 0.875 for most bitrates.
+    ///
+    /// Returns `Error::InvalidSamplePoint` unless the sample point is between 0
+    /// and 1. Like `Interface::set_bitrate`, this can be done while running.
+    pub fn set_bitrate_with_sample_point(
+        &mut self,
+        channel: usize,
+        bitrate: u32,
+        sample_point: f32,
+    ) -> Result<BitTiming, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !(sample_point > 0.0 && sample_point < 1.0) {
+            return Err(Error::InvalidSamplePoint);
+        }
+
+        let bt = calculate_bit_timing_with_sample_point(
+            self.can_clock,
+            bitrate,
+            sample_point,
+            self.sjw[channel],
+            &self.limits[channel],
+        )?;
+        self.write_timing(channel, Some(bt), None)?;
         self.channels[channel].bitrate = bitrate;
+        Ok(bt)
+    }
+
+    /// Configure the specified channel using a bitrate preset.
+    ///
+    /// Classic CAN presets set the bitrate at the preset's sample point and disable
+    /// CAN FD mode. CAN FD presets also set the data phase bitrate and enable CAN FD
+    /// mode, and return `Error::Unsupported` if the device does not support CAN FD.
+    ///
+    /// Like `Interface::set_bitrate`, a preset can be applied while running, but
+    /// CAN FD mode can't be switched on or off while running.
+    pub fn set_bitrate_preset(&mut self, channel: usize, preset: Bitrate) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if preset.is_fd() && !self.features(channel).fd {
+            return Err(Error::Unsupported);
+        }
+        if *self.running.read().unwrap() && preset.is_fd() != self.channels[channel].fd {
+            return Err(Error::Running);
+        }
+
+        let bitrate = preset.nominal_bitrate();
+        let limits = self.limits[channel];
+        let bt = calculate_bit_timing_with_sample_point(
+            self.can_clock,
+            bitrate,
+            preset.sample_point(),
+            self.sjw[channel],
+            &limits,
+        )?;
+        let data_bt = match (preset.data_bitrate(), preset.data_sample_point()) {
+            (Some(data_bitrate), Some(sp)) => Some(calculate_bit_timing_with_sample_point(
+                self.can_clock,
+                data_bitrate,
+                sp,
+                1,
+                &limits,
+            )?),
+            _ => None,
+        };
+
+        self.write_timing(channel, Some(bt), data_bt)?;
+        self.timings.lock().unwrap()[channel].data = data_bt;
+
+        let ch = &mut self.channels[channel];
+        ch.bitrate = bitrate;
+        ch.data_bitrate = preset.data_bitrate().unwrap_or(0);
+        ch.fd = preset.is_fd();
         Ok(())
     }
 
-    /// Set a custom bit timing for the specified channel.
+    /// Set a custom bit timing for the specified channel. Like
+    /// `Interface::set_bitrate`, this can be done while running.
     pub fn set_bit_timing(
         &mut self,
         channel: usize,
@@ -325,6 +1193,9 @@ impl Interface {
         phase_seg2: u32,
         sjw: u32,
     ) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
         let bt = BitTiming {
             brp,
             prop_seg: 0,
@@ -332,12 +1203,119 @@ impl Interface {
             phase_seg2,
             sjw,
         };
-        self.dev
-            .set_bit_timing(channel as u16, bt)
-            .expect("failed to set bit timing");
+        self.write_timing(channel, Some(bt), None)
+    }
+
+    /// Set the bitrate of the data phase of FD frames for the specified channel, in
+    /// bits per second, and enable CAN FD mode on the channel. The arbitration
+    /// phase keeps the bitrate set with `Interface::set_bitrate`.
+    ///
+    /// Frames are only sent at the data bitrate if their `brs` flag is set. Returns
+    /// `Error::Unsupported` if the device does not support CAN FD. While running,
+    /// the data bitrate can only be changed on a channel already in CAN FD mode.
+    pub fn set_data_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !self.features(channel).fd {
+            return Err(Error::Unsupported);
+        }
+        if *self.running.read().unwrap() && !self.channels[channel].fd {
+            return Err(Error::Running);
+        }
+
+        let bt = calculate_bit_timing_with_sample_point(
+            self.can_clock,
+            bitrate,
+            bitrate::FD_DATA_SAMPLE_POINT,
+            1,
+            &self.limits[channel],
+        )?;
+        self.write_timing(channel, None, Some(bt))?;
+
+        let ch = &mut self.channels[channel];
+        ch.data_bitrate = bitrate;
+        ch.fd = true;
+        Ok(())
+    }
+
+    /// Set a custom bit timing for the data phase of FD frames on the specified
+    /// channel, and enable CAN FD mode on the channel. Returns
+    /// `Error::Unsupported` if the device does not support CAN FD, and
+    /// `Error::Running` while running unless the channel is in CAN FD mode.
+    pub fn set_data_bit_timing(
+        &mut self,
+        channel: usize,
+        brp: u32,
+        phase_seg1: u32,
+        phase_seg2: u32,
+        sjw: u32,
+    ) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !self.features(channel).fd {
+            return Err(Error::Unsupported);
+        }
+        if *self.running.read().unwrap() && !self.channels[channel].fd {
+            return Err(Error::Running);
+        }
+
+        if brp == 0 {
+            return Err(Error::InvalidBitrate(0));
+        }
+        let bt = BitTiming {
+            brp,
+            prop_seg: 0,
+            phase_seg1,
+            phase_seg2,
+            sjw,
+        };
+        let bitrate = bt.bitrate(self.can_clock);
+        self.write_timing(channel, None, Some(bt))?;
+
+        let ch = &mut self.channels[channel];
+        ch.data_bitrate = bitrate;
+        ch.fd = true;
+        Ok(())
+    }
+
+    /// Set the clock used for frame timestamps. Defaults to
+    /// `TimestampMode::Hardware` if the device supports hardware timestamps, and
+    /// `TimestampMode::Monotonic` otherwise.
+    ///
+    /// Returns `Error::Unsupported` for hardware timestamps on a device without
+    /// them. The mode cannot be changed while the interface is running.
+    pub fn set_timestamp_mode(&mut self, mode: TimestampMode) -> Result<(), Error> {
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+        let hw_timestamp = (0..self.channels()).all(|ch| self.features(ch).hw_timestamp);
+        if mode == TimestampMode::Hardware && !hw_timestamp {
+            return Err(Error::Unsupported);
+        }
+        self.timestamp_mode = mode;
         Ok(())
     }
 
+    /// Returns the clock used for frame timestamps.
+    pub fn timestamp_mode(&self) -> TimestampMode {
+        self.timestamp_mode
+    }
+
+    /// Returns the current time on the clock used for frame timestamps, to
+    /// record events alongside received frames. Hardware timestamps are mapped
+    /// onto the host clock, so the host clock is read for them too. Returns
+    /// `None` if the interface was never started.
+    pub fn timestamp_now(&self) -> Option<time::Duration> {
+        let (start, wall_start) = self.clock_start?;
+        let host_time = start.elapsed();
+        Some(match self.timestamp_mode {
+            TimestampMode::WallClock => wall_start + host_time,
+            _ => host_time,
+        })
+    }
+
     /// Enable or disable a channel's listen only mode. When this mode is enabled,
     /// the device will not transmit any frames, errors, or acknowledgements.
     pub fn set_monitor(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
@@ -366,12 +1344,11 @@ impl Interface {
         Ok(())
     }
 
-    /// Enable or disable a channel's loopback mode. When this mode is enabled,
-    /// frames sent by the device will be received by the device
-    /// *as if they had been sent by another node on the bus*.
-    ///
-    /// This mode is primarily intended for device testing!
-    pub fn set_loopback(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+    /// Enable or disable a channel's one-shot mode. When this mode is enabled,
+    /// the device will make a single attempt to transmit each frame. Frames which
+    /// lose arbitration or are not acknowledged are reported as failed through
+    /// `Interface::on_tx_result` instead of being retransmitted.
+    pub fn set_one_shot(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
         if channel > self.channel_count {
             return Err(Error::InvalidChannel);
         }
@@ -379,89 +1356,1665 @@ impl Interface {
             return Err(Error::Running);
         }
 
-        self.channels[channel].loopback = enabled;
+        self.channels[channel].one_shot = enabled;
         Ok(())
     }
 
-    /// Send a CAN frame using the device
-    pub fn send(&mut self, f: Frame) -> Result<(), Error> {
-        if !*self.running.read().unwrap() {
-            return Err(Error::NotRunning);
+    /// Enable or disable a channel's triple sampling mode. When this mode is
+    /// enabled, the controller samples each bit three times and takes the
+    /// majority value, which makes slow buses more robust against noise.
+    ///
+    /// Returns `Error::Unsupported` if the device does not support triple
+    /// sampling.
+    pub fn set_triple_sample(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+        if enabled && !self.features(channel).triple_sample {
+            return Err(Error::Unsupported);
         }
 
-        self.dev.send(f.to_host_frame()).unwrap();
+        self.channels[channel].triple_sample = enabled;
         Ok(())
     }
 
-    /// Returns the number of channels this Interface has
+    /// Enable or disable a channel's loopback mode. When this mode is enabled,
+    /// frames sent by the device will be received by the device
+    /// *as if they had been sent by another node on the bus*.
+    ///
+    /// This mode is primarily intended for device testing!
+    pub fn set_loopback(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+
+        self.channels[channel].loopback = enabled;
+        Ok(())
+    }
+
+    /// Send a CAN frame using the device.
+    ///
+    /// Returns the echo ID assigned to the frame. The outcome of the transmission
+    /// is reported with this ID to the callback set by `Interface::on_tx_result`.
+    /// Frames which fail `Frame::validate` are not sent.
+    pub fn send(&mut self, f: Frame) -> Result<u32, Error> {
+        self.transmitter().send(&f)
+    }
+
+    /// Send a CAN frame like `Interface::send`, but return `Error::WouldBlock`
+    /// instead of waiting if the frame can't be handed to the device right away.
+    /// That is the case while the channel has as many frames waiting to be
+    /// transmitted as the device can hold, or another thread is sending.
+    ///
+    /// Senders can back off and retry when the bus is busy, instead of queueing
+    /// up frames.
+    pub fn try_send(&mut self, f: Frame) -> Result<u32, Error> {
+        self.transmitter().try_send(&f)
+    }
+
+    /// Send a CAN frame like `Interface::send`, returning a confirmation which
+    /// can be waited on for the outcome of this particular transmission.
+    pub fn send_confirmed(&mut self, f: Frame) -> Result<TxConfirmation, Error> {
+        self.transmitter().send_confirmed(&f)
+    }
+
+    /// Send a CAN frame and wait up to `timeout` until the device echoes it back,
+    /// meaning it was transmitted on the bus and acknowledged.
+    ///
+    /// Returns `Error::TxFailed` if the device reports that the frame was not
+    /// transmitted, and `Error::Timeout` if there is no outcome in time, for
+    /// example because no other node is acknowledging frames. On timeout the
+    /// frame may still be sent later.
+    pub fn send_blocking(&mut self, f: Frame, timeout: time::Duration) -> Result<TxEvent, Error> {
+        let ev = self.send_confirmed(f)?.wait(timeout)?;
+        match ev.result {
+            TxResult::Sent => Ok(ev),
+            result => Err(Error::TxFailed(result)),
+        }
+    }
+
+    /// Limit the rate of frames sent on all channels. Frames over the limit
+    /// are held back: `Interface::send` waits until they can be sent, and
+    /// `Interface::try_send` returns `Error::WouldBlock`. `None` removes the
+    /// limit.
+    ///
+    /// Periodic frames (see `Interface::send_periodic`) and frames forwarded by
+    /// routes are never held back, so a flood of frames sent by a script can't
+    /// delay them. They still count against the limits.
+    ///
+    /// Returns `Error::InvalidRateLimit` if the rate is not positive.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) -> Result<(), Error> {
+        if limit.is_some_and(|l| !l.is_valid()) {
+            return Err(Error::InvalidRateLimit);
+        }
+        self.limiter
+            .lock()
+            .unwrap()
+            .set_global(limit, time::Instant::now());
+        Ok(())
+    }
+
+    /// Limit the rate of frames sent with ID `id` on `channel`, like
+    /// `Interface::set_rate_limit`. A standard and an extended ID with the same
+    /// number have limits of their own. `None` removes the limit.
+    pub fn set_id_rate_limit(
+        &mut self,
+        channel: usize,
+        id: Id,
+        limit: Option<RateLimit>,
+    ) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if limit.is_some_and(|l| !l.is_valid()) {
+            return Err(Error::InvalidRateLimit);
+        }
+        self.limiter
+            .lock()
+            .unwrap()
+            .set_id(channel as u8, id, limit, time::Instant::now());
+        Ok(())
+    }
+
+    /// Limit the share of the bus time used by frames sent on `channel` to
+    /// `load`, a fraction of the bitrate between 0 and 1. Frames over the limit
+    /// are held back like with `Interface::set_rate_limit`. `None` removes the
+    /// limit.
+    ///
+    /// Frames are counted by their length without bit stuffing, and bursts of
+    /// up to a tenth of a second at the limit are allowed. The limit follows
+    /// changes of the bitrate, and has no effect until a bitrate is set.
+    pub fn set_max_bus_load(&mut self, channel: usize, load: Option<f32>) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if load.is_some_and(|l| !(l > 0.0 && l <= 1.0)) {
+            return Err(Error::InvalidRateLimit);
+        }
+        self.limiter
+            .lock()
+            .unwrap()
+            .set_max_load(channel, load, time::Instant::now());
+        Ok(())
+    }
+
+    /// Send a CAN frame every `interval`, starting immediately.
+    ///
+    /// Frames are sent from a timing thread inside the driver, which keeps the
+    /// schedule without drifting. Frames are only sent while the interface is
+    /// running. The frame can be changed with `Interface::update_periodic`, and the
+    /// transmission stopped with `Interface::stop_periodic`.
+    pub fn send_periodic(
+        &mut self,
+        f: Frame,
+        interval: time::Duration,
+    ) -> Result<TaskHandle, Error> {
+        if f.channel as usize > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if interval == time::Duration::from_secs(0) {
+            return Err(Error::InvalidInterval);
+        }
+        f.validate()?;
+        Ok(self.scheduler.add(f, interval))
+    }
+
+    /// Replace the frame sent by a periodic transmission, keeping its schedule.
+    pub fn update_periodic(&mut self, handle: TaskHandle, f: Frame) -> Result<(), Error> {
+        if f.channel as usize > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        f.validate()?;
+        self.scheduler.update(handle, f);
+        Ok(())
+    }
+
+    /// Change the interval of a periodic transmission. The next frame is sent one
+    /// new interval after the previous one.
+    pub fn set_periodic_interval(
+        &mut self,
+        handle: TaskHandle,
+        interval: time::Duration,
+    ) -> Result<(), Error> {
+        if interval == time::Duration::from_secs(0) {
+            return Err(Error::InvalidInterval);
+        }
+        self.scheduler.set_interval(handle, interval);
+        Ok(())
+    }
+
+    /// Returns how many frames a periodic transmission sent, and how closely it
+    /// kept its schedule. Returns `None` if the transmission was stopped.
+    pub fn periodic_stats(&self, handle: TaskHandle) -> Option<PeriodicStats> {
+        self.scheduler.stats(handle)
+    }
+
+    /// Call `hook` with the frame of a periodic transmission before every time it is
+    /// sent, for example to update a rolling counter or checksum.
+    ///
+    /// The hook is called from the timing thread and should return quickly.
+    pub fn set_periodic_hook(
+        &mut self,
+        handle: TaskHandle,
+        hook: impl FnMut(&mut Frame) + Send + 'static,
+    ) {
+        self.scheduler.set_hook(handle, Box::new(hook));
+    }
+
+    /// Open an ISO-TP connection which sends on `tx_id` and receives on `rx_id`.
+    ///
+    /// IDs greater than 0x7FF are extended IDs, and only frames with the same
+    /// type of ID as `rx_id` are received. The interface must be running to send
+    /// and receive on the connection. Returns `Error::InvalidFrame` if an ID is
+    /// greater than 0x1FFFFFFF.
+    pub fn isotp(&self, channel: usize, tx_id: u32, rx_id: u32) -> Result<IsoTpSocket, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        let id = |raw: u32| Id::new(raw, raw > MAX_STANDARD_ID).ok_or(Error::InvalidFrame);
+        Ok(IsoTpSocket::new(
+            self.transmitter(),
+            Arc::clone(&self.dispatcher),
+            channel as u8,
+            id(tx_id)?,
+            id(rx_id)?,
+        ))
+    }
+
+    /// Stop a periodic transmission started with `Interface::send_periodic`.
+    pub fn stop_periodic(&mut self, handle: TaskHandle) {
+        self.scheduler.remove(handle);
+    }
+
+    /// Request data from another node using a remote frame.
+    ///
+    /// Sends a Remote Transmission Request (RTR) frame with the given ID and a DLC
+    /// of zero, then waits up to `timeout` for a data frame with the same ID to be
+    /// received on the channel. IDs greater than 0x7FF are sent as extended IDs.
+    /// The response is also passed to the rx callback as usual.
+    pub fn request_remote(
+        &mut self,
+        channel: usize,
+        id: u32,
+        timeout: time::Duration,
+    ) -> Result<Frame, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+
+        let id = Id::new(id, id > MAX_STANDARD_ID).ok_or(Error::InvalidFrame)?;
+        let request = Frame {
+            id,
+            channel: channel as u8,
+            rtr: true,
+            ..Default::default()
+        };
+        self.query(request, move |f: &Frame| f.id == id && !f.rtr, timeout)
+    }
+
+    /// Send a frame and wait up to `timeout` for the first response accepted by
+    /// `response_filter`.
+    ///
+    /// Only frames received from other nodes on the channel the request was sent
+    /// on are passed to the filter. The response is also passed to the rx
+    /// callback as usual. Returns `Error::Timeout` if no matching frame arrives.
+    pub fn query(
+        &mut self,
+        request: Frame,
+        response_filter: impl Fn(&Frame) -> bool + Send + 'static,
+        timeout: time::Duration,
+    ) -> Result<Frame, Error> {
+        let channel = request.channel;
+        if channel as usize > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+
+        self.begin(
+            request,
+            Response::Matches(Box::new(response_filter)),
+            timeout,
+        )?
+        .wait()
+    }
+
+    /// Send a frame and return a `Transaction` which waits up to `timeout` for the
+    /// first matching response, without blocking.
+    ///
+    /// Many transactions can be outstanding at once, for example to poll several
+    /// nodes in parallel. Only frames received from other nodes on the channel the
+    /// request was sent on are considered as responses.
+    pub fn begin(
+        &mut self,
+        request: Frame,
+        response: Response,
+        timeout: time::Duration,
+    ) -> Result<Transaction, Error> {
+        let channel = request.channel;
+        if channel as usize > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+
+        // wait before sending, so a fast response can't be missed
+        let (token, recv) = self.dispatcher.wait_for(move |f: &Frame| {
+            f.channel == channel && f.direction == Direction::Rx && response.matches(f)
+        });
+        let t = Transaction::new(Arc::clone(&self.dispatcher), token, recv, timeout);
+        self.send(request)?;
+        Ok(t)
+    }
+
+    /// Run a sequence of frames on a thread of the driver, see `Sequence`. The
+    /// sequence stops when the returned handle is dropped, when sending a frame
+    /// fails, or when an expected response does not arrive.
+    ///
+    /// Returns `Error::NotRunning` if the interface is not running.
+    pub fn run_sequence(&mut self, seq: Sequence) -> Result<SequenceHandle, Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        for f in seq.frames() {
+            if f.channel as usize > self.channel_count {
+                return Err(Error::InvalidChannel);
+            }
+            f.validate()?;
+        }
+        Ok(sequence::start(
+            seq,
+            self.transmitter(),
+            Arc::clone(&self.dispatcher),
+        ))
+    }
+
+    /// Run several transactions at once, returning the result of each in order.
+    pub fn transact_all(
+        &mut self,
+        requests: Vec<(Frame, Response, time::Duration)>,
+    ) -> Vec<Result<Frame, Error>> {
+        let pending: Vec<Result<Transaction, Error>> = requests
+            .into_iter()
+            .map(|(request, response, timeout)| self.begin(request, response, timeout))
+            .collect();
+        pending
+            .into_iter()
+            .map(|t| t.and_then(|t| t.wait()))
+            .collect()
+    }
+
+    /// Watch a cyclic message for changes, like a SocketCAN broadcast manager
+    /// RX_SETUP.
+    ///
+    /// The callback is called with `WatchEvent::Changed` when a frame with the given
+    /// ID is first received on the channel, and afterwards only when its DLC or the
+    /// data bits selected by `mask` change. If `timeout` is set and no frame is
+    /// received within it, the callback is called once with `WatchEvent::RxTimeout`.
+    /// The next frame is then reported with `WatchEvent::Restored`, and as changed.
+    ///
+    /// Callbacks are called from the receive thread, and must not add or remove
+    /// watches.
+    pub fn watch(
+        &mut self,
+        channel: usize,
+        id: u32,
+        mask: [u8; 8],
+        timeout: Option<time::Duration>,
+        callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> Result<WatchHandle, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        let handle = self.watches.lock().unwrap().add(
+            channel as u8,
+            id,
+            mask,
+            timeout,
+            true,
+            Box::new(callback),
+        );
+        Ok(handle)
+    }
+
+    /// Supervise a cyclic message, reporting when it stops and resumes.
+    ///
+    /// The callback is called with `WatchEvent::RxTimeout` when no frame with the
+    /// given ID is received on the channel within `deadline`, and with
+    /// `WatchEvent::Restored` when a frame is received again. Frame contents are
+    /// not reported. This is intended for supervising that other nodes are alive.
+    ///
+    /// The supervision is removed with `Interface::unwatch`.
+    pub fn supervise(
+        &mut self,
+        channel: usize,
+        id: u32,
+        deadline: time::Duration,
+        callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> Result<WatchHandle, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        let handle = self.watches.lock().unwrap().add(
+            channel as u8,
+            id,
+            [0u8; 8],
+            Some(deadline),
+            false,
+            Box::new(callback),
+        );
+        Ok(handle)
+    }
+
+    /// Supervise a message which is expected every `period`, reporting when it
+    /// stops, resumes, or its period drifts.
+    ///
+    /// Like `Interface::supervise` with a deadline of `period` plus `tolerance`,
+    /// and the callback is also called with `WatchEvent::PeriodDrift` when a
+    /// frame is received more than `tolerance` earlier or later than `period`
+    /// after the previous one. Times are taken on the receive thread as frames
+    /// arrive.
+    ///
+    /// Returns `Error::InvalidInterval` if `period` is zero. The supervision is
+    /// removed with `Interface::unwatch`.
+    pub fn expect_period(
+        &mut self,
+        channel: usize,
+        id: u32,
+        period: time::Duration,
+        tolerance: time::Duration,
+        callback: impl FnMut(WatchEvent) + Send + 'static,
+    ) -> Result<WatchHandle, Error> {
+        if period.is_zero() {
+            return Err(Error::InvalidInterval);
+        }
+        let handle = self.supervise(channel, id, period + tolerance, callback)?;
+        self.watches
+            .lock()
+            .unwrap()
+            .set_period(handle, period, tolerance);
+        Ok(handle)
+    }
+
+    /// Remove a watch added with `Interface::watch`.
+    pub fn unwatch(&mut self, handle: WatchHandle) {
+        self.watches.lock().unwrap().remove(handle);
+    }
+
+    /// Call `callback` for every frame received on any channel with an ID matching
+    /// `id` in the bits set in `mask`.
+    ///
+    /// Any number of subscriptions can be active at once, each with its own filter.
+    /// Only frames received from other nodes are delivered, not echoes of sent
+    /// frames or error frames. Callbacks are called from the receive thread, in
+    /// addition to the rx callback passed to `Interface::start`.
+    pub fn subscribe(
+        &mut self,
+        id: u32,
+        mask: u32,
+        callback: impl FnMut(Frame) + Send + 'static,
+    ) -> SubscriptionHandle {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .add(None, id, mask, Box::new(callback))
+    }
+
+    pub(crate) fn subscribe_channel(
+        &mut self,
+        channel: usize,
+        id: u32,
+        mask: u32,
+        callback: impl FnMut(Frame) + Send + 'static,
+    ) -> SubscriptionHandle {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .add(Some(channel as u8), id, mask, Box::new(callback))
+    }
+
+    /// Call `callback` for every frame received from other nodes on `channel`,
+    /// so applications using several channels don't have to look at
+    /// `Frame::channel` themselves. See `Interface::subscribe`.
+    pub fn on_channel(
+        &mut self,
+        channel: usize,
+        callback: impl FnMut(Frame) + Send + 'static,
+    ) -> Result<SubscriptionHandle, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(self.subscribe_channel(channel, 0, 0, callback))
+    }
+
+    /// Returns a queue receiving every frame received from other nodes on
+    /// `channel`. Frames which are not received are queued without limit. The
+    /// queue is removed when the receiver is dropped.
+    pub fn channel_receiver(&mut self, channel: usize) -> Result<Receiver<Frame>, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        let (send, recv) = unbounded();
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .add_queue(Some(channel as u8), 0, 0, send);
+        Ok(recv)
+    }
+
+    /// Add a filter for frames received on `channel`. Filtered frames are
+    /// dropped on the receive thread, and are not passed to the rx callback or
+    /// the queues of `Interface::start_queued`, `Interface::start_channel` and
+    /// `Interface::start_events`. They are still counted in the statistics, and
+    /// subscriptions and watches, which have their own ID filters, still see
+    /// them.
+    ///
+    /// A frame is delivered if it matches any of the filters of its channel, or
+    /// if the channel has no filters, unless it is rejected by an inverted
+    /// filter (`Filter::Not`). Filters can be changed while running.
+    ///
+    /// If the channel has acceptance filters in hardware (see
+    /// `Capabilities::hw_filter`) and all of its filters are `Filter::Mask`,
+    /// the filters are programmed into the device, so unwanted frames are not
+    /// even sent over USB. Otherwise frames are filtered on the host.
+    ///
+    /// ```no_run
+    /// # use cantact::{Filter, Interface};
+    /// # let mut i = Interface::new()?;
+    /// i.add_filter(0, Filter::Mask { id: 0x100, mask: 0x700 })?;
+    /// i.add_filter(0, Filter::Range { first: 0x7E0, last: 0x7EF })?;
+    /// i.add_filter(0, Filter::Expr("dlc > 2 && data[0] == 0x10".parse()?))?;
+    /// # Ok::<(), cantact::Error>(())
+    /// ```
+    pub fn add_filter(&mut self, channel: usize, filter: Filter) -> Result<FilterHandle, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        let handle = self.filters.lock().unwrap().add(channel as u8, filter);
+        if let Err(e) = self.write_hw_filters(channel) {
+            self.remove_filter(handle).ok();
+            return Err(e);
+        }
+        Ok(handle)
+    }
+
+    /// Remove a filter added with `Interface::add_filter`.
+    pub fn remove_filter(&mut self, handle: FilterHandle) -> Result<(), Error> {
+        let channel = self.filters.lock().unwrap().remove(handle);
+        match channel {
+            Some(channel) => self.write_hw_filters(channel as usize),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove all filters of `channel`, so every frame is delivered.
+    pub fn clear_filters(&mut self, channel: usize) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        self.filters.lock().unwrap().clear(channel as u8);
+        self.write_hw_filters(channel)
+    }
+
+    /// Hold back received frames until the trigger's start condition is met,
+    /// see `Trigger`. Frames received shortly before the trigger are delivered
+    /// when it starts. Applies to the rx callback and the queues of
+    /// `Interface::start_channel`, `Interface::start_queued` and
+    /// `Interface::start_events`, after filtering with `Interface::add_filter`.
+    /// Subscriptions and watches see every frame.
+    ///
+    /// Setting a trigger arms it, `None` delivers every frame again.
+    pub fn set_trigger(&mut self, trigger: Option<Trigger>) {
+        self.capture.lock().unwrap().set(trigger);
+    }
+
+    /// Meet the `TriggerCondition::External` condition of the trigger, starting
+    /// or stopping it.
+    pub fn fire_trigger(&self) {
+        self.capture.lock().unwrap().fire();
+    }
+
+    /// Returns true while the trigger has started and frames are delivered.
+    pub fn is_triggered(&self) -> bool {
+        self.capture.lock().unwrap().is_triggered()
+    }
+
+    /// Forward frames received on one channel to another channel of this
+    /// interface, see `Route`. Frames are forwarded by the receive thread as
+    /// soon as they arrive, before they are filtered and delivered, so a
+    /// CANtact Pro can bridge its two buses. Frames sent by this interface are
+    /// not forwarded, so routes in both directions don't loop.
+    ///
+    /// Returns `Error::InvalidChannel` if either channel does not exist.
+    pub fn add_route(&mut self, route: Route) -> Result<RouteHandle, Error> {
+        let target = self.transmitter();
+        self.add_route_to_transmitter(route, self.channels(), target)
+    }
+
+    /// Forward frames received on a channel of this interface to a channel of
+    /// `other`, like `Interface::add_route`. Frames are only forwarded while
+    /// both interfaces are running.
+    pub fn add_route_to(&mut self, route: Route, other: &Interface) -> Result<RouteHandle, Error> {
+        self.add_route_to_transmitter(route, other.channels(), other.transmitter())
+    }
+
+    fn add_route_to_transmitter(
+        &mut self,
+        route: Route,
+        target_channels: usize,
+        target: Transmitter,
+    ) -> Result<RouteHandle, Error> {
+        if route.from_channel() as usize >= self.channels()
+            || route.to_channel() as usize >= target_channels
+        {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(self.gateway.lock().unwrap().add(route, target))
+    }
+
+    /// Stop forwarding frames along a route.
+    pub fn remove_route(&mut self, handle: RouteHandle) {
+        self.gateway.lock().unwrap().remove(handle)
+    }
+
+    /// Remove all routes.
+    pub fn clear_routes(&mut self) {
+        self.gateway.lock().unwrap().clear()
+    }
+
+    /// Returns the number of frames forwarded and dropped by a route, or `None`
+    /// if the route was removed.
+    pub fn route_counters(&self, handle: RouteHandle) -> Option<RouteCounters> {
+        self.gateway.lock().unwrap().counters(handle)
+    }
+
+    /// Add a hook which sees every frame received from the device, including
+    /// echoes of sent frames, before anything else does. The hook can change
+    /// the frame or drop it, and a frame it drops is not forwarded, delivered,
+    /// or seen by subscriptions. Hooks run in the order they were added, on the
+    /// receive thread, so they should be quick. See `Hook`.
+    pub fn add_rx_hook<H: Hook + 'static>(&mut self, hook: H) -> HookHandle {
+        self.rx_hooks.lock().unwrap().add(Box::new(hook))
+    }
+
+    /// Add a hook which sees every frame before it is sent, including periodic,
+    /// forwarded and scripted frames. The hook can change the frame, which is
+    /// then validated, or drop it, in which case sending returns
+    /// `Error::FrameDropped`. Hooks run in the order they were added.
+    pub fn add_tx_hook<H: Hook + 'static>(&mut self, hook: H) -> HookHandle {
+        self.tx_hooks.lock().unwrap().add(Box::new(hook))
+    }
+
+    /// Add a hook to the end of the pipeline of a route, after its filter, ID
+    /// map and changes. A frame the hook drops is not forwarded. Returns `None`
+    /// if the route was removed.
+    pub fn add_route_hook<H: Hook + 'static>(
+        &mut self,
+        route: RouteHandle,
+        hook: H,
+    ) -> Option<HookHandle> {
+        self.gateway.lock().unwrap().add_hook(route, Box::new(hook))
+    }
+
+    /// Remove a hook added with `Interface::add_rx_hook`,
+    /// `Interface::add_tx_hook` or `Interface::add_route_hook`.
+    pub fn remove_hook(&mut self, handle: HookHandle) {
+        let removed = self.rx_hooks.lock().unwrap().remove(handle)
+            || self.tx_hooks.lock().unwrap().remove(handle);
+        if !removed {
+            self.gateway.lock().unwrap().remove_hook(handle);
+        }
+    }
+
+    /// Remove all rx and tx hooks. The hooks of routes are kept.
+    pub fn clear_hooks(&mut self) {
+        self.rx_hooks.lock().unwrap().clear();
+        self.tx_hooks.lock().unwrap().clear();
+    }
+
+    // program the filters of a channel into the device, if it can filter
+    fn write_hw_filters(&self, channel: usize) -> Result<(), Error> {
+        if !self.features(channel).hw_filter {
+            return Ok(());
+        }
+        let table = self.filters.lock().unwrap().hw_table(channel as u8);
+        Ok(self
+            .dev
+            .lock()
+            .unwrap()
+            .set_filters(channel as u16, &table)?)
+    }
+
+    /// Remove a subscription added with `Interface::subscribe`.
+    pub fn unsubscribe(&mut self, handle: SubscriptionHandle) {
+        self.subscriptions.lock().unwrap().remove(handle);
+    }
+
+    /// Set a callback which is called with the result of every transmitted frame.
+    ///
+    /// Frames which are echoed back by the device are reported as `TxResult::Sent`.
+    /// Frames which the device reports as dropped (transmit overflow, bus-off, or
+    /// failed attempts in one-shot mode) are reported with the corresponding error.
+    /// The callback is called from the receive thread.
+    pub fn on_tx_result(&mut self, tx_callback: impl FnMut(TxEvent) + Send + 'static) {
+        *self.tx_callback.lock().unwrap() = Some(Box::new(tx_callback));
+    }
+
+    /// Set a callback which is called with every error frame reported by the
+    /// device, such as stuff, form, or ACK errors and changes of the bus state.
+    ///
+    /// Error frames are not passed to the rx callback given to `Interface::start`.
+    /// The callback is called from the receive thread.
+    pub fn on_error(&mut self, error_callback: impl FnMut(BusError) + Send + 'static) {
+        *self.error_callback.lock().unwrap() = Some(Box::new(error_callback));
+    }
+
+    /// Returns the user ID stored in the device, see `Interface::set_user_id`.
+    ///
+    /// Returns `Error::Unsupported` if the device cannot store a user ID.
+    pub fn user_id(&self) -> Result<u32, Error> {
+        if !self.features(0).user_id {
+            return Err(Error::Unsupported);
+        }
+        Ok(self.dev.lock().unwrap().get_user_id()?)
+    }
+
+    /// Store an application defined ID in the device, for example to tell
+    /// adapters in a fleet apart. The ID survives power cycles, and a device can
+    /// be opened by its ID with `Interface::open_user_id`.
+    ///
+    /// Returns `Error::Unsupported` if the device cannot store a user ID.
+    pub fn set_user_id(&mut self, id: u32) -> Result<(), Error> {
+        if !self.features(0).user_id {
+            return Err(Error::Unsupported);
+        }
+        self.dev.lock().unwrap().set_user_id(id)?;
+        Ok(())
+    }
+
+    /// Start or stop blinking the LED of a channel, to tell which of several
+    /// devices is which. The LED keeps blinking until switched off again.
+    ///
+    /// Returns `Error::Unsupported` if the device cannot identify itself.
+    pub fn identify(&mut self, channel: usize, on: bool) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !self.features(channel).identify {
+            return Err(Error::Unsupported);
+        }
+        self.dev.lock().unwrap().set_identify(channel as u16, on)?;
+        Ok(())
+    }
+
+    /// Switch the 120 Ohm termination resistor of a channel on or off, on devices
+    /// with switchable termination. The setting takes effect immediately, also
+    /// while the interface is running.
+    ///
+    /// Returns `Error::Unsupported` if the channel has no switchable termination.
+    pub fn set_termination(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !self.features(channel).termination {
+            return Err(Error::Unsupported);
+        }
+        self.dev
+            .lock()
+            .unwrap()
+            .set_termination(channel as u16, enabled)?;
+        Ok(())
+    }
+
+    /// Returns true if the termination resistor of a channel is switched on.
+    ///
+    /// Returns `Error::Unsupported` if the channel has no switchable termination.
+    pub fn get_termination(&self, channel: usize) -> Result<bool, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !self.features(channel).termination {
+            return Err(Error::Unsupported);
+        }
+        Ok(self.dev.lock().unwrap().get_termination(channel as u16)?)
+    }
+
+    /// Returns the controller state of a channel, as reported by the device.
+    ///
+    /// Returns `Error::Unsupported` if the device can't report its state, and
+    /// `Error::NotRunning` if the channel is not on the bus.
+    pub fn bus_state(&self, channel: usize) -> Result<BusState, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !self.features(channel).get_state {
+            return Err(Error::Unsupported);
+        }
+        let state = self.dev.lock().unwrap().get_state(channel as u16)?;
+        BusState::from_device_state(state.state).ok_or(Error::NotRunning)
+    }
+
+    /// Returns the transmit and receive error counters of a channel, which can be
+    /// polled while running to spot marginal wiring or termination.
+    ///
+    /// Devices which can report their state are asked for the current counters.
+    /// For other devices, the counters reported by the last error frame received
+    /// on the channel are returned, or zeros if there was none.
+    pub fn error_counters(&self, channel: usize) -> Result<ErrorCounters, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if self.features(channel).get_state {
+            let state = self.dev.lock().unwrap().get_state(channel as u16)?;
+            return Ok(ErrorCounters {
+                tx: state.txerr,
+                rx: state.rxerr,
+            });
+        }
+        Ok(self.error_counters.lock().unwrap()[channel])
+    }
+
+    /// Returns the traffic counters for a channel.
+    pub fn counters(&self, channel: usize) -> Result<ChannelCounters, Error> {
+        match self.counters.lock().unwrap().get(channel) {
+            Some(c) => Ok(c.clone()),
+            None => Err(Error::InvalidChannel),
+        }
+    }
+
+    /// Reset all traffic counters for a channel to zero.
+    pub fn reset_counters(&mut self, channel: usize) -> Result<(), Error> {
+        match self.counters.lock().unwrap().get_mut(channel) {
+            Some(c) => {
+                *c = ChannelCounters::default();
+                Ok(())
+            }
+            None => Err(Error::InvalidChannel),
+        }
+    }
+
+    /// Returns a snapshot of live statistics for all channels: bus load, frame
+    /// and error rates, the busiest IDs, and queue depths.
+    ///
+    /// Rates are averaged over the last second. The snapshot is cheap to take and
+    /// is meant to be polled at the refresh rate of a user interface.
+    pub fn snapshot(&self) -> LiveStats {
+        // read first, the other locks are never taken while the device is held
+        let rx_queue = self.dev.lock().unwrap().can_rx_recv.len();
+        let now = time::Instant::now();
+        let mut live = self.live.lock().unwrap();
+        let counters = self.counters.lock().unwrap();
+        let tx = self.tx.lock().unwrap();
+        let channels = (0..self.channels())
+            .map(|ch| {
+                let mut s = live.channel(ch, self.channels[ch].bitrate, now);
+                s.error_frames = counters[ch].error_frames;
+                s.tx_pending = tx.pending(ch as u8);
+                s
+            })
+            .collect();
+        LiveStats { channels, rx_queue }
+    }
+
+    /// Observe a channel for `duration` and report likely problems with the bus,
+    /// such as missing acknowledgements, a stuck-dominant bus, persistent error
+    /// frames, or a bitrate which does not match the other nodes.
+    ///
+    /// The interface must be running. Frames sent while the diagnosis runs are
+    /// taken into account, so sending some traffic gives more useful results.
+    /// Error reporting depends on the device firmware, devices which do not send
+    /// error frames can only be checked for missing traffic.
+    pub fn diagnose(
+        &mut self,
+        channel: usize,
+        duration: time::Duration,
+    ) -> Result<DiagnosticReport, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let (token, errors) = self.dispatcher.listen_errors(None);
+        let before = self.counters(channel)?;
+        thread::sleep(duration);
+        let after = self.counters(channel)?;
+        self.dispatcher.remove_error_listener(token);
+
+        let errors = errors
+            .try_iter()
+            .filter(|e| e.channel as usize == channel)
+            .collect();
+        Ok(DiagnosticReport::analyze(
+            channel as u8,
+            duration,
+            &before,
+            &after,
+            errors,
+        ))
+    }
+
+    /// Returns traffic statistics for all channels, and counts of received
+    /// frames lost on the device or in the driver's queues to tell whether a
+    /// capture is complete. Statistics cover the time since the interface was
+    /// created or `Interface::reset_stats` was last called.
+    pub fn stats(&self) -> Stats {
+        let now = time::Instant::now();
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.queue_drops += self.dev.lock().unwrap().rx_drops();
+        let mut live = self.live.lock().unwrap();
+        let counters = self.counters.lock().unwrap();
+        stats.channels = (0..self.channels())
+            .map(|ch| live.stats(ch, self.channels[ch].bitrate, now, counters[ch].clone()))
+            .collect();
+        stats
+    }
+
+    /// Reset all statistics returned by `Interface::stats` to zero, including
+    /// the traffic counters of all channels.
+    pub fn reset_stats(&mut self) {
+        *self.stats.lock().unwrap() = Stats::default();
+        self.dev.lock().unwrap().reset_rx_drops();
+        self.live.lock().unwrap().reset();
+        for c in self.counters.lock().unwrap().iter_mut() {
+            *c = ChannelCounters::default();
+        }
+    }
+
+    /// Enable or disable counting frames per ID in `ChannelStats::id_counts`.
+    /// Disabled by default, since every ID seen on the bus takes memory.
+    pub fn set_count_ids(&mut self, enabled: bool) {
+        self.live.lock().unwrap().set_count_ids(enabled);
+    }
+
+    /// Enable or disable the statistics per ID returned by `Interface::id_stats`.
+    /// Disabled by default, since every ID seen on the bus takes memory.
+    /// Disabling drops the statistics.
+    pub fn set_id_stats(&mut self, enabled: bool) {
+        self.live.lock().unwrap().set_id_stats(enabled);
+    }
+
+    /// Returns the number of frames, the period and the last data of every ID
+    /// seen since the statistics were enabled with `Interface::set_id_stats` or
+    /// cleared with `Interface::reset_stats`, sorted by channel and ID.
+    /// Frames sent by this device are included.
+    pub fn id_stats(&self) -> Vec<IdStats> {
+        self.live.lock().unwrap().id_stats()
+    }
+
+    /// Returns statistics about the USB transfers between the host and the device.
+    pub fn usb_stats(&self) -> UsbStats {
+        self.dev.lock().unwrap().usb_stats()
+    }
+
+    /// Returns `Error::CallbackPanicked` if the rx or tx result callback panicked
+    /// since the interface was started or this was last called.
+    pub fn check_rx(&self) -> Result<(), Error> {
+        match self.rx_panic.lock().unwrap().take() {
+            Some(msg) => Err(Error::CallbackPanicked(msg)),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep delivering frames to the rx callback after it panicked, instead of
+    /// dropping them. Disabled by default.
+    pub fn set_restart_on_panic(&mut self, enabled: bool) {
+        *self.restart_on_panic.write().unwrap() = enabled;
+    }
+
+    /// Enable or disable reconnecting when the device is unplugged while running.
+    /// Disabled by default, in which case the interface stops when the device
+    /// is unplugged.
+    ///
+    /// When enabled, the interface waits for a device with the same USB serial
+    /// number to be plugged in, restores the bit timings and modes of its
+    /// channels, and carries on receiving and sending. Devices without a serial
+    /// number are never reconnected. Changes are reported to the callback set
+    /// with `Interface::on_connection`.
+    pub fn set_auto_reconnect(&mut self, enabled: bool) {
+        *self.auto_reconnect.write().unwrap() = enabled;
+    }
+
+    /// Set a callback to be called when the device is unplugged or reconnected.
+    /// The callback is called from the receive thread.
+    pub fn on_connection(&mut self, callback: impl FnMut(ConnectionEvent) + Send + 'static) {
+        *self.connection_callback.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    /// Limit the queues of received frames to `capacity` frames, or remove the
+    /// limit with None, and choose what happens to frames arriving while a queue
+    /// is full. Applies to the queue between the device and the receive thread,
+    /// and to the queues of `Interface::start_channel` and
+    /// `Interface::start_queued`. Queues are unbounded by default, so a slow
+    /// callback on a busy bus can use a lot of memory.
+    ///
+    /// With `OverflowPolicy::Block`, a full queue of `Interface::start_channel`
+    /// makes the receive thread wait until the application catches up or the
+    /// interface stops. The queue between the device and the receive thread
+    /// never waits, frames which don't fit are dropped and counted instead.
+    ///
+    /// Returns `Error::Running` while the interface is running.
+    pub fn set_rx_queue(
+        &mut self,
+        capacity: Option<usize>,
+        policy: OverflowPolicy,
+    ) -> Result<(), Error> {
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+        self.dev.lock().unwrap().set_rx_queue(capacity, policy);
+        self.rx_capacity = capacity;
+        self.rx_policy = policy;
+        Ok(())
+    }
+
+    /// Returns a handle for a single channel of the device.
+    ///
+    /// Returns `Error::InvalidChannel` if the channel does not exist.
+    pub fn channel(&mut self, channel: usize) -> Result<ChannelHandle<'_>, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(ChannelHandle::new(self, channel))
+    }
+
+    /// Returns the optional features supported by a channel, so applications can
+    /// adapt to the device at runtime.
+    ///
+    /// Returns `Error::InvalidChannel` if the channel does not exist.
+    pub fn capabilities(&self, channel: usize) -> Result<Capabilities, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(self.features(channel))
+    }
+
+    /// Returns the ranges of bit timing values the CAN controller of a channel
+    /// accepts, as reported by the device. Bitrates are solved within these
+    /// limits, and custom bit timings must lie within them.
+    ///
+    /// Returns `Error::InvalidChannel` if the channel does not exist.
+    pub fn bit_timing_limits(&self, channel: usize) -> Result<BitTimingLimits, Error> {
+        if channel > self.channel_count {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(self.limits[channel])
+    }
+
+    // features of a channel which has been validated
+    fn features(&self, channel: usize) -> Capabilities {
+        Capabilities::from_features(self.features[channel])
+    }
+
+    /// Returns the number of channels this Interface has
     pub fn channels(&self) -> usize {
         self.channel_count + 1
     }
+
+    /// Returns the USB serial number of the device, if it has one.
+    pub fn serial_number(&self) -> Option<String> {
+        self.dev.lock().unwrap().serial().map(String::from)
+    }
+
+    /// Returns the hardware version reported by the device.
+    pub fn hardware_version(&self) -> u32 {
+        self.hw_version
+    }
+
+    /// Returns the firmware version reported by the device.
+    pub fn software_version(&self) -> u32 {
+        self.sw_version
+    }
+
+    /// Returns the frequency of the CAN controller clock in Hz, which bit timings
+    /// are based on.
+    pub fn can_clock(&self) -> u32 {
+        self.can_clock
+    }
+
+    /// Returns the configuration of a channel, which is applied when the
+    /// interface is started.
+    ///
+    /// Returns `Error::InvalidChannel` if the channel does not exist.
+    pub fn channel_config(&self, channel: usize) -> Result<&Channel, Error> {
+        self.channels.get(channel).ok_or(Error::InvalidChannel)
+    }
+
+    pub(crate) fn transmitter(&self) -> Transmitter {
+        Transmitter {
+            dev: Arc::clone(&self.dev),
+            running: Arc::clone(&self.running),
+            tx: Arc::clone(&self.tx),
+            counters: Arc::clone(&self.counters),
+            limiter: Arc::clone(&self.limiter),
+            hooks: Arc::clone(&self.tx_hooks),
+        }
+    }
 }
 
-fn calculate_bit_timing(clk: u32, bitrate: u32) -> Result<BitTiming, Error> {
-    let max_brp = 32;
-    let min_seg1 = 3;
-    let max_seg1 = 18;
-    let min_seg2 = 2;
-    let max_seg2 = 8;
-    let tolerances = vec![0.0, 0.1 / 100.0, 0.5 / 100.0];
-
-    for tolerance in tolerances {
-        let tmp = clk as f32 / bitrate as f32;
-        for brp in 1..(max_brp + 1) {
-            let btq = tmp / brp as f32;
-            let btq_rounded = btq.round() as u32;
-
-            if btq_rounded >= 4 && btq_rounded <= 32 {
-                let err = ((btq / (btq_rounded as f32) - 1.0) * 10000.0).round() / 10000.0;
-                if err.abs() > tolerance {
-                    // error is not acceptable
-                    continue;
-                }
-            }
+// the frame received in `hf` after the rx hooks, or None if a hook dropped it.
+// A hook which panics drops the frame.
+fn rx_frame(
+    hf: HostFrame,
+    timestamp: time::Duration,
+    hooks: &Mutex<Pipeline>,
+    rx_panic: &Mutex<Option<String>>,
+) -> Option<Frame> {
+    let mut f = hf.to_frame();
+    f.timestamp = Some(timestamp);
+    let mut hooks = hooks.lock().unwrap();
+    match panic::catch_unwind(AssertUnwindSafe(|| hooks.run(&mut f))) {
+        Ok(true) => Some(f),
+        Ok(false) => None,
+        Err(e) => {
+            *rx_panic.lock().unwrap() = Some(panic_message(e));
+            None
+        }
+    }
+}
 
-            for seg1 in min_seg1..max_seg1 {
-                // subtract 1 from seg2 to account for propagation phase
-                let seg2 = btq_rounded - seg1 - 1;
-                if seg2 < min_seg2 || seg2 > max_seg2 {
-                    // invalid seg2 value
-                    continue;
-                }
-                // brp, seg1, and seg2 are all valid
-                return Ok(BitTiming {
+fn connection_event(callback: &ConnectionCallback, ev: ConnectionEvent) {
+    if let Some(cb) = callback.lock().unwrap().as_mut() {
+        cb(ev);
+    }
+}
+
+fn panic_message(e: Box<dyn Any + Send>) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// largest accepted difference between the requested and the actual bitrate
+const MAX_BITRATE_ERROR: f64 = 0.005;
+
+// sample point for a bitrate, following CiA 301
+fn default_sample_point(bitrate: u32) -> f32 {
+    if bitrate > 800_000 {
+        0.75
+    } else if bitrate > 500_000 {
+        0.8
+    } else {
+        0.875
+    }
+}
+
+fn calculate_bit_timing(
+    clk: u32,
+    bitrate: u32,
+    sjw: u32,
+    limits: &BitTimingLimits,
+) -> Result<BitTiming, Error> {
+    calculate_bit_timing_with_sample_point(clk, bitrate, default_sample_point(bitrate), sjw, limits)
+}
+
+// tries every prescaler and segment length the controller accepts, and chooses
+// the timing closest to the requested bitrate, then the one with the sample
+// point closest to the requested one
+fn calculate_bit_timing_with_sample_point(
+    clk: u32,
+    bitrate: u32,
+    sample_point: f32,
+    sjw: u32,
+    limits: &BitTimingLimits,
+) -> Result<BitTiming, Error> {
+    if sjw == 0 || sjw > limits.sjw_max {
+        return Err(Error::InvalidSjw);
+    }
+    if bitrate == 0 {
+        return Err(Error::InvalidBitrate(bitrate));
+    }
+    let min_tq = 1 + limits.tseg1_min + limits.tseg2_min;
+    let max_tq = 1 + limits.tseg1_max + limits.tseg2_max;
+
+    // (bitrate error, sample point error, timing) of the best candidate
+    let mut best: Option<(f64, f32, BitTiming)> = None;
+    let brps = (limits.brp_min.max(1)..=limits.brp_max).step_by(limits.brp_inc.max(1) as usize);
+    for brp in brps {
+        let tq = (clk as f64 / (brp as f64 * bitrate as f64)).round() as u32;
+        if tq < min_tq || tq > max_tq {
+            continue;
+        }
+        let actual = clk as f64 / (brp as u64 * tq as u64) as f64;
+        let err = (actual / bitrate as f64 - 1.0).abs();
+        if err > MAX_BITRATE_ERROR {
+            continue;
+        }
+
+        for tseg1 in limits.tseg1_min..=limits.tseg1_max {
+            // one quantum is the sync segment
+            let tseg2 = match (tq - 1).checked_sub(tseg1) {
+                Some(tseg2) => tseg2,
+                None => break,
+            };
+            // the jump width can't be longer than phase segment 2
+            if tseg2 < limits.tseg2_min.max(sjw) || tseg2 > limits.tseg2_max {
+                continue;
+            }
+            // the sample point is at the end of tseg1
+            let sp_err = ((1 + tseg1) as f32 / tq as f32 - sample_point).abs();
+            let better = match &best {
+                Some((e, s, _)) => err < *e || (err == *e && sp_err < *s),
+                None => true,
+            };
+            if better {
+                let bt = BitTiming {
                     brp,
                     prop_seg: 0,
-                    phase_seg1: seg1,
-                    phase_seg2: seg2,
-                    sjw: 1,
-                });
+                    phase_seg1: tseg1,
+                    phase_seg2: tseg2,
+                    sjw,
+                };
+                best = Some((err, sp_err, bt));
             }
         }
     }
-    Err(Error::InvalidBitrate(bitrate))
-}
-
-#[allow(dead_code)]
-fn effective_bitrate(clk: u32, bt: BitTiming) -> u32 {
-    clk / bt.brp / (bt.prop_seg + bt.phase_seg1 + bt.phase_seg2 + 1)
+    best.map(|(_, _, bt)| bt)
+        .ok_or(Error::InvalidBitrate(bitrate))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use device::mock::{self, Mock};
+
     #[test]
     fn test_bit_timing() {
         let clk = 24000000;
+        let limits = BitTimingLimits::default();
         let bitrates = vec![1000000, 500000, 250000, 125000, 33333];
         for b in bitrates {
-            let bt = calculate_bit_timing(clk, b).unwrap();
+            let bt = calculate_bit_timing(clk, b, 1, &limits).unwrap();
 
             // ensure error < 0.5%
             println!("{:?}", &bt);
-            let err = 100.0 * (1.0 - (effective_bitrate(clk, bt) as f32 / b as f32).abs());
+            let err = 100.0 * (1.0 - (bt.bitrate(clk) as f32 / b as f32).abs());
             println!("{:?}", err);
             assert!(err < 0.5);
         }
     }
+
+    #[test]
+    fn test_bit_timing_sample_point() {
+        let clk = 24000000;
+        let limits = BitTimingLimits::default();
+        for preset in [Bitrate::K125, Bitrate::K500, Bitrate::M1, Bitrate::Fd500k2M] {
+            let b = preset.nominal_bitrate();
+            let sp = preset.sample_point();
+            let bt = calculate_bit_timing_with_sample_point(clk, b, sp, 1, &limits).unwrap();
+            let tq = bt.prop_seg + bt.phase_seg1 + bt.phase_seg2 + 1;
+            let actual_sp = (1 + bt.prop_seg + bt.phase_seg1) as f32 / tq as f32;
+            assert!((actual_sp - sp).abs() < 0.05);
+            assert_eq!(bt.bitrate(clk), b);
+        }
+        // the CiA recommended sample point is hit exactly at 500 kbit/s
+        let bt = calculate_bit_timing_with_sample_point(clk, 500_000, 0.875, 1, &limits).unwrap();
+        let tq = bt.prop_seg + bt.phase_seg1 + bt.phase_seg2 + 1;
+        assert_eq!((1 + bt.phase_seg1) * 8, tq * 7);
+        // not enough time quanta per bit
+        assert!(calculate_bit_timing_with_sample_point(clk, 8_000_000, 0.75, 1, &limits).is_err());
+
+        // data phase bitrates on an FD capable clock
+        for b in [2_000_000, 5_000_000, 8_000_000] {
+            let bt = calculate_bit_timing_with_sample_point(
+                80_000_000,
+                b,
+                bitrate::FD_DATA_SAMPLE_POINT,
+                1,
+                &limits,
+            )
+            .unwrap();
+            assert_eq!(bt.bitrate(80_000_000), b);
+        }
+
+        // the limits reported by the device are respected
+        let narrow = BitTimingLimits {
+            tseg1_min: 1,
+            tseg1_max: 8,
+            brp_min: 2,
+            brp_inc: 2,
+            ..limits
+        };
+        let bt = calculate_bit_timing_with_sample_point(clk, 125_000, 0.875, 2, &narrow).unwrap();
+        assert!(bt.brp.is_multiple_of(2) && bt.phase_seg1 <= 8 && bt.sjw == 2);
+        assert_eq!(bt.bitrate(clk), 125_000);
+        assert!(calculate_bit_timing(clk, 125_000, 5, &limits).is_err());
+    }
+
+    #[test]
+    fn test_fd_host_frame() {
+        let mut f = Frame {
+            id: Id::new(0x123, false).unwrap(),
+            fd: true,
+            brs: true,
+            can_dlc: 15,
+            ..Default::default()
+        };
+        for (i, b) in f.data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let bytes = HostFrame::from_frame(&f, 7).to_le_bytes();
+        assert_eq!(bytes.len(), 12 + 64);
+        let g = HostFrame::from_le_bytes(&bytes).to_frame();
+        assert!(g.fd && g.brs && !g.esi);
+        assert_eq!(g.payload(), f.payload());
+
+        // classic frames keep the 8 byte layout, and have no BRS flag
+        f.fd = false;
+        f.can_dlc = 8;
+        let bytes = HostFrame::from_frame(&f, 7).to_le_bytes();
+        assert_eq!(bytes.len(), 12 + 8);
+        let g = HostFrame::from_le_bytes(&bytes).to_frame();
+        assert_eq!((g.fd, g.brs, g.payload()), (false, false, &f.data[..8]));
+
+        // hardware timestamps follow the data
+        let mut bytes = bytes;
+        bytes.extend_from_slice(&1234u32.to_le_bytes());
+        assert_eq!(HostFrame::from_le_bytes(&bytes).timestamp_us, Some(1234));
+
+        // flag bits which are not modelled pass through
+        f.raw_flags = Some(0x80);
+        let g = HostFrame::from_frame(&f, 7).to_frame();
+        assert_eq!(g.raw_flags, Some(0x80));
+        assert!(HostFrame::from_frame(&g, 7).to_frame().raw_flags == Some(0x80));
+    }
+
+    #[test]
+    fn test_frame_validate() {
+        let valid = Frame::new(0x7FF, &[1]).unwrap();
+        assert!(valid.validate().is_ok());
+        for invalid in [
+            Frame {
+                can_dlc: 9,
+                ..valid.clone()
+            },
+            Frame {
+                fd: true,
+                can_dlc: 16,
+                ..valid.clone()
+            },
+            Frame {
+                fd: true,
+                rtr: true,
+                ..valid.clone()
+            },
+            Frame {
+                brs: true,
+                ..valid.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+        assert!(Frame {
+            fd: true,
+            brs: true,
+            can_dlc: 15,
+            ..valid
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_device_errors() {
+        let e = Error::from(device::Error::Libusb("libusb_open", LIBUSB_ERROR_ACCESS));
+        assert!(matches!(e, Error::PermissionDenied));
+        let e = Error::from(device::Error::Transfer("send", LIBUSB_TRANSFER_NO_DEVICE));
+        assert!(matches!(e, Error::Disconnected));
+        let e = Error::from(device::Error::ControlTransfer(
+            "get state",
+            LIBUSB_TRANSFER_STALL,
+        ));
+        assert_eq!(e.to_string(), "the device failed the get state request");
+        let e = Error::from(device::Error::TransferAllocFailed);
+        assert_eq!(
+            e.to_string(),
+            "device error: failed to allocate a USB transfer"
+        );
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut i = mock::interface(Mock::default());
+        i.start(|_| {}).unwrap();
+        i.send(Frame::new(0x100, &[1, 2, 3]).unwrap()).unwrap();
+        mock::inject(
+            &i,
+            HostFrame::from_frame(&Frame::new(0x200, &[1, 2]).unwrap(), GSUSB_RX_ECHO_ID),
+        );
+        mock::inject(
+            &i,
+            HostFrame {
+                echo_id: GSUSB_RX_ECHO_ID,
+                can_id: GSUSB_ERR_FLAG | CAN_ERR_PROT,
+                can_dlc: 8,
+                channel: 1,
+                flags: 0,
+                reserved: 0,
+                data: [0; 64],
+                timestamp_us: None,
+            },
+        );
+        mock::wait_for(|| {
+            let c = i.counters(0).unwrap();
+            c.echo_frames == 1 && c.rx_frames == 1 && i.counters(1).unwrap().error_frames == 1
+        });
+        let expected = ChannelCounters {
+            tx_frames: 1,
+            tx_bytes: 3,
+            rx_frames: 1,
+            rx_bytes: 2,
+            echo_frames: 1,
+            error_frames: 0,
+        };
+        assert_eq!(i.counters(0).unwrap(), expected);
+
+        // resetting a channel leaves the other channels alone
+        i.reset_counters(0).unwrap();
+        assert_eq!(i.counters(0).unwrap(), ChannelCounters::default());
+        assert_eq!(i.counters(1).unwrap().error_frames, 1);
+        assert!(matches!(i.counters(2), Err(Error::InvalidChannel)));
+        assert!(matches!(i.reset_counters(2), Err(Error::InvalidChannel)));
+        i.stop().unwrap();
+    }
+
+    #[test]
+    fn test_request_remote() {
+        let mut i = mock::interface(Mock::default());
+        let ms = time::Duration::from_millis;
+        i.start(|_| {}).unwrap();
+        assert!(matches!(
+            i.request_remote(0, 0x123, ms(20)),
+            Err(Error::Timeout)
+        ));
+
+        // only a data frame with the same ID is the response
+        let dev = Arc::clone(&i.dev);
+        let responder = thread::spawn(move || {
+            thread::sleep(ms(20));
+            // the response on another channel
+            let mut other = Frame::new(0x123, &[4]).unwrap();
+            other.channel = 1;
+            for f in [
+                Frame::new_ext(0x123, &[1]).unwrap(),
+                Frame::new_remote(0x123, false, 1).unwrap(),
+                Frame::new(0x124, &[2]).unwrap(),
+                other,
+                Frame::new(0x123, &[3]).unwrap(),
+            ] {
+                dev.lock()
+                    .unwrap()
+                    .inject(HostFrame::from_frame(&f, GSUSB_RX_ECHO_ID));
+            }
+        });
+        let f = i.request_remote(0, 0x123, ms(1000)).unwrap();
+        responder.join().unwrap();
+        assert_eq!(
+            (f.raw_id(), f.is_extended(), f.payload()),
+            (0x123, false, &[3][..])
+        );
+
+        assert!(matches!(
+            i.request_remote(2, 0x123, ms(20)),
+            Err(Error::InvalidChannel)
+        ));
+        assert!(matches!(
+            i.request_remote(0, 0x2000_0000, ms(20)),
+            Err(Error::InvalidFrame)
+        ));
+        i.stop().unwrap();
+    }
+
+    #[test]
+    fn test_start_channel() {
+        let mut i = mock::interface(Mock::default());
+        let ms = time::Duration::from_millis;
+        let frames = i.start_channel().unwrap();
+        i.send(Frame::new(0x100, &[1]).unwrap()).unwrap();
+        let echo = frames.recv_timeout(ms(1000)).unwrap();
+        assert_eq!(echo.raw_id(), 0x100);
+        assert!(matches!(echo.direction, Direction::TxEcho(_)));
+
+        mock::inject(
+            &i,
+            HostFrame::from_frame(&Frame::new(0x200, &[2]).unwrap(), GSUSB_RX_ECHO_ID),
+        );
+        crossbeam_channel::select! {
+            recv(frames) -> f => {
+                let f = f.unwrap();
+                assert_eq!((f.raw_id(), f.direction), (0x200, Direction::Rx));
+            }
+            default(ms(1000)) => panic!("no frame received"),
+        }
+
+        // frames received before stopping can still be read, then the channel
+        // is disconnected
+        mock::inject(
+            &i,
+            HostFrame::from_frame(&Frame::new(0x300, &[3]).unwrap(), GSUSB_RX_ECHO_ID),
+        );
+        mock::wait_for(|| !frames.is_empty());
+        i.stop().unwrap();
+        assert_eq!(frames.recv().unwrap().raw_id(), 0x300);
+        assert!(frames.recv().is_err());
+
+        // with a bounded queue the oldest frames are dropped
+        i.set_rx_queue(Some(2), OverflowPolicy::DropOldest).unwrap();
+        let frames = i.start_channel().unwrap();
+        for id in 1..=4 {
+            mock::inject(
+                &i,
+                HostFrame::from_frame(&Frame::new(id, &[]).unwrap(), GSUSB_RX_ECHO_ID),
+            );
+        }
+        mock::wait_for(|| i.stats().queue_drops == 2);
+        i.stop().unwrap();
+        let ids: Vec<u32> = frames.iter().map(|f| f.raw_id()).collect();
+        assert_eq!(ids, [3, 4]);
+    }
+
+    #[test]
+    fn test_recv() {
+        let mut i = mock::interface(Mock::default());
+        let ms = time::Duration::from_millis;
+        assert!(matches!(i.recv(), Err(Error::NotRunning)));
+        assert!(matches!(i.recv_timeout(ms(1)), Err(Error::NotRunning)));
+
+        i.start_queued().unwrap();
+        assert!(matches!(i.recv_timeout(ms(20)), Err(Error::Timeout)));
+        for id in 1..=3 {
+            mock::inject(
+                &i,
+                HostFrame::from_frame(&Frame::new(id, &[]).unwrap(), GSUSB_RX_ECHO_ID),
+            );
+        }
+        assert_eq!(i.recv().unwrap().raw_id(), 1);
+        assert_eq!(i.recv_timeout(ms(1000)).unwrap().raw_id(), 2);
+
+        // the remaining frames are returned after stopping
+        mock::wait_for(|| i.rx_queue.as_ref().unwrap().len() == 1);
+        i.stop().unwrap();
+        assert_eq!(i.frames().next().unwrap().raw_id(), 3);
+        assert!(i.frames().next().is_none());
+        assert!(matches!(i.recv(), Err(Error::NotRunning)));
+        assert!(matches!(i.recv_timeout(ms(1)), Err(Error::NotRunning)));
+    }
+
+    #[test]
+    fn test_faults_do_not_panic() {
+        let mock = Mock::default();
+        let mut i = mock::interface(mock.clone());
+        assert_eq!(i.channels(), 2);
+
+        // a stalled control request is reported, and the bitrate is not changed
+        mock.faults.lock().unwrap().requests = vec![UsbBreq::BitTiming];
+        assert!(matches!(
+            i.set_bitrate(0, 500_000),
+            Err(Error::ControlTransferFailed {
+                request: "bit timing"
+            })
+        ));
+        assert!(i.set_bit_timing(0, 6, 13, 2, 1).is_err());
+        assert_eq!(i.channel_config(0).unwrap().bitrate, 0);
+        mock.faults.lock().unwrap().requests.clear();
+        i.set_bitrate(0, 500_000).unwrap();
+        let bt = i.set_bitrate(1, 500_000).unwrap();
+        assert_eq!(bt.bitrate(i.can_clock()), 500_000);
+        assert_eq!(i.bit_timing(1), Some(bt));
+
+        // the jump width is checked against the limits of the channel
+        i.set_sjw(1, 2).unwrap();
+        assert_eq!(i.bit_timing(1).unwrap().sjw, 2);
+        assert!(matches!(i.set_sjw(1, 5), Err(Error::InvalidSjw)));
+        assert_eq!(i.set_bitrate(1, 250_000).unwrap().sjw, 2);
+        assert!(matches!(
+            i.set_bitrate_with_sample_point(1, 500_000, 1.5),
+            Err(Error::InvalidSamplePoint)
+        ));
+        i.set_bitrate_with_sample_point(1, 500_000, 0.875).unwrap();
+
+        // the interface is stopped again if starting fails part way
+        mock.faults.lock().unwrap().start = true;
+        assert!(i.start(|_| {}).is_err());
+        assert!(matches!(
+            i.send(Frame::new(0x123, &[1]).unwrap()),
+            Err(Error::NotRunning)
+        ));
+        mock.faults.lock().unwrap().start = false;
+        mock.faults.lock().unwrap().requests = vec![UsbBreq::Mode];
+        assert!(i.start(|_| {}).is_err());
+        mock.faults.lock().unwrap().requests.clear();
+
+        i.start(|_| {}).unwrap();
+        i.send(Frame::new(0x123, &[1]).unwrap()).unwrap();
+        mock.faults.lock().unwrap().send = true;
+        assert!(i.send(Frame::new(0x123, &[2]).unwrap()).is_err());
+        mock.faults.lock().unwrap().send = false;
+
+        // the bitrate can be changed while running, and the channel is started
+        // again if the change fails
+        i.set_bitrate(0, 250_000).unwrap();
+        assert_eq!(i.channel_config(0).unwrap().bitrate, 250_000);
+        mock.faults.lock().unwrap().requests = vec![UsbBreq::BitTiming];
+        assert!(i.set_bitrate(0, 125_000).is_err());
+        assert_eq!(i.channel_config(0).unwrap().bitrate, 250_000);
+        mock.faults.lock().unwrap().requests.clear();
+
+        // unplugging the device fails sending, and stops the interface
+        mock.faults.lock().unwrap().unplugged = true;
+        assert!(matches!(
+            i.send(Frame::new(0x123, &[3]).unwrap()),
+            Err(Error::Disconnected)
+        ));
+        i.stop().ok();
+        assert!(i.set_bitrate(0, 250_000).is_err());
+        assert!(i.start(|_| {}).is_err());
+    }
+
+    #[test]
+    fn test_live_bitrate() {
+        let mock = Mock::default();
+        let mut i = mock::interface(mock.clone());
+        i.set_loopback(0, true).unwrap();
+        i.set_enabled(1, false).unwrap();
+        i.start(|_| {}).unwrap();
+        mock.faults.lock().unwrap().no_echo = true;
+        i.send(Frame::new(0x123, &[1]).unwrap()).unwrap();
+        mock.written.lock().unwrap().clear();
+
+        // the channel is reset, given the new timing and started in its mode
+        i.set_bitrate(0, 250_000).unwrap();
+        let written = mock.written.lock().unwrap().clone();
+        let reqs: Vec<UsbBreq> = written.iter().map(|(req, _, _)| *req).collect();
+        assert_eq!(reqs, [UsbBreq::Mode, UsbBreq::BitTiming, UsbBreq::Mode]);
+        assert!(written.iter().all(|(_, channel, _)| *channel == 0));
+        let flags = &written[2].2[4..8];
+        assert_eq!(
+            flags[0] as u32 & GSUSB_FEATURE_LOOP_BACK,
+            GSUSB_FEATURE_LOOP_BACK
+        );
+        // the device discarded the frame which was waiting
+        assert_eq!(i.tx.lock().unwrap().pending(0), 0);
+        assert_eq!(i.bit_timing(0).unwrap().bitrate(i.can_clock()), 250_000);
+
+        // a disabled channel is not started
+        mock.written.lock().unwrap().clear();
+        i.set_bitrate(1, 250_000).unwrap();
+        let reqs: Vec<UsbBreq> = mock
+            .written
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(req, _, _)| *req)
+            .collect();
+        assert_eq!(reqs, [UsbBreq::BitTiming]);
+        i.stop().unwrap();
+    }
 }
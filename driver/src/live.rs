@@ -0,0 +1,428 @@
+//! Rolling traffic statistics for dashboards.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::{ChannelCounters, ChannelStats, Direction, Frame, Id, IdStats};
+
+// statistics cover the last WINDOW, kept in BUCKETS buckets so old traffic
+// expires without storing every frame
+const WINDOW: Duration = Duration::from_secs(1);
+const BUCKETS: u32 = 10;
+
+// number of IDs reported in `ChannelLiveStats::top_talkers`
+const TOP_TALKERS: usize = 5;
+
+/// An ID and how often it was seen, in `ChannelLiveStats::top_talkers`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Talker {
+    /// CAN ID.
+    pub id: u32,
+    /// True if the ID is extended.
+    pub ext: bool,
+    /// Frames per second with this ID.
+    pub frames_per_sec: f32,
+}
+
+/// Live statistics for one channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelLiveStats {
+    /// Channel index.
+    pub channel: u8,
+    /// Estimated fraction of the bus bandwidth in use, from 0.0 to 1.0. Bit
+    /// stuffing is not included, so the real load is slightly higher.
+    pub bus_load: f32,
+    /// Frames per second on the bus, including frames sent by this device.
+    pub frames_per_sec: f32,
+    /// Frames per second sent by this device.
+    pub tx_frames_per_sec: f32,
+    /// Error frames per second reported by the device.
+    pub errors_per_sec: f32,
+    /// Total error frames since the counters were last reset.
+    pub error_frames: u64,
+    /// The most frequent IDs, most frequent first.
+    pub top_talkers: Vec<Talker>,
+    /// Frames handed to the device which have not been echoed back yet.
+    pub tx_pending: usize,
+}
+
+/// Snapshot of live statistics for an `Interface`, returned by
+/// `Interface::snapshot`. Rates are averaged over the last second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveStats {
+    /// Statistics for each channel.
+    pub channels: Vec<ChannelLiveStats>,
+    /// Frames received from the device which the receive thread has not
+    /// processed yet. A growing queue means callbacks are too slow.
+    pub rx_queue: usize,
+}
+
+#[derive(Default)]
+struct Bucket {
+    frames: u64,
+    tx_frames: u64,
+    errors: u64,
+    bits: u64,
+    ids: HashMap<(u32, bool), u64>,
+}
+
+struct Window {
+    start: Instant,
+    // most recent bucket last
+    buckets: VecDeque<Bucket>,
+    // highest frames and bits in a full window, kept until reset
+    peak_frames: u64,
+    peak_bits: u64,
+}
+
+impl Window {
+    fn new(now: Instant) -> Window {
+        Window {
+            start: now,
+            buckets: VecDeque::from(vec![Bucket::default()]),
+            peak_frames: 0,
+            peak_bits: 0,
+        }
+    }
+
+    // drop buckets which have left the window and return the current bucket
+    fn advance(&mut self, now: Instant) -> &mut Bucket {
+        let width = WINDOW / BUCKETS;
+        while now.duration_since(self.start) >= width {
+            if self.buckets.len() == BUCKETS as usize {
+                // the completed buckets cover the whole window
+                let frames = self.buckets.iter().map(|b| b.frames).sum();
+                let bits = self.buckets.iter().map(|b| b.bits).sum();
+                self.peak_frames = self.peak_frames.max(frames);
+                self.peak_bits = self.peak_bits.max(bits);
+            }
+            self.start += width;
+            self.buckets.push_back(Bucket::default());
+            if self.buckets.len() > BUCKETS as usize {
+                self.buckets.pop_front();
+            }
+            if self.buckets.len() == BUCKETS as usize && now.duration_since(self.start) >= WINDOW {
+                // idle for longer than the window, start over
+                let (peak_frames, peak_bits) = (self.peak_frames, self.peak_bits);
+                *self = Window::new(now);
+                self.peak_frames = peak_frames;
+                self.peak_bits = peak_bits;
+            }
+        }
+        self.buckets.back_mut().unwrap()
+    }
+}
+
+/// Collects traffic into a rolling window. Shared with the rx thread.
+pub(crate) struct LiveMonitor {
+    windows: Vec<Window>,
+    // frames per ID on each channel, None unless enabled
+    id_counts: Option<Vec<HashMap<Id, u64>>>,
+    // statistics per channel and ID, None unless enabled
+    id_stats: Option<HashMap<(u8, Id), IdStats>>,
+}
+
+// nominal length of a frame in bits, without stuffing. FD frames are counted
+// as if the whole frame was sent at the nominal bitrate.
+pub(crate) fn frame_bits(f: &Frame) -> u64 {
+    let header = if f.is_extended() { 67 } else { 47 };
+    let data = if f.rtr { 0 } else { f.len() as u64 * 8 };
+    header + data
+}
+
+// add a frame to the statistics of its ID
+fn id_frame(stats: &mut HashMap<(u8, Id), IdStats>, f: &Frame) {
+    let ts = f.timestamp.unwrap_or_default();
+    let s = stats.entry((f.channel, f.id)).or_insert_with(|| IdStats {
+        channel: f.channel,
+        id: f.id,
+        count: 0,
+        min_period: None,
+        mean_period: None,
+        max_period: None,
+        last_dlc: 0,
+        last_data: vec![],
+        first_seen: ts,
+        last_seen: ts,
+    });
+    if s.count > 0 {
+        let period = ts.saturating_sub(s.last_seen);
+        s.min_period = Some(s.min_period.map_or(period, |p| p.min(period)));
+        s.max_period = Some(s.max_period.map_or(period, |p| p.max(period)));
+        s.mean_period = Some(ts.saturating_sub(s.first_seen) / s.count as u32);
+    }
+    s.count += 1;
+    s.last_dlc = f.can_dlc;
+    s.last_data = f.payload().to_vec();
+    s.last_seen = ts;
+}
+
+impl LiveMonitor {
+    pub(crate) fn new(channels: usize) -> LiveMonitor {
+        let now = Instant::now();
+        LiveMonitor {
+            windows: (0..channels).map(|_| Window::new(now)).collect(),
+            id_counts: None,
+            id_stats: None,
+        }
+    }
+
+    /// Enable or disable counting frames per ID. Disabling drops the counts.
+    pub(crate) fn set_count_ids(&mut self, enabled: bool) {
+        if !enabled {
+            self.id_counts = None;
+        } else if self.id_counts.is_none() {
+            self.id_counts = Some(vec![HashMap::new(); self.windows.len()]);
+        }
+    }
+
+    /// Enable or disable the statistics per ID. Disabling drops them.
+    pub(crate) fn set_id_stats(&mut self, enabled: bool) {
+        if !enabled {
+            self.id_stats = None;
+        } else if self.id_stats.is_none() {
+            self.id_stats = Some(HashMap::new());
+        }
+    }
+
+    /// Returns the statistics per ID, sorted by channel and ID with standard
+    /// IDs first.
+    pub(crate) fn id_stats(&self) -> Vec<IdStats> {
+        let mut stats: Vec<IdStats> = self
+            .id_stats
+            .iter()
+            .flat_map(|s| s.values().cloned())
+            .collect();
+        stats.sort_by_key(|s| (s.channel, s.id.is_extended(), s.id.as_raw()));
+        stats
+    }
+
+    /// Forget the peak rates and frames per ID.
+    pub(crate) fn reset(&mut self) {
+        for w in self.windows.iter_mut() {
+            w.peak_frames = 0;
+            w.peak_bits = 0;
+        }
+        for ids in self.id_counts.iter_mut().flatten() {
+            ids.clear();
+        }
+        if let Some(s) = self.id_stats.as_mut() {
+            s.clear();
+        }
+    }
+
+    /// Count a frame seen on the bus, either received or echoed.
+    pub(crate) fn frame(&mut self, f: &Frame, now: Instant) {
+        if let Some(w) = self.windows.get_mut(f.channel as usize) {
+            let b = w.advance(now);
+            b.frames += 1;
+            if let Direction::TxEcho(_) = f.direction {
+                b.tx_frames += 1;
+            }
+            b.bits += frame_bits(f);
+            *b.ids.entry((f.raw_id(), f.is_extended())).or_default() += 1;
+        }
+        if let Some(ids) = self
+            .id_counts
+            .as_mut()
+            .and_then(|c| c.get_mut(f.channel as usize))
+        {
+            *ids.entry(f.id).or_default() += 1;
+        }
+        if let Some(stats) = self.id_stats.as_mut() {
+            id_frame(stats, f);
+        }
+    }
+
+    /// Count an error frame.
+    pub(crate) fn error(&mut self, channel: u8, now: Instant) {
+        if let Some(w) = self.windows.get_mut(channel as usize) {
+            w.advance(now).errors += 1;
+        }
+    }
+
+    /// Summarize the window of a channel. The caller fills in the fields which
+    /// the monitor does not track.
+    pub(crate) fn channel(
+        &mut self,
+        channel: usize,
+        bitrate: u32,
+        now: Instant,
+    ) -> ChannelLiveStats {
+        let w = &mut self.windows[channel];
+        w.advance(now);
+        // the current bucket is partly filled
+        let span = (WINDOW / BUCKETS) * (w.buckets.len() as u32 - 1) + now.duration_since(w.start);
+        let secs = span.as_secs_f32().max(0.001);
+
+        let mut ids: HashMap<(u32, bool), u64> = HashMap::new();
+        for b in w.buckets.iter() {
+            for (id, n) in b.ids.iter() {
+                *ids.entry(*id).or_default() += n;
+            }
+        }
+        let mut top: Vec<_> = ids.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let top_talkers = top
+            .into_iter()
+            .take(TOP_TALKERS)
+            .map(|((id, ext), n)| Talker {
+                id,
+                ext,
+                frames_per_sec: n as f32 / secs,
+            })
+            .collect();
+
+        let sum = |field: fn(&Bucket) -> u64| w.buckets.iter().map(field).sum::<u64>() as f32;
+        let bits_per_sec = sum(|b| b.bits) / secs;
+        ChannelLiveStats {
+            channel: channel as u8,
+            bus_load: if bitrate == 0 {
+                0.0
+            } else {
+                (bits_per_sec / bitrate as f32).min(1.0)
+            },
+            frames_per_sec: sum(|b| b.frames) / secs,
+            tx_frames_per_sec: sum(|b| b.tx_frames) / secs,
+            errors_per_sec: sum(|b| b.errors) / secs,
+            error_frames: 0,
+            top_talkers,
+            tx_pending: 0,
+        }
+    }
+
+    /// Statistics of a channel since the last reset, from its `counters` and
+    /// the traffic seen by the monitor.
+    pub(crate) fn stats(
+        &mut self,
+        channel: usize,
+        bitrate: u32,
+        now: Instant,
+        counters: ChannelCounters,
+    ) -> ChannelStats {
+        let live = self.channel(channel, bitrate, now);
+        let w = &self.windows[channel];
+        let load = |bits: u64| {
+            if bitrate == 0 {
+                0.0
+            } else {
+                (bits as f32 / WINDOW.as_secs_f32() / bitrate as f32).min(1.0)
+            }
+        };
+        ChannelStats {
+            channel: channel as u8,
+            counters,
+            frames_per_sec: live.frames_per_sec,
+            tx_frames_per_sec: live.tx_frames_per_sec,
+            errors_per_sec: live.errors_per_sec,
+            bus_load: live.bus_load,
+            peak_frames_per_sec: (w.peak_frames as f32 / WINDOW.as_secs_f32())
+                .max(live.frames_per_sec),
+            peak_bus_load: load(w.peak_bits).max(live.bus_load),
+            id_counts: self
+                .id_counts
+                .as_ref()
+                .map(|c| c[channel].clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_live_stats() {
+        let start = Instant::now();
+        let mut m = LiveMonitor::new(1);
+        // 100 frames per second on 0x100 and 10 on 0x200 for one second
+        for n in 0..100u32 {
+            let t = start + Duration::from_millis(n as u64 * 10);
+            m.frame(
+                &Frame {
+                    id: Id::new(0x100, false).unwrap(),
+                    can_dlc: 8,
+                    ..Default::default()
+                },
+                t,
+            );
+            if n % 10 == 0 {
+                m.frame(
+                    &Frame {
+                        id: Id::new(0x200, false).unwrap(),
+                        direction: Direction::TxEcho(n),
+                        ..Default::default()
+                    },
+                    t,
+                );
+            }
+        }
+        let s = m.channel(0, 125_000, start + Duration::from_millis(999));
+        assert!((s.frames_per_sec - 110.0).abs() < 15.0);
+        assert!((s.tx_frames_per_sec - 10.0).abs() < 3.0);
+        assert_eq!(s.top_talkers[0].id, 0x100);
+        assert_eq!(s.top_talkers[1].id, 0x200);
+        // 100 * 111 + 10 * 47 bits per second
+        assert!((s.bus_load - 0.0926).abs() < 0.015);
+
+        // traffic expires once it leaves the window
+        let s = m.channel(0, 125_000, start + Duration::from_secs(5));
+        assert_eq!(s.frames_per_sec, 0.0);
+        assert!(s.top_talkers.is_empty());
+
+        // the peak is kept after the traffic expired
+        let now = start + Duration::from_secs(5);
+        let s = m.stats(0, 125_000, now, ChannelCounters::default());
+        assert!((s.peak_frames_per_sec - 110.0).abs() < 15.0);
+        assert!(s.id_counts.is_empty());
+        assert!(m.id_stats().is_empty());
+        m.reset();
+        assert_eq!(
+            m.stats(0, 125_000, now, ChannelCounters::default())
+                .peak_bus_load,
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_id_stats() {
+        let now = Instant::now();
+        let frame = |id, data: &[u8], ms| {
+            let mut f = Frame::new(id, data).unwrap();
+            f.timestamp = Some(Duration::from_millis(ms));
+            f
+        };
+        let mut m = LiveMonitor::new(2);
+        m.frame(&frame(0x100, &[1], 0), now);
+        assert!(m.id_stats().is_empty());
+
+        m.set_id_stats(true);
+        m.frame(&frame(0x100, &[1], 0), now);
+        m.frame(&frame(0x100, &[2], 10), now);
+        m.frame(&frame(0x100, &[3, 4], 40), now);
+        let ext = Frame {
+            timestamp: Some(Duration::from_millis(5)),
+            ..Frame::new_ext(0x1_0000, &[]).unwrap()
+        };
+        m.frame(&ext, now);
+        m.frame(&frame(0x050, &[], 5), now);
+        let stats = m.id_stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].id.as_raw(), 0x050);
+        assert!(stats[2].id.is_extended());
+        assert_eq!(stats[0].mean_period, None);
+        let s = &stats[1];
+        assert_eq!(s.count, 3);
+        assert_eq!(s.min_period, Some(Duration::from_millis(10)));
+        assert_eq!(s.mean_period, Some(Duration::from_millis(20)));
+        assert_eq!(s.max_period, Some(Duration::from_millis(30)));
+        assert_eq!((s.last_dlc, &s.last_data[..]), (2, &[3, 4][..]));
+
+        m.reset();
+        assert!(m.id_stats().is_empty());
+        m.frame(&frame(0x100, &[1], 0), now);
+        m.set_id_stats(false);
+        assert!(m.id_stats().is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+//! Periodic transmission of frames.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Frame;
+
+/// Handle to a periodic transmission, returned by `Interface::send_periodic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskHandle(pub(crate) u64);
+
+pub(crate) type Hook = Box<dyn FnMut(&mut Frame) + Send>;
+
+/// Timing of a periodic transmission, returned by `Interface::periodic_stats`.
+///
+/// Jitter is how late a frame was handed to the device compared to its slot in
+/// the schedule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeriodicStats {
+    /// Frames handed to the device.
+    pub sent: u64,
+    /// Slots which were skipped because the timing thread fell behind by more
+    /// than an interval.
+    pub skipped: u64,
+    /// Jitter of the last frame.
+    pub last_jitter: Duration,
+    /// Average jitter.
+    pub mean_jitter: Duration,
+    /// Largest jitter.
+    pub max_jitter: Duration,
+}
+
+impl PeriodicStats {
+    fn record(&mut self, jitter: Duration) {
+        self.sent += 1;
+        self.last_jitter = jitter;
+        self.max_jitter = self.max_jitter.max(jitter);
+        // running average, exact for up to u32::MAX frames
+        let n = self.sent.min(u32::MAX as u64) as u32;
+        self.mean_jitter = (self.mean_jitter * (n - 1) + jitter) / n;
+    }
+}
+
+struct Task {
+    token: u64,
+    frame: Frame,
+    interval: Duration,
+    next: Instant,
+    hook: Option<Hook>,
+    stats: PeriodicStats,
+}
+
+#[derive(Default)]
+struct State {
+    next_token: u64,
+    tasks: Vec<Task>,
+    shutdown: bool,
+}
+
+/// Sends frames at fixed intervals from a dedicated timing thread. Deadlines are
+/// absolute, so the schedule does not drift when a send is delayed.
+pub(crate) struct Scheduler {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Scheduler {
+    /// Start the timing thread. `send` is called from the timing thread for every
+    /// frame which is due.
+    pub(crate) fn new(mut send: impl FnMut(&Frame) + Send + 'static) -> Scheduler {
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let thread_state = Arc::clone(&state);
+        thread::spawn(move || {
+            let (lock, cvar) = &*thread_state;
+            let mut state = lock.lock().unwrap();
+            loop {
+                if state.shutdown {
+                    return;
+                }
+
+                let now = Instant::now();
+                let mut due = vec![];
+                for t in state.tasks.iter_mut() {
+                    if t.next <= now {
+                        if let Some(hook) = t.hook.as_mut() {
+                            hook(&mut t.frame);
+                        }
+                        due.push((t.token, t.next, t.frame.clone()));
+                        t.next += t.interval;
+                        if t.next <= now {
+                            // we fell behind, skip the missed slots instead of
+                            // sending a burst of frames
+                            let missed = (now - t.next).as_nanos() / t.interval.as_nanos() + 1;
+                            t.stats.skipped += missed as u64;
+                            t.next = now + t.interval;
+                        }
+                    }
+                }
+                if !due.is_empty() {
+                    // don't hold the lock while talking to the device
+                    drop(state);
+                    let mut sent = vec![];
+                    for (token, slot, f) in due.iter() {
+                        sent.push((*token, Instant::now().saturating_duration_since(*slot)));
+                        send(f);
+                    }
+                    state = lock.lock().unwrap();
+                    for (token, jitter) in sent {
+                        // the task may have been removed meanwhile
+                        if let Some(t) = state.tasks.iter_mut().find(|t| t.token == token) {
+                            t.stats.record(jitter);
+                        }
+                    }
+                    continue;
+                }
+
+                state = match state.tasks.iter().map(|t| t.next).min() {
+                    Some(next) => cvar.wait_timeout(state, next - now).unwrap().0,
+                    None => cvar.wait(state).unwrap(),
+                };
+            }
+        });
+        Scheduler { state }
+    }
+
+    /// Send `frame` every `interval`, starting immediately.
+    pub(crate) fn add(&self, frame: Frame, interval: Duration) -> TaskHandle {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        state.next_token += 1;
+        let token = state.next_token;
+        state.tasks.push(Task {
+            token,
+            frame,
+            interval,
+            next: Instant::now(),
+            hook: None,
+            stats: PeriodicStats::default(),
+        });
+        cvar.notify_one();
+        TaskHandle(token)
+    }
+
+    /// Replace the frame sent by a task, keeping its schedule.
+    pub(crate) fn update(&self, handle: TaskHandle, frame: Frame) {
+        let mut state = self.state.0.lock().unwrap();
+        if let Some(t) = state.tasks.iter_mut().find(|t| t.token == handle.0) {
+            t.frame = frame;
+        }
+    }
+
+    /// Change the interval of a task. The next frame is sent one new interval
+    /// after the last one, or right away if that time has passed.
+    pub(crate) fn set_interval(&self, handle: TaskHandle, interval: Duration) {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if let Some(t) = state.tasks.iter_mut().find(|t| t.token == handle.0) {
+            t.next = t.next - t.interval + interval;
+            t.interval = interval;
+        }
+        cvar.notify_one();
+    }
+
+    /// Returns the timing of a task, or `None` if it was stopped.
+    pub(crate) fn stats(&self, handle: TaskHandle) -> Option<PeriodicStats> {
+        let state = self.state.0.lock().unwrap();
+        state
+            .tasks
+            .iter()
+            .find(|t| t.token == handle.0)
+            .map(|t| t.stats)
+    }
+
+    /// Set a hook which may modify a task's frame before every transmission.
+    pub(crate) fn set_hook(&self, handle: TaskHandle, hook: Hook) {
+        let mut state = self.state.0.lock().unwrap();
+        if let Some(t) = state.tasks.iter_mut().find(|t| t.token == handle.0) {
+            t.hook = Some(hook);
+        }
+    }
+
+    /// Stop a task.
+    pub(crate) fn remove(&self, handle: TaskHandle) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().tasks.retain(|t| t.token != handle.0);
+        cvar.notify_one();
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().shutdown = true;
+        cvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use crossbeam_channel::unbounded;
+
+    #[test]
+    fn test_periodic() {
+        let (send, recv) = unbounded();
+        let s = Scheduler::new(move |f: &Frame| send.send(f.clone()).unwrap());
+
+        let h = s.add(
+            Frame {
+                id: Id::new(0x100, false).unwrap(),
+                ..Default::default()
+            },
+            Duration::from_millis(5),
+        );
+        let timeout = Duration::from_secs(1);
+        assert_eq!(recv.recv_timeout(timeout).unwrap().raw_id(), 0x100);
+        assert_eq!(recv.recv_timeout(timeout).unwrap().raw_id(), 0x100);
+
+        s.update(
+            h,
+            Frame {
+                id: Id::new(0x200, false).unwrap(),
+                ..Default::default()
+            },
+        );
+        // skip a frame which may have been sent before the update
+        recv.recv_timeout(timeout).unwrap();
+        assert_eq!(recv.recv_timeout(timeout).unwrap().raw_id(), 0x200);
+
+        // a rolling counter updated before every transmission
+        s.set_hook(
+            h,
+            Box::new(|f: &mut Frame| f.data[0] = f.data[0].wrapping_add(1)),
+        );
+        let a = recv.recv_timeout(timeout).unwrap().data[0];
+        let b = recv.recv_timeout(timeout).unwrap().data[0];
+        assert!(b > a);
+
+        // frames are counted with how late they were sent
+        let stats = s.stats(h).unwrap();
+        assert!(stats.sent >= 6);
+        assert!(stats.max_jitter >= stats.mean_jitter);
+
+        s.set_interval(h, Duration::from_millis(50));
+        while recv.try_recv().is_ok() {}
+        let sent = s.stats(h).unwrap().sent;
+        thread::sleep(Duration::from_millis(20));
+        assert!(s.stats(h).unwrap().sent <= sent + 1);
+
+        s.remove(h);
+        assert!(s.stats(h).is_none());
+        thread::sleep(Duration::from_millis(20));
+        while recv.try_recv().is_ok() {}
+        assert!(recv.recv_timeout(Duration::from_millis(20)).is_err());
+    }
+}
@@ -1,36 +1,165 @@
 use crate::Error;
-use crate::{Frame, Interface};
+use crate::{BusError, BusErrorKind, BusState, Direction, Frame, Id, Interface, IsoTpSocket};
 use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
 use pyo3::exceptions;
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
+use pyo3::types::{PyBytes, PyDict};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// errors kept for recv_error, older errors are dropped once it is full
+const ERROR_QUEUE_CAPACITY: usize = 1024;
 
 #[pyclass(name = Interface)]
 struct PyInterface {
     i: Interface,
     rx_recv: Receiver<Frame>,
     rx_send: Sender<Frame>,
+    err_token: u64,
+    err_recv: Receiver<BusError>,
+    // errors already taken from err_recv to update bus_states
+    pending_errors: VecDeque<BusError>,
+    // errors dropped from pending_errors
+    pending_drops: u64,
+    bus_states: Vec<BusState>,
 }
-impl IntoPy<PyObject> for Frame {
+
+fn state_name(state: BusState) -> &'static str {
+    match state {
+        BusState::ErrorActive => "error_active",
+        BusState::ErrorWarning => "error_warning",
+        BusState::ErrorPassive => "error_passive",
+        BusState::BusOff => "bus_off",
+    }
+}
+
+fn kind_name(kind: BusErrorKind) -> &'static str {
+    match kind {
+        BusErrorKind::TxTimeout => "tx_timeout",
+        BusErrorKind::LostArbitration => "lost_arbitration",
+        BusErrorKind::NoAck => "no_ack",
+        BusErrorKind::BusOff => "bus_off",
+        BusErrorKind::Restarted => "restarted",
+        BusErrorKind::RxOverflow => "rx_overflow",
+        BusErrorKind::TxOverflow => "tx_overflow",
+        BusErrorKind::Bit => "bit",
+        BusErrorKind::Bit0 => "bit0",
+        BusErrorKind::Bit1 => "bit1",
+        BusErrorKind::Form => "form",
+        BusErrorKind::Stuff => "stuff",
+        BusErrorKind::Crc => "crc",
+        BusErrorKind::Overload => "overload",
+        BusErrorKind::Transceiver => "transceiver",
+        BusErrorKind::Other => "other",
+    }
+}
+
+impl IntoPy<PyObject> for BusError {
     fn into_py(self, py: Python) -> PyObject {
         let d = PyDict::new(py);
-        d.set_item("id", self.can_id).unwrap();
-        d.set_item("dlc", self.can_dlc).unwrap();
-        d.set_item("data", self.data.to_vec()).unwrap();
-        d.set_item("extended", self.ext).unwrap();
-        d.set_item("rtr", self.rtr).unwrap();
         d.set_item("channel", self.channel).unwrap();
-        d.set_item("loopback", self.loopback).unwrap();
-        match self.timestamp {
-            Some(t) => d
-                .set_item("timestamp", t.as_micros() as f32 / 1000000.0)
-                .unwrap(),
-            None => d.set_item("timestamp", 0).unwrap(),
+        let kinds: Vec<&str> = self.kinds.iter().map(|k| kind_name(*k)).collect();
+        d.set_item("kinds", kinds).unwrap();
+        match self.state {
+            Some(s) => d.set_item("state", state_name(s)).unwrap(),
+            None => d.set_item("state", py.None()).unwrap(),
         };
+        d.set_item("tx_error_count", self.tx_error_count).unwrap();
+        d.set_item("rx_error_count", self.rx_error_count).unwrap();
         d.to_object(py)
     }
 }
 
+impl PyInterface {
+    // take errors from the receiver, keeping them for recv_error
+    fn update_errors(&mut self) {
+        while let Ok(e) = self.err_recv.try_recv() {
+            self.track_state(&e);
+            if self.pending_errors.len() == ERROR_QUEUE_CAPACITY {
+                self.pending_errors.pop_front();
+                self.pending_drops += 1;
+            }
+            self.pending_errors.push_back(e);
+        }
+    }
+
+    fn track_state(&mut self, e: &BusError) {
+        if let (Some(state), Some(s)) = (e.state, self.bus_states.get_mut(e.channel as usize)) {
+            *s = state;
+        }
+    }
+}
+
+// a frame as a dict, for Python
+fn frame_dict(f: Frame, py: Python) -> PyObject {
+    let d = PyDict::new(py);
+    d.set_item("id", f.raw_id()).unwrap();
+    d.set_item("dlc", f.can_dlc).unwrap();
+    // classic frames always carry 8 bytes, as before FD support
+    let data = if f.fd { f.payload() } else { &f.data[..8] };
+    d.set_item("data", data.to_vec()).unwrap();
+    d.set_item("extended", f.is_extended()).unwrap();
+    d.set_item("rtr", f.rtr).unwrap();
+    d.set_item("fd", f.fd).unwrap();
+    d.set_item("brs", f.brs).unwrap();
+    d.set_item("esi", f.esi).unwrap();
+    d.set_item("channel", f.channel).unwrap();
+    d.set_item("loopback", f.is_echo()).unwrap();
+    match f.direction {
+        Direction::Rx => {
+            d.set_item("direction", "rx").unwrap();
+            d.set_item("echo_id", py.None()).unwrap();
+        }
+        Direction::TxEcho(id) => {
+            d.set_item("direction", "tx").unwrap();
+            d.set_item("echo_id", id).unwrap();
+        }
+    };
+    match f.timestamp {
+        Some(t) => d
+            .set_item("timestamp", t.as_micros() as f32 / 1000000.0)
+            .unwrap(),
+        None => d.set_item("timestamp", 0).unwrap(),
+    };
+    d.to_object(py)
+}
+
+#[pyclass(name = IsoTp)]
+struct PyIsoTp {
+    s: IsoTpSocket,
+}
+
+#[pymethods]
+impl PyIsoTp {
+    fn send(&mut self, data: &[u8]) -> PyResult<()> {
+        self.s.send(data)?;
+        Ok(())
+    }
+
+    fn recv(&mut self, py: Python, timeout_ms: u64) -> PyResult<Option<PyObject>> {
+        match self.s.recv(Duration::from_millis(timeout_ms)) {
+            Ok(data) => Ok(Some(PyBytes::new(py, &data).to_object(py))),
+            Err(Error::Timeout) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn request(&mut self, py: Python, data: &[u8], timeout_ms: u64) -> PyResult<PyObject> {
+        let resp = self.s.request(data, Duration::from_millis(timeout_ms))?;
+        Ok(PyBytes::new(py, &resp).to_object(py))
+    }
+
+    fn set_flow_control(&mut self, block_size: u8, st_min: u8) -> PyResult<()> {
+        self.s.set_flow_control(block_size, st_min);
+        Ok(())
+    }
+}
+
+// raised if a queue filled by the receive thread is disconnected
+fn thread_died() -> PyErr {
+    PyErr::new::<exceptions::RuntimeError, _>("device thread died")
+}
+
 impl std::convert::From<Error> for PyErr {
     fn from(err: Error) -> PyErr {
         PyErr::new::<exceptions::SystemError, _>(format!("{:?}", err))
@@ -49,10 +178,17 @@ impl PyInterface {
         }
 
         let (send, recv) = unbounded();
+        let (err_token, err_recv) = i.dispatcher.listen_errors(Some(ERROR_QUEUE_CAPACITY));
+        let bus_states = vec![BusState::ErrorActive; i.channels()];
         Ok(PyInterface {
             i: i,
             rx_recv: recv,
             rx_send: send,
+            err_token: err_token,
+            err_recv: err_recv,
+            pending_errors: VecDeque::new(),
+            pending_drops: 0,
+            bus_states: bus_states,
         })
     }
 
@@ -61,6 +197,22 @@ impl PyInterface {
         Ok(())
     }
 
+    fn set_bitrate_with_sample_point(
+        &mut self,
+        channel: usize,
+        bitrate: u32,
+        sample_point: f32,
+    ) -> PyResult<()> {
+        self.i
+            .set_bitrate_with_sample_point(channel, bitrate, sample_point)?;
+        Ok(())
+    }
+
+    fn set_data_bitrate(&mut self, channel: usize, bitrate: u32) -> PyResult<()> {
+        self.i.set_data_bitrate(channel, bitrate)?;
+        Ok(())
+    }
+
     fn set_bit_timing(
         &mut self,
         channel: usize,
@@ -86,6 +238,9 @@ impl PyInterface {
 
     fn start(&mut self) -> PyResult<()> {
         let rx = self.rx_send.clone();
+        for s in self.bus_states.iter_mut() {
+            *s = BusState::ErrorActive;
+        }
 
         self.i.start(move |f: Frame| {
             match rx.send(f) {
@@ -102,16 +257,62 @@ impl PyInterface {
         Ok(())
     }
 
-    fn recv(&self, timeout_ms: u64) -> PyResult<Option<Frame>> {
+    fn recv(&self, py: Python, timeout_ms: u64) -> PyResult<Option<PyObject>> {
         let f = match self
             .rx_recv
             .recv_timeout(std::time::Duration::from_millis(timeout_ms))
         {
             Ok(f) => f,
             Err(RecvTimeoutError::Timeout) => return Ok(None),
-            Err(RecvTimeoutError::Disconnected) => panic!("device thread died"),
+            Err(RecvTimeoutError::Disconnected) => return Err(thread_died()),
         };
-        Ok(Some(f))
+        Ok(Some(frame_dict(f, py)))
+    }
+
+    /// Wait for an error reported by the device, returning a dict describing the
+    /// error or None on timeout.
+    fn recv_error(&mut self, timeout_ms: u64) -> PyResult<Option<BusError>> {
+        if let Some(e) = self.pending_errors.pop_front() {
+            return Ok(Some(e));
+        }
+        match self
+            .err_recv
+            .recv_timeout(std::time::Duration::from_millis(timeout_ms))
+        {
+            Ok(e) => {
+                self.track_state(&e);
+                Ok(Some(e))
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(thread_died()),
+        }
+    }
+
+    /// Returns the number of errors which were dropped because they were not
+    /// taken with recv_error in time.
+    fn dropped_errors(&mut self) -> u64 {
+        self.update_errors();
+        self.i.dispatcher.dropped_errors(self.err_token) + self.pending_drops
+    }
+
+    /// Returns the transmit and receive error counters of a channel as a dict
+    /// with "tx_error_count" and "rx_error_count".
+    fn error_counters(&self, py: Python, channel: usize) -> PyResult<PyObject> {
+        let c = self.i.error_counters(channel)?;
+        let d = PyDict::new(py);
+        d.set_item("tx_error_count", c.tx).unwrap();
+        d.set_item("rx_error_count", c.rx).unwrap();
+        Ok(d.to_object(py))
+    }
+
+    /// Returns the last bus state reported by the device for a channel, one of
+    /// "error_active", "error_warning", "error_passive", or "bus_off".
+    fn bus_state(&mut self, channel: usize) -> PyResult<&'static str> {
+        self.update_errors();
+        match self.bus_states.get(channel) {
+            Some(s) => Ok(state_name(*s)),
+            None => Err(Error::InvalidChannel.into()),
+        }
     }
 
     fn send(
@@ -123,24 +324,35 @@ impl PyInterface {
         dlc: u8,
         data: Vec<u8>,
     ) -> PyResult<()> {
-        let mut data_array = [0u8; 8];
+        let mut data_array = [0u8; 64];
         for i in 0..dlc as usize {
             data_array[i] = data[i];
         }
+        let id = Id::new(id, ext).ok_or(Error::InvalidFrame)?;
         self.i.send(Frame {
-            can_id: id,
+            id: id,
             can_dlc: dlc,
-            ext: ext,
             rtr: rtr,
             data: data_array,
             channel: channel,
-            loopback: false,
+            direction: Direction::Rx,
             fd: false,
+            brs: false,
+            esi: false,
             timestamp: None,
+            raw_flags: None,
         })?;
         Ok(())
     }
 
+    /// Open an ISO-TP connection which sends on tx_id and receives on rx_id.
+    #[args(channel = "0")]
+    fn isotp(&self, tx_id: u32, rx_id: u32, channel: usize) -> PyResult<PyIsoTp> {
+        Ok(PyIsoTp {
+            s: self.i.isotp(channel, tx_id, rx_id)?,
+        })
+    }
+
     fn channel_count(&self) -> PyResult<usize> {
         Ok(self.i.channels())
     }
@@ -149,5 +361,6 @@ impl PyInterface {
 #[pymodule]
 fn cantact(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyInterface>()?;
+    m.add_class::<PyIsoTp>()?;
     Ok(())
 }
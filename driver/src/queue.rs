@@ -0,0 +1,196 @@
+//! Queues of received frames, and what happens when they fill up.
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, unbounded, Receiver, SendTimeoutError, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+
+// how often a sender waiting for room checks whether the interface stopped
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What happens to a received frame when its queue is full, set with
+/// `Interface::set_rx_queue`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Wait until there is room in the queue of `Interface::start_channel` or
+    /// `Interface::start_queued`. Receiving from the device stops meanwhile, so
+    /// if the application falls behind for long, frames are dropped once the
+    /// queue between the device and the receive thread is full as well. That
+    /// queue never waits, because the USB transfers are completed by the same
+    /// thread as the control requests which stop the interface.
+    #[default]
+    Block,
+    /// Drop the frame which does not fit.
+    DropNewest,
+    /// Drop the oldest frame in the queue to make room.
+    DropOldest,
+}
+
+/// Create a queue holding up to `capacity` items, or any number if None.
+pub(crate) fn channel<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    match capacity {
+        Some(capacity) => bounded(capacity),
+        None => unbounded(),
+    }
+}
+
+/// Add `item` to the queue without waiting, following `policy` if it is full.
+/// `recv` is the receiving end of the same queue, needed to drop the oldest
+/// item, without it the newest item is dropped. With `OverflowPolicy::Block`
+/// the item is dropped, like with `DropNewest`. Returns the number of items
+/// dropped.
+pub(crate) fn push<T>(
+    send: &Sender<T>,
+    recv: Option<&Receiver<T>>,
+    policy: OverflowPolicy,
+    item: T,
+) -> u64 {
+    match policy {
+        OverflowPolicy::Block | OverflowPolicy::DropNewest => match send.try_send(item) {
+            Err(TrySendError::Full(_)) => 1,
+            _ => 0,
+        },
+        OverflowPolicy::DropOldest => {
+            let recv = match recv {
+                Some(recv) => recv,
+                None => return push(send, None, OverflowPolicy::DropNewest, item),
+            };
+            let mut item = item;
+            let mut dropped = 0;
+            loop {
+                match send.try_send(item) {
+                    Err(TrySendError::Full(rejected)) => {
+                        // the queue may have been emptied meanwhile
+                        if recv.try_recv().is_ok() {
+                            dropped += 1;
+                        }
+                        item = rejected;
+                    }
+                    _ => return dropped,
+                }
+            }
+        }
+    }
+}
+
+/// Add `item` to the queue, waiting for room while `running` is set. Returns
+/// the number of items dropped, one if the interface stopped first. The item is
+/// discarded without counting it if nobody receives from the queue anymore.
+pub(crate) fn push_wait<T>(send: &Sender<T>, item: T, running: &RwLock<bool>) -> u64 {
+    let mut item = item;
+    loop {
+        match send.send_timeout(item, WAIT_POLL_INTERVAL) {
+            Err(SendTimeoutError::Timeout(rejected)) => {
+                if !*running.read().unwrap() {
+                    return 1;
+                }
+                item = rejected;
+            }
+            _ => return 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::gsusb::{HostFrame, GSUSB_RX_ECHO_ID};
+    use crate::device::mock::{self, Mock};
+    use crate::Frame;
+
+    #[test]
+    fn test_push() {
+        let (send, recv) = channel(Some(2));
+        for i in 0..3 {
+            push(&send, Some(&recv), OverflowPolicy::DropNewest, i);
+        }
+        assert_eq!(recv.try_iter().collect::<Vec<_>>(), [0, 1]);
+
+        let mut dropped = 0;
+        for i in 0..5 {
+            dropped += push(&send, Some(&recv), OverflowPolicy::DropOldest, i);
+        }
+        assert_eq!(dropped, 3);
+        assert_eq!(recv.try_iter().collect::<Vec<_>>(), [3, 4]);
+
+        // pushing never waits
+        for i in 0..3 {
+            dropped = push(&send, Some(&recv), OverflowPolicy::Block, i);
+        }
+        assert_eq!(dropped, 1);
+        assert_eq!(recv.len(), 2);
+
+        let (send, recv) = channel(None);
+        for i in 0..100 {
+            assert_eq!(push(&send, Some(&recv), OverflowPolicy::Block, i), 0);
+        }
+        assert_eq!(recv.len(), 100);
+    }
+
+    #[test]
+    fn test_push_wait() {
+        let running = RwLock::new(true);
+        let (send, recv) = channel(Some(1));
+        assert_eq!(push_wait(&send, 0, &running), 0);
+        let receiver = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            recv.iter().collect::<Vec<_>>()
+        });
+        // waits until the receiver takes the first item
+        assert_eq!(push_wait(&send, 1, &running), 0);
+        assert_eq!(push_wait(&send, 2, &running), 0);
+        drop(send);
+        assert_eq!(receiver.join().unwrap(), [0, 1, 2]);
+
+        // gives up once the interface stops
+        let (send, recv) = channel(Some(1));
+        *running.write().unwrap() = false;
+        assert_eq!(push_wait(&send, 0, &running), 0);
+        assert_eq!(push_wait(&send, 1, &running), 1);
+        // or the receiver is gone
+        *running.write().unwrap() = true;
+        drop(recv);
+        assert_eq!(push_wait(&send, 2, &running), 0);
+    }
+
+    #[test]
+    fn test_full_rx_queue() {
+        let mut i = mock::interface(Mock::default());
+        i.set_rx_queue(Some(2), OverflowPolicy::Block).unwrap();
+        let recv = i.start_channel().unwrap();
+        let rx = mock::device_queue(&i);
+        for id in 0..8 {
+            let f = Frame::new(id, &[1]).unwrap();
+            // the queue between the device and the receive thread never waits
+            rx.try_send(HostFrame::from_frame(&f, GSUSB_RX_ECHO_ID))
+                .ok();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        // the receive thread waits for room until the interface stops
+        let (done, stopped) = bounded(1);
+        std::thread::spawn(move || {
+            i.stop().unwrap();
+            done.send(i).unwrap();
+        });
+        let timeout = Duration::from_secs(10);
+        let i = stopped.recv_timeout(timeout).expect("stop hung");
+        let ids: Vec<u32> = recv.iter().map(|f| f.raw_id()).collect();
+        assert_eq!(ids, [0, 1]);
+        assert!(i.stats().queue_drops > 0);
+
+        // a receiver which is dropped doesn't hold up the receive thread
+        let mut i = i;
+        drop(i.start_channel().unwrap());
+        let rx = mock::device_queue(&i);
+        for id in 0..4 {
+            let f = Frame::new(id, &[1]).unwrap();
+            rx.send(HostFrame::from_frame(&f, GSUSB_RX_ECHO_ID))
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(rx.is_empty());
+        i.stop().unwrap();
+    }
+}
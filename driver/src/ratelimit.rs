@@ -0,0 +1,244 @@
+//! Rate limiting of sent frames with token buckets, so bursts of frames sent by
+//! the application can't crowd out periodic frames on the bus.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::live::frame_bits;
+use crate::{Frame, Id};
+
+// bits in the longest frame, a CAN FD frame with an extended ID and 64 bytes
+const MAX_FRAME_BITS: f64 = 67.0 + 64.0 * 8.0;
+// period over which the bus load limit can be exceeded by a burst
+const LOAD_WINDOW: f64 = 0.1;
+
+/// Limit on the rate of sent frames, see `Interface::set_rate_limit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Frames per second which can be sent in the long run.
+    pub rate: f64,
+    /// Frames which can be sent at once after a pause, at least one.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Returns a limit of `rate` frames per second, allowing bursts of `burst`
+    /// frames.
+    pub fn new(rate: f64, burst: u32) -> RateLimit {
+        RateLimit { rate, burst }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.rate.is_finite() && self.rate > 0.0
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // tokens added per second
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    // buckets start out full
+    fn new(rate: f64, capacity: f64, now: Instant) -> Bucket {
+        Bucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    fn from_limit(limit: RateLimit, now: Instant) -> Bucket {
+        Bucket::new(limit.rate, limit.burst.max(1) as f64, now)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    // time until `cost` tokens are available
+    fn wait(&self, cost: f64) -> Duration {
+        match self.tokens >= cost {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((cost - self.tokens) / self.rate),
+        }
+    }
+}
+
+// maximum bus load of a channel
+#[derive(Debug)]
+struct LoadLimit {
+    // fraction of the bitrate
+    load: f32,
+    // bits, None while the bitrate is unknown
+    bucket: Option<Bucket>,
+}
+
+impl LoadLimit {
+    fn new(load: f32, bitrate: u32, now: Instant) -> LoadLimit {
+        let rate = load as f64 * bitrate as f64;
+        LoadLimit {
+            load,
+            bucket: (bitrate > 0)
+                .then(|| Bucket::new(rate, (rate * LOAD_WINDOW).max(MAX_FRAME_BITS), now)),
+        }
+    }
+}
+
+/// Rate limits of an `Interface`, shared by everything sending frames on it.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    global: Option<Bucket>,
+    ids: HashMap<(u8, Id), Bucket>,
+    load: Vec<Option<LoadLimit>>,
+    bitrates: Vec<u32>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(channels: usize) -> RateLimiter {
+        RateLimiter {
+            global: None,
+            ids: HashMap::new(),
+            load: (0..channels).map(|_| None).collect(),
+            bitrates: vec![0; channels],
+        }
+    }
+
+    pub(crate) fn set_global(&mut self, limit: Option<RateLimit>, now: Instant) {
+        self.global = limit.map(|l| Bucket::from_limit(l, now));
+    }
+
+    pub(crate) fn set_id(&mut self, channel: u8, id: Id, limit: Option<RateLimit>, now: Instant) {
+        match limit {
+            Some(l) => self.ids.insert((channel, id), Bucket::from_limit(l, now)),
+            None => self.ids.remove(&(channel, id)),
+        };
+    }
+
+    pub(crate) fn set_max_load(&mut self, channel: usize, load: Option<f32>, now: Instant) {
+        let bitrate = self.bitrates[channel];
+        self.load[channel] = load.map(|load| LoadLimit::new(load, bitrate, now));
+    }
+
+    /// Update the bitrate a bus load limit of the channel is relative to.
+    pub(crate) fn set_bitrate(&mut self, channel: usize, bitrate: u32, now: Instant) {
+        self.bitrates[channel] = bitrate;
+        if let Some(l) = &self.load[channel] {
+            self.load[channel] = Some(LoadLimit::new(l.load, bitrate, now));
+        }
+    }
+
+    // the buckets a frame takes tokens from, with the number of tokens
+    fn buckets(&mut self, f: &Frame) -> Vec<(&mut Bucket, f64)> {
+        let mut buckets = vec![];
+        if let Some(b) = self.global.as_mut() {
+            buckets.push((b, 1.0));
+        }
+        if let Some(b) = self.ids.get_mut(&(f.channel, f.id)) {
+            buckets.push((b, 1.0));
+        }
+        let load = self.load.get_mut(f.channel as usize);
+        if let Some(b) = load
+            .and_then(|l| l.as_mut())
+            .and_then(|l| l.bucket.as_mut())
+        {
+            buckets.push((b, frame_bits(f) as f64));
+        }
+        buckets
+    }
+
+    /// Take the tokens for sending `f` if all of its limits allow it now,
+    /// otherwise return how long to wait before trying again.
+    pub(crate) fn try_take(&mut self, f: &Frame, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets(f);
+        let mut wait = Duration::ZERO;
+        for (b, cost) in buckets.iter_mut() {
+            b.refill(now);
+            wait = wait.max(b.wait(*cost));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for (b, cost) in buckets {
+            b.tokens -= cost;
+        }
+        Ok(())
+    }
+
+    /// Take the tokens for sending `f` even if that exceeds its limits, for
+    /// frames which are not held back. Other frames then wait longer.
+    pub(crate) fn take(&mut self, f: &Frame, now: Instant) {
+        for (b, cost) in self.buckets(f) {
+            b.refill(now);
+            b.tokens -= cost;
+        }
+    }
+
+    /// Give back the tokens taken for `f` when it could not be handed to the
+    /// device after all.
+    pub(crate) fn refund(&mut self, f: &Frame, now: Instant) {
+        for (b, cost) in self.buckets(f) {
+            b.refill(now);
+            b.tokens = (b.tokens + cost).min(b.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let now = Instant::now();
+        let ms = |n| now + Duration::from_millis(n);
+        let f = Frame::new(0x123, &[0; 8]).unwrap();
+        let other = Frame::new(0x456, &[0; 8]).unwrap();
+        let mut l = RateLimiter::new(2);
+        assert!(l.try_take(&f, now).is_ok());
+
+        // 100 frames per second in bursts of 2
+        l.set_global(Some(RateLimit::new(100.0, 2)), now);
+        assert!(l.try_take(&f, now).is_ok());
+        assert!(l.try_take(&other, now).is_ok());
+        assert_eq!(l.try_take(&f, now), Err(Duration::from_millis(10)));
+        assert!(l.try_take(&f, ms(10)).is_ok());
+        // a frame which wasn't sent doesn't count
+        assert!(l.try_take(&f, ms(10)).is_err());
+        l.refund(&f, ms(10));
+        assert!(l.try_take(&f, ms(10)).is_ok());
+        // frames which are not held back still count
+        l.take(&f, ms(20));
+        assert!(l.try_take(&f, ms(20)).is_err());
+        l.set_global(None, now);
+
+        // one ID is limited to 10 frames per second, the extended ID with the
+        // same number is not
+        l.set_id(0, f.id, Some(RateLimit::new(10.0, 1)), now);
+        assert!(l.try_take(&f, now).is_ok());
+        assert_eq!(l.try_take(&f, ms(50)), Err(Duration::from_millis(50)));
+        assert!(l.try_take(&other, ms(50)).is_ok());
+        let ext = Frame {
+            id: Id::new(0x123, true).unwrap(),
+            ..f.clone()
+        };
+        assert!(l.try_take(&ext, ms(50)).is_ok());
+        assert!(l.try_take(&ext, ms(50)).is_ok());
+        l.set_id(0, f.id, None, now);
+
+        // 10% of 125 kbit/s is 12500 bit/s, a frame with 8 bytes is 111 bits
+        l.set_max_load(0, Some(0.1), now);
+        assert!(l.try_take(&f, now).is_ok());
+        l.set_bitrate(0, 125_000, now);
+        let sent = (0..100).take_while(|_| l.try_take(&f, now).is_ok()).count();
+        assert_eq!(sent, 1250 / 111);
+        assert!(l.try_take(&f, ms(9)).is_ok());
+    }
+}
@@ -0,0 +1,123 @@
+//! Reconnecting to a device after it was unplugged.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+
+use crate::device::gsusb::*;
+use crate::device::{Device, HostFrame, Selector, UsbContext};
+use crate::filter::Filters;
+use crate::queue::OverflowPolicy;
+use crate::{BitTiming, Error};
+
+// time between attempts to reopen an unplugged device
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Change in the connection to the device, reported to the callback registered
+/// with `Interface::on_connection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The device was unplugged. Frames queued for transmission are lost. Unless
+    /// auto-reconnect is enabled with `Interface::set_auto_reconnect`, the
+    /// interface stops.
+    Disconnected,
+    /// The device was plugged in again and has been restored to the
+    /// configuration it had when it was unplugged.
+    Reconnected,
+}
+
+pub(crate) type ConnectionCallback = Mutex<Option<Box<dyn FnMut(ConnectionEvent) + Send>>>;
+
+/// Bit timings last written to a channel, restored after reconnecting.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ChannelTiming {
+    pub(crate) nominal: Option<BitTiming>,
+    pub(crate) data: Option<BitTiming>,
+}
+
+/// The configuration of a started device, used to bring a reopened device back
+/// into the same state.
+pub(crate) struct Restore {
+    pub(crate) serial: Option<String>,
+    // shared with the interface, which updates it when timings are changed
+    // while running
+    pub(crate) timings: Arc<Mutex<Vec<ChannelTiming>>>,
+    // mode flags of each channel, None for disabled channels
+    pub(crate) flags: Vec<Option<u32>>,
+    // additional USB vendor and product IDs to look for
+    pub(crate) usb_ids: Vec<(u16, u16)>,
+    // capacity and overflow policy of the queue of received frames
+    pub(crate) rx_queue: (Option<usize>, OverflowPolicy),
+    // receive filters, and the channels which have them in hardware
+    pub(crate) filters: Arc<Mutex<Filters>>,
+    pub(crate) hw_filter: Vec<bool>,
+}
+
+impl Restore {
+    /// Put the enabled channels on bus.
+    pub(crate) fn start(&self, dev: &mut Device) -> Result<(), Error> {
+        for (i, flags) in self.flags.iter().enumerate() {
+            if let Some(flags) = flags {
+                let mode = Mode {
+                    mode: CanMode::Start as u32,
+                    flags: *flags,
+                };
+                dev.set_mode(i as u16, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    // write the bit timings and start the channels of a reopened device
+    fn configure(&self, dev: &mut Device) -> Result<(), Error> {
+        dev.set_rx_queue(self.rx_queue.0, self.rx_queue.1);
+        let timings = self.timings.lock().unwrap().clone();
+        for (i, t) in timings.iter().enumerate() {
+            if let Some(bt) = t.nominal {
+                dev.set_bit_timing(i as u16, bt)?;
+            }
+            if let Some(bt) = t.data {
+                dev.set_data_bit_timing(i as u16, bt)?;
+            }
+        }
+        for (i, hw_filter) in self.hw_filter.iter().enumerate() {
+            if *hw_filter {
+                let table = self.filters.lock().unwrap().hw_table(i as u8);
+                dev.set_filters(i as u16, &table)?;
+            }
+        }
+        self.start(dev)
+    }
+
+    /// Wait for the device with the same serial number to be plugged in again,
+    /// then configure it, start it, and swap it in for the old one in `dev`.
+    /// Returns the queue of frames received by the new device, or None if
+    /// reconnecting was disabled or the interface stopped while waiting.
+    pub(crate) fn reconnect(
+        &self,
+        dev: &Mutex<Device>,
+        running: &RwLock<bool>,
+        enabled: &RwLock<bool>,
+    ) -> Option<Receiver<HostFrame>> {
+        // without a serial number, another device could be mistaken for this one
+        let serial = self.serial.as_deref()?;
+        while *running.read().unwrap() && *enabled.read().unwrap() {
+            let new = UsbContext::new()
+                .and_then(|ctx| Device::new(ctx, Selector::Serial(serial), &self.usb_ids))
+                .map_err(Error::from)
+                .and_then(|mut d| self.configure(&mut d).map(|_| d));
+            if let Ok(new) = new {
+                // dropping the old device closes it
+                let mut dev = dev.lock().unwrap();
+                *dev = new;
+                if dev.start_transfers().is_ok() {
+                    return Some(dev.can_rx_recv.clone());
+                }
+            }
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+        None
+    }
+}
@@ -0,0 +1,316 @@
+//! Scripted transmission of frames with delays and waits for responses, run by
+//! the driver on a thread of its own.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{after, bounded, select, Receiver, RecvTimeoutError, Sender};
+
+use crate::dispatch::Dispatcher;
+use crate::tx::Transmitter;
+use crate::{Direction, Error, Frame};
+
+#[derive(Debug, Clone)]
+enum Step {
+    Send(Duration, Frame),
+    WaitFor {
+        channel: u8,
+        id: u32,
+        timeout: Duration,
+    },
+    Nested(Sequence),
+}
+
+/// An ordered list of frames to send with delays between them, repeated a
+/// number of times. Run with `Interface::run_sequence`.
+///
+/// A step can wait for a response before the sequence goes on, for wake-up or
+/// keep-alive handshakes. Sequences can be nested, each with its own number of
+/// repetitions.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use cantact::{Frame, Interface, Sequence};
+/// # let mut i = Interface::new()?;
+/// let ms = Duration::from_millis;
+/// // press and release a key three times
+/// let press = Sequence::new()
+///     .send(ms(0), Frame::new(0x3C0, &[0x01])?)
+///     .send(ms(100), Frame::new(0x3C0, &[0x00])?)
+///     .send(ms(400), Frame::new(0x3C0, &[0x00])?)
+///     .repeat(3);
+/// // wake the ECU up first, and wait for it to answer
+/// let seq = Sequence::new()
+///     .send(ms(0), Frame::new(0x100, &[0xFF])?)
+///     .wait_for(0, 0x101, ms(500))
+///     .then(press);
+/// i.start(|_| {})?;
+/// i.run_sequence(seq)?.wait()?;
+/// # Ok::<(), cantact::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    steps: Vec<Step>,
+    // None repeats forever
+    count: Option<u32>,
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence::new()
+    }
+}
+
+impl Sequence {
+    /// Returns an empty sequence, which runs once.
+    pub fn new() -> Sequence {
+        Sequence {
+            steps: vec![],
+            count: Some(1),
+        }
+    }
+
+    /// Wait for `delay`, then send `frame`.
+    pub fn send(mut self, delay: Duration, frame: Frame) -> Sequence {
+        self.steps.push(Step::Send(delay, frame));
+        self
+    }
+
+    /// Wait up to `timeout` for a frame with ID `id` to be received on
+    /// `channel`. If it does not arrive, the sequence stops with
+    /// `Error::Timeout`.
+    ///
+    /// The wait starts when the frame before it is sent, so a fast response is
+    /// not missed.
+    pub fn wait_for(mut self, channel: u8, id: u32, timeout: Duration) -> Sequence {
+        self.steps.push(Step::WaitFor {
+            channel,
+            id,
+            timeout,
+        });
+        self
+    }
+
+    /// Run another sequence as the next step, with its own repetitions.
+    pub fn then(mut self, sequence: Sequence) -> Sequence {
+        self.steps.push(Step::Nested(sequence));
+        self
+    }
+
+    /// Run the steps `count` times.
+    pub fn repeat(mut self, count: u32) -> Sequence {
+        self.count = Some(count);
+        self
+    }
+
+    /// Run the steps until the sequence is stopped.
+    pub fn forever(mut self) -> Sequence {
+        self.count = None;
+        self
+    }
+
+    /// Returns the frames sent by the sequence and its nested sequences.
+    pub(crate) fn frames(&self) -> Vec<&Frame> {
+        let mut frames = vec![];
+        for step in self.steps.iter() {
+            match step {
+                Step::Send(_, f) => frames.push(f),
+                Step::WaitFor { .. } => {}
+                Step::Nested(seq) => frames.extend(seq.frames()),
+            }
+        }
+        frames
+    }
+}
+
+/// A sequence running on the driver, returned by `Interface::run_sequence`.
+/// Dropping the handle stops the sequence.
+#[derive(Debug)]
+pub struct SequenceHandle {
+    // dropped to stop the sequence
+    stop: Option<Sender<()>>,
+    thread: Option<thread::JoinHandle<Result<(), Error>>>,
+}
+
+impl SequenceHandle {
+    /// Returns true once the sequence has finished or failed.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+
+    /// Wait for the sequence to finish. Returns the error which stopped it, such
+    /// as `Error::Timeout` if a response did not arrive. Sequences repeating
+    /// forever never finish.
+    pub fn wait(mut self) -> Result<(), Error> {
+        match self.thread.take().map(|t| t.join()) {
+            Some(Ok(result)) => result,
+            // a panic on the sequence thread
+            Some(Err(e)) => Err(Error::CallbackPanicked(crate::panic_message(e))),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop the sequence, waiting until it has stopped. A frame which is being
+    /// sent is finished first.
+    pub fn stop(mut self) {
+        self.stop.take();
+        if let Some(t) = self.thread.take() {
+            t.join().ok();
+        }
+    }
+}
+
+impl Drop for SequenceHandle {
+    fn drop(&mut self) {
+        // wakes the sequence thread, which then returns
+        self.stop.take();
+    }
+}
+
+struct Runner {
+    tx: Transmitter,
+    dispatcher: Arc<Dispatcher>,
+    stop: Receiver<()>,
+}
+
+impl Runner {
+    // sleep for `delay`, returning Err(Error::NotRunning) if stopped meanwhile
+    fn sleep(&self, delay: Duration) -> Result<(), Error> {
+        match self.stop.recv_timeout(delay) {
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            _ => Err(Error::NotRunning),
+        }
+    }
+
+    fn wait(&self, channel: u8, id: u32) -> (u64, Receiver<Frame>) {
+        self.dispatcher.wait_for(move |f: &Frame| {
+            f.channel == channel && f.raw_id() == id && f.direction == Direction::Rx
+        })
+    }
+
+    fn run(&self, seq: &Sequence) -> Result<(), Error> {
+        let mut n = 0;
+        while seq.count.is_none_or(|count| n < count) {
+            self.run_steps(&seq.steps)?;
+            n += 1;
+        }
+        Ok(())
+    }
+
+    fn run_steps(&self, steps: &[Step]) -> Result<(), Error> {
+        // registered before the frame the wait follows is sent
+        let mut pending: Option<(u64, Receiver<Frame>)> = None;
+        for (i, step) in steps.iter().enumerate() {
+            match step {
+                Step::Send(delay, f) => {
+                    self.sleep(*delay)?;
+                    if let Some(Step::WaitFor { channel, id, .. }) = steps.get(i + 1) {
+                        pending = Some(self.wait(*channel, *id));
+                    }
+                    if let Err(e) = self.tx.send(f) {
+                        if let Some((token, _)) = pending {
+                            self.dispatcher.cancel(token);
+                        }
+                        return Err(e);
+                    }
+                }
+                Step::WaitFor {
+                    channel,
+                    id,
+                    timeout,
+                } => {
+                    let (token, response) =
+                        pending.take().unwrap_or_else(|| self.wait(*channel, *id));
+                    let timer = after(*timeout);
+                    let result = select! {
+                        recv(response) -> f => f.map(|_| ()).map_err(|_| Error::NotRunning),
+                        recv(timer) -> _ => Err(Error::Timeout),
+                        recv(self.stop) -> _ => Err(Error::NotRunning),
+                    };
+                    self.dispatcher.cancel(token);
+                    result?;
+                }
+                Step::Nested(seq) => self.run(seq)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Start running a sequence on a thread of its own.
+pub(crate) fn start(seq: Sequence, tx: Transmitter, dispatcher: Arc<Dispatcher>) -> SequenceHandle {
+    let (stop, stop_recv) = bounded(0);
+    let runner = Runner {
+        tx,
+        dispatcher,
+        stop: stop_recv,
+    };
+    let thread = thread::spawn(move || runner.run(&seq));
+    SequenceHandle {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::{self, Mock};
+
+    #[test]
+    fn test_sequence() {
+        let mut i = mock::interface(Mock::default());
+        let ms = Duration::from_millis;
+        let f = |id| Frame::new(id, &[]).unwrap();
+        assert!(matches!(
+            i.run_sequence(Sequence::new()),
+            Err(Error::NotRunning)
+        ));
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        i.start(move |f| send.send(f).unwrap()).unwrap();
+        let seq = Sequence::new()
+            .send(ms(0), f(0x100))
+            .then(Sequence::new().send(ms(1), f(0x200)).repeat(2))
+            .repeat(2);
+        i.run_sequence(seq).unwrap().wait().unwrap();
+        let ids: Vec<u32> = (0..6)
+            .map(|_| recv.recv_timeout(ms(1000)).unwrap().raw_id())
+            .collect();
+        assert_eq!(ids, [0x100, 0x200, 0x200, 0x100, 0x200, 0x200]);
+
+        // sent frames are echoes, not responses
+        let seq = Sequence::new()
+            .send(ms(0), f(0x100))
+            .wait_for(0, 0x100, ms(20));
+        assert!(matches!(
+            i.run_sequence(seq).unwrap().wait(),
+            Err(Error::Timeout)
+        ));
+
+        // a response received while waiting lets the sequence go on
+        let seq = Sequence::new()
+            .send(ms(0), f(0x100))
+            .wait_for(0, 0x101, ms(1000))
+            .send(ms(0), f(0x102));
+        let handle = i.run_sequence(seq).unwrap();
+        std::thread::sleep(ms(20));
+        mock::receive(&i, &f(0x101));
+        handle.wait().unwrap();
+        // the echo of the last frame may still be on its way
+        let mut ids = vec![];
+        while ids.last() != Some(&0x102) {
+            ids.push(recv.recv_timeout(ms(1000)).unwrap().raw_id());
+        }
+        assert_eq!(&ids[ids.len() - 3..], [0x100, 0x101, 0x102]);
+
+        // stopping a sequence which runs forever
+        let seq = Sequence::new().send(ms(5), f(0x100)).forever();
+        let handle = i.run_sequence(seq).unwrap();
+        std::thread::sleep(ms(20));
+        assert!(!handle.is_finished());
+        handle.stop();
+        i.stop().unwrap();
+    }
+}
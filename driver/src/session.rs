@@ -0,0 +1,385 @@
+//! Logging sessions producing self-describing capture bundles.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::capture::{CandumpWriter, DeltaWriter, Index};
+use crate::{Channel, Error, Frame, Interface, TimestampMode};
+
+/// Name of the manifest written to the bundle directory by `Session::finish`.
+pub const MANIFEST: &str = "session.toml";
+
+/// A destination for the frames logged by a `Session`.
+pub trait Sink: Send {
+    /// Write a frame.
+    fn write(&mut self, f: &Frame) -> io::Result<()>;
+    /// Flush any buffered frames.
+    fn flush(&mut self) -> io::Result<()>;
+    /// Returns the index of the frames written, if the sink builds one. It is
+    /// saved next to the log when the session finishes.
+    fn index(&self) -> Option<&Index> {
+        None
+    }
+}
+
+impl<W: Write + Send> Sink for CandumpWriter<W> {
+    fn write(&mut self, f: &Frame) -> io::Result<()> {
+        CandumpWriter::write(self, f)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        CandumpWriter::flush(self)
+    }
+    fn index(&self) -> Option<&Index> {
+        Some(CandumpWriter::index(self))
+    }
+}
+
+impl<W: Write + Send> Sink for DeltaWriter<W> {
+    fn write(&mut self, f: &Frame) -> io::Result<()> {
+        DeltaWriter::write(self, f)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        DeltaWriter::flush(self)
+    }
+    fn index(&self) -> Option<&Index> {
+        Some(DeltaWriter::index(self))
+    }
+}
+
+/// Description of a session, saved in the manifest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Vehicle or device under test.
+    pub vehicle: Option<String>,
+    /// Person running the session.
+    pub operator: Option<String>,
+    /// Free-form notes.
+    pub notes: Option<String>,
+}
+
+/// A labelled point in time during a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    /// Label given to `Session::add_marker`, or the trigger which fired.
+    pub label: String,
+    /// Time on the clock of the frame timestamps, given by the manifest's
+    /// `timestamp_mode`.
+    pub timestamp: Duration,
+}
+
+/// Contents of the manifest of a finished session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Start of the session, in seconds since the Unix epoch.
+    pub started: u64,
+    /// Length of the session.
+    pub duration: Duration,
+    /// Number of frames logged.
+    pub frames: u64,
+    /// Clock of the timestamps of the logged frames and of the markers.
+    pub timestamp_mode: TimestampMode,
+    /// File names of the logs in the bundle, relative to the manifest.
+    pub logs: Vec<String>,
+    /// Description of the session.
+    pub metadata: Metadata,
+    /// Configuration of each channel of the interface.
+    pub channels: Vec<Channel>,
+    /// Markers added during the session, in order.
+    pub markers: Vec<Marker>,
+}
+
+impl Manifest {
+    /// Load the manifest of a session bundle.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Manifest, Error> {
+        let text = fs::read_to_string(dir.as_ref().join(MANIFEST))?;
+        toml::from_str(&text).map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    fn to_toml(&self) -> io::Result<String> {
+        // going through a Value puts plain values before tables, as TOML requires
+        toml::Value::try_from(self)
+            .map(|v| v.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+type Predicate = Box<dyn Fn(&Frame) -> bool + Send>;
+
+// state shared with the receive thread
+struct Log {
+    sinks: Vec<Box<dyn Sink>>,
+    start: Option<Predicate>,
+    stop: Option<Predicate>,
+    logging: bool,
+    frames: u64,
+    markers: Vec<Marker>,
+    // first write error, logging stops once a sink fails
+    error: Option<io::Error>,
+}
+
+impl Log {
+    fn new() -> Log {
+        Log {
+            sinks: vec![],
+            start: None,
+            stop: None,
+            logging: true,
+            frames: 0,
+            markers: vec![],
+            error: None,
+        }
+    }
+
+    fn frame(&mut self, f: &Frame) {
+        if self.error.is_some() {
+            return;
+        }
+        let t = f.timestamp.unwrap_or_default();
+        if !self.logging && self.start.as_ref().map(|p| p(f)).unwrap_or(false) {
+            self.logging = true;
+            self.mark(t, "start trigger");
+        }
+        if !self.logging {
+            return;
+        }
+        for s in self.sinks.iter_mut() {
+            if let Err(e) = s.write(f) {
+                self.error = Some(e);
+                return;
+            }
+        }
+        self.frames += 1;
+        if self.stop.as_ref().map(|p| p(f)).unwrap_or(false) {
+            self.logging = false;
+            self.mark(t, "stop trigger");
+        }
+    }
+
+    fn mark(&mut self, timestamp: Duration, label: &str) {
+        self.markers.push(Marker {
+            timestamp,
+            label: label.to_string(),
+        });
+    }
+}
+
+/// A logging session: an interface, a description of what is being logged,
+/// and the logs it is written to.
+///
+/// All files of a session are written to one directory. When the session
+/// finishes, the indexes of the logs and a manifest (`session.toml`) holding the
+/// metadata, channel configuration, and markers are saved next to the logs, so
+/// the bundle can be understood without knowing how it was recorded.
+pub struct Session {
+    i: Interface,
+    dir: PathBuf,
+    metadata: Metadata,
+    logs: Vec<String>,
+    log: Arc<Mutex<Log>>,
+    started: Option<(SystemTime, Instant)>,
+}
+
+impl Session {
+    /// Create a session writing its bundle to `dir`. The directory is created if
+    /// it does not exist.
+    pub fn create(i: Interface, dir: impl AsRef<Path>) -> Result<Session, Error> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Session {
+            i,
+            dir: dir.as_ref().to_path_buf(),
+            metadata: Metadata::default(),
+            logs: vec![],
+            log: Arc::new(Mutex::new(Log::new())),
+            started: None,
+        })
+    }
+
+    /// Returns the underlying interface, for configuration and sending.
+    pub fn interface(&mut self) -> &mut Interface {
+        &mut self.i
+    }
+
+    /// Returns the description of the session, to be filled in before it
+    /// finishes.
+    pub fn metadata(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    /// Log to a candump file in the bundle directory.
+    pub fn log_candump(&mut self, name: &str) -> Result<(), Error> {
+        let w = BufWriter::new(File::create(self.dir.join(name))?);
+        self.add_log(name, CandumpWriter::new(w));
+        Ok(())
+    }
+
+    /// Log to a delta-encoded capture in the bundle directory.
+    pub fn log_delta(&mut self, name: &str) -> Result<(), Error> {
+        let w = BufWriter::new(File::create(self.dir.join(name))?);
+        self.add_log(name, DeltaWriter::new(w)?);
+        Ok(())
+    }
+
+    /// Log to a custom sink. `name` is recorded in the manifest, and is the file
+    /// name used for the index of the sink.
+    pub fn add_log(&mut self, name: &str, sink: impl Sink + 'static) {
+        self.logs.push(name.to_string());
+        self.log.lock().unwrap().sinks.push(Box::new(sink));
+    }
+
+    /// Only start logging once a frame matching `trigger` is received. The
+    /// matching frame is the first one logged.
+    pub fn start_when(&mut self, trigger: impl Fn(&Frame) -> bool + Send + 'static) {
+        let mut log = self.log.lock().unwrap();
+        log.start = Some(Box::new(trigger));
+        if self.started.is_none() {
+            log.logging = false;
+        }
+    }
+
+    /// Stop logging after a frame matching `trigger` is received. If a start
+    /// trigger is set, logging resumes when it matches again.
+    pub fn stop_when(&mut self, trigger: impl Fn(&Frame) -> bool + Send + 'static) {
+        self.log.lock().unwrap().stop = Some(Box::new(trigger));
+    }
+
+    /// Start the interface and begin logging.
+    pub fn start(&mut self) -> Result<(), Error> {
+        let log = Arc::clone(&self.log);
+        self.started = Some((SystemTime::now(), Instant::now()));
+        self.i.start(move |f| log.lock().unwrap().frame(&f))
+    }
+
+    /// Record a marker at the current time, such as `"pressed brake"`.
+    pub fn add_marker(&mut self, label: &str) {
+        let t = self.i.timestamp_now().unwrap_or_default();
+        self.log.lock().unwrap().mark(t, label);
+    }
+
+    /// Returns the number of frames logged so far.
+    pub fn frames(&self) -> u64 {
+        self.log.lock().unwrap().frames
+    }
+
+    /// Stop the interface, flush the logs, and write the indexes and manifest.
+    /// Returns the interface for further use.
+    ///
+    /// If writing to a log failed during the session, the error is returned
+    /// here. The manifest is written either way.
+    pub fn finish(mut self) -> Result<Interface, Error> {
+        if self.started.is_some() {
+            self.i.stop()?;
+        }
+        let duration = self.elapsed();
+        let mut log = self.log.lock().unwrap();
+        let mut result = match log.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+        for (name, sink) in self.logs.iter().zip(log.sinks.iter_mut()) {
+            let saved = sink.flush().and_then(|_| match sink.index() {
+                Some(index) => {
+                    let w = BufWriter::new(File::create(self.dir.join(format!("{}.idx", name)))?);
+                    index.write_to(w)
+                }
+                None => Ok(()),
+            });
+            result = result.and(saved);
+        }
+
+        let manifest = Manifest {
+            started: self
+                .started
+                .and_then(|(t, _)| t.duration_since(UNIX_EPOCH).ok())
+                .map(|t| t.as_secs())
+                .unwrap_or(0),
+            duration,
+            frames: log.frames,
+            timestamp_mode: self.i.timestamp_mode(),
+            metadata: self.metadata.clone(),
+            channels: self.i.channels.clone(),
+            logs: self.logs.clone(),
+            markers: log.markers.clone(),
+        };
+        drop(log);
+        let text = manifest.to_toml()?;
+        fs::write(self.dir.join(MANIFEST), text)?;
+
+        result?;
+        Ok(self.i)
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.started.map(|(_, t)| t.elapsed()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::{self, Mock};
+    use crate::Id;
+
+    #[test]
+    fn test_triggers() {
+        let mut log = Log::new();
+        log.sinks.push(Box::new(CandumpWriter::new(vec![])));
+        log.start = Some(Box::new(|f: &Frame| f.raw_id() == 0x100));
+        log.stop = Some(Box::new(|f: &Frame| f.raw_id() == 0x200));
+        log.logging = false;
+
+        for (n, id) in [0x1, 0x100, 0x2, 0x200, 0x3, 0x100].iter().enumerate() {
+            log.frame(&Frame {
+                id: Id::new(*id, false).unwrap(),
+                timestamp: Some(Duration::from_millis(n as u64)),
+                ..Default::default()
+            });
+        }
+        // 0x100, 0x2, 0x200, then 0x100 again
+        assert_eq!(log.frames, 4);
+        let labels: Vec<_> = log.markers.iter().map(|m| m.label.as_str()).collect();
+        assert_eq!(labels, ["start trigger", "stop trigger", "start trigger"]);
+        assert_eq!(log.markers[1].timestamp, Duration::from_millis(3));
+
+        let manifest = Manifest {
+            started: 0,
+            duration: Duration::from_secs(1),
+            frames: log.frames,
+            timestamp_mode: TimestampMode::Monotonic,
+            metadata: Metadata {
+                vehicle: Some("test bench".to_string()),
+                ..Default::default()
+            },
+            channels: vec![],
+            logs: vec!["can.log".to_string()],
+            markers: log.markers.clone(),
+        };
+        let text = manifest.to_toml().unwrap();
+        let loaded: Manifest = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.metadata, manifest.metadata);
+        assert_eq!(loaded.markers, manifest.markers);
+    }
+
+    #[test]
+    fn test_marker_clock() {
+        let dir = std::env::temp_dir().join(format!("cantact-session-{}", std::process::id()));
+        let mut i = mock::interface(Mock::default());
+        i.set_timestamp_mode(TimestampMode::WallClock).unwrap();
+        let mut s = Session::create(i, &dir).unwrap();
+        s.start().unwrap();
+        s.add_marker("pressed brake");
+        s.finish().unwrap();
+
+        // markers are on the same clock as frames, here since the Unix epoch
+        let manifest = Manifest::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(manifest.timestamp_mode, TimestampMode::WallClock);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let marker = manifest.markers[0].timestamp;
+        assert!(marker <= now && now - marker < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,170 @@
+//! Encoding and decoding of the SLCAN (Lawicel) serial-line CAN protocol.
+//!
+//! This is used to expose a CANtact device to tools which only speak SLCAN.
+//! Commands and frames are ASCII lines terminated by a carriage return.
+
+use crate::{Bitrate, Frame, Id};
+
+/// Response to a command which succeeded.
+pub const OK: &str = "\r";
+/// Response to a command which failed.
+pub const ERROR: &str = "\x07";
+
+/// A command sent by an SLCAN host.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Open the channel (`O`).
+    Open,
+    /// Open the channel in listen only mode (`L`).
+    ListenOnly,
+    /// Close the channel (`C`).
+    Close,
+    /// Set one of the standard bitrates (`S0` to `S8`).
+    Bitrate(Bitrate),
+    /// Transmit a frame (`t`, `T`, `r`, or `R`).
+    Transmit(Frame),
+    /// Enable or disable timestamps on received frames (`Z0` or `Z1`).
+    Timestamps(bool),
+    /// Read the hardware and software version (`V`).
+    Version,
+    /// Read the serial number (`N`).
+    SerialNumber,
+    /// Read the status flags (`F`).
+    Status,
+}
+
+/// Parse a single command line, without the trailing carriage return.
+///
+/// Returns `None` if the line is not a valid command.
+pub fn parse_command(line: &str) -> Option<Command> {
+    let mut chars = line.chars();
+    let cmd = chars.next()?;
+    let args = chars.as_str();
+    match cmd {
+        'O' => Some(Command::Open),
+        'L' => Some(Command::ListenOnly),
+        'C' => Some(Command::Close),
+        'V' => Some(Command::Version),
+        'N' => Some(Command::SerialNumber),
+        'F' => Some(Command::Status),
+        'Z' => match args {
+            "0" => Some(Command::Timestamps(false)),
+            "1" => Some(Command::Timestamps(true)),
+            _ => None,
+        },
+        'S' => {
+            let b = match args {
+                "0" => Bitrate::K10,
+                "1" => Bitrate::K20,
+                "2" => Bitrate::K50,
+                "3" => Bitrate::K100,
+                "4" => Bitrate::K125,
+                "5" => Bitrate::K250,
+                "6" => Bitrate::K500,
+                "7" => Bitrate::K800,
+                "8" => Bitrate::M1,
+                _ => return None,
+            };
+            Some(Command::Bitrate(b))
+        }
+        't' => parse_frame(args, false, false).map(Command::Transmit),
+        'T' => parse_frame(args, true, false).map(Command::Transmit),
+        'r' => parse_frame(args, false, true).map(Command::Transmit),
+        'R' => parse_frame(args, true, true).map(Command::Transmit),
+        _ => None,
+    }
+}
+
+fn parse_frame(args: &str, ext: bool, rtr: bool) -> Option<Frame> {
+    let id_len = if ext { 8 } else { 3 };
+    if !args.is_ascii() || args.len() < id_len + 1 {
+        return None;
+    }
+    let id = Id::new(u32::from_str_radix(&args[..id_len], 16).ok()?, ext)?;
+    let can_dlc = u8::from_str_radix(&args[id_len..id_len + 1], 16).ok()?;
+    if can_dlc > 8 {
+        return None;
+    }
+
+    let mut data = [0u8; 64];
+    let hex = &args[id_len + 1..];
+    if !rtr {
+        if hex.len() != can_dlc as usize * 2 {
+            return None;
+        }
+        for (i, b) in data.iter_mut().take(can_dlc as usize).enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+    } else if !hex.is_empty() {
+        return None;
+    }
+
+    Some(Frame {
+        id,
+        can_dlc,
+        data,
+        rtr,
+        ..Default::default()
+    })
+}
+
+/// Encode a received frame as an SLCAN line, including the trailing carriage
+/// return. If `timestamp` is given, it is appended in milliseconds modulo 60000
+/// as SLCAN hosts expect.
+pub fn encode_frame(f: &Frame, timestamp: Option<u32>) -> String {
+    let cmd = match (f.is_extended(), f.rtr) {
+        (false, false) => 't',
+        (true, false) => 'T',
+        (false, true) => 'r',
+        (true, true) => 'R',
+    };
+    let mut s = if f.is_extended() {
+        format!("{}{:08X}{:X}", cmd, f.raw_id(), f.can_dlc)
+    } else {
+        format!("{}{:03X}{:X}", cmd, f.raw_id(), f.can_dlc)
+    };
+    if !f.rtr {
+        for b in f.payload() {
+            s.push_str(&format!("{:02X}", b));
+        }
+    }
+    if let Some(t) = timestamp {
+        s.push_str(&format!("{:04X}", t % 60000));
+    }
+    s.push('\r');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slcan() {
+        assert!(matches!(
+            parse_command("S6"),
+            Some(Command::Bitrate(Bitrate::K500))
+        ));
+        assert!(parse_command("S9").is_none());
+
+        let f = match parse_command("t1232AABB") {
+            Some(Command::Transmit(f)) => f,
+            c => panic!("unexpected command {:?}", c),
+        };
+        assert_eq!(f.raw_id(), 0x123);
+        assert_eq!(f.can_dlc, 2);
+        assert_eq!(f.data[..2], [0xAA, 0xBB]);
+        assert_eq!(encode_frame(&f, None), "t1232AABB\r");
+
+        let f = match parse_command("R1ABCDEF04") {
+            Some(Command::Transmit(f)) => f,
+            c => panic!("unexpected command {:?}", c),
+        };
+        assert!(f.is_extended() && f.rtr);
+        assert_eq!(encode_frame(&f, Some(60001)), "R1ABCDEF040001\r");
+
+        // too much data for the DLC, and an ID out of range
+        assert!(parse_command("t1231AABB").is_none());
+        assert!(parse_command("t8000").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+//! Tracking of the last data seen for every ID and of the bits which change,
+//! like `cansniffer`, for reverse engineering a bus.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::Frame;
+
+/// The last frame seen with one ID, and how its data has been changing. A row
+/// of the table returned by `Sniffer::entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnifferEntry {
+    /// Channel the frames were received on.
+    pub channel: u8,
+    /// ID of the frames.
+    pub id: u32,
+    /// True if the ID is extended.
+    pub ext: bool,
+    /// Data of the last frame.
+    pub data: Vec<u8>,
+    /// Bits of each data byte which changed with the last frame. Bytes the
+    /// previous frame didn't have count as changed.
+    pub changed: Vec<u8>,
+    /// When each data byte last changed, as the timestamp of the frame.
+    pub last_change: Vec<Duration>,
+    /// Frames seen with the ID.
+    pub count: u64,
+    /// Timestamp of the first frame.
+    pub first_seen: Duration,
+    /// Timestamp of the last frame.
+    pub last_seen: Duration,
+    /// Time between the last two frames, `None` until two frames were seen.
+    pub last_period: Option<Duration>,
+}
+
+impl SnifferEntry {
+    /// Returns the average time between frames, `None` until two frames were
+    /// seen.
+    pub fn period(&self) -> Option<Duration> {
+        match self.count {
+            0 | 1 => None,
+            n => Some((self.last_seen - self.first_seen) / (n - 1) as u32),
+        }
+    }
+
+    /// Returns true if data byte `index` changed at or after `since`, for
+    /// highlighting recent changes.
+    pub fn changed_since(&self, index: usize, since: Duration) -> bool {
+        self.last_change.get(index).is_some_and(|t| *t >= since)
+    }
+}
+
+/// Keeps the last data seen for every ID, and which bytes and bits changed.
+///
+/// Frames are added with `Sniffer::frame`, for example from a subscription to
+/// all IDs, and the table is read with `Sniffer::entries` for display.
+///
+/// ```no_run
+/// # use std::sync::{Arc, Mutex};
+/// # use std::time::Duration;
+/// # use cantact::{Interface, Sniffer};
+/// # let mut i = Interface::new()?;
+/// let sniffer = Arc::new(Mutex::new(Sniffer::new()));
+/// let s = Arc::clone(&sniffer);
+/// i.subscribe(0, 0, move |f| s.lock().unwrap().frame(&f));
+/// i.start(|_| {})?;
+/// loop {
+///     std::thread::sleep(Duration::from_millis(100));
+///     for e in sniffer.lock().unwrap().entries() {
+///         println!("{:03X} {:02X?} changed {:02X?}", e.id, e.data, e.changed);
+///     }
+/// }
+/// # Ok::<(), cantact::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sniffer {
+    // keyed by (channel, ext, id) so the table is sorted by ID
+    entries: BTreeMap<(u8, bool, u32), SnifferEntry>,
+    // bits of each data byte which are ignored, such as counters, by ID
+    ignored: BTreeMap<(bool, u32), Vec<u8>>,
+    // clock for frames without a timestamp
+    start: Instant,
+}
+
+impl Default for Sniffer {
+    fn default() -> Sniffer {
+        Sniffer::new()
+    }
+}
+
+impl Sniffer {
+    /// Returns an empty sniffer.
+    pub fn new() -> Sniffer {
+        Sniffer {
+            entries: BTreeMap::new(),
+            ignored: BTreeMap::new(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Add a frame to the table. Frames without a timestamp are timestamped
+    /// with the time since the sniffer was created. Remote frames, which have
+    /// no data, are not tracked.
+    pub fn frame(&mut self, f: &Frame) {
+        if f.rtr {
+            return;
+        }
+        let now = f.timestamp.unwrap_or_else(|| self.start.elapsed());
+        let data = f.payload();
+        let ignored = self.ignored.get(&(f.is_extended(), f.raw_id()));
+        let mask = |i: usize| !ignored.and_then(|m| m.get(i)).copied().unwrap_or(0);
+
+        let key = (f.channel, f.is_extended(), f.raw_id());
+        let e = self.entries.entry(key).or_insert_with(|| SnifferEntry {
+            channel: f.channel,
+            id: f.raw_id(),
+            ext: f.is_extended(),
+            data: vec![],
+            changed: vec![],
+            last_change: vec![],
+            count: 0,
+            first_seen: now,
+            last_seen: now,
+            last_period: None,
+        });
+        let changed: Vec<u8> = data
+            .iter()
+            .enumerate()
+            .map(|(i, b)| match e.data.get(i) {
+                Some(old) => (old ^ b) & mask(i),
+                // new bytes count as changed
+                None => mask(i),
+            })
+            .collect();
+        let mut last_change = e.last_change.clone();
+        last_change.resize(data.len(), now);
+        for (i, c) in changed.iter().enumerate() {
+            if *c != 0 {
+                last_change[i] = now;
+            }
+        }
+
+        if e.count > 0 {
+            e.last_period = Some(now.saturating_sub(e.last_seen));
+        }
+        e.data = data.to_vec();
+        e.changed = changed;
+        e.last_change = last_change;
+        e.count += 1;
+        e.last_seen = now;
+    }
+
+    /// Returns the table of IDs, sorted by channel, type of ID and ID.
+    pub fn entries(&self) -> Vec<SnifferEntry> {
+        self.entries.values().cloned().collect()
+    }
+
+    /// Returns the entry for an ID.
+    pub fn entry(&self, channel: u8, id: u32, ext: bool) -> Option<&SnifferEntry> {
+        self.entries.get(&(channel, ext, id))
+    }
+
+    /// Returns the IDs with data which changed at or after `since`.
+    pub fn changed_since(&self, since: Duration) -> Vec<&SnifferEntry> {
+        self.entries
+            .values()
+            .filter(|e| e.last_change.iter().any(|t| *t >= since))
+            .collect()
+    }
+
+    /// Ignore changes of the bits set in `mask` in frames with ID `id`, one
+    /// mask byte per data byte. Used to hide counters and checksums, which
+    /// change in every frame.
+    pub fn ignore(&mut self, id: u32, ext: bool, mask: &[u8]) {
+        self.ignored.insert((ext, id), mask.to_vec());
+    }
+
+    /// Remove IDs which were not seen since `since`, for example to drop
+    /// messages which stopped.
+    pub fn remove_stale(&mut self, since: Duration) {
+        self.entries.retain(|_, e| e.last_seen >= since);
+    }
+
+    /// Remove all IDs.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniffer() {
+        let frame = |id, data: &[u8], ms| {
+            let mut f = Frame::new(id, data).unwrap();
+            f.timestamp = Some(Duration::from_millis(ms));
+            f
+        };
+        let mut s = Sniffer::new();
+        s.ignore(0x200, false, &[0x0F]);
+        s.frame(&frame(0x123, &[1, 2], 0));
+        s.frame(&frame(0x100, &[0], 5));
+        s.frame(&frame(0x123, &[1, 3, 0], 10));
+        s.frame(&frame(0x123, &[1, 3, 0], 30));
+
+        let entries = s.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, 0x100);
+        let e = &entries[1];
+        assert_eq!(e.data, [1, 3, 0]);
+        assert_eq!(e.changed, [0, 0, 0]);
+        assert_eq!(e.count, 3);
+        assert_eq!(e.period(), Some(Duration::from_millis(15)));
+        assert_eq!(e.last_period, Some(Duration::from_millis(20)));
+        // byte 1 changed at 10 ms, byte 2 appeared then
+        assert!(!e.changed_since(0, Duration::from_millis(1)));
+        assert!(e.changed_since(1, Duration::from_millis(10)));
+        assert!(e.changed_since(2, Duration::from_millis(10)));
+        assert_eq!(s.changed_since(Duration::from_millis(6)).len(), 1);
+
+        // ignored bits don't count as changes
+        s.frame(&frame(0x200, &[0x10], 40));
+        s.frame(&frame(0x200, &[0x1F], 50));
+        assert_eq!(s.entry(0, 0x200, false).unwrap().changed, [0]);
+        s.frame(&frame(0x200, &[0x3F], 60));
+        assert_eq!(s.entry(0, 0x200, false).unwrap().changed, [0x20]);
+
+        s.remove_stale(Duration::from_millis(20));
+        assert!(s.entry(0, 0x100, false).is_none());
+        assert_eq!(s.entries().len(), 2);
+    }
+}
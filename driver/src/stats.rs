@@ -0,0 +1,143 @@
+//! Traffic counters kept by the driver.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::Id;
+
+/// Frame and byte counters for a single channel.
+///
+/// Counters start at zero when the `Interface` is created and can be cleared
+/// with `Interface::reset_counters`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelCounters {
+    /// Frames handed to the device for transmission.
+    pub tx_frames: u64,
+    /// Data bytes handed to the device for transmission.
+    pub tx_bytes: u64,
+    /// Frames received from other nodes on the bus.
+    pub rx_frames: u64,
+    /// Data bytes received from other nodes on the bus.
+    pub rx_bytes: u64,
+    /// Transmitted frames echoed back by the device.
+    pub echo_frames: u64,
+    /// Error frames reported by the device.
+    pub error_frames: u64,
+}
+
+/// Traffic statistics for a single channel, in `Stats::channels`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelStats {
+    /// Channel index.
+    pub channel: u8,
+    /// Frame, byte and error frame counters.
+    pub counters: ChannelCounters,
+    /// Frames per second on the bus over the last second, including frames sent
+    /// by this device.
+    pub frames_per_sec: f32,
+    /// Frames per second sent by this device over the last second.
+    pub tx_frames_per_sec: f32,
+    /// Error frames per second over the last second.
+    pub errors_per_sec: f32,
+    /// Estimated fraction of the bus bandwidth in use over the last second, see
+    /// `ChannelLiveStats::bus_load`.
+    pub bus_load: f32,
+    /// Highest frames per second over any one second window.
+    pub peak_frames_per_sec: f32,
+    /// Highest bus load over any one second window.
+    pub peak_bus_load: f32,
+    /// Frames seen with each ID, including frames sent by this device. Empty
+    /// unless enabled with `Interface::set_count_ids`.
+    pub id_counts: HashMap<Id, u64>,
+}
+
+/// Statistics of one ID on one channel, returned by `Interface::id_stats`.
+/// Periods are measured between frame timestamps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdStats {
+    /// Channel index.
+    pub channel: u8,
+    /// CAN ID.
+    pub id: Id,
+    /// Frames seen with the ID, including frames sent by this device.
+    pub count: u64,
+    /// Shortest time between two frames, `None` until two frames were seen.
+    pub min_period: Option<Duration>,
+    /// Average time between frames, `None` until two frames were seen.
+    pub mean_period: Option<Duration>,
+    /// Longest time between two frames, `None` until two frames were seen.
+    pub max_period: Option<Duration>,
+    /// DLC of the last frame.
+    pub last_dlc: u8,
+    /// Data of the last frame.
+    pub last_data: Vec<u8>,
+    /// Timestamp of the first frame.
+    pub first_seen: Duration,
+    /// Timestamp of the last frame.
+    pub last_seen: Duration,
+}
+
+/// Traffic statistics for an `Interface`, returned by `Interface::stats` and
+/// cleared with `Interface::reset_stats`.
+///
+/// Besides the traffic on each channel, it counts received frames lost before
+/// reaching the application. A capture is complete only if no frames were lost.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Statistics for each channel.
+    pub channels: Vec<ChannelStats>,
+    /// Times the device reported that it lost received frames because its
+    /// buffers were full, either with the overflow flag of a frame or with an
+    /// error frame.
+    pub rx_overflows: u64,
+    /// Received frames dropped by the driver because a queue of received frames
+    /// was full, see `Interface::set_rx_queue`.
+    pub queue_drops: u64,
+}
+
+impl Stats {
+    /// Returns true if no received frames were lost.
+    pub fn is_complete(&self) -> bool {
+        self.rx_overflows == 0 && self.queue_drops == 0
+    }
+}
+
+/// USB transfer statistics for an `Interface`, returned by `Interface::usb_stats`.
+///
+/// These help to tell whether throughput problems are caused by the USB link
+/// rather than the CAN bus or the application.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbStats {
+    /// Bulk in transfers which completed successfully.
+    pub in_transfers: u64,
+    /// Bytes received by completed bulk in transfers.
+    pub in_bytes: u64,
+    /// Bulk in transfers which timed out without receiving data.
+    pub in_timeouts: u64,
+    /// Bulk in transfers which failed.
+    pub in_errors: u64,
+    /// Bulk out transfers which completed successfully.
+    pub out_transfers: u64,
+    /// Bulk out transfers which failed or timed out.
+    pub out_errors: u64,
+    /// Bulk transfers which failed because the endpoint stalled.
+    pub stalls: u64,
+    /// Bulk in transfers resubmitted after completing.
+    pub resubmissions: u64,
+    /// Bulk in transfers which could not be resubmitted. Each of these reduces
+    /// the number of transfers available to receive frames.
+    pub resubmit_failures: u64,
+    /// Size of the bulk in transfer buffers in bytes.
+    pub in_buffer_size: u64,
+}
+
+impl UsbStats {
+    /// Returns the average fill level of completed bulk in transfers, as a
+    /// fraction of the transfer buffer size.
+    pub fn average_fill(&self) -> f32 {
+        if self.in_transfers == 0 || self.in_buffer_size == 0 {
+            return 0.0;
+        }
+        self.in_bytes as f32 / (self.in_transfers * self.in_buffer_size) as f32
+    }
+}
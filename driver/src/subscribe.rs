@@ -0,0 +1,163 @@
+//! Delivery of received frames to callbacks filtered by ID.
+
+use crossbeam_channel::Sender;
+
+use crate::Frame;
+
+/// Handle to a subscription, used to remove it with `Interface::unsubscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionHandle(u64);
+
+struct Subscription {
+    handle: SubscriptionHandle,
+    // None matches frames on all channels
+    channel: Option<u8>,
+    id: u32,
+    mask: u32,
+    sink: Sink,
+}
+
+// where matching frames go
+enum Sink {
+    Callback(Box<dyn FnMut(Frame) + Send>),
+    // removed once the receiver is dropped
+    Queue(Sender<Frame>),
+}
+
+impl Subscription {
+    fn matches(&self, f: &Frame) -> bool {
+        if let Some(ch) = self.channel {
+            if ch != f.channel {
+                return false;
+            }
+        }
+        f.raw_id() & self.mask == self.id & self.mask
+    }
+}
+
+/// All subscriptions registered on an `Interface`. Shared with the rx thread.
+pub(crate) struct Subscriptions {
+    next_handle: u64,
+    subscriptions: Vec<Subscription>,
+}
+
+impl Subscriptions {
+    pub(crate) fn new() -> Subscriptions {
+        Subscriptions {
+            next_handle: 0,
+            subscriptions: vec![],
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        channel: Option<u8>,
+        id: u32,
+        mask: u32,
+        callback: Box<dyn FnMut(Frame) + Send>,
+    ) -> SubscriptionHandle {
+        self.push(channel, id, mask, Sink::Callback(callback))
+    }
+
+    /// Send matching frames to a queue. The subscription is removed when the
+    /// receiving end of the queue is dropped.
+    pub(crate) fn add_queue(
+        &mut self,
+        channel: Option<u8>,
+        id: u32,
+        mask: u32,
+        queue: Sender<Frame>,
+    ) -> SubscriptionHandle {
+        self.push(channel, id, mask, Sink::Queue(queue))
+    }
+
+    fn push(&mut self, channel: Option<u8>, id: u32, mask: u32, sink: Sink) -> SubscriptionHandle {
+        self.next_handle += 1;
+        let handle = SubscriptionHandle(self.next_handle);
+        self.subscriptions.push(Subscription {
+            handle,
+            channel,
+            id,
+            mask,
+            sink,
+        });
+        handle
+    }
+
+    pub(crate) fn remove(&mut self, handle: SubscriptionHandle) {
+        self.subscriptions.retain(|s| s.handle != handle);
+    }
+
+    /// Call every subscription matching the frame.
+    pub(crate) fn frame(&mut self, f: &Frame) {
+        self.subscriptions.retain_mut(|s| {
+            if !s.matches(f) {
+                return true;
+            }
+            match &mut s.sink {
+                Sink::Callback(callback) => {
+                    callback(f.clone());
+                    true
+                }
+                Sink::Queue(queue) => queue.send(f.clone()).is_ok(),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_subscriptions() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut s = Subscriptions::new();
+
+        let all = Arc::clone(&seen);
+        let h = s.add(
+            None,
+            0x100,
+            0x700,
+            Box::new(move |f: Frame| all.lock().unwrap().push(("all", f.raw_id()))),
+        );
+        let ch1 = Arc::clone(&seen);
+        s.add(
+            Some(1),
+            0x123,
+            0x7FF,
+            Box::new(move |f: Frame| ch1.lock().unwrap().push(("ch1", f.raw_id()))),
+        );
+
+        let frame = |channel, can_id| Frame {
+            channel,
+            id: Id::new(can_id, false).unwrap(),
+            ..Default::default()
+        };
+        s.frame(&frame(0, 0x123));
+        s.frame(&frame(1, 0x123));
+        s.frame(&frame(1, 0x223));
+        s.remove(h);
+        s.frame(&frame(0, 0x1FF));
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![("all", 0x123), ("all", 0x123), ("ch1", 0x123)]
+        );
+
+        // queues are removed once their receiver is dropped
+        let (send, recv) = crossbeam_channel::unbounded();
+        s.add_queue(Some(0), 0, 0, send);
+        s.frame(&frame(0, 0x10));
+        s.frame(&frame(1, 0x11));
+        assert_eq!(
+            recv.try_iter().map(|f| f.raw_id()).collect::<Vec<_>>(),
+            [0x10]
+        );
+        drop(recv);
+        s.frame(&frame(0, 0x12));
+        assert_eq!(s.subscriptions.len(), 1);
+    }
+}
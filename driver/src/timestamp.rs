@@ -0,0 +1,124 @@
+//! Conversion of device timestamps to frame timestamps.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Clock used for frame timestamps, set with `Interface::set_timestamp_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimestampMode {
+    /// Time since the interface was started, measured by the host when the frame
+    /// is received from the device.
+    Monotonic,
+    /// Time since the Unix epoch, measured by the host when the frame is received
+    /// from the device. Frames of interfaces on the same host can be merged in
+    /// order. The clock is read once when the interface starts, and advanced
+    /// monotonically from there.
+    WallClock,
+    /// Time since the interface was started, measured by the device when the
+    /// frame is sent or received on the bus. The device clock is mapped onto the
+    /// host clock with its drift compensated, so the timestamps are comparable
+    /// to `Monotonic` ones. Requires a device with hardware timestamps.
+    Hardware,
+}
+
+// largest difference between the device and host clock rates which is tracked,
+// in parts per million. Crystals are usually within 100 ppm.
+const MAX_DRIFT_PPM: i64 = 500;
+
+/// Maps the 32 bit microsecond counter of a device onto the host clock. Reset
+/// when the interface is started.
+///
+/// The counter wraps about every 71 minutes, so it is first extended to 64 bits.
+/// The offset from device to host time is then estimated as the smallest one
+/// seen, since USB latency only ever delays frames. To follow drift between the
+/// clocks, the estimate may rise by up to `MAX_DRIFT_PPM` of the elapsed device
+/// time, and falls whenever a frame arrives sooner than expected.
+pub(crate) struct HwClock {
+    // last counter value seen, and the counter value at the last wrap
+    last: Option<u32>,
+    high: u64,
+    // extended device time of the last frame, and the offset to host time,
+    // both in microseconds
+    last_time: i64,
+    offset: Option<i64>,
+    // last timestamp returned, timestamps never go backwards
+    last_timestamp: Duration,
+}
+
+impl HwClock {
+    pub(crate) fn new() -> HwClock {
+        HwClock {
+            last: None,
+            high: 0,
+            last_time: 0,
+            offset: None,
+            last_timestamp: Duration::from_secs(0),
+        }
+    }
+
+    fn extend(&mut self, us: u32) -> u64 {
+        // frames arrive in order, so going backwards means the counter wrapped
+        if let Some(last) = self.last {
+            if us < last {
+                self.high += 1 << 32;
+            }
+        }
+        self.last = Some(us);
+        self.high + us as u64
+    }
+
+    /// Returns the timestamp of a frame with the device time `us`, received at
+    /// `host` on the host clock.
+    pub(crate) fn timestamp(&mut self, us: u32, host: Duration) -> Duration {
+        let t = self.extend(us) as i64;
+        let observed = host.as_micros() as i64 - t;
+        let offset = match self.offset {
+            Some(offset) => {
+                let drift = (t - self.last_time) * MAX_DRIFT_PPM / 1_000_000;
+                observed.min(offset + drift)
+            }
+            None => observed,
+        };
+        self.offset = Some(offset);
+        self.last_time = t;
+
+        let ts = Duration::from_micros((t + offset).max(0) as u64);
+        self.last_timestamp = ts.max(self.last_timestamp);
+        self.last_timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hw_clock() {
+        let mut c = HwClock::new();
+        let host = Duration::from_millis(3);
+        assert_eq!(c.timestamp(0xFFFF_FF00, host), host);
+        assert_eq!(
+            c.timestamp(0xFFFF_FFF0, Duration::from_secs(1)),
+            host + Duration::from_micros(0xF0)
+        );
+        // wrapped, 0x110 microseconds after the first frame
+        assert_eq!(
+            c.timestamp(0x10, Duration::from_secs(1)),
+            host + Duration::from_micros(0x110)
+        );
+
+        // a device clock 100 ppm slow, with up to 2 ms of latency
+        let mut c = HwClock::new();
+        let mut error = Duration::from_secs(0);
+        for n in 0..10_000u64 {
+            let host_us = n * 10_000;
+            let device_us = host_us - host_us / 10_000;
+            let latency = Duration::from_micros((n * 7919) % 2000);
+            let ts = c.timestamp(device_us as u32, Duration::from_micros(host_us) + latency);
+            let actual = Duration::from_micros(host_us);
+            error = ts.abs_diff(actual);
+        }
+        assert!(error < Duration::from_millis(1), "{:?}", error);
+    }
+}
@@ -0,0 +1,80 @@
+//! Correlation of sent requests with their responses.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crate::dispatch::Dispatcher;
+use crate::{Error, Frame};
+
+/// Selects the response to a request sent with `Interface::begin`.
+pub enum Response {
+    /// The first frame received with this ID.
+    Id(u32),
+    /// The first frame accepted by the closure.
+    Matches(Box<dyn Fn(&Frame) -> bool + Send>),
+}
+
+impl Response {
+    pub(crate) fn matches(&self, f: &Frame) -> bool {
+        match self {
+            Response::Id(id) => f.raw_id() == *id,
+            Response::Matches(m) => m(f),
+        }
+    }
+}
+
+/// An outstanding request, returned by `Interface::begin`.
+///
+/// Any number of transactions can be outstanding at once, each with its own
+/// timeout. Dropping a transaction stops waiting for its response.
+pub struct Transaction {
+    dispatcher: Arc<Dispatcher>,
+    token: u64,
+    response: Receiver<Frame>,
+    deadline: Instant,
+}
+
+impl Transaction {
+    pub(crate) fn new(
+        dispatcher: Arc<Dispatcher>,
+        token: u64,
+        response: Receiver<Frame>,
+        timeout: Duration,
+    ) -> Transaction {
+        Transaction {
+            dispatcher,
+            token,
+            response,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Wait for the response, returning `Error::Timeout` if it has not been
+    /// received when the transaction's timeout expires.
+    pub fn wait(self) -> Result<Frame, Error> {
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        match self.response.recv_timeout(timeout) {
+            Ok(f) => Ok(f),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::NotRunning),
+        }
+    }
+
+    /// Returns the response if it has been received, without waiting.
+    pub fn try_response(&self) -> Option<Frame> {
+        self.response.try_recv().ok()
+    }
+
+    /// Returns true if the transaction's timeout has expired.
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.dispatcher.cancel(self.token);
+    }
+}
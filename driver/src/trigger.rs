@@ -0,0 +1,300 @@
+//! Capture triggers, which hold back received frames until a condition is met,
+//! keeping the frames received shortly before it.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::{Filter, Frame};
+
+// frames kept before a trigger at most, however short the pre-trigger time
+const MAX_PRE_TRIGGER_FRAMES: usize = 100_000;
+
+/// Condition which starts or stops a `Trigger`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerCondition {
+    /// A frame with this ID is received, on any channel.
+    Id(u32),
+    /// A frame with `id` is received with data matching `data` in the bits set
+    /// in `mask`. Bytes past the end of `mask` are compared fully.
+    Data {
+        /// ID of the frame.
+        id: u32,
+        /// Expected data.
+        data: Vec<u8>,
+        /// Bits of the data which are compared.
+        mask: Vec<u8>,
+    },
+    /// A frame passing the filter is received.
+    Filter(Filter),
+    /// An error frame is received, on any channel.
+    BusError,
+    /// `Interface::fire_trigger` is called.
+    External,
+}
+
+impl TriggerCondition {
+    fn matches(&self, f: &Frame) -> bool {
+        match self {
+            TriggerCondition::Id(id) => f.raw_id() == *id,
+            TriggerCondition::Data { id, data, mask } => {
+                let payload = f.payload();
+                f.raw_id() == *id
+                    && payload.len() >= data.len()
+                    && data.iter().enumerate().all(|(i, b)| {
+                        let m = mask.get(i).copied().unwrap_or(0xFF);
+                        payload[i] & m == b & m
+                    })
+            }
+            TriggerCondition::Filter(filter) => filter.matches(f),
+            TriggerCondition::BusError | TriggerCondition::External => false,
+        }
+    }
+}
+
+/// Holds back received frames until a start condition is met, set with
+/// `Interface::set_trigger`. Frames received up to `pre_trigger` before the
+/// start are delivered as well. Delivery ends at the stop condition or after
+/// `post_trigger`, and the trigger is armed again.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use cantact::{Interface, Trigger, TriggerCondition};
+/// # let mut i = Interface::new()?;
+/// // capture 100 ms around every bus error
+/// let trigger = Trigger::new(TriggerCondition::BusError)
+///     .pre_trigger(Duration::from_millis(50))
+///     .post_trigger(Duration::from_millis(50));
+/// i.set_trigger(Some(trigger));
+/// # Ok::<(), cantact::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    start: TriggerCondition,
+    stop: Option<TriggerCondition>,
+    pre_trigger: Duration,
+    post_trigger: Option<Duration>,
+}
+
+impl Trigger {
+    /// Returns a trigger which starts delivering frames at `start`, without
+    /// frames from before it, and doesn't stop.
+    pub fn new(start: TriggerCondition) -> Trigger {
+        Trigger {
+            start,
+            stop: None,
+            pre_trigger: Duration::ZERO,
+            post_trigger: None,
+        }
+    }
+
+    /// Stop delivering frames at `stop`. The frame meeting the condition is
+    /// delivered.
+    pub fn stop(mut self, stop: TriggerCondition) -> Trigger {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Deliver the frames received up to `duration` before the trigger.
+    pub fn pre_trigger(mut self, duration: Duration) -> Trigger {
+        self.pre_trigger = duration;
+        self
+    }
+
+    /// Stop delivering frames `duration` after the trigger.
+    pub fn post_trigger(mut self, duration: Duration) -> Trigger {
+        self.post_trigger = Some(duration);
+        self
+    }
+}
+
+/// State of the trigger of an `Interface`. Shared with the rx thread.
+pub(crate) struct Capture {
+    trigger: Option<Trigger>,
+    // when the trigger started, None while armed
+    since: Option<Instant>,
+    // frames received while armed, kept for the pre-trigger time
+    buffer: VecDeque<(Instant, Frame)>,
+    // set by `Interface::fire_trigger`
+    fired: bool,
+}
+
+impl Capture {
+    pub(crate) fn new() -> Capture {
+        Capture {
+            trigger: None,
+            since: None,
+            buffer: VecDeque::new(),
+            fired: false,
+        }
+    }
+
+    /// Set or remove the trigger, arming it.
+    pub(crate) fn set(&mut self, trigger: Option<Trigger>) {
+        self.trigger = trigger;
+        self.since = None;
+        self.buffer.clear();
+        self.fired = false;
+    }
+
+    pub(crate) fn fire(&mut self) {
+        self.fired = true;
+    }
+
+    pub(crate) fn is_triggered(&self) -> bool {
+        self.since.is_some()
+    }
+
+    // drops frames older than the pre-trigger time
+    fn trim(&mut self, now: Instant) {
+        let pre_trigger = self
+            .trigger
+            .as_ref()
+            .map_or(Duration::ZERO, |t| t.pre_trigger);
+        while self
+            .buffer
+            .front()
+            .is_some_and(|(t, _)| now.saturating_duration_since(*t) > pre_trigger)
+            || self.buffer.len() > MAX_PRE_TRIGGER_FRAMES
+        {
+            self.buffer.pop_front();
+        }
+    }
+
+    // starts the trigger, returning the pre-trigger frames
+    fn start(&mut self, now: Instant) -> Vec<Frame> {
+        self.trim(now);
+        self.since = Some(now);
+        self.buffer.drain(..).map(|(_, f)| f).collect()
+    }
+
+    // re-arms the trigger once the post-trigger time is over
+    fn expire(&mut self, now: Instant) {
+        let post = self.trigger.as_ref().and_then(|t| t.post_trigger);
+        if let (Some(since), Some(post)) = (self.since, post) {
+            if now.saturating_duration_since(since) > post {
+                self.since = None;
+            }
+        }
+    }
+
+    /// Handle a received frame. Returns the frames to deliver now, or `None` if
+    /// there is no trigger and the frame is delivered as usual. `wanted` is
+    /// false for frames rejected by the filters, which can still start or stop
+    /// the trigger but are not delivered.
+    pub(crate) fn frame(&mut self, f: &Frame, wanted: bool, now: Instant) -> Option<Vec<Frame>> {
+        self.expire(now);
+        let trigger = self.trigger.as_ref()?;
+        let mut out = vec![];
+        if self.since.is_none() {
+            if !trigger.start.matches(f) {
+                if wanted {
+                    self.buffer.push_back((now, f.clone()));
+                    self.trim(now);
+                }
+                return Some(out);
+            }
+            out = self.start(now);
+        } else if trigger.stop.as_ref().is_some_and(|s| s.matches(f)) {
+            self.since = None;
+        }
+        if wanted {
+            out.push(f.clone());
+        }
+        Some(out)
+    }
+
+    // handles a condition which is not a frame, returning the pre-trigger
+    // frames if it starts the trigger
+    fn event(&mut self, condition: TriggerCondition, now: Instant) -> Vec<Frame> {
+        let (starts, stops) = match &self.trigger {
+            Some(t) => (t.start == condition, t.stop.as_ref() == Some(&condition)),
+            None => return vec![],
+        };
+        match self.since {
+            None if starts => self.start(now),
+            Some(_) if stops => {
+                self.since = None;
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Handle an error frame, returning the pre-trigger frames if it starts
+    /// the trigger.
+    pub(crate) fn error(&mut self, now: Instant) -> Vec<Frame> {
+        self.expire(now);
+        self.event(TriggerCondition::BusError, now)
+    }
+
+    /// Handle `Interface::fire_trigger` and the end of the post-trigger time.
+    /// Returns the pre-trigger frames if the trigger was started.
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<Frame> {
+        self.expire(now);
+        match std::mem::take(&mut self.fired) {
+            true => self.event(TriggerCondition::External, now),
+            false => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let now = Instant::now();
+        let ms = |n| now + Duration::from_millis(n);
+        let f = |id, data: &[u8]| Frame::new(id, data).unwrap();
+        let ids = |frames: Option<Vec<Frame>>| -> Vec<u32> {
+            frames.unwrap().iter().map(|f| f.raw_id()).collect()
+        };
+
+        let mut c = Capture::new();
+        assert!(c.frame(&f(0x100, &[]), true, now).is_none());
+
+        let start = TriggerCondition::Data {
+            id: 0x200,
+            data: vec![0x10, 0x02],
+            mask: vec![0xF0],
+        };
+        c.set(Some(
+            Trigger::new(start)
+                .stop(TriggerCondition::Id(0x300))
+                .pre_trigger(Duration::from_millis(10)),
+        ));
+        assert!(ids(c.frame(&f(0x100, &[]), true, ms(0))).is_empty());
+        assert!(ids(c.frame(&f(0x101, &[]), true, ms(15))).is_empty());
+        assert!(ids(c.frame(&f(0x102, &[]), false, ms(16))).is_empty());
+        // the first byte is masked, the second is not
+        assert!(ids(c.frame(&f(0x200, &[0x1F, 0x03]), true, ms(20))).is_empty());
+        assert!(!c.is_triggered());
+        let started = c.frame(&f(0x200, &[0x1F, 0x02]), true, ms(22));
+        // 0x100 is too old, 0x102 was filtered
+        assert_eq!(ids(started), [0x101, 0x200, 0x200]);
+        assert!(c.is_triggered());
+        assert_eq!(ids(c.frame(&f(0x100, &[]), true, ms(40))), [0x100]);
+        assert_eq!(ids(c.frame(&f(0x300, &[]), true, ms(50))), [0x300]);
+        assert!(!c.is_triggered());
+
+        // external start, stopped after the post-trigger time
+        c.set(Some(
+            Trigger::new(TriggerCondition::External)
+                .pre_trigger(Duration::from_millis(100))
+                .post_trigger(Duration::from_millis(10)),
+        ));
+        c.frame(&f(0x100, &[]), true, ms(0));
+        assert!(c.poll(ms(1)).is_empty());
+        c.fire();
+        assert_eq!(c.poll(ms(2)).len(), 1);
+        assert_eq!(ids(c.frame(&f(0x101, &[]), true, ms(5))), [0x101]);
+        c.poll(ms(20));
+        assert!(!c.is_triggered());
+
+        // bus errors
+        c.set(Some(Trigger::new(TriggerCondition::BusError)));
+        assert!(c.error(ms(0)).is_empty());
+        assert!(c.is_triggered());
+    }
+}
@@ -0,0 +1,632 @@
+//! Tracking of transmitted frames and their outcomes.
+
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+
+use crate::device::gsusb::*;
+use crate::device::{Device, HostFrame};
+use crate::hook::Pipeline;
+use crate::ratelimit::RateLimiter;
+use crate::{BusError, BusErrorKind, ChannelCounters, Error, Frame};
+
+/// Frames a channel can have waiting in the device for transmission. The gs_usb
+/// firmware has this many transmit slots, further frames would be dropped or
+/// delay the USB link.
+pub(crate) const TX_SLOTS: usize = 10;
+
+/// Outcome of transmitting a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxResult {
+    /// The frame was transmitted on the bus and echoed back by the device.
+    Sent,
+    /// No other node acknowledged the frame. Only reported in one-shot mode,
+    /// otherwise the controller retransmits until the frame is acknowledged.
+    NoAck,
+    /// The frame lost arbitration. Only reported in one-shot mode, otherwise
+    /// the controller retransmits until arbitration is won.
+    ArbitrationLost,
+    /// The device's transmit buffer overflowed and the frame was dropped.
+    Overflow,
+    /// The device gave up transmitting the frame after a timeout.
+    Timeout,
+    /// The channel went bus-off while the frame was waiting to be sent.
+    BusOff,
+}
+
+/// Result of a single transmission, reported to the callback registered with
+/// `Interface::on_tx_result`.
+#[derive(Debug, Clone)]
+pub struct TxEvent {
+    /// Echo ID returned by `Interface::send` when the frame was queued.
+    pub echo_id: u32,
+    /// Channel the frame was sent on.
+    pub channel: u8,
+    /// Outcome of the transmission.
+    pub result: TxResult,
+    /// Time the transmission completed or failed, on the same clock as frame
+    /// timestamps. For sent frames, this is the timestamp of the echoed frame.
+    pub timestamp: Duration,
+    /// Time from queueing the frame with `Interface::send` until the transmission
+    /// completed or failed.
+    pub latency: Duration,
+}
+
+/// Confirmation of a single transmission, returned by `Interface::send_confirmed`.
+///
+/// The result is also reported to the callback set by `Interface::on_tx_result`.
+#[derive(Debug)]
+pub struct TxConfirmation {
+    echo_id: u32,
+    result: Receiver<TxEvent>,
+}
+
+impl TxConfirmation {
+    /// Returns the echo ID assigned to the frame.
+    pub fn echo_id(&self) -> u32 {
+        self.echo_id
+    }
+
+    /// Wait up to `timeout` for the transmission to complete or fail. Returns
+    /// `Error::Timeout` if there is no result in time, and `Error::NotRunning` if
+    /// the interface stopped before the frame was sent.
+    pub fn wait(&self, timeout: Duration) -> Result<TxEvent, Error> {
+        self.result.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => Error::Timeout,
+            RecvTimeoutError::Disconnected => Error::NotRunning,
+        })
+    }
+
+    /// Returns the result of the transmission if it has completed or failed.
+    pub fn try_result(&self) -> Option<TxEvent> {
+        self.result.try_recv().ok()
+    }
+}
+
+/// Allocates echo IDs and keeps track of frames which have been handed to the
+/// device but not yet echoed back.
+pub(crate) struct TxTracker {
+    next_echo_id: u32,
+    // outstanding (channel, echo id, time queued), oldest first
+    pending: VecDeque<(u8, u32, Instant)>,
+    // channels which are running in one-shot mode
+    one_shot: Vec<bool>,
+    // where to report the results of confirmed frames, by echo id
+    confirmations: HashMap<u32, Sender<TxEvent>>,
+}
+
+impl TxTracker {
+    pub(crate) fn new() -> TxTracker {
+        TxTracker {
+            next_echo_id: 0,
+            pending: VecDeque::new(),
+            one_shot: vec![],
+            confirmations: HashMap::new(),
+        }
+    }
+
+    /// Forget all outstanding frames. Called when the device is (re)started.
+    /// Waiting confirmations are disconnected.
+    pub(crate) fn reset(&mut self, one_shot: Vec<bool>) {
+        self.pending.clear();
+        self.confirmations.clear();
+        self.one_shot = one_shot;
+    }
+
+    /// Forget the outstanding frames on `channel`, which the device discards
+    /// when the channel is reset. Waiting confirmations are disconnected.
+    pub(crate) fn discard(&mut self, channel: u8) {
+        let confirmations = &mut self.confirmations;
+        self.pending.retain(|(ch, id, _)| {
+            if *ch == channel {
+                confirmations.remove(id);
+            }
+            *ch != channel
+        });
+    }
+
+    /// Report the result of the frame with `echo_id` to the returned
+    /// confirmation.
+    pub(crate) fn confirm(&mut self, echo_id: u32) -> TxConfirmation {
+        let (send, result) = bounded(1);
+        self.confirmations.insert(echo_id, send);
+        TxConfirmation { echo_id, result }
+    }
+
+    // pass an event to its confirmation, if there is one
+    fn report(&mut self, ev: TxEvent) -> TxEvent {
+        if let Some(send) = self.confirmations.remove(&ev.echo_id) {
+            send.send(ev.clone()).ok();
+        }
+        ev
+    }
+
+    /// Allocate an echo ID for a frame about to be sent on `channel`.
+    pub(crate) fn allocate(&mut self, channel: u8) -> u32 {
+        let echo_id = self.next_echo_id;
+        self.next_echo_id = self.next_echo_id.wrapping_add(1);
+        if self.next_echo_id == GSUSB_RX_ECHO_ID {
+            // this value marks received frames and can't be used for transmissions
+            self.next_echo_id = 0;
+        }
+        self.pending.push_back((channel, echo_id, Instant::now()));
+        echo_id
+    }
+
+    /// Drop an echo ID without reporting a result, used when the frame never
+    /// made it to the device.
+    pub(crate) fn cancel(&mut self, echo_id: u32) {
+        self.pending.retain(|(_, id, _)| *id != echo_id);
+        self.confirmations.remove(&echo_id);
+    }
+
+    /// Returns the number of frames on `channel` waiting to be echoed.
+    pub(crate) fn pending(&self, channel: u8) -> usize {
+        self.pending
+            .iter()
+            .filter(|(ch, _, _)| *ch == channel)
+            .count()
+    }
+
+    /// Handle an echoed frame received from the device at `now`, with the frame
+    /// timestamp `timestamp`.
+    pub(crate) fn echo(
+        &mut self,
+        hf: &HostFrame,
+        now: Instant,
+        timestamp: Duration,
+    ) -> Option<TxEvent> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|(_, id, _)| *id == hf.echo_id)?;
+        let pending = self.pending.remove(pos)?;
+        Some(self.report(event(pending, TxResult::Sent, now, timestamp)))
+    }
+
+    /// Handle an error reported by the device at `now`, failing any outstanding
+    /// transmissions which the error says will never be sent.
+    pub(crate) fn error(
+        &mut self,
+        err: &BusError,
+        now: Instant,
+        timestamp: Duration,
+    ) -> Vec<TxEvent> {
+        let channel = err.channel;
+        let one_shot = self
+            .one_shot
+            .get(channel as usize)
+            .copied()
+            .unwrap_or(false);
+
+        if err.has(BusErrorKind::BusOff) {
+            // bus-off discards everything queued on the channel
+            return self.fail_all(channel, TxResult::BusOff, now, timestamp);
+        }
+
+        let result = if err.has(BusErrorKind::TxOverflow) {
+            TxResult::Overflow
+        } else if err.has(BusErrorKind::TxTimeout) {
+            TxResult::Timeout
+        } else if one_shot && err.has(BusErrorKind::NoAck) {
+            TxResult::NoAck
+        } else if one_shot && err.has(BusErrorKind::LostArbitration) {
+            TxResult::ArbitrationLost
+        } else {
+            return vec![];
+        };
+
+        // errors are not tagged with an echo ID, attribute this one to the
+        // oldest frame waiting on the channel
+        match self.pending.iter().position(|(ch, _, _)| *ch == channel) {
+            Some(pos) => {
+                let pending = self.pending.remove(pos).unwrap();
+                vec![self.report(event(pending, result, now, timestamp))]
+            }
+            None => vec![],
+        }
+    }
+
+    fn fail_all(
+        &mut self,
+        channel: u8,
+        result: TxResult,
+        now: Instant,
+        timestamp: Duration,
+    ) -> Vec<TxEvent> {
+        let (failed, pending) = self
+            .pending
+            .drain(..)
+            .partition(|(ch, _, _)| *ch == channel);
+        self.pending = pending;
+        failed
+            .into_iter()
+            .map(|p| self.report(event(p, result, now, timestamp)))
+            .collect::<Vec<_>>()
+    }
+}
+
+fn event(
+    pending: (u8, u32, Instant),
+    result: TxResult,
+    now: Instant,
+    timestamp: Duration,
+) -> TxEvent {
+    let (channel, echo_id, queued) = pending;
+    TxEvent {
+        echo_id,
+        channel,
+        result,
+        timestamp,
+        latency: now.saturating_duration_since(queued),
+    }
+}
+
+/// Sends frames on behalf of an `Interface`. Can be cloned and handed to other
+/// threads, such as the periodic scheduler.
+#[derive(Clone)]
+pub(crate) struct Transmitter {
+    pub(crate) dev: Arc<Mutex<Device>>,
+    pub(crate) running: Arc<RwLock<bool>>,
+    pub(crate) tx: Arc<Mutex<TxTracker>>,
+    pub(crate) counters: Arc<Mutex<Vec<ChannelCounters>>>,
+    pub(crate) limiter: Arc<Mutex<RateLimiter>>,
+    pub(crate) hooks: Arc<Mutex<Pipeline>>,
+}
+
+impl Transmitter {
+    /// Send a frame, keeping track of its echo ID and updating the tx counters.
+    /// Waits until the rate limits allow the frame.
+    pub(crate) fn send(&self, f: &Frame) -> Result<u32, Error> {
+        self.send_inner(f, false, false, true, true)
+            .map(|(echo_id, _)| echo_id)
+    }
+
+    /// Send a frame without waiting for the rate limits, for periodic and
+    /// forwarded frames. The frame still counts against the limits.
+    pub(crate) fn send_priority(&self, f: &Frame) -> Result<u32, Error> {
+        self.send_inner(f, false, false, false, true)
+            .map(|(echo_id, _)| echo_id)
+    }
+
+    /// Send a frame, returning a confirmation which receives its result.
+    pub(crate) fn send_confirmed(&self, f: &Frame) -> Result<TxConfirmation, Error> {
+        self.send_inner(f, true, false, true, true)
+            .map(|(_, c)| c.unwrap())
+    }
+
+    /// Send a frame if it can be done without waiting, otherwise return
+    /// `Error::WouldBlock`.
+    pub(crate) fn try_send(&self, f: &Frame) -> Result<u32, Error> {
+        self.send_inner(f, false, true, true, true)
+            .map(|(echo_id, _)| echo_id)
+    }
+
+    /// Send a frame without checking that CAN allows it, and without waiting
+    /// for the rate limits, to test how other nodes handle invalid frames.
+    pub(crate) fn send_unchecked(&self, f: &Frame) -> Result<u32, Error> {
+        self.send_inner(f, false, false, false, false)
+            .map(|(echo_id, _)| echo_id)
+    }
+
+    fn send_inner(
+        &self,
+        f: &Frame,
+        confirm: bool,
+        nonblocking: bool,
+        limited: bool,
+        checked: bool,
+    ) -> Result<(u32, Option<TxConfirmation>), Error> {
+        // the tx hooks run first, so the frame they return is validated
+        let mut f = f.clone();
+        let mut hooks = self.hooks.lock().unwrap();
+        match panic::catch_unwind(AssertUnwindSafe(|| hooks.run(&mut f))) {
+            Ok(true) => {}
+            Ok(false) => return Err(Error::FrameDropped),
+            Err(e) => return Err(Error::CallbackPanicked(crate::panic_message(e))),
+        }
+        drop(hooks);
+        let f = &f;
+        if checked {
+            f.validate()?;
+        }
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        // wait for the rate limits before taking the device, so other senders
+        // aren't held up
+        if limited {
+            loop {
+                let taken = self.limiter.lock().unwrap().try_take(f, Instant::now());
+                match taken {
+                    Ok(()) => break,
+                    Err(_) if nonblocking => return Err(Error::WouldBlock),
+                    Err(wait) => thread::sleep(wait),
+                }
+            }
+        } else {
+            self.limiter.lock().unwrap().take(f, Instant::now());
+        }
+        let sent = self.deliver(f, confirm, nonblocking);
+        if sent.is_err() {
+            // the frame never reached the bus, so it doesn't count
+            self.limiter.lock().unwrap().refund(f, Instant::now());
+        }
+        sent
+    }
+
+    // hand a frame which passed the rate limits to the device
+    fn deliver(
+        &self,
+        f: &Frame,
+        confirm: bool,
+        nonblocking: bool,
+    ) -> Result<(u32, Option<TxConfirmation>), Error> {
+        // the echo ID is allocated and the confirmation registered before the
+        // device is taken, so the echo can't be missed. The tracker is never
+        // locked while the device is held, see `Interface::snapshot`.
+        let (echo_id, confirmation) = {
+            let mut tx = self.tx.lock().unwrap();
+            if nonblocking && tx.pending(f.channel) >= TX_SLOTS {
+                return Err(Error::WouldBlock);
+            }
+            let echo_id = tx.allocate(f.channel);
+            (
+                echo_id,
+                if confirm {
+                    Some(tx.confirm(echo_id))
+                } else {
+                    None
+                },
+            )
+        };
+        let dev = if nonblocking {
+            // another thread is busy handing a frame to the device
+            self.dev.try_lock().ok()
+        } else {
+            Some(self.dev.lock().unwrap())
+        };
+        let sent = match dev {
+            Some(mut dev) => dev
+                .send(HostFrame::from_frame(f, echo_id))
+                .map_err(Error::from),
+            None => Err(Error::WouldBlock),
+        };
+        if let Err(e) = sent {
+            self.tx.lock().unwrap().cancel(echo_id);
+            return Err(e);
+        }
+        if let Some(c) = self.counters.lock().unwrap().get_mut(f.channel as usize) {
+            c.tx_frames += 1;
+            c.tx_bytes += f.len() as u64;
+        }
+        Ok((echo_id, confirmation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::mock::{self, Mock};
+
+    fn error_frame(channel: u8, class: u32, ctrl: u8) -> HostFrame {
+        let mut data = [0u8; 64];
+        data[1] = ctrl;
+        HostFrame {
+            echo_id: GSUSB_RX_ECHO_ID,
+            can_id: GSUSB_ERR_FLAG | class,
+            can_dlc: 8,
+            channel,
+            flags: 0,
+            reserved: 0,
+            data,
+            timestamp_us: None,
+        }
+    }
+
+    fn error(channel: u8, class: u32, ctrl: u8) -> BusError {
+        BusError::from_host_frame(&error_frame(channel, class, ctrl))
+    }
+
+    #[test]
+    fn test_tx_results() {
+        let now = Instant::now() + Duration::from_millis(5);
+        let ts = Duration::from_millis(5);
+        let mut t = TxTracker::new();
+        t.reset(vec![true, false]);
+        let a = t.allocate(0);
+        let b = t.allocate(1);
+        let c = t.allocate(0);
+
+        // ACK errors fail frames in one-shot mode only
+        assert!(t.error(&error(1, CAN_ERR_ACK, 0), now, ts).is_empty());
+        let ev = t.error(&error(0, CAN_ERR_ACK, 0), now, ts);
+        assert_eq!(ev.len(), 1);
+        assert_eq!(ev[0].echo_id, a);
+        assert_eq!(ev[0].result, TxResult::NoAck);
+
+        let mut echo = error_frame(1, 0, 0);
+        echo.can_id = 0x123;
+        echo.echo_id = b;
+        let ev = t.echo(&echo, now, ts).unwrap();
+        assert_eq!(ev.result, TxResult::Sent);
+        assert_eq!(ev.timestamp, Duration::from_millis(5));
+        assert!(ev.latency <= Duration::from_millis(5));
+        assert!(t.echo(&echo, now, ts).is_none());
+
+        let confirmation = t.confirm(c);
+        assert!(confirmation.try_result().is_none());
+        let ev = t.error(&error(0, CAN_ERR_BUSOFF, 0), now, ts);
+        assert_eq!(ev.len(), 1);
+        assert_eq!(ev[0].echo_id, c);
+        assert_eq!(ev[0].result, TxResult::BusOff);
+        assert_eq!(confirmation.try_result().unwrap().result, TxResult::BusOff);
+
+        // confirmations are disconnected when the tracker is reset
+        let d = t.allocate(1);
+        let confirmation = t.confirm(d);
+        t.reset(vec![false, false]);
+        assert!(matches!(
+            confirmation.wait(Duration::from_millis(1)),
+            Err(Error::NotRunning)
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_while_sending() {
+        let mut i = mock::interface(Mock::default());
+        i.start(|_| {}).unwrap();
+        let tx = i.transmitter();
+        let (done, finished) = bounded(2);
+        let sent = done.clone();
+        let sender = thread::spawn(move || {
+            let end = Instant::now() + Duration::from_millis(200);
+            while Instant::now() < end {
+                tx.send(&Frame::new(0x123, &[1]).unwrap()).unwrap();
+            }
+            sent.send(()).unwrap();
+        });
+        // snapshots take the device and the tracker in the same order as sends
+        let i = thread::spawn(move || {
+            while !sender.is_finished() {
+                i.snapshot();
+            }
+            done.send(()).unwrap();
+            i
+        });
+        let timeout = Duration::from_secs(10);
+        finished.recv_timeout(timeout).expect("deadlock");
+        finished.recv_timeout(timeout).expect("deadlock");
+        i.join().unwrap().stop().unwrap();
+    }
+
+    #[test]
+    fn test_transmitter() {
+        let mock = Mock::default();
+        let mut i = mock::interface(mock.clone());
+        let timeout = Duration::from_secs(1);
+        let f = Frame::new(0x123, &[1]).unwrap();
+        i.start(|_| {}).unwrap();
+
+        // every frame gets its own echo ID
+        let a = i.send_confirmed(f.clone()).unwrap();
+        let b = i.send_confirmed(f.clone()).unwrap();
+        assert_ne!(a.echo_id(), b.echo_id());
+        assert_eq!(a.wait(timeout).unwrap().echo_id, a.echo_id());
+        assert_eq!(b.wait(timeout).unwrap().echo_id, b.echo_id());
+
+        // frames which are not echoed fill the transmit slots of the channel
+        mock.faults.lock().unwrap().no_echo = true;
+        for _ in 0..TX_SLOTS {
+            i.try_send(f.clone()).unwrap();
+        }
+        assert!(matches!(i.try_send(f.clone()), Err(Error::WouldBlock)));
+        assert_eq!(i.tx.lock().unwrap().pending(0), TX_SLOTS);
+        // other channels have slots of their own, and a frame which found no
+        // free slot doesn't use up the rate limit
+        let mut other = f.clone();
+        other.channel = 1;
+        i.set_rate_limit(Some(crate::RateLimit::new(0.001, 1)))
+            .unwrap();
+        assert!(matches!(i.try_send(f.clone()), Err(Error::WouldBlock)));
+        i.try_send(other.clone()).unwrap();
+        assert!(matches!(i.try_send(other.clone()), Err(Error::WouldBlock)));
+        i.set_rate_limit(None).unwrap();
+        assert_eq!(i.tx.lock().unwrap().pending(1), 1);
+
+        // a frame the device didn't take is not pending
+        mock.faults.lock().unwrap().send = true;
+        assert!(i.send(other).is_err());
+        assert_eq!(i.tx.lock().unwrap().pending(1), 1);
+        mock.faults.lock().unwrap().send = false;
+        i.stop().unwrap();
+    }
+
+    #[test]
+    fn test_send_confirmed() {
+        let mock = Mock::default();
+        let mut i = mock::interface(mock.clone());
+        let ms = Duration::from_millis;
+        let f = Frame::new(0x123, &[1]).unwrap();
+        assert!(matches!(
+            i.send_confirmed(f.clone()),
+            Err(Error::NotRunning)
+        ));
+        let results = Arc::new(Mutex::new(vec![]));
+        let r = Arc::clone(&results);
+        i.on_tx_result(move |ev| r.lock().unwrap().push(ev.result));
+        i.start(|_| {}).unwrap();
+
+        let c = i.send_confirmed(f.clone()).unwrap();
+        let ev = c.wait(ms(1000)).unwrap();
+        assert_eq!(ev.result, TxResult::Sent);
+        assert_eq!(ev.channel, 0);
+
+        // without an echo the wait times out, and the result can come later
+        mock.faults.lock().unwrap().no_echo = true;
+        let c = i.send_confirmed(f.clone()).unwrap();
+        assert!(matches!(c.wait(ms(20)), Err(Error::Timeout)));
+        assert!(c.try_result().is_none());
+
+        // with the device's queue full, the oldest frame is reported as dropped
+        let rest: Vec<_> = (1..TX_SLOTS)
+            .map(|_| i.send_confirmed(f.clone()).unwrap())
+            .collect();
+        mock::inject(&i, error_frame(0, CAN_ERR_CRTL, CAN_ERR_CRTL_TX_OVERFLOW));
+        assert_eq!(c.wait(ms(1000)).unwrap().result, TxResult::Overflow);
+        assert!(rest[0].try_result().is_none());
+
+        // waiting confirmations are disconnected when the interface stops
+        i.stop().unwrap();
+        assert!(matches!(rest[0].wait(ms(20)), Err(Error::NotRunning)));
+        assert_eq!(
+            *results.lock().unwrap(),
+            [TxResult::Sent, TxResult::Overflow]
+        );
+    }
+
+    #[test]
+    fn test_send_blocking() {
+        let mock = Mock::default();
+        let mut i = mock::interface(mock.clone());
+        let ms = Duration::from_millis;
+        let f = Frame::new(0x123, &[1]).unwrap();
+        assert!(matches!(
+            i.send_blocking(f.clone(), ms(20)),
+            Err(Error::NotRunning)
+        ));
+        i.set_one_shot(0, true).unwrap();
+        i.start(|_| {}).unwrap();
+
+        let ev = i.send_blocking(f.clone(), ms(1000)).unwrap();
+        assert_eq!(ev.result, TxResult::Sent);
+
+        // nothing acknowledges the frame
+        mock.faults.lock().unwrap().no_echo = true;
+        assert!(matches!(
+            i.send_blocking(f.clone(), ms(20)),
+            Err(Error::Timeout)
+        ));
+
+        // in one-shot mode, a missing acknowledgement fails the oldest frame,
+        // which is the one timed out above
+        let inject = mock::injector(&i);
+        let nack = thread::spawn(move || {
+            thread::sleep(ms(20));
+            inject(error_frame(0, CAN_ERR_ACK, 0));
+            thread::sleep(ms(20));
+            inject(error_frame(0, CAN_ERR_ACK, 0));
+        });
+        assert!(matches!(
+            i.send_blocking(f, ms(1000)),
+            Err(Error::TxFailed(TxResult::NoAck))
+        ));
+        nack.join().unwrap();
+        i.stop().unwrap();
+    }
+}
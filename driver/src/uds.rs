@@ -0,0 +1,318 @@
+//! Unified Diagnostic Services (ISO 14229) client, including reading and
+//! clearing diagnostic trouble codes (DTCs).
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::{Error, IsoTpSocket};
+
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+const POSITIVE_RESPONSE: u8 = 0x40;
+const NRC_RESPONSE_PENDING: u8 = 0x78;
+
+const SID_CLEAR_DIAGNOSTIC_INFORMATION: u8 = 0x14;
+const SID_READ_DTC_INFORMATION: u8 = 0x19;
+
+const REPORT_NUMBER_OF_DTC_BY_STATUS_MASK: u8 = 0x01;
+const REPORT_DTC_BY_STATUS_MASK: u8 = 0x02;
+const REPORT_DTC_SNAPSHOT_RECORD_BY_DTC_NUMBER: u8 = 0x04;
+const REPORT_DTC_EXT_DATA_RECORD_BY_DTC_NUMBER: u8 = 0x06;
+const REPORT_DTC_BY_SEVERITY_MASK_RECORD: u8 = 0x08;
+
+/// Groups all DTCs when clearing diagnostic information.
+pub const ALL_DTCS: u32 = 0xFF_FFFF;
+
+/// Errors in a UDS exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdsError {
+    /// The server rejected the request. Contains the negative response code.
+    NegativeResponse(u8),
+    /// The response is too short or does not match the request.
+    InvalidResponse,
+}
+
+/// Status byte of a DTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcStatus(pub u8);
+
+impl DtcStatus {
+    /// The most recent test failed.
+    pub fn test_failed(self) -> bool {
+        self.0 & 0x01 != 0
+    }
+    /// A test failed during the current operation cycle.
+    pub fn test_failed_this_operation_cycle(self) -> bool {
+        self.0 & 0x02 != 0
+    }
+    /// The DTC is pending.
+    pub fn pending(self) -> bool {
+        self.0 & 0x04 != 0
+    }
+    /// The DTC is confirmed.
+    pub fn confirmed(self) -> bool {
+        self.0 & 0x08 != 0
+    }
+    /// The test has not completed since the DTC was last cleared.
+    pub fn test_not_completed_since_last_clear(self) -> bool {
+        self.0 & 0x10 != 0
+    }
+    /// A test failed since the DTC was last cleared.
+    pub fn test_failed_since_last_clear(self) -> bool {
+        self.0 & 0x20 != 0
+    }
+    /// The test has not completed during the current operation cycle.
+    pub fn test_not_completed_this_operation_cycle(self) -> bool {
+        self.0 & 0x40 != 0
+    }
+    /// The server requests the warning indicator to be on.
+    pub fn warning_indicator_requested(self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+/// A diagnostic trouble code with its status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtc {
+    /// The 3 byte DTC number.
+    pub code: u32,
+    /// Status of the DTC.
+    pub status: DtcStatus,
+}
+
+impl fmt::Display for Dtc {
+    /// Formats the DTC as in ISO 15031-6, for example `P0123-1A`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let system = ['P', 'C', 'B', 'U'][(self.code >> 22) as usize & 0x3];
+        write!(
+            f,
+            "{}{:01X}{:03X}-{:02X}",
+            system,
+            (self.code >> 20) & 0x3,
+            (self.code >> 8) & 0xFFF,
+            self.code & 0xFF
+        )
+    }
+}
+
+/// A DTC reported with its severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcSeverity {
+    /// The DTC and its status.
+    pub dtc: Dtc,
+    /// Severity byte. The upper three bits are the severity class defined by
+    /// ISO 14229: maintenance only, check at next halt, and check immediately.
+    pub severity: u8,
+    /// Functional unit the DTC belongs to.
+    pub functional_unit: u8,
+}
+
+impl DtcSeverity {
+    /// Only maintenance is required.
+    pub fn maintenance_only(&self) -> bool {
+        self.severity & 0x20 != 0
+    }
+    /// The DTC should be checked at the next halt.
+    pub fn check_at_next_halt(&self) -> bool {
+        self.severity & 0x40 != 0
+    }
+    /// The DTC should be checked immediately.
+    pub fn check_immediately(&self) -> bool {
+        self.severity & 0x80 != 0
+    }
+}
+
+/// A snapshot (freeze frame) or extended data record of a DTC.
+///
+/// The layout of the data is specific to the server, so it is returned
+/// undecoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtcRecord {
+    /// The DTC and its status when the record was read.
+    pub dtc: Dtc,
+    /// Record number.
+    pub number: u8,
+    /// Record contents. For snapshot records this starts with the number of data
+    /// identifiers, followed by each identifier and its data.
+    pub data: Vec<u8>,
+}
+
+/// A UDS client, sending requests to a server over an ISO-TP connection.
+pub struct UdsClient {
+    socket: IsoTpSocket,
+    timeout: Duration,
+    pending_timeout: Duration,
+}
+
+impl UdsClient {
+    /// Create a client using an ISO-TP connection to the server, see
+    /// `Interface::isotp`.
+    pub fn new(socket: IsoTpSocket) -> UdsClient {
+        UdsClient {
+            socket,
+            timeout: Duration::from_millis(50),
+            pending_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Set how long to wait for a response (P2), and for the final response after
+    /// the server reports that the response is pending (P2*). Default to 50ms and
+    /// 5s.
+    pub fn set_timeouts(&mut self, timeout: Duration, pending_timeout: Duration) {
+        self.timeout = timeout;
+        self.pending_timeout = pending_timeout;
+    }
+
+    /// Send a request and return the positive response, without the service ID.
+    pub fn request(&mut self, service: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut req = vec![service];
+        req.extend_from_slice(data);
+        let mut resp = self.socket.request(&req, self.timeout)?;
+        loop {
+            match resp.as_slice() {
+                [NEGATIVE_RESPONSE, s, NRC_RESPONSE_PENDING, ..] if *s == service => {
+                    resp = self.socket.recv(self.pending_timeout)?;
+                }
+                [NEGATIVE_RESPONSE, s, nrc, ..] if *s == service => {
+                    return Err(Error::Uds(UdsError::NegativeResponse(*nrc)))
+                }
+                [s, ..] if *s == (service | POSITIVE_RESPONSE) => return Ok(resp[1..].to_vec()),
+                _ => return Err(Error::Uds(UdsError::InvalidResponse)),
+            }
+        }
+    }
+
+    /// Clear the stored DTCs of a group, or all DTCs with `ALL_DTCS`.
+    pub fn clear_diagnostic_information(&mut self, group: u32) -> Result<(), Error> {
+        self.request(SID_CLEAR_DIAGNOSTIC_INFORMATION, &dtc_bytes(group))?;
+        Ok(())
+    }
+
+    /// Returns the number of DTCs with any of the status bits in `status_mask` set.
+    pub fn read_dtc_count(&mut self, status_mask: u8) -> Result<u16, Error> {
+        let resp =
+            self.read_dtc_information(REPORT_NUMBER_OF_DTC_BY_STATUS_MASK, &[status_mask])?;
+        // availability mask, format, count
+        match resp.as_slice() {
+            [_, _, hi, lo] => Ok(u16::from_be_bytes([*hi, *lo])),
+            _ => Err(Error::Uds(UdsError::InvalidResponse)),
+        }
+    }
+
+    /// Returns the DTCs with any of the status bits in `status_mask` set.
+    pub fn read_dtcs(&mut self, status_mask: u8) -> Result<Vec<Dtc>, Error> {
+        let resp = self.read_dtc_information(REPORT_DTC_BY_STATUS_MASK, &[status_mask])?;
+        // skip the availability mask
+        parse_dtcs(&resp[1..])
+    }
+
+    /// Returns the DTCs matching the severity and status masks, with their
+    /// severity.
+    pub fn read_dtcs_by_severity(
+        &mut self,
+        severity_mask: u8,
+        status_mask: u8,
+    ) -> Result<Vec<DtcSeverity>, Error> {
+        let resp = self.read_dtc_information(
+            REPORT_DTC_BY_SEVERITY_MASK_RECORD,
+            &[severity_mask, status_mask],
+        )?;
+        parse_severities(&resp[1..])
+    }
+
+    /// Read a snapshot record of a DTC.
+    pub fn read_snapshot(&mut self, dtc: u32, record: u8) -> Result<DtcRecord, Error> {
+        self.read_record(REPORT_DTC_SNAPSHOT_RECORD_BY_DTC_NUMBER, dtc, record)
+    }
+
+    /// Read an extended data record of a DTC.
+    pub fn read_extended_data(&mut self, dtc: u32, record: u8) -> Result<DtcRecord, Error> {
+        self.read_record(REPORT_DTC_EXT_DATA_RECORD_BY_DTC_NUMBER, dtc, record)
+    }
+
+    fn read_record(&mut self, sub_function: u8, dtc: u32, record: u8) -> Result<DtcRecord, Error> {
+        let mut req = dtc_bytes(dtc).to_vec();
+        req.push(record);
+        let resp = self.read_dtc_information(sub_function, &req)?;
+        parse_record(&resp)
+    }
+
+    // returns the response after the echoed sub-function
+    fn read_dtc_information(&mut self, sub_function: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut req = vec![sub_function];
+        req.extend_from_slice(data);
+        let resp = self.request(SID_READ_DTC_INFORMATION, &req)?;
+        match resp.split_first() {
+            Some((s, rest)) if *s == sub_function && !rest.is_empty() => Ok(rest.to_vec()),
+            _ => Err(Error::Uds(UdsError::InvalidResponse)),
+        }
+    }
+}
+
+fn dtc_bytes(dtc: u32) -> [u8; 3] {
+    [(dtc >> 16) as u8, (dtc >> 8) as u8, dtc as u8]
+}
+
+fn parse_dtc(b: &[u8]) -> Dtc {
+    Dtc {
+        code: (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32,
+        status: DtcStatus(b[3]),
+    }
+}
+
+fn parse_dtcs(data: &[u8]) -> Result<Vec<Dtc>, Error> {
+    if !data.len().is_multiple_of(4) {
+        return Err(Error::Uds(UdsError::InvalidResponse));
+    }
+    Ok(data.chunks(4).map(parse_dtc).collect())
+}
+
+fn parse_severities(data: &[u8]) -> Result<Vec<DtcSeverity>, Error> {
+    if !data.len().is_multiple_of(6) {
+        return Err(Error::Uds(UdsError::InvalidResponse));
+    }
+    Ok(data
+        .chunks(6)
+        .map(|c| DtcSeverity {
+            severity: c[0],
+            functional_unit: c[1],
+            dtc: parse_dtc(&c[2..]),
+        })
+        .collect())
+}
+
+// DTC and status, followed by the record number and its data
+fn parse_record(data: &[u8]) -> Result<DtcRecord, Error> {
+    if data.len() < 5 {
+        return Err(Error::Uds(UdsError::InvalidResponse));
+    }
+    Ok(DtcRecord {
+        dtc: parse_dtc(&data[..4]),
+        number: data[4],
+        data: data[5..].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uds_dtc() {
+        let dtcs = parse_dtcs(&[0x01, 0x23, 0x1A, 0x09, 0xC1, 0x00, 0x00, 0x28]).unwrap();
+        assert_eq!(dtcs.len(), 2);
+        assert_eq!(dtcs[0].to_string(), "P0123-1A");
+        assert!(dtcs[0].status.test_failed() && dtcs[0].status.confirmed());
+        assert!(!dtcs[0].status.pending());
+        assert_eq!(dtcs[1].to_string(), "U0100-00");
+        assert!(parse_dtcs(&[0x01, 0x23]).is_err());
+
+        let s = parse_severities(&[0x40, 0x10, 0x41, 0x23, 0x00, 0x08]).unwrap();
+        assert!(s[0].check_at_next_halt() && !s[0].check_immediately());
+        assert_eq!(s[0].dtc.to_string(), "C0123-00");
+
+        let r = parse_record(&[0x01, 0x23, 0x1A, 0x09, 0x01, 0x01, 0xF4, 0x0D, 0x42]).unwrap();
+        assert_eq!(r.number, 1);
+        assert_eq!(r.data, vec![0x01, 0xF4, 0x0D, 0x42]);
+        assert_eq!(dtc_bytes(ALL_DTCS), [0xFF, 0xFF, 0xFF]);
+    }
+}
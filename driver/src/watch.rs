@@ -0,0 +1,316 @@
+//! Content-change and timeout supervision of cyclic messages, similar to the
+//! SocketCAN broadcast manager's RX_SETUP.
+
+use std::time::{Duration, Instant};
+
+use crate::Frame;
+
+/// Notification from a watch registered with `Interface::watch`.
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    /// A frame was received for the first time, after a timeout, or with
+    /// different content than the previous frame (in the bits selected by the
+    /// watch's mask, or in its DLC).
+    Changed(Frame),
+    /// No frame with the watched ID was received within the watch's timeout.
+    /// Reported once until a frame is received again.
+    RxTimeout(u32),
+    /// A frame with the watched ID was received after an `RxTimeout`.
+    Restored(u32),
+    /// The time between two frames with the watched ID differed from the
+    /// expected period by more than the tolerance, see
+    /// `Interface::expect_period`.
+    PeriodDrift {
+        /// ID of the frames.
+        id: u32,
+        /// Time between the two frames.
+        period: Duration,
+    },
+}
+
+/// Handle to a watch, used to remove it with `Interface::unwatch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchHandle(u64);
+
+struct Watch {
+    handle: WatchHandle,
+    channel: u8,
+    id: u32,
+    mask: [u8; 8],
+    timeout: Option<Duration>,
+    // when false, only timeouts and restorations are reported
+    report_changes: bool,
+    // expected period and tolerance, if drift is reported
+    period: Option<(Duration, Duration)>,
+    callback: Box<dyn FnMut(WatchEvent) + Send>,
+
+    // masked data and dlc of the last frame, None until a frame is received
+    last: Option<([u8; 8], u8)>,
+    last_seen: Instant,
+    // when the last frame was received, None until a frame is received after
+    // starting or after a timeout
+    last_frame: Option<Instant>,
+    timed_out: bool,
+}
+
+impl Watch {
+    fn masked(&self, f: &Frame) -> ([u8; 8], u8) {
+        let mut data = [0u8; 8];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = f.data[i] & self.mask[i];
+        }
+        (data, f.can_dlc)
+    }
+}
+
+/// All watches registered on an `Interface`. Shared with the rx thread.
+pub(crate) struct Watches {
+    next_handle: u64,
+    watches: Vec<Watch>,
+}
+
+impl Watches {
+    pub(crate) fn new() -> Watches {
+        Watches {
+            next_handle: 0,
+            watches: vec![],
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        channel: u8,
+        id: u32,
+        mask: [u8; 8],
+        timeout: Option<Duration>,
+        report_changes: bool,
+        callback: Box<dyn FnMut(WatchEvent) + Send>,
+    ) -> WatchHandle {
+        let handle = WatchHandle(self.next_handle);
+        self.next_handle += 1;
+        self.watches.push(Watch {
+            handle,
+            channel,
+            id,
+            mask,
+            timeout,
+            report_changes,
+            period: None,
+            callback,
+            last: None,
+            last_seen: Instant::now(),
+            last_frame: None,
+            timed_out: false,
+        });
+        handle
+    }
+
+    /// Report frames of a watch which arrive more than `tolerance` earlier or
+    /// later than `period` after the previous one.
+    pub(crate) fn set_period(
+        &mut self,
+        handle: WatchHandle,
+        period: Duration,
+        tolerance: Duration,
+    ) {
+        if let Some(w) = self.watches.iter_mut().find(|w| w.handle == handle) {
+            w.period = Some((period, tolerance));
+        }
+    }
+
+    pub(crate) fn remove(&mut self, handle: WatchHandle) {
+        self.watches.retain(|w| w.handle != handle);
+    }
+
+    /// Restart all timeouts, called when the device goes on bus.
+    pub(crate) fn restart(&mut self, now: Instant) {
+        for w in self.watches.iter_mut() {
+            w.last_seen = now;
+            w.last_frame = None;
+        }
+    }
+
+    /// Handle a frame received from the bus.
+    pub(crate) fn frame(&mut self, f: &Frame, now: Instant) {
+        for w in self.watches.iter_mut() {
+            if w.channel != f.channel || w.id != f.raw_id() || f.rtr {
+                continue;
+            }
+            let masked = w.masked(f);
+            let restored = w.timed_out;
+            let changed = restored || w.last != Some(masked);
+            let drift = match (w.period, w.last_frame) {
+                (Some((period, tolerance)), Some(last)) if !restored => {
+                    let interval = now.saturating_duration_since(last);
+                    let deviation = interval.abs_diff(period);
+                    (deviation > tolerance).then_some(interval)
+                }
+                _ => None,
+            };
+            w.last = Some(masked);
+            w.last_seen = now;
+            w.last_frame = Some(now);
+            w.timed_out = false;
+            if restored {
+                (w.callback)(WatchEvent::Restored(w.id));
+            }
+            if let Some(period) = drift {
+                (w.callback)(WatchEvent::PeriodDrift { id: w.id, period });
+            }
+            if changed && w.report_changes {
+                (w.callback)(WatchEvent::Changed(f.clone()));
+            }
+        }
+    }
+
+    /// Report watches whose timeout has expired.
+    pub(crate) fn check_timeouts(&mut self, now: Instant) {
+        for w in self.watches.iter_mut() {
+            let timeout = match w.timeout {
+                Some(t) => t,
+                None => continue,
+            };
+            if !w.timed_out && now.duration_since(w.last_seen) > timeout {
+                w.timed_out = true;
+                w.last_frame = None;
+                (w.callback)(WatchEvent::RxTimeout(w.id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_watch_changes() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let ev = Arc::clone(&events);
+        let mut watches = Watches::new();
+        let start = Instant::now();
+        watches.add(
+            0,
+            0x123,
+            [0xFF, 0x0F, 0, 0, 0, 0, 0, 0],
+            Some(Duration::from_millis(100)),
+            true,
+            Box::new(move |e| ev.lock().unwrap().push(e)),
+        );
+
+        let mut f = Frame {
+            id: Id::new(0x123, false).unwrap(),
+            can_dlc: 2,
+            ..Default::default()
+        };
+        watches.frame(&f, start);
+        // only unmasked bits change
+        f.data[1] = 0xF0;
+        f.data[2] = 0xAA;
+        watches.frame(&f, start);
+        // masked bits change
+        f.data[1] = 0xF1;
+        watches.frame(&f, start);
+        // other IDs are ignored
+        f.id = Id::new(0x124, false).unwrap();
+        watches.frame(&f, start);
+        f.id = Id::new(0x123, false).unwrap();
+
+        watches.check_timeouts(start + Duration::from_millis(50));
+        watches.check_timeouts(start + Duration::from_millis(150));
+        watches.check_timeouts(start + Duration::from_millis(250));
+        // first frame after a timeout is always reported
+        watches.frame(&f, start + Duration::from_millis(300));
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(events[0], WatchEvent::Changed(_)));
+        assert!(matches!(events[1], WatchEvent::Changed(ref f) if f.data[1] == 0xF1));
+        assert!(matches!(events[2], WatchEvent::RxTimeout(0x123)));
+        assert!(matches!(events[3], WatchEvent::Restored(0x123)));
+        assert!(matches!(events[4], WatchEvent::Changed(_)));
+    }
+
+    #[test]
+    fn test_supervision() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let ev = Arc::clone(&events);
+        let mut watches = Watches::new();
+        let start = Instant::now();
+        watches.add(
+            1,
+            0x18FF_0000,
+            [0xFF; 8],
+            Some(Duration::from_millis(100)),
+            false,
+            Box::new(move |e| ev.lock().unwrap().push(e)),
+        );
+
+        let mut f = Frame {
+            id: Id::new(0x18FF_0000, true).unwrap(),
+            channel: 1,
+            ..Default::default()
+        };
+        watches.frame(&f, start);
+        f.data[0] = 1;
+        watches.frame(&f, start + Duration::from_millis(50));
+        watches.check_timeouts(start + Duration::from_millis(200));
+        watches.frame(&f, start + Duration::from_millis(300));
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], WatchEvent::RxTimeout(0x18FF_0000)));
+        assert!(matches!(events[1], WatchEvent::Restored(0x18FF_0000)));
+    }
+
+    #[test]
+    fn test_period_drift() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let ev = Arc::clone(&events);
+        let mut watches = Watches::new();
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let handle = watches.add(
+            0,
+            0x100,
+            [0; 8],
+            Some(Duration::from_millis(120)),
+            false,
+            Box::new(move |e| ev.lock().unwrap().push(e)),
+        );
+        watches.set_period(
+            handle,
+            Duration::from_millis(100),
+            Duration::from_millis(20),
+        );
+
+        let f = Frame {
+            id: Id::new(0x100, false).unwrap(),
+            ..Default::default()
+        };
+        watches.frame(&f, ms(0));
+        watches.frame(&f, ms(110));
+        // too early, then too late
+        watches.frame(&f, ms(150));
+        watches.frame(&f, ms(280));
+        watches.check_timeouts(ms(450));
+        // the first frame after the timeout is not a drift
+        watches.frame(&f, ms(500));
+        watches.frame(&f, ms(600));
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(matches!(
+            events[0],
+            WatchEvent::PeriodDrift { id: 0x100, period } if period == Duration::from_millis(40)
+        ));
+        assert!(matches!(
+            events[1],
+            WatchEvent::PeriodDrift { period, .. } if period == Duration::from_millis(130)
+        ));
+        assert!(matches!(events[2], WatchEvent::RxTimeout(0x100)));
+        assert!(matches!(events[3], WatchEvent::Restored(0x100)));
+    }
+}
@@ -20,6 +20,10 @@ const DEFAULT_CONFIG: Channel = Channel {
     loopback: false,
     monitor: false,
     enabled: true,
+    one_shot: false,
+    fd: false,
+    data_bitrate: 0,
+    triple_sample: false,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,13 +41,15 @@ impl fmt::Display for Config {
     }
 }
 
-impl Config {
-    pub fn default() -> Config {
+impl Default for Config {
+    fn default() -> Config {
         Config {
             channels: vec![DEFAULT_CONFIG, DEFAULT_CONFIG],
         }
     }
+}
 
+impl Config {
     // since config files are not mandatory, this should never fail
     pub fn read() -> Config {
         let dir = match get_app_root(AppDataType::UserConfig, &APP_INFO) {
@@ -87,6 +93,9 @@ impl Config {
             i.set_enabled(n, ch.enabled)?;
             i.set_loopback(n, ch.loopback)?;
             i.set_monitor(n, ch.monitor)?;
+            if ch.triple_sample {
+                i.set_triple_sample(n, true)?;
+            }
         }
         Ok(())
     }
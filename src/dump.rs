@@ -1,5 +1,5 @@
 use crate::Error;
-use cantact::{Frame, Interface};
+use cantact::{BusError, Direction, Frame, Interface};
 use clap::ArgMatches;
 use log::info;
 
@@ -7,18 +7,36 @@ use crate::config::Config;
 use crate::helpers;
 
 fn print_frame(f: Frame) {
-    let mut s = format!("  ch:{}  {:03X}   [{}]  ", f.channel, f.can_id, f.can_dlc);
-    for b in f.data.iter().take(f.can_dlc as usize) {
+    let dir = match f.direction {
+        Direction::Rx => "rx",
+        Direction::TxEcho(_) => "tx",
+    };
+    let mut s = format!(
+        "  ch:{}  {}  {:03X}   [{}]  ",
+        f.channel,
+        dir,
+        f.raw_id(),
+        f.can_dlc
+    );
+    for b in f.payload() {
         s = format!("{}{:02X} ", s, b);
     }
     println!("{}", s)
 }
 
+fn print_error(e: BusError) {
+    let mut s = format!("  ch:{}  err  {:?}", e.channel, e.kinds);
+    if let Some(state) = e.state {
+        s = format!("{}  {:?}", s, state);
+    }
+    println!("{}  tec:{} rec:{}", s, e.tx_error_count, e.rx_error_count)
+}
+
 pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
     let flag = helpers::initialize_ctrlc();
     let mut config = Config::read();
 
-    let ch = helpers::parse_channel(&matches)?;
+    let ch = helpers::parse_channel(matches)?;
     match ch {
         None => { /* no channel specified, follow config */ }
         Some(ch) => {
@@ -35,9 +53,15 @@ pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
     // initialize the interface
     let mut i = Interface::new()?;
     config.apply_to_interface(&mut i)?;
+    if let Some(filter) = helpers::parse_filter(matches)? {
+        for n in 0..i.channels() {
+            i.add_filter(n, filter.clone())?;
+        }
+    }
 
     // start the device
     info!("starting dump");
+    i.on_error(print_error);
     i.start(move |f: Frame| {
         print_frame(f);
     })
@@ -1,4 +1,5 @@
 use crate::Error;
+use cantact::Filter;
 use clap::ArgMatches;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -42,3 +43,31 @@ pub fn parse_channel(matches: &ArgMatches) -> Result<Option<usize>, Error> {
         Ok(ch) => Ok(Some(ch)),
     }
 }
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let s = s.trim();
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u32::from_str_radix(s, 16).ok()
+}
+
+/// Parses the filter argument, either `[id]:[mask]` in hex or a filter
+/// expression such as `id==0x7E8 || (id&0x700)==0x100`.
+pub fn parse_filter(matches: &ArgMatches) -> Result<Option<Filter>, Error> {
+    let s = match matches.value_of("filter") {
+        None => return Ok(None),
+        Some(s) => s,
+    };
+    if let Some((id, mask)) = s.split_once(':') {
+        return match (parse_hex(id), parse_hex(mask)) {
+            (Some(id), Some(mask)) => Ok(Some(Filter::Mask { id, mask })),
+            _ => Err(Error::InvalidArgument(String::from("invalid filter mask"))),
+        };
+    }
+    match s.parse() {
+        Ok(expr) => Ok(Some(Filter::Expr(expr))),
+        Err(e) => Err(Error::InvalidArgument(e.to_string())),
+    }
+}
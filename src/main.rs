@@ -6,7 +6,9 @@ use simplelog::*;
 // commands
 mod cfg;
 mod dump;
+mod selftest;
 mod send;
+mod slcan;
 
 pub mod config;
 pub mod helpers;
@@ -15,12 +17,18 @@ pub mod helpers;
 pub enum Error {
     DeviceError(DevError),
     InvalidArgument(String),
+    Io(std::io::Error),
 }
 impl From<DevError> for Error {
     fn from(de: DevError) -> Error {
         Error::DeviceError(de)
     }
 }
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
 
 fn main() {
     let yaml = load_yaml!("cli.yml");
@@ -37,6 +45,8 @@ fn main() {
         ("dump", Some(m)) => dump::cmd(m),
         ("send", Some(m)) => send::cmd(m),
         ("cfg", Some(m)) => cfg::cmd(m),
+        ("slcan", Some(m)) => slcan::cmd(m),
+        ("selftest", Some(m)) => selftest::cmd(m),
         _ => Ok(()),
     };
 
@@ -0,0 +1,314 @@
+use crate::Error;
+use clap::ArgMatches;
+
+#[cfg(feature = "selftest")]
+pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
+    harness::cmd(matches)
+}
+
+#[cfg(not(feature = "selftest"))]
+pub fn cmd(_matches: &ArgMatches) -> Result<(), Error> {
+    Err(Error::InvalidArgument(String::from(
+        "self test support was not enabled, rebuild with --features selftest",
+    )))
+}
+
+#[cfg(feature = "selftest")]
+mod harness {
+    use crate::Error;
+    use cantact::{Bitrate, Direction, Frame, Id, Interface};
+    use clap::ArgMatches;
+    use log::info;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::{Duration, Instant};
+
+    // how long to wait for a frame to arrive before failing a check
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    const BITRATES: [Bitrate; 4] = [Bitrate::K125, Bitrate::K250, Bitrate::K500, Bitrate::M1];
+
+    enum Outcome {
+        Pass,
+        Fail(String),
+        Skip(&'static str),
+    }
+
+    struct Harness {
+        i: Interface,
+        rx: Option<Receiver<Frame>>,
+        // frames received while waiting for another frame
+        backlog: Vec<Frame>,
+        results: Vec<(String, Outcome)>,
+    }
+
+    impl Harness {
+        // reset every channel to a known configuration, with the device stopped
+        fn reset(&mut self) -> Result<(), Error> {
+            if self.rx.take().is_some() {
+                self.i.stop()?;
+            }
+            for n in 0..self.i.channels() {
+                self.i.set_enabled(n, true)?;
+                self.i.set_monitor(n, false)?;
+                self.i.set_loopback(n, false)?;
+                self.i.set_bitrate_preset(n, Bitrate::K500)?;
+            }
+            Ok(())
+        }
+
+        fn start(&mut self) -> Result<(), Error> {
+            let (send, recv) = channel();
+            self.i.start(move |f: Frame| {
+                send.send(f).ok();
+            })?;
+            self.rx = Some(recv);
+            self.backlog.clear();
+            Ok(())
+        }
+
+        // wait for a frame on `channel` matching the sent frame's ID and data
+        fn expect(&mut self, channel: usize, sent: &Frame, echo: bool) -> Result<Frame, String> {
+            let matches = |f: &Frame| {
+                f.channel as usize == channel
+                    && f.id == sent.id
+                    && matches!(f.direction, Direction::TxEcho(_)) == echo
+            };
+            let f = match self.backlog.iter().position(&matches) {
+                Some(n) => self.backlog.remove(n),
+                None => {
+                    let rx = self.rx.as_ref().unwrap();
+                    let deadline = Instant::now() + TIMEOUT;
+                    loop {
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        let f = rx.recv_timeout(timeout).map_err(|_| {
+                            format!(
+                                "frame {:03X} not seen on channel {}",
+                                sent.raw_id(),
+                                channel
+                            )
+                        })?;
+                        if matches(&f) {
+                            break f;
+                        }
+                        self.backlog.push(f);
+                    }
+                }
+            };
+            if f.payload() != sent.payload() {
+                return Err(format!("frame {:03X} data corrupted", sent.raw_id()));
+            }
+            Ok(f)
+        }
+
+        fn check(
+            &mut self,
+            name: String,
+            run: impl FnOnce(&mut Harness) -> Result<Outcome, Error>,
+        ) {
+            info!("running check: {}", name);
+            let outcome = match self.reset().and_then(|_| run(self)) {
+                Ok(o) => o,
+                Err(e) => Outcome::Fail(format!("{:?}", e)),
+            };
+            self.results.push((name, outcome));
+        }
+    }
+
+    fn frame(channel: usize, can_id: u32) -> Frame {
+        let mut f = Frame {
+            id: Id::new(can_id, can_id > 0x7FF).unwrap(),
+            can_dlc: 8,
+            channel: channel as u8,
+            ..Default::default()
+        };
+        f.data[..8].copy_from_slice(&[0x55, 0xAA, 0x00, 0xFF, 0x01, 0x02, 0x04, 0x08]);
+        f
+    }
+
+    fn outcome(r: Result<(), String>) -> Outcome {
+        match r {
+            Ok(()) => Outcome::Pass,
+            Err(e) => Outcome::Fail(e),
+        }
+    }
+
+    // checks which only need the device, using its internal loopback mode
+    fn loopback_checks(h: &mut Harness, ch: usize) {
+        if !h.i.capabilities(ch).is_ok_and(|c| c.loop_back) {
+            h.check(format!("ch{} loopback", ch), |_| {
+                Ok(Outcome::Skip("not supported by device"))
+            });
+            return;
+        }
+
+        for b in BITRATES.iter() {
+            let b = *b;
+            h.check(format!("ch{} loopback at {:?}", ch, b), move |h| {
+                h.i.set_loopback(ch, true)?;
+                h.i.set_bitrate_preset(ch, b)?;
+                h.start()?;
+                let mut result = Ok(());
+                for id in [0x123, 0x7FF, 0x1ABC_DEF0].iter() {
+                    let f = frame(ch, *id);
+                    h.i.send(f.clone())?;
+                    result = result.and_then(|_| h.expect(ch, &f, true).map(|_| ()));
+                }
+                Ok(outcome(result))
+            });
+        }
+
+        h.check(format!("ch{} remote frames", ch), move |h| {
+            h.i.set_loopback(ch, true)?;
+            h.start()?;
+            let f = Frame {
+                rtr: true,
+                can_dlc: 0,
+                ..frame(ch, 0x321)
+            };
+            h.i.send(f.clone())?;
+            Ok(outcome(h.expect(ch, &f, true).and_then(|e| match e.rtr {
+                true => Ok(()),
+                false => Err(String::from("echo is not a remote frame")),
+            })))
+        });
+
+        h.check(format!("ch{} timestamps", ch), move |h| {
+            h.i.set_loopback(ch, true)?;
+            h.start()?;
+            let mut last = Duration::from_secs(0);
+            for n in 0..10 {
+                let f = frame(ch, 0x100 + n);
+                h.i.send(f.clone())?;
+                let t = match h.expect(ch, &f, true) {
+                    Ok(e) => e.timestamp,
+                    Err(e) => return Ok(Outcome::Fail(e)),
+                };
+                match t {
+                    Some(t) if t >= last => last = t,
+                    Some(_) => return Ok(Outcome::Fail(String::from("timestamps not monotonic"))),
+                    None => return Ok(Outcome::Fail(String::from("frame has no timestamp"))),
+                }
+            }
+            Ok(Outcome::Pass)
+        });
+
+        h.check(format!("ch{} FD", ch), move |h| {
+            if !h.i.capabilities(ch).is_ok_and(|c| c.fd) {
+                return Ok(Outcome::Skip("not supported by device"));
+            }
+            h.i.set_loopback(ch, true)?;
+            h.i.set_bitrate_preset(ch, Bitrate::Fd500k2M)?;
+            h.start()?;
+            // a full 64 byte payload
+            let mut f = Frame {
+                fd: true,
+                can_dlc: 15,
+                ..frame(ch, 0x456)
+            };
+            for (i, b) in f.data.iter_mut().enumerate() {
+                *b = i as u8;
+            }
+            h.i.send(f.clone())?;
+            Ok(outcome(h.expect(ch, &f, true).map(|_| ())))
+        });
+    }
+
+    // checks which need channels 0 and 1 connected with a loopback cable
+    fn cable_checks(h: &mut Harness) {
+        for b in BITRATES.iter() {
+            let b = *b;
+            h.check(format!("ch0 <-> ch1 at {:?}", b), move |h| {
+                h.i.set_bitrate_preset(0, b)?;
+                h.i.set_bitrate_preset(1, b)?;
+                h.start()?;
+                let mut result = Ok(());
+                for (tx, rx) in [(0, 1), (1, 0)].iter() {
+                    let f = frame(*tx, 0x200 + *tx as u32);
+                    h.i.send(f.clone())?;
+                    result = result
+                        .and_then(|_| h.expect(*rx, &f, false))
+                        .and_then(|_| h.expect(*tx, &f, true))
+                        .map(|_| ());
+                }
+                Ok(outcome(result))
+            });
+        }
+
+        h.check(String::from("ch1 listen only"), |h| {
+            if !h.i.capabilities(1).is_ok_and(|c| c.listen_only) {
+                return Ok(Outcome::Skip("not supported by device"));
+            }
+            h.i.set_monitor(1, true)?;
+            h.start()?;
+            let f = frame(0, 0x300);
+            h.i.send(f.clone())?;
+            // ch0 retransmits without an acknowledgement, so the frame is still seen
+            Ok(outcome(h.expect(1, &f, false).map(|_| ())))
+        });
+
+        h.check(String::from("ch1 filters"), |h| {
+            let (send, recv) = channel();
+            let sub = h.i.subscribe(0x400, 0x7F0, move |f: Frame| {
+                send.send(f.raw_id()).ok();
+            });
+            h.start()?;
+            for id in [0x405, 0x505, 0x40A].iter() {
+                let f = frame(0, *id);
+                h.i.send(f.clone())?;
+                if let Err(e) = h.expect(1, &f, false) {
+                    h.i.unsubscribe(sub);
+                    return Ok(Outcome::Fail(e));
+                }
+            }
+            h.i.unsubscribe(sub);
+            let ids: Vec<u32> = recv.try_iter().collect();
+            match ids == vec![0x405, 0x40A] {
+                true => Ok(Outcome::Pass),
+                false => Ok(Outcome::Fail(format!("filter delivered {:X?}", ids))),
+            }
+        });
+    }
+
+    pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
+        let pro = matches.is_present("pro");
+        let mut h = Harness {
+            i: Interface::new()?,
+            rx: None,
+            backlog: vec![],
+            results: vec![],
+        };
+        println!("CANtact self test: {} channel(s)", h.i.channels());
+
+        for ch in 0..h.i.channels() {
+            loopback_checks(&mut h, ch);
+        }
+        if pro {
+            if h.i.channels() < 2 {
+                h.results.push((
+                    String::from("ch0 <-> ch1"),
+                    Outcome::Fail(String::from("device has only one channel")),
+                ));
+            } else {
+                cable_checks(&mut h);
+            }
+        }
+        h.reset()?;
+
+        let mut failed = 0;
+        for (name, outcome) in h.results.iter() {
+            match outcome {
+                Outcome::Pass => println!("PASS  {}", name),
+                Outcome::Skip(why) => println!("SKIP  {} ({})", name, why),
+                Outcome::Fail(why) => {
+                    failed += 1;
+                    println!("FAIL  {}: {}", name, why)
+                }
+            }
+        }
+        println!("{} checks, {} failed", h.results.len(), failed);
+        if failed > 0 {
+            std::process::exit(1);
+        }
+        Ok(())
+    }
+}
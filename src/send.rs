@@ -1,5 +1,5 @@
 use crate::Error;
-use cantact::{Frame, Interface};
+use cantact::{Frame, Id, Interface};
 use clap::ArgMatches;
 use std::thread;
 use std::time::Duration;
@@ -17,10 +17,12 @@ pub fn cmd(_matches: &ArgMatches) -> Result<(), Error> {
     i.start(|_: Frame| {}).expect("failed to start device");
 
     let mut count = 0;
-    let mut f = Frame::default();
-    f.can_dlc = 8;
+    let mut f = Frame {
+        can_dlc: 8,
+        ..Default::default()
+    };
     loop {
-        f.can_id = count % 0x800;
+        f.id = Id::new(count % 0x800, false).unwrap();
         i.send(f.clone()).unwrap();
         count += 1;
         if count % 1000 == 0 {
@@ -0,0 +1,199 @@
+use crate::Error;
+use clap::ArgMatches;
+
+#[cfg(unix)]
+pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
+    unix::cmd(matches)
+}
+
+#[cfg(not(unix))]
+pub fn cmd(_matches: &ArgMatches) -> Result<(), Error> {
+    Err(Error::InvalidArgument(String::from(
+        "SLCAN mode requires a pseudo-terminal and is only supported on Linux and macOS",
+    )))
+}
+
+#[cfg(unix)]
+mod unix {
+    use crate::config::Config;
+    use crate::helpers;
+    use crate::Error;
+    use cantact::slcan::{self, Command};
+    use cantact::{Direction, Frame, Interface};
+    use clap::ArgMatches;
+    use log::info;
+    use std::ffi::CStr;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // how long to wait for input before checking for ctrl-c
+    const POLL_TIMEOUT_MS: i32 = 100;
+
+    // open a pseudo-terminal in raw mode, returning the master side, the slave
+    // side (kept open so the master stays usable between clients), and its path
+    fn open_pty() -> io::Result<(File, File, String)> {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if master < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(master);
+            if libc::grantpt(master.as_raw_fd()) != 0 || libc::unlockpt(master.as_raw_fd()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(master.as_raw_fd());
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+            let mut t = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut t) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut t);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &t) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((master, slave, path))
+        }
+    }
+
+    // wait until the file has data to read, returns false on timeout
+    fn poll_readable(f: &File) -> bool {
+        let mut pfd = libc::pollfd {
+            fd: f.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pfd, 1, POLL_TIMEOUT_MS) > 0 }
+    }
+
+    struct Gateway {
+        i: Interface,
+        channel: usize,
+        running: bool,
+        timestamps: Arc<AtomicBool>,
+        out: Arc<Mutex<File>>,
+    }
+
+    impl Gateway {
+        fn start(&mut self, listen_only: bool) -> Result<(), Error> {
+            self.i.set_monitor(self.channel, listen_only)?;
+
+            let channel = self.channel as u8;
+            let timestamps = Arc::clone(&self.timestamps);
+            let out = Arc::clone(&self.out);
+            self.i.start(move |f: Frame| {
+                // SLCAN hosts don't expect their own frames back
+                if f.channel != channel || f.direction != Direction::Rx {
+                    return;
+                }
+                let t = match (timestamps.load(Ordering::SeqCst), f.timestamp) {
+                    (true, Some(t)) => Some(t.as_millis() as u32),
+                    _ => None,
+                };
+                let line = slcan::encode_frame(&f, t);
+                out.lock().unwrap().write_all(line.as_bytes()).ok();
+            })?;
+            self.running = true;
+            Ok(())
+        }
+
+        fn handle(&mut self, line: &str) -> String {
+            let cmd = match slcan::parse_command(line) {
+                Some(c) => c,
+                None => return String::from(slcan::ERROR),
+            };
+            info!("slcan command: {:?}", cmd);
+            let result = match cmd {
+                Command::Open | Command::ListenOnly if self.running => {
+                    return String::from(slcan::ERROR)
+                }
+                Command::Open => self.start(false),
+                Command::ListenOnly => self.start(true),
+                Command::Close => {
+                    self.running = false;
+                    self.i.stop().map_err(Error::from)
+                }
+                Command::Bitrate(b) => self
+                    .i
+                    .set_bitrate_preset(self.channel, b)
+                    .map_err(Error::from),
+                Command::Transmit(mut f) => {
+                    f.channel = self.channel as u8;
+                    let ext = f.is_extended();
+                    return match self.i.send(f) {
+                        Ok(_) if ext => String::from("Z\r"),
+                        Ok(_) => String::from("z\r"),
+                        Err(_) => String::from(slcan::ERROR),
+                    };
+                }
+                Command::Timestamps(enabled) => {
+                    self.timestamps.store(enabled, Ordering::SeqCst);
+                    Ok(())
+                }
+                Command::Version => return String::from("V1010\r"),
+                Command::SerialNumber => return String::from("N0000\r"),
+                Command::Status => return String::from("F00\r"),
+            };
+            match result {
+                Ok(()) => String::from(slcan::OK),
+                Err(_) => String::from(slcan::ERROR),
+            }
+        }
+    }
+
+    pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
+        let flag = helpers::initialize_ctrlc();
+        let config = Config::read();
+        let channel = helpers::parse_channel(matches)?.unwrap_or(0);
+
+        let mut i = Interface::new()?;
+        config.apply_to_interface(&mut i)?;
+        // only the selected channel is exposed
+        for n in 0..i.channels() {
+            i.set_enabled(n, n == channel)?;
+        }
+
+        let (mut master, _slave, path) = open_pty()?;
+        println!("SLCAN device for channel {}: {}", channel, path);
+
+        let mut gw = Gateway {
+            i,
+            channel,
+            running: false,
+            timestamps: Arc::new(AtomicBool::new(false)),
+            out: Arc::new(Mutex::new(master.try_clone()?)),
+        };
+
+        let mut line = String::new();
+        let mut buf = [0u8; 256];
+        while !helpers::check_ctrlc(&flag) {
+            if !poll_readable(&master) {
+                continue;
+            }
+            let n = master.read(&mut buf)?;
+            for &b in buf[..n].iter() {
+                match b {
+                    b'\r' => {
+                        let resp = gw.handle(&line);
+                        gw.out.lock().unwrap().write_all(resp.as_bytes())?;
+                        line.clear();
+                    }
+                    b'\n' => {}
+                    b => line.push(b as char),
+                }
+            }
+        }
+
+        if gw.running {
+            gw.i.stop()?;
+        }
+        Ok(())
+    }
+}